@@ -0,0 +1,15 @@
+//! 编译 `src/jpegtran_shim.c`：在C中用setjmp/longjmp处理libjpeg的致命错误，
+//! 使无损变换在 `panic = "abort"` 的构建中也能把损坏的JPEG报告为错误
+
+fn main() {
+    #[cfg(feature = "encode")]
+    {
+        println!("cargo:rerun-if-changed=src/jpegtran_shim.c");
+        // mozjpeg-sys (links = "jpeg") 导出jconfig.h和vendor头文件所在的目录
+        let include = std::env::var_os("DEP_JPEG_INCLUDE").expect("mozjpeg-sys没有导出头文件目录");
+        cc::Build::new()
+            .file("src/jpegtran_shim.c")
+            .includes(std::env::split_paths(&include))
+            .compile("pixforge_jpegtran");
+    }
+}
@@ -0,0 +1,27 @@
+//! 对给定的JPEG文件执行三种无损重写，逐行输出结果
+//!
+//! `tests/jpegtran_errors.rs` 用 `abort-check` 配置 (panic = "abort") 编译运行它，
+//! 检查损坏的输入在release构建中得到错误而不是终止进程
+
+#[cfg(feature = "encode")]
+fn main() {
+    use pixforge::jpegtran;
+    use pixforge::transform::Reorient;
+
+    let path = std::env::args().nth(1).expect("用法: jpegtran_check <JPEG文件>");
+    let data = std::fs::read(&path).expect("无法读取输入");
+    let results = [
+        jpegtran::transform(&data, Reorient::from_degrees(90).unwrap()).map(|_| ()),
+        jpegtran::optimize(&data).map(|_| ()),
+        jpegtran::set_restart_interval(&data, 1).map(|_| ()),
+    ];
+    for result in results {
+        match result {
+            Ok(()) => println!("ok"),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
+
+#[cfg(not(feature = "encode"))]
+fn main() {}
@@ -0,0 +1,117 @@
+//! 动画帧的读取、重采样与GIF输出
+//!
+//! 按目标帧率在时间轴上取样，被丢弃的帧时长并入前一个保留帧，
+//! 因此动画总时长不变，只是帧数变少
+
+use anyhow::{Context, Result};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, Delay, Frame};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// 浮点累加误差的容差 (毫秒)
+const EPSILON_MS: f64 = 1e-6;
+
+/// 读取GIF或动态WebP的全部帧，其它格式或静态WebP返回 `Ok(None)`
+pub fn decode_frames(path: &Path, format: &str) -> Result<Option<Vec<Frame>>> {
+    let open = || -> Result<BufReader<File>> {
+        let file = File::open(path).with_context(|| format!("无法打开图像: {}", path.display()))?;
+        Ok(BufReader::new(file))
+    };
+
+    let frames = match format {
+        "gif" => GifDecoder::new(open()?)?.into_frames().collect_frames(),
+        "webp" => {
+            let decoder = WebPDecoder::new(open()?)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames().collect_frames()
+        }
+        _ => return Ok(None),
+    };
+    frames.map(Some).with_context(|| format!("无法解码动画帧: {}", path.display()))
+}
+
+/// 按目标帧率重采样
+///
+/// 在 `0, 1/fps, 2/fps, ...` 处取当时显示的帧；目标帧率不低于源帧率时每帧都会保留
+pub fn resample_frames(frames: Vec<Frame>, fps: f32) -> Vec<Frame> {
+    let interval = 1000.0 / f64::from(fps);
+    let mut kept: Vec<(Frame, f64)> = Vec::new();
+    let mut elapsed = 0.0;
+    let mut next_sample = 0.0;
+
+    for frame in frames {
+        let delay = delay_ms(frame.delay());
+        match kept.last_mut() {
+            Some((_, duration)) if elapsed + EPSILON_MS < next_sample => *duration += delay,
+            _ => {
+                kept.push((frame, delay));
+                while next_sample <= elapsed + EPSILON_MS {
+                    next_sample += interval;
+                }
+            }
+        }
+        elapsed += delay;
+    }
+
+    kept.into_iter()
+        .map(|(frame, duration)| {
+            let (left, top) = (frame.left(), frame.top());
+            let delay = Delay::from_numer_denom_ms(duration.round() as u32, 1);
+            Frame::from_parts(frame.into_buffer(), left, top, delay)
+        })
+        .collect()
+}
+
+/// 编码为无限循环的GIF动画
+pub fn encode_gif(frames: Vec<Frame>) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut data);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames)?;
+    }
+    Ok(data)
+}
+
+fn delay_ms(delay: Delay) -> f64 {
+    let (numer, denom) = delay.numer_denom_ms();
+    f64::from(numer) / f64::from(denom.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn frames(count: u32, delay_ms: u32) -> Vec<Frame> {
+        (0..count)
+            .map(|i| {
+                let buffer = RgbaImage::from_pixel(8, 8, Rgba([(i * 10) as u8, 0, 0, 255]));
+                Frame::from_parts(buffer, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_keeps_total_duration() {
+        let resampled = resample_frames(frames(20, 20), 10.0);
+        assert_eq!(resampled.len(), 4);
+        for frame in &resampled {
+            assert_eq!(delay_ms(frame.delay()), 100.0);
+        }
+        // 取样的是第 0, 5, 10, 15 帧
+        assert_eq!(resampled[1].buffer().get_pixel(0, 0)[0], 50);
+    }
+
+    #[test]
+    fn test_higher_target_fps_keeps_source_frames() {
+        let resampled = resample_frames(frames(6, 100), 30.0);
+        assert_eq!(resampled.len(), 6);
+        assert!(resampled.iter().all(|frame| delay_ms(frame.delay()) == 100.0));
+    }
+}
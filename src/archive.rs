@@ -0,0 +1,315 @@
+//! 读写zip压缩包，用于直接转换压缩包里的图像，以及把转换结果打包
+//!
+//! 读取时逐个条目解压到内存，不在磁盘上展开整个压缩包；写入时每个输出文件先写到暂存目录，
+//! 转换完成后立即移入压缩包并删除，暂存目录中同时只有一个文件
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::utils;
+
+/// 输入路径是否按zip压缩包处理 (只看扩展名，docx、jar等同样以zip格式存储的文件不算)
+pub fn is_zip(path: &Path) -> bool {
+    path.is_file() && utils::get_extension(path) == "zip"
+}
+
+/// 输出路径是否表示写入zip压缩包：扩展名为 `.zip` 且不是已存在的目录
+pub fn is_zip_output(path: &Path) -> bool {
+    !path.is_dir() && utils::get_extension(path) == "zip"
+}
+
+/// 压缩包中的一个文件
+#[derive(Debug)]
+pub struct ArchiveEntry {
+    /// 在压缩包内的相对路径
+    pub path: PathBuf,
+    /// 解压后的内容
+    pub data: Vec<u8>,
+}
+
+/// 没有解压的条目
+#[derive(Debug, PartialEq, Eq)]
+pub enum SkippedEntry {
+    /// 路径是绝对路径或者含有 `..`，会写到输出目录之外
+    Unsafe(String),
+    /// 解压后超过大小上限
+    TooLarge(PathBuf),
+}
+
+/// 依次解压压缩包中的每个文件并交给 `visit`，跳过目录条目
+///
+/// 路径不安全的条目和超过 `max_size` 的条目不解压，以 `Err` 交给 `visit` 由调用方决定是否继续。
+/// 条目头中的大小可以伪造，解压时同样最多读取 `max_size` 字节；
+/// `visit` 返回错误或 [`ControlFlow::Break`] 时停止
+pub fn for_each_file(
+    archive: &Path,
+    max_size: Option<u64>,
+    mut visit: impl FnMut(Result<ArchiveEntry, SkippedEntry>) -> Result<ControlFlow<()>>,
+) -> Result<()> {
+    let file = File::open(archive).with_context(|| format!("无法打开压缩包: {}", archive.display()))?;
+    let mut zip = zip::ZipArchive::new(BufReader::new(file))
+        .with_context(|| format!("无法解析zip压缩包: {}", archive.display()))?;
+
+    for index in 0..zip.len() {
+        let entry = zip
+            .by_index(index)
+            .with_context(|| format!("无法读取压缩包条目 #{}: {}", index, archive.display()))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let skipped = match entry.enclosed_name() {
+            None => Err(SkippedEntry::Unsafe(entry.name().to_string())),
+            Some(path) if max_size.is_some_and(|max| entry.size() > max) => Err(SkippedEntry::TooLarge(path)),
+            Some(path) => Ok(path),
+        };
+        let path = match skipped {
+            Ok(path) => path,
+            Err(skipped) => {
+                if visit(Err(skipped))?.is_break() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        // 多读一个字节，超过上限时不必读完整个条目就能发现
+        let limit = max_size.map_or(u64::MAX, |max| max.saturating_add(1));
+        let mut data = Vec::with_capacity(entry.size().min(64 * 1024 * 1024) as usize);
+        entry
+            .take(limit)
+            .read_to_end(&mut data)
+            .with_context(|| format!("无法解压 {}: {}", path.display(), archive.display()))?;
+        let visited = if max_size.is_some_and(|max| data.len() as u64 > max) {
+            visit(Err(SkippedEntry::TooLarge(path)))
+        } else {
+            visit(Ok(ArchiveEntry { path, data }))
+        };
+        if visited?.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// 正在写入的zip压缩包
+///
+/// 压缩包先写到同目录的临时文件，[`ZipOutput::finish`] 时才改名为目标路径；
+/// 没有完成就被丢弃时删除临时文件和暂存目录，不留下不完整的压缩包
+pub struct ZipOutput {
+    path: PathBuf,
+    temp: PathBuf,
+    staging: PathBuf,
+    writer: Option<zip::ZipWriter<BufWriter<File>>>,
+    names: HashSet<String>,
+}
+
+impl ZipOutput {
+    /// 开始写入 `path`，需要时创建上级目录
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).with_context(|| format!("无法创建输出目录: {}", parent.display()))?;
+        }
+        let temp = utils::temp_path(path);
+        let staging = utils::temp_path(&path.with_extension("staging"));
+        fs::create_dir(&staging).with_context(|| format!("无法创建暂存目录: {}", staging.display()))?;
+        let file = File::create(&temp).with_context(|| format!("无法创建压缩包: {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            temp,
+            staging,
+            writer: Some(zip::ZipWriter::new(BufWriter::new(file))),
+            names: HashSet::new(),
+        })
+    }
+
+    /// 目标压缩包路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 转换结果先写到这个目录，目录中的相对路径就是压缩包中的条目名
+    pub fn staging_dir(&self) -> &Path {
+        &self.staging
+    }
+
+    /// 把暂存目录中的文件写入压缩包并删除，返回条目名
+    ///
+    /// 条目名已存在时返回错误，不会覆盖先写入的条目
+    pub fn add(&mut self, file: &Path) -> Result<String> {
+        let name = self.entry_name(file)?;
+        if !self.names.insert(name.clone()) {
+            let _ = fs::remove_file(file);
+            anyhow::bail!("压缩包中已有同名条目: {}", name);
+        }
+
+        let writer = self.writer.as_mut().expect("压缩包已完成");
+        writer
+            .start_file(name.as_str(), zip::write::SimpleFileOptions::default())
+            .with_context(|| format!("无法写入压缩包条目: {}", name))?;
+        let mut source = File::open(file).with_context(|| format!("无法读取输出文件: {}", file.display()))?;
+        std::io::copy(&mut source, writer).with_context(|| format!("无法写入压缩包条目: {}", name))?;
+        fs::remove_file(file).with_context(|| format!("无法删除暂存文件: {}", file.display()))?;
+        Ok(name)
+    }
+
+    /// 暂存目录中的这个文件对应的条目是否已经写入压缩包
+    pub fn contains(&self, file: &Path) -> bool {
+        self.entry_name(file).is_ok_and(|name| self.names.contains(&name))
+    }
+
+    /// 暂存目录中的文件对应的条目名，路径分隔符统一为 `/`
+    fn entry_name(&self, file: &Path) -> Result<String> {
+        let relative = file
+            .strip_prefix(&self.staging)
+            .with_context(|| format!("文件不在暂存目录中: {}", file.display()))?;
+        Ok(relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"))
+    }
+
+    /// 把暂存目录中剩余的文件 (例如PDF的其余页面) 按路径顺序写入，完成压缩包并改名为目标路径
+    pub fn finish(mut self) -> Result<()> {
+        let leftovers: Vec<PathBuf> = WalkDir::new(&self.staging)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .collect();
+        for file in leftovers {
+            self.add(&file)?;
+        }
+
+        let writer = self.writer.take().expect("压缩包已完成");
+        writer
+            .finish()
+            .and_then(|mut file| file.flush().map_err(Into::into))
+            .with_context(|| format!("无法写入压缩包: {}", self.path.display()))?;
+        fs::rename(&self.temp, &self.path).with_context(|| format!("无法写入压缩包: {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+impl Drop for ZipOutput {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.staging);
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// 按给定的名称和内容写出zip压缩包
+    pub(crate) fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, data) in entries {
+            if name.ends_with('/') {
+                writer.add_directory(*name, options).unwrap();
+            } else {
+                writer.start_file(*name, options).unwrap();
+                writer.write_all(data).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_for_each_file_reads_entries_and_rejects_escapes() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("input.zip");
+        write_zip(&archive, &[("a.txt", b"alpha"), ("nested/", b""), ("nested/b.txt", b"beta"), ("../evil.txt", b"x")]);
+        assert!(is_zip(&archive));
+        assert!(!is_zip(&dir.path().join("missing.zip")));
+
+        let mut files = Vec::new();
+        let mut rejected = Vec::new();
+        for_each_file(&archive, None, |entry| {
+            match entry {
+                Ok(entry) => files.push((entry.path, entry.data)),
+                Err(skipped) => rejected.push(skipped),
+            }
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        assert_eq!(files, vec![(PathBuf::from("a.txt"), b"alpha".to_vec()), (PathBuf::from("nested/b.txt"), b"beta".to_vec())]);
+        assert_eq!(rejected, vec![SkippedEntry::Unsafe("../evil.txt".to_string())]);
+    }
+
+    #[test]
+    fn test_for_each_file_skips_large_entries_and_stops_on_break() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("input.zip");
+        write_zip(&archive, &[("small.txt", b"tiny"), ("big.txt", &[0; 100]), ("last.txt", b"end")]);
+
+        let mut visited = Vec::new();
+        for_each_file(&archive, Some(10), |entry| {
+            visited.push(entry.map(|entry| entry.path));
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        assert_eq!(
+            visited,
+            vec![
+                Ok(PathBuf::from("small.txt")),
+                Err(SkippedEntry::TooLarge(PathBuf::from("big.txt"))),
+                Ok(PathBuf::from("last.txt"))
+            ]
+        );
+
+        let mut count = 0;
+        for_each_file(&archive, None, |_| {
+            count += 1;
+            Ok(ControlFlow::Break(()))
+        })
+        .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_zip_output_packs_staged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("results/out.zip");
+        assert!(is_zip_output(&target));
+
+        let mut output = ZipOutput::create(&target).unwrap();
+        let staging = output.staging_dir().to_path_buf();
+        fs::create_dir_all(staging.join("nested")).unwrap();
+        fs::write(staging.join("a.txt"), b"alpha").unwrap();
+        assert_eq!(output.add(&staging.join("a.txt")).unwrap(), "a.txt");
+        assert!(!staging.join("a.txt").exists());
+        fs::write(staging.join("a.txt"), b"again").unwrap();
+        assert!(output.add(&staging.join("a.txt")).is_err());
+        // 没有单独加入的文件在完成时写入
+        fs::write(staging.join("nested/b.txt"), b"beta").unwrap();
+        assert!(!target.exists());
+        output.finish().unwrap();
+
+        assert!(!staging.exists());
+        let mut files = Vec::new();
+        for_each_file(&target, None, |entry| {
+            let entry = entry.unwrap();
+            files.push((entry.path, entry.data));
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        assert_eq!(files, vec![(PathBuf::from("a.txt"), b"alpha".to_vec()), (PathBuf::from("nested/b.txt"), b"beta".to_vec())]);
+
+        // 未完成就丢弃时不留下任何文件
+        let abandoned = dir.path().join("abandoned.zip");
+        drop(ZipOutput::create(&abandoned).unwrap());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
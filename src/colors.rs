@@ -0,0 +1,234 @@
+//! 图像的平均颜色、主色调色板和颜色数量
+//!
+//! 平均颜色和主色先缩小到最多64x64再统计，主色用k-means聚类得到，
+//! 初始中心按亮度分位数选取，结果是确定的；颜色数量和灰度检查使用全部像素
+
+use image::{imageops::FilterType, DynamicImage};
+use std::collections::HashSet;
+use std::fmt;
+
+/// 统计前缩小到的最大边长
+const SAMPLE_SIZE: u32 = 64;
+
+/// `--extract-colors` 输出的主色数量
+pub const PALETTE_SIZE: usize = 5;
+
+/// k-means迭代次数上限
+const ITERATIONS: usize = 10;
+
+/// 调色板中的一种颜色
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaletteColor {
+    pub rgb: [u8; 3],
+    /// 该颜色覆盖的像素比例 (0-1)
+    pub share: f32,
+}
+
+/// 一幅图像的颜色统计
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorSummary {
+    /// 平均颜色
+    pub average: [u8; 3],
+    /// 主色，按覆盖比例从高到低排序
+    pub palette: Vec<PaletteColor>,
+}
+
+impl fmt::Display for ColorSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "平均 {}, 主色", hex(self.average))?;
+        for (i, color) in self.palette.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{} ({:.0}%)", separator, hex(color.rgb), color.share * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// 计算平均颜色和最多 `colors` 种主色，完全透明的像素不参与统计
+///
+/// 图像完全透明时返回 `None`
+pub fn summarize(img: &DynamicImage, colors: usize) -> Option<ColorSummary> {
+    let sample = if img.width() > SAMPLE_SIZE || img.height() > SAMPLE_SIZE {
+        img.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+    } else {
+        img.clone()
+    };
+    let pixels: Vec<[f32; 3]> = sample
+        .to_rgba8()
+        .pixels()
+        .filter(|pixel| pixel[3] > 0)
+        .map(|pixel| [f32::from(pixel[0]), f32::from(pixel[1]), f32::from(pixel[2])])
+        .collect();
+    if pixels.is_empty() {
+        return None;
+    }
+
+    let average = to_rgb(mean(pixels.iter()));
+    let palette = kmeans(&pixels, colors.max(1))
+        .into_iter()
+        .map(|(center, count)| PaletteColor { rgb: to_rgb(center), share: count as f32 / pixels.len() as f32 })
+        .collect();
+    Some(ColorSummary { average, palette })
+}
+
+/// 聚类并返回各簇中心和像素数，空簇会被丢弃
+fn kmeans(pixels: &[[f32; 3]], k: usize) -> Vec<([f32; 3], usize)> {
+    let mut by_luma = pixels.to_vec();
+    by_luma.sort_by(|a, b| luma(a).total_cmp(&luma(b)));
+    let k = k.min(by_luma.len());
+    let mut centers: Vec<[f32; 3]> = (0..k).map(|i| by_luma[(2 * i + 1) * by_luma.len() / (2 * k)]).collect();
+
+    let mut assignment = vec![0; pixels.len()];
+    for _ in 0..ITERATIONS {
+        let mut changed = false;
+        for (pixel, assigned) in pixels.iter().zip(assignment.iter_mut()) {
+            let nearest = nearest(&centers, pixel);
+            changed |= nearest != *assigned;
+            *assigned = nearest;
+        }
+        for (index, center) in centers.iter_mut().enumerate() {
+            let members = pixels.iter().zip(&assignment).filter(|(_, &a)| a == index).map(|(p, _)| p);
+            if let Some(mean) = mean_of_nonempty(members) {
+                *center = mean;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let mut clusters: Vec<([f32; 3], usize)> = centers
+        .iter()
+        .enumerate()
+        .map(|(index, center)| (*center, assignment.iter().filter(|&&a| a == index).count()))
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    clusters.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    clusters
+}
+
+fn nearest(centers: &[[f32; 3]], pixel: &[f32; 3]) -> usize {
+    let distance = |center: &[f32; 3]| (0..3).map(|c| (center[c] - pixel[c]).powi(2)).sum::<f32>();
+    (0..centers.len())
+        .min_by(|&a, &b| distance(&centers[a]).total_cmp(&distance(&centers[b])))
+        .unwrap_or(0)
+}
+
+fn mean<'a>(pixels: impl Iterator<Item = &'a [f32; 3]>) -> [f32; 3] {
+    mean_of_nonempty(pixels).unwrap_or_default()
+}
+
+fn mean_of_nonempty<'a>(pixels: impl Iterator<Item = &'a [f32; 3]>) -> Option<[f32; 3]> {
+    let mut sum = [0.0f64; 3];
+    let mut count = 0usize;
+    for pixel in pixels {
+        for c in 0..3 {
+            sum[c] += f64::from(pixel[c]);
+        }
+        count += 1;
+    }
+    (count > 0).then(|| sum.map(|total| (total / count as f64) as f32))
+}
+
+/// 统计不重复的颜色数量 (按8位RGBA比较)
+pub fn count_colors(img: &DynamicImage) -> usize {
+    match img {
+        DynamicImage::ImageLuma8(buffer) => buffer.as_raw().iter().collect::<HashSet<_>>().len(),
+        DynamicImage::ImageRgb8(buffer) => buffer.as_raw().chunks_exact(3).collect::<HashSet<_>>().len(),
+        DynamicImage::ImageRgba8(buffer) => buffer.as_raw().chunks_exact(4).collect::<HashSet<_>>().len(),
+        _ => img.to_rgba8().pixels().collect::<HashSet<_>>().len(),
+    }
+}
+
+/// 图像是否实际是灰度：没有颜色通道，或者每个像素的R、G、B都相等 (不看透明度)
+///
+/// 8位RGB/RGBA直接检查解码后的缓冲区，其它颜色类型按16位精度比较
+pub fn is_effectively_grayscale(img: &DynamicImage) -> bool {
+    let gray = |pixel: &[u16]| pixel[0] == pixel[1] && pixel[1] == pixel[2];
+    match img {
+        _ if !img.color().has_color() => true,
+        DynamicImage::ImageRgb8(buffer) => buffer.as_raw().chunks_exact(3).all(|p| p[0] == p[1] && p[1] == p[2]),
+        DynamicImage::ImageRgba8(buffer) => buffer.as_raw().chunks_exact(4).all(|p| p[0] == p[1] && p[1] == p[2]),
+        DynamicImage::ImageRgb16(buffer) => buffer.as_raw().chunks_exact(3).all(gray),
+        DynamicImage::ImageRgba16(buffer) => buffer.as_raw().chunks_exact(4).all(gray),
+        _ => img.to_rgba16().as_raw().chunks_exact(4).all(gray),
+    }
+}
+
+/// 把R、G、B相等的彩色图像转为相同位深的灰度图像，保留透明通道；浮点图像转为16位
+pub fn to_grayscale(img: &DynamicImage) -> DynamicImage {
+    let alpha = img.color().has_alpha();
+    match img {
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) if alpha => img.to_luma_alpha8().into(),
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => img.to_luma8().into(),
+        _ if alpha => img.to_luma_alpha16().into(),
+        _ => img.to_luma16().into(),
+    }
+}
+
+fn luma(pixel: &[f32; 3]) -> f32 {
+    0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2]
+}
+
+fn to_rgb(color: [f32; 3]) -> [u8; 3] {
+    color.map(|channel| channel.round().clamp(0.0, 255.0) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    #[test]
+    fn test_mostly_red_image() {
+        // 80%红色，20%蓝色
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(100, 100, |x, _| {
+            if x < 80 { Rgba([220, 20, 30, 255]) } else { Rgba([10, 40, 200, 255]) }
+        }));
+
+        let summary = summarize(&img, 3).unwrap();
+        let dominant = summary.palette[0];
+        assert!(dominant.rgb[0] > 200 && dominant.rgb[1] < 50 && dominant.rgb[2] < 50, "{:?}", dominant);
+        assert!((dominant.share - 0.8).abs() < 0.05, "{:?}", dominant);
+        assert!(summary.average[0] > summary.average[2]);
+        assert!(summary.to_string().starts_with("平均 #"), "{}", summary);
+    }
+
+    #[test]
+    fn test_transparent_pixels_are_ignored() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(10, 10, |x, _| {
+            if x < 5 { Rgba([0, 255, 0, 255]) } else { Rgba([255, 0, 0, 0]) }
+        }));
+        let summary = summarize(&img, 2).unwrap();
+        assert_eq!(summary.average, [0, 255, 0]);
+        assert_eq!(summary.palette.len(), 1);
+
+        let transparent = DynamicImage::ImageRgba8(RgbaImage::new(4, 4));
+        assert!(summarize(&transparent, 2).is_none());
+    }
+
+    #[test]
+    fn test_rgb_encoded_gray_is_detected() {
+        let gray = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 16, |x, y| Rgb([(x * 16 + y) as u8; 3])));
+        assert!(is_effectively_grayscale(&gray));
+        assert_eq!(count_colors(&gray), 256);
+
+        let converted = to_grayscale(&gray);
+        assert!(matches!(converted, DynamicImage::ImageLuma8(_)));
+        // R、G、B相等时灰度值与原值相同
+        assert_eq!(converted.to_rgb8(), gray.to_rgb8());
+
+        let mut tinted = gray.to_rgb8();
+        tinted.put_pixel(5, 5, Rgb([10, 10, 11]));
+        assert!(!is_effectively_grayscale(&DynamicImage::ImageRgb8(tinted)));
+
+        let with_alpha = DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(4, 4, Rgba([300u16, 300, 300, 7])));
+        assert!(is_effectively_grayscale(&with_alpha));
+        assert_eq!(count_colors(&with_alpha), 1);
+        assert!(matches!(to_grayscale(&with_alpha), DynamicImage::ImageLumaA16(_)));
+    }
+}
@@ -0,0 +1,140 @@
+//! 比较两张图像的像素差异，用于检查转换质量
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage, RgbaImage};
+
+/// 两张图像的比较结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// 第一张图像的尺寸
+    pub first: (u32, u32),
+    /// 第二张图像的尺寸 (缩放前)
+    pub second: (u32, u32),
+    /// 每个RGBA通道的平均绝对差 (0-255)
+    pub mean_abs_diff: f64,
+    /// 峰值信噪比 (dB)，完全相同时为无穷大
+    pub psnr: f64,
+    /// 单个通道的最大差值
+    pub max_diff: u8,
+}
+
+impl Comparison {
+    /// 两张图像尺寸是否一致
+    pub fn dimensions_match(&self) -> bool {
+        self.first == self.second
+    }
+}
+
+/// 比较两张图像，按RGBA8逐通道计算差异
+///
+/// 尺寸不一致时 `resize` 为真则把第二张图像缩放到第一张的尺寸，否则返回错误；
+/// 同时返回参与比较的两张RGBA图像，供生成差异热力图
+pub fn compare(first: &DynamicImage, second: &DynamicImage, resize: bool) -> Result<(Comparison, RgbaImage, RgbaImage)> {
+    let first_size = (first.width(), first.height());
+    let second_size = (second.width(), second.height());
+    let second_rgba = if first_size == second_size {
+        second.to_rgba8()
+    } else if resize {
+        second.resize_exact(first_size.0, first_size.1, FilterType::Lanczos3).to_rgba8()
+    } else {
+        anyhow::bail!(
+            "图像尺寸不一致: {}x{} 与 {}x{} (使用 --resize 把第二张图像缩放到第一张的尺寸)",
+            first_size.0,
+            first_size.1,
+            second_size.0,
+            second_size.1
+        );
+    };
+    let first_rgba = first.to_rgba8();
+
+    let mut abs_sum = 0u64;
+    let mut square_sum = 0u64;
+    let mut max_diff = 0u8;
+    for (a, b) in first_rgba.as_raw().iter().zip(second_rgba.as_raw()) {
+        let diff = a.abs_diff(*b);
+        abs_sum += u64::from(diff);
+        square_sum += u64::from(diff) * u64::from(diff);
+        max_diff = max_diff.max(diff);
+    }
+
+    let samples = first_rgba.as_raw().len().max(1) as f64;
+    let mse = square_sum as f64 / samples;
+    let psnr = if mse == 0.0 { f64::INFINITY } else { 10.0 * (255.0 * 255.0 / mse).log10() };
+    let comparison = Comparison {
+        first: first_size,
+        second: second_size,
+        mean_abs_diff: abs_sum as f64 / samples,
+        psnr,
+        max_diff,
+    };
+    Ok((comparison, first_rgba, second_rgba))
+}
+
+/// 差异热力图：每个像素取通道最大差值，按整幅图像的最大差值归一化后从黑经红、黄映射到白
+///
+/// 两张图像完全相同时输出全黑图像
+pub fn diff_heatmap(first: &RgbaImage, second: &RgbaImage) -> RgbImage {
+    let diffs: Vec<u8> = first
+        .pixels()
+        .zip(second.pixels())
+        .map(|(a, b)| a.0.iter().zip(b.0).map(|(x, y)| x.abs_diff(y)).max().unwrap_or(0))
+        .collect();
+    let peak = diffs.iter().copied().max().unwrap_or(0).max(1);
+
+    RgbImage::from_fn(first.width(), first.height(), |x, y| {
+        let diff = diffs[(y * first.width() + x) as usize];
+        let level = u32::from(diff) * 765 / u32::from(peak);
+        let channel = |start: u32| (level.saturating_sub(start)).min(255) as u8;
+        Rgb([channel(0), channel(255), channel(510)])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| Rgb([(x * 8) as u8, (y * 8) as u8, 128])))
+    }
+
+    #[test]
+    fn test_identical_images_have_no_difference() {
+        let img = gradient(16, 16);
+        let (comparison, first, second) = compare(&img, &img, false).unwrap();
+        assert!(comparison.dimensions_match());
+        assert_eq!(comparison.mean_abs_diff, 0.0);
+        assert_eq!(comparison.max_diff, 0);
+        assert!(comparison.psnr.is_infinite());
+        assert!(diff_heatmap(&first, &second).pixels().all(|p| p.0 == [0, 0, 0]));
+    }
+
+    #[test]
+    fn test_modified_copy_reports_difference() {
+        let img = gradient(16, 16);
+        let mut modified = img.to_rgb8();
+        modified.put_pixel(3, 4, Rgb([255, 255, 255]));
+        let modified = DynamicImage::ImageRgb8(modified);
+
+        let (comparison, first, second) = compare(&img, &modified, false).unwrap();
+        let expected = f64::from((255 - 24) + (255 - 32) + (255 - 128)) / (16.0 * 16.0 * 4.0);
+        assert!((comparison.mean_abs_diff - expected).abs() < 1e-9);
+        assert_eq!(comparison.max_diff, 231);
+        assert!(comparison.psnr.is_finite() && comparison.psnr > 20.0);
+
+        let heatmap = diff_heatmap(&first, &second);
+        assert_eq!(heatmap.get_pixel(3, 4).0, [255, 255, 255]);
+        assert_eq!(heatmap.get_pixel(0, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_mismatched_sizes_error_or_resize() {
+        let small = gradient(8, 8);
+        let large = gradient(16, 16);
+        assert!(compare(&small, &large, false).is_err());
+
+        let (comparison, _, second) = compare(&small, &large, true).unwrap();
+        assert!(!comparison.dimensions_match());
+        assert_eq!(second.dimensions(), (8, 8));
+    }
+}
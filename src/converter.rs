@@ -1,14 +1,386 @@
 use anyhow::{Context, Result};
-use image::{codecs::jpeg::JpegEncoder, codecs::png::PngEncoder, ColorType, ImageFormat};
-use std::fs::{self, File};
-use std::path::Path;
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use image::{codecs::jpeg::JpegEncoder, codecs::png::PngEncoder, ColorType, GenericImageView, ImageDecoder, ImageFormat};
+use image::ExtendedColorType;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+use sha2::{Digest, Sha256};
 use walkdir::WalkDir;
 
+use crate::animation;
+use crate::archive;
+use crate::colors;
+use crate::deskew;
+use crate::error::PixForgeError;
+use crate::fallback::{DecodedPng, FallbackDecoder};
+use crate::hooks::HookCommand;
+use crate::ico::{self, IcoPick};
+use crate::jpeg_quality;
+use crate::jpegtran;
+use crate::jxl;
+use crate::palette::Palette;
+use crate::pdf;
+use crate::profile::{Profiler, Stage};
+use crate::quality::QualityProfile;
+use crate::recipe::{Recipe, RecipeEntry};
+use crate::resume::ResumeState;
+use crate::sidecar;
+use crate::summary::{ProgressEvent, ProgressStatus, SkipBreakdown, SkipReason, SummaryTemplate, SummaryValues};
+use crate::tiff_stream;
+use crate::transform::{self, Backdrop, Channel, CropRect, MissingAlpha, OrientationTarget, Reorient, Resize};
 use crate::utils;
 
+/// 输出给用户看的提示信息；开启 `progress_json` 时写到标准错误，标准输出只有进度行
+macro_rules! notice {
+    ($options:expr, $($arg:tt)*) => {
+        if $options.progress_json {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// 未指定质量时使用的默认质量
+pub const DEFAULT_QUALITY: f32 = 80.0;
+
+/// `--adaptive-quality` 按输出像素数限制的JPEG和WebP质量上限，按像素数从小到大排列
+///
+/// 不超过64x64最高60，不超过128x128最高70，不超过256x256最高80，不超过512x512最高88，更大的图像不限制。
+/// 缩略图上高质量的差别看不出来，只会增大文件
+pub const SMALL_OUTPUT_QUALITY_CAPS: &[(u64, f32)] =
+    &[(64 * 64, 60.0), (128 * 128, 70.0), (256 * 256, 80.0), (512 * 512, 88.0)];
+
 /// 图像转换器，提供各种格式间的转换功能
-pub struct ImageConverter;
+///
+/// 实现了 `Send` 和 `Sync`，可以移到工作线程中使用，或者在线程间共享
+pub struct ImageConverter {
+    options: ConvertOptions,
+    /// 按处理方案派生的转换器与原转换器共用计时
+    profiler: Arc<Profiler>,
+    /// `convert_file` 和 `convert_dir` 使用的质量
+    quality: f32,
+    /// 估算输出大小时记录的编码结果字节数
+    estimated_size: Mutex<Option<u64>>,
+    /// 单个文件转换后没有写出输出的原因
+    skip_reason: Mutex<Option<SkipReason>>,
+    /// `palette_from` 生成的调色板，第一次输出GIF时生成，派生的转换器共用
+    palette: Arc<OnceLock<Palette>>,
+    /// `flatten` 指定的背景图像，第一次合成时解码，派生的转换器共用
+    backdrop: Arc<OnceLock<image::DynamicImage>>,
+    /// `progress_json` 的进度行写到这里，未设置时写到标准输出
+    progress_writer: Mutex<Option<Box<dyn Write + Send>>>,
+}
+
+/// 转换选项，控制格式参数之外的转换行为
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// ICO输入的帧选择策略
+    pub ico_pick: IcoPick,
+    /// ICO输出包含的帧尺寸，为空时只输出一帧 (超过256时缩小到256)
+    pub ico_sizes: Vec<u32>,
+    /// 多尺寸ICO中不超过32像素的帧做轻微锐化
+    pub ico_sharpen: bool,
+    /// 从该参考图像生成调色板，所有GIF输出共用，未指定时每张GIF单独量化
+    pub palette_from: Option<PathBuf>,
+    /// 无损目标格式写出后重新解码，校验像素与源图像一致
+    pub verify_lossless: bool,
+    /// 跳过写出后的输出检查 (默认检查输出能识别为目标格式并读出文件头)
+    pub no_verify_output: bool,
+    /// 根据图像内容类型在用户质量基础上调整WebP质量，并按 [`SMALL_OUTPUT_QUALITY_CAPS`] 限制小尺寸JPEG和WebP输出的质量
+    pub adaptive_quality: bool,
+    /// 根据图像内容类型选择WebP编码方式：图形用无损，照片用有损
+    pub auto_webp_mode: bool,
+    /// 内容类型分析的固定采样点数，未指定时按图像尺寸采样
+    pub analysis_samples: Option<usize>,
+    /// 每个文件转换成功后执行的命令
+    pub on_success: Option<HookCommand>,
+    /// 每个文件转换失败后执行的命令
+    pub on_failure: Option<HookCommand>,
+    /// 无法识别的输入 (如相机RAW、SVG) 先用该命令转换为PNG再处理
+    pub fallback_decoder: Option<FallbackDecoder>,
+    /// 单个文件的解码时限，超时的文件按解码失败跳过
+    pub decode_timeout: Option<Duration>,
+    /// 批量转换最多处理的文件数
+    pub limit: Option<usize>,
+    /// 文件数上限的计数方式
+    pub limit_mode: LimitMode,
+    /// 批量转换写出的输出总字节数上限，达到或写出下一个文件会超出时停止
+    pub max_total_bytes: Option<u64>,
+    /// 批量转换成功的文件少于该数量时返回错误，预演时按将要转换的文件计数
+    pub expect_min: Option<u32>,
+    /// 批量转换时将输出放入以目标格式命名的子目录
+    pub split_by_format: bool,
+    /// 批量转换时跳过这些名称的子目录，不进入目录遍历 (按目录名匹配，不是完整路径)
+    pub exclude_dirs: Vec<String>,
+    /// 按文件内容检测到这些格式时报错而不转换 (如 `bmp`，别名按同一格式处理)
+    pub reject_formats: Vec<String>,
+    /// 只转换按文件内容检测到这些格式的输入，为空时不限制；无法识别格式的文件同样不接受
+    pub allow_formats: Vec<String>,
+    /// 批量转换时不在 `allow_formats` 中的输入计为跳过而不是失败
+    pub skip_disallowed: bool,
+    /// 批量转换时第一个文件失败或被拒绝就停止
+    pub fail_fast: bool,
+    /// 批量转换前先解码全部输入，有任何失败就中止整个批次
+    pub validate_first: bool,
+    /// 批量转换时边遍历目录边转换，不预先列出全部条目，内存占用与目录树大小无关；
+    /// 原地转换时不生效 (需要先列出条目，避免处理刚写出的输出)
+    pub stream: bool,
+    /// 批量转换结束时用该模板输出统计摘要，代替默认的摘要
+    pub summary_template: Option<SummaryTemplate>,
+    /// 批量转换结束时把统计 (含未转换原因的分类) 输出为一行JSON，代替默认的摘要
+    pub summary_json: bool,
+    /// 批量转换时每处理完一个文件输出一行JSON进度 (index、total、path、status、bytes_out)
+    pub progress_json: bool,
+    /// 批量转换时保留的目录层数，更深的文件归入第N层的目录，重名时加序号；
+    /// 未指定时所有输出都放在同一目录
+    pub collapse_depth: Option<usize>,
+    /// 输出中保留的源目录名 (`collapse_depth` 和压缩包条目) 的大小写处理方式
+    pub subpath_case: SubpathCase,
+    /// 输出文件按编码结果的SHA-256命名 (`<64位十六进制>.<扩展名>`)，同名文件已存在时内容相同，跳过写出
+    pub name_by_hash: bool,
+    /// 批量转换记录已完成输入的状态文件
+    pub state_file: Option<PathBuf>,
+    /// 读取状态文件，跳过上次已完成且未修改的输入
+    pub resume: bool,
+    /// 按EXIF方向标签自动旋转图像
+    pub auto_orient: bool,
+    /// 在自动方向校正之后额外执行的旋转
+    pub rotate: Reorient,
+    /// 在EXIF方向校正之后、旋转之前裁剪的区域
+    pub crop: Option<CropRect>,
+    /// 按文件指定裁剪、旋转和缩放的处理方案，列出的文件用方案中的设置替换对应选项
+    pub recipe: Option<Arc<Recipe>>,
+    /// 在EXIF校正和旋转之后估计并校正扫描文档的倾斜 (±15°以内)
+    pub deskew: bool,
+    /// 在EXIF校正和旋转之后，把方向不符的图像顺时针旋转90°
+    pub normalize_orientation: Option<OrientationTarget>,
+    /// 缩放前居中裁剪到的宽高比
+    pub aspect: Option<(u32, u32)>,
+    /// 编码前将图像缩放到的目标尺寸
+    pub resize: Option<Resize>,
+    /// 源图像已小于缩放目标时保持原尺寸，不放大
+    pub no_upscale: bool,
+    /// 只在图像超出该尺寸时缩小到以内，较小的图像保持不变
+    pub resize_if_larger: Option<(u32, u32)>,
+    /// 缩放前用该颜色填充为居中的正方形
+    pub pad_square: Option<image::Rgba<u8>>,
+    /// 只输出指定通道的灰度图像
+    pub extract: Option<Channel>,
+    /// 提取alpha但源图像没有透明通道时的处理方式
+    pub missing_alpha: MissingAlpha,
+    /// 高位深图像降到8位时做色调映射，保留高光细节
+    pub tone_map: bool,
+    /// 交换红蓝通道，修正被当作RGB的BGR数据
+    pub swap_rb: bool,
+    /// 编码前把alpha低于该值的像素设为完全透明、其余设为完全不透明 (8位刻度)
+    pub alpha_threshold: Option<u8>,
+    /// 带透明通道的图像在编码前合成到该背景上
+    pub flatten: Option<Backdrop>,
+    /// GIF输出保留动画并按该帧率重采样，未指定时只转换第一帧
+    pub fps: Option<f32>,
+    /// 输出每个图像的平均颜色和主色，使用转换时已解码的像素
+    pub extract_colors: bool,
+    /// 输出每个图像不重复的颜色数量以及是否实际是灰度
+    pub color_count: bool,
+    /// R、G、B处处相等的彩色图像自动转为灰度后再编码
+    pub auto_grayscale: bool,
+    /// 输出更详细的处理信息
+    pub verbose: bool,
+    /// PDF输入的渲染分辨率，未指定时使用 [`pdf::DEFAULT_DPI`]
+    pub pdf_dpi: Option<f32>,
+    /// PDF输入转换全部页面，输出文件名加 `_p1`、`_p2` 等页码；否则只转换第一页
+    pub pdf_all_pages: bool,
+    /// JPEG XL无损编码 (当前的JPEG XL编码器只支持无损)
+    pub jxl_lossless: bool,
+    /// JPEG色度抽样方式，未指定时使用image内置编码器
+    pub jpeg_subsampling: Option<ChromaSubsampling>,
+    /// 输出比源文件大时的处理方式，未指定时总是写出
+    pub skip_larger: Option<LargerAction>,
+    /// WebP输出总是丢弃透明通道
+    pub webp_no_alpha: bool,
+    /// WebP输出使用近无损编码，值为libwebp的预处理级别 (0-100，越小预处理越多、文件越小，100与无损相同)；
+    /// 指定时所有WebP输出都按无损编码，不使用质量
+    pub webp_near_lossless: Option<u8>,
+    /// 按阶段统计耗时并在结束时输出
+    pub profile: bool,
+    /// 逐个文件输出格式检测、内容分析和编码参数的决策过程
+    pub trace: bool,
+    /// 决策跟踪写入的文件，未指定时写到标准错误
+    pub trace_file: Option<PathBuf>,
+    /// 批量转换前删除输出目录 (原地转换时为输入目录) 中残留的临时文件
+    pub clean_temp: bool,
+    /// 转换zip压缩包时把不是图像的条目原样写到输出目录
+    pub copy_unconverted: bool,
+    /// 跳过扩展名检查，只按文件内容识别图像
+    pub any_extension: bool,
+    /// 只按扩展名识别图像，不读取文件头；扩展名不符的文件在解码时才失败
+    pub trust_extensions: bool,
+    /// 跳过超过该字节数的输入文件，只读取文件元数据
+    pub max_file_size: Option<u64>,
+    /// 把输入当作该格式解码，不按内容识别 (规范名称，见 `INPUT_FORMATS`)；用于TGA等没有魔数或文件头损坏的数据
+    pub input_format: Option<String>,
+    /// 输出文件扩展名，未指定时按目标格式选择 (JPEG为 `.jpg`)
+    pub extension: Option<String>,
+    /// JPEG/WebP输出的字节预算，超出时降低质量重新编码
+    pub max_bytes: Option<u64>,
+    /// 按字节预算降低质量时的下限，优先于预算
+    pub min_quality: Option<f32>,
+    /// 编码后再做一遍无损优化：PNG使用oxipng (需要 `optimize` 特性)，JPEG重新计算霍夫曼表
+    pub optimize: bool,
+    /// JPEG输出每隔多少行MCU插入一个重启标记，未指定时不插入
+    pub jpeg_restart: Option<u16>,
+    /// JPEG源改用从量化表估计的源质量代替传入的质量，命令行未指定 `-q` 时开启
+    pub source_quality: bool,
+    /// 只列出将要执行的转换，不写任何文件、不执行钩子
+    pub dry_run: bool,
+    /// 预演时把每个文件编码到内存中，报告准确的输出大小
+    pub estimate_sizes: bool,
+    /// 原地转换：输出写到源文件旁边，成功后删除源文件
+    pub in_place: bool,
+    /// 原地转换时把源文件改名为 `.bak` 而不是删除
+    pub backup: bool,
+    /// 原地转换时格式和扩展名都不变也照样转换
+    pub force: bool,
+}
+
+/// `--limit` 的计数方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LimitMode {
+    /// 只统计转换成功的文件
+    #[default]
+    Converted,
+    /// 统计所有尝试转换的文件，包括失败的
+    Attempted,
+}
+
+impl FromStr for LimitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "converted" => Ok(LimitMode::Converted),
+            "attempted" => Ok(LimitMode::Attempted),
+            _ => Err(format!("无效的计数方式: {} (可选 converted, attempted)", s)),
+        }
+    }
+}
+
+/// 输出中镜像的源目录名的大小写
+///
+/// 源文件系统不区分大小写时，同一个目录可能以不同的大小写出现；目标区分大小写时统一为小写，
+/// 输出路径才是确定的。只影响目录名，文件名保持原样
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SubpathCase {
+    /// 原样复制源目录名
+    #[default]
+    Preserve,
+    /// 目录名转为小写
+    Lower,
+}
+
+impl FromStr for SubpathCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "preserve" => Ok(SubpathCase::Preserve),
+            "lower" => Ok(SubpathCase::Lower),
+            _ => Err(format!("无效的目录名大小写方式: {} (可选 preserve, lower)", s)),
+        }
+    }
+}
+
+/// `--skip-larger` 在输出比源文件大时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LargerAction {
+    /// 改为复制源文件；格式不同时保留源文件的扩展名
+    #[default]
+    Keep,
+    /// 不写出任何文件
+    Skip,
+}
+
+impl FromStr for LargerAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keep" => Ok(LargerAction::Keep),
+            "skip" => Ok(LargerAction::Skip),
+            _ => Err(format!("无效的处理方式: {} (可选 keep, skip)", s)),
+        }
+    }
+}
+
+/// JPEG色度抽样方式
+///
+/// 4:4:4 保留全部色度信息，彩色文字和细线边缘清晰但文件更大；
+/// 4:2:0 色度分辨率减半，照片几乎看不出差别且文件更小
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    Yuv444,
+    Yuv422,
+    Yuv420,
+}
 
+impl FromStr for ChromaSubsampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.replace(':', "").as_str() {
+            "444" => Ok(ChromaSubsampling::Yuv444),
+            "422" => Ok(ChromaSubsampling::Yuv422),
+            "420" => Ok(ChromaSubsampling::Yuv420),
+            _ => Err(format!("无效的色度抽样: {} (可选 444, 422, 420)", s)),
+        }
+    }
+}
+
+impl ChromaSubsampling {
+    /// 每个色度采样覆盖的亮度像素数 (水平, 垂直)
+    fn chroma_pixel_size(self) -> (u8, u8) {
+        match self {
+            ChromaSubsampling::Yuv444 => (1, 1),
+            ChromaSubsampling::Yuv422 => (2, 1),
+            ChromaSubsampling::Yuv420 => (2, 2),
+        }
+    }
+}
+
+/// libjpeg能编码的最大边长 (JPEG_MAX_DIMENSION)
+const JPEG_MAX_DIMENSION: u32 = 65500;
+
+/// 质量参数会影响文件大小、可以按字节预算搜索质量的格式
+const LOSSY_FORMATS: &[&str] = &["jpeg", "jpg", "webp"];
+
+/// `input_format` 可以指定的输入格式 (规范名称)
+pub const INPUT_FORMATS: &[&str] =
+    &["png", "jpeg", "gif", "webp", "ico", "tiff", "tga", "dds", "farbfeld", "pnm", "ppm", "pgm", "pbm", "jxl", "pdf"];
+
+/// 指定的输入格式对应的image解码器格式
+fn input_image_format(format: &str) -> Result<ImageFormat> {
+    // PNM的各种变体由同一个解码器按文件头区分
+    let extension = match format {
+        "farbfeld" => "ff",
+        "pnm" => "ppm",
+        other => other,
+    };
+    ImageFormat::from_extension(extension).with_context(|| format!("不支持的输入格式: {}", format))
+}
+
+/// 支持输出的目标格式
+pub const TARGET_FORMATS: &[&str] = &["png", "jpeg", "jpg", "gif", "webp", "ico", "farbfeld", "ff", "tga", "pnm", "ppm", "pgm", "pbm", "jxl"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ImageType {
     SimpleGraphics, // 简单图形
     HorizontalGraphics, // 水平渐变
@@ -21,36 +393,194 @@ enum ImageType {
 impl ImageConverter {
     /// 创建新的图像转换器实例
     pub fn new() -> Self {
-        Self
+        Self::with_options(ConvertOptions::default())
+    }
+
+    /// 使用指定选项创建图像转换器
+    pub fn with_options(options: ConvertOptions) -> Self {
+        let profiler = Arc::new(Profiler::new(options.profile));
+        let (palette, backdrop) = (Arc::new(OnceLock::new()), Arc::new(OnceLock::new()));
+        Self {
+            options,
+            profiler,
+            quality: DEFAULT_QUALITY,
+            estimated_size: Mutex::new(None),
+            skip_reason: Mutex::new(None),
+            palette,
+            backdrop,
+            progress_writer: Mutex::new(None),
+        }
+    }
+
+    /// 创建构建器，一次设置好质量和转换选项
+    pub fn builder() -> ImageConverterBuilder {
+        ImageConverterBuilder::new()
+    }
+
+    /// 当前的转换选项
+    pub fn options(&self) -> &ConvertOptions {
+        &self.options
+    }
+
+    /// 把 `progress_json` 的进度行写到指定位置 (例如Unix套接字)，而不是标准输出
+    pub fn set_progress_writer(&mut self, writer: Box<dyn Write + Send>) {
+        self.progress_writer = Mutex::new(Some(writer));
+    }
+
+    /// 按转换时的解码路径读取图像 (ICO、PDF、JPEG XL、TGA等)，开启自动方向校正时按EXIF旋转，不执行其它变换
+    ///
+    /// 失败时返回 [`PixForgeError::DecodeFailed`]，只解码没有目标格式，错误中的目标格式为 `无`
+    pub fn decode(&self, input: &Path) -> Result<image::DynamicImage, PixForgeError> {
+        let (img, orientation) = self.open_image(input).map_err(|source| PixForgeError::DecodeFailed {
+            path: input.to_path_buf(),
+            detected: self.source_format(input).unwrap_or_else(|| "未知".to_string()),
+            target: "无".to_string(),
+            source: source.into(),
+        })?;
+        Ok(orientation.apply(img))
+    }
+
+    /// 构建时设置的质量
+    pub fn quality(&self) -> f32 {
+        self.quality
+    }
+
+    /// 使用构建时设置的质量转换单个文件
+    pub fn convert_file(&self, input: &Path, output: &Path, target_format: &str) -> Result<(), PixForgeError> {
+        self.convert_single_file(input, output, target_format, self.quality)
+    }
+
+    /// 使用构建时设置的质量批量转换目录
+    pub fn convert_dir(&self, input_dir: &Path, output_dir: &Path, target_format: &str) -> Result<(), PixForgeError> {
+        self.convert_directory(input_dir, output_dir, target_format, self.quality)
     }
     
+    /// 编码调用方已经解码好的图像并写出到 `output`，不读取任何源文件
+    ///
+    /// 跳过打开文件和格式检测，照常执行转换选项中的几何变换和像素处理，再按目标格式编码、写出并检查输出。
+    /// `output` 必须是文件路径，不存在的父目录会自动创建；消息中用输出路径标识这张图像
+    pub fn encode_image(
+        &self,
+        img: &image::DynamicImage,
+        output: &Path,
+        target_format: &str,
+        quality: f32,
+    ) -> Result<(), PixForgeError> {
+        let format = target_format.to_lowercase();
+        if !TARGET_FORMATS.contains(&format.as_str()) {
+            return Err(PixForgeError::UnsupportedTargetFormat(target_format.to_string()));
+        }
+        let img = self.profiler.time(Stage::Transform, || self.apply_transforms(img.clone(), Reorient::IDENTITY));
+        self.ensure_output_directory(output)
+            .and_then(|()| self.encode_decoded(output, img, output, &format, quality))
+            .and_then(|()| self.verify_output(output, &format))
+            .map_err(PixForgeError::from_anyhow)
+    }
+
     /// 转换单个文件
     /// 
     /// # 参数
     /// * `input` - 输入文件路径
     /// * `output` - 输出路径（可以是文件或目录）
     /// * `target_format` - 目标格式
-    /// * `quality` - 质量参数 (0-100)
+    /// * `quality` - 质量参数 (0-100)，WebP使用小数精度，其它编码器四舍五入
     pub fn convert_single_file(
         &self, 
         input: &Path, 
         output: &Path, 
         target_format: &str, 
-        quality: u8
-    ) -> Result<()> {
-        if !utils::is_image_file(input) {
-            anyhow::bail!("不支持的图像格式: {}", input.display());
+        quality: f32
+    ) -> Result<(), PixForgeError> {
+        self.profiler.reset();
+        let sidecar = self
+            .check_single_input(input)
+            .and_then(|()| sidecar::load(input).map_err(PixForgeError::Other));
+        let sidecar = match sidecar {
+            Ok(sidecar) => sidecar.unwrap_or_default(),
+            Err(e) => {
+                if !self.options.dry_run {
+                    let output_file = self.single_output(input, output, target_format);
+                    self.run_hook(self.options.on_failure.as_ref(), input, &output_file);
+                }
+                return Err(e);
+            }
+        };
+        let target_format = sidecar.format.as_deref().unwrap_or(target_format);
+        let quality = sidecar.quality.unwrap_or_else(|| self.effective_quality(input, quality));
+        let recipe_entry = self.options.recipe.as_ref().and_then(|recipe| {
+            let name = input.file_name().map(Path::new)?;
+            recipe.lookup(name).map(|(_, entry)| entry)
+        });
+        let with_recipe;
+        let converter = match recipe_entry {
+            Some(entry) => {
+                with_recipe = self.with_recipe(entry);
+                &with_recipe
+            }
+            None => self,
+        };
+        converter
+            .convert_and_report(input, output, target_format, quality)
+            .map_err(PixForgeError::from_anyhow)
+    }
+
+    /// 单个文件在读取内容之前的检查：大小上限、是否可以转换以及拒绝或不允许的格式
+    fn check_single_input(&self, input: &Path) -> Result<(), PixForgeError> {
+        if let Some(limit) = self.options.max_file_size {
+            let size = fs::metadata(input).map(|metadata| metadata.len()).unwrap_or_default();
+            if size > limit {
+                return Err(PixForgeError::TooLarge { path: input.to_path_buf(), size, limit });
+            }
         }
-        
-        let output_file = self.determine_output_path(input, output, target_format);
-        
-        // 确保输出目录存在
-        self.ensure_output_directory(&output_file)?;
-        
-        self.convert_image(input, &output_file, target_format, quality)
-            .with_context(|| format!("转换失败: {}", input.display()))?;
-        
-        println!("✅ 转换完成: {} -> {}", input.display(), output_file.display());
+        if !self.is_image_input(input) && !self.needs_fallback(input) {
+            return Err(PixForgeError::UnsupportedSourceFormat { path: input.to_path_buf() });
+        }
+        self.check_rejected(input)
+    }
+
+    /// 单文件转换的输出文件路径
+    fn single_output(&self, input: &Path, output: &Path, target_format: &str) -> PathBuf {
+        if self.options.in_place {
+            self.in_place_output(input, target_format)
+        } else {
+            self.determine_output_path(input, output, target_format)
+        }
+    }
+
+    /// 单文件转换的主体，完成后输出结果并执行钩子；任何一步失败都执行失败钩子
+    fn convert_and_report(&self, input: &Path, output: &Path, target_format: &str, quality: f32) -> Result<()> {
+        let output_file = self.single_output(input, output, target_format);
+        if self.options.dry_run {
+            return self
+                .dry_run_file(input, &output_file, target_format, quality)
+                .map(|_| ())
+                .map_err(|e| Self::classify_failure(e, input, target_format).into());
+        }
+
+        let written = self.prepare_in_place(input, &output_file, target_format).and_then(|prepared| {
+            if !prepared {
+                return Ok(None);
+            }
+            // 确保输出目录存在
+            self.ensure_output_directory(&output_file)?;
+            let written = self.convert_image(input, &output_file, target_format, quality)?;
+            if let Some(written) = &written {
+                self.replace_original(input, written)?;
+            }
+            Ok(written)
+        });
+        match written {
+            Ok(Some(written)) => {
+                notice!(self.options, "✅ 转换完成: {} -> {}", input.display(), written.display());
+                self.run_hook(self.options.on_success.as_ref(), input, &written);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                self.run_hook(self.options.on_failure.as_ref(), input, &output_file);
+                return Err(Self::classify_failure(e, input, target_format).into());
+            }
+        }
+        self.print_profile();
         Ok(())
     }
     
@@ -58,348 +588,5263 @@ impl ImageConverter {
     /// 
     /// # 参数
     /// * `input_dir` - 输入目录
-    /// * `output_dir` - 输出目录
+    /// * `output_dir` - 输出目录，扩展名为 `.zip` 时把结果写入该zip压缩包
     /// * `target_format` - 目标格式
-    /// * `quality` - 质量参数 (0-100)
+    /// * `quality` - 质量参数 (0-100)，WebP使用小数精度，其它编码器四舍五入
     pub fn convert_directory(
         &self, 
         input_dir: &Path, 
         output_dir: &Path, 
         target_format: &str, 
-        quality: u8
-    ) -> Result<()> {
-        let mut stats = ConversionStats::new();
-        
-        // 确保输出目录存在
-        fs::create_dir_all(output_dir)?;
+        quality: f32
+    ) -> Result<(), PixForgeError> {
+        let stats = self
+            .with_zip_output(output_dir, |output_dir, zip| {
+                self.convert_entries(input_dir, output_dir, target_format, quality, zip, None)
+            })
+            .map_err(PixForgeError::from_anyhow)?;
+        self.check_expected(&stats)
+    }
+
+    /// 可以中途取消的 [`convert_directory`](Self::convert_directory)，返回已完成部分的统计
+    ///
+    /// 每处理下一个文件前检查 `cancel`，其它线程 (例如界面的取消按钮) 把它设为 `true` 后，
+    /// 转换完当前文件就停止。被取消时不检查 `expect_min`
+    pub fn convert_directory_cancellable(
+        &self,
+        input_dir: &Path,
+        output_dir: &Path,
+        target_format: &str,
+        quality: f32,
+        cancel: &AtomicBool,
+    ) -> Result<SummaryValues, PixForgeError> {
+        let stats = self
+            .with_zip_output(output_dir, |output_dir, zip| {
+                self.convert_entries(input_dir, output_dir, target_format, quality, zip, Some(cancel))
+            })
+            .map_err(PixForgeError::from_anyhow)?;
+        if !cancel.load(Ordering::Relaxed) {
+            self.check_expected(&stats)?;
+        }
+        Ok(stats.summary_values())
+    }
+
+    /// 检查 `expect_min`：转换成功的文件数不能少于要求的数量
+    fn check_expected(&self, stats: &ConversionStats) -> Result<(), PixForgeError> {
+        match self.options.expect_min {
+            Some(expected) if stats.converted < expected => {
+                Err(PixForgeError::TooFewConverted { converted: stats.converted, expected })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 输出路径是zip压缩包时在暂存目录中执行批量转换，每个输出文件转换完成后立即移入压缩包
+    ///
+    /// 预演时不创建压缩包，列出的输出路径以压缩包路径为前缀
+    fn with_zip_output(
+        &self,
+        output_dir: &Path,
+        run: impl FnOnce(&Path, Option<&mut archive::ZipOutput>) -> Result<ConversionStats>,
+    ) -> Result<ConversionStats> {
+        if !archive::is_zip_output(output_dir) || self.options.dry_run {
+            return run(output_dir, None);
+        }
+        if self.options.in_place {
+            anyhow::bail!("原地转换不能输出到zip压缩包: {}", output_dir.display());
+        }
+
+        let mut zip = archive::ZipOutput::create(output_dir)?;
+        let staging = zip.staging_dir().to_path_buf();
+        let stats = run(&staging, Some(&mut zip))?;
+        zip.finish()?;
+        notice!(self.options, "🗜️  已写入压缩包: {}", output_dir.display());
+        Ok(stats)
+    }
+
+    /// 把写出的文件移入压缩包，返回用于显示的 `压缩包/条目` 路径；没有压缩包时原样返回
+    fn pack_output(zip: Option<&mut archive::ZipOutput>, written: &Path) -> Result<PathBuf> {
+        match zip {
+            Some(zip) => {
+                let name = zip.add(written)?;
+                Ok(zip.path().join(name))
+            }
+            None => Ok(written.to_path_buf()),
+        }
+    }
+
+    /// 批量转换zip压缩包中的图片
+    ///
+    /// 逐个条目解压到内存中解码和转换，输出保留压缩包内的目录结构 (`output_dir` 同样可以是zip压缩包)；
+    /// 不是图像的条目跳过，
+    /// 开启 `copy_unconverted` 时原样写到输出目录。压缩包内的文件没有附属设置文件，
+    /// 也不支持原地转换、断点续传、钩子命令以及PDF、JPEG XL、TGA等需要按路径解码的格式
+    pub fn convert_archive(
+        &self,
+        archive: &Path,
+        output_dir: &Path,
+        target_format: &str,
+        quality: f32,
+    ) -> Result<(), PixForgeError> {
+        let stats = self
+            .with_zip_output(output_dir, |output_dir, zip| {
+                self.convert_archive_entries(archive, output_dir, target_format, quality, zip)
+            })
+            .map_err(PixForgeError::from_anyhow)?;
+        self.check_expected(&stats)
+    }
+
+    fn convert_archive_entries(
+        &self,
+        archive: &Path,
+        output_dir: &Path,
+        target_format: &str,
+        quality: f32,
+        mut zip: Option<&mut archive::ZipOutput>,
+    ) -> Result<ConversionStats> {
+        let format = target_format.to_lowercase();
+        if !TARGET_FORMATS.contains(&format.as_str()) {
+            return Err(PixForgeError::UnsupportedTargetFormat(target_format.to_string()).into());
+        }
+        self.profiler.reset();
+        let mut stats = ConversionStats::new(self.options.dry_run);
+        let base = self.format_output_dir(output_dir, &format);
+
+        notice!(self.options, "🔄 开始转换压缩包: {}", archive.display());
+        let mut used_outputs = HashSet::new();
+        // 压缩包事先不统计条目数，进度行的 `total` 为空
+        let mut progress = self.options.progress_json.then(BatchProgress::default);
+        archive::for_each_file(archive, self.options.max_file_size, |entry| {
+            self.finish_progress(progress.as_mut(), &stats);
+            if self.limit_reached(&stats) {
+                notice!(self.options, "⏹️  已达到数量上限 {}，停止转换", self.options.limit.unwrap_or_default());
+                return Ok(ControlFlow::Break(()));
+            }
+            if self.options.max_total_bytes.is_some_and(|cap| stats.written_bytes >= cap) {
+                self.report_archive_bytes_cap(&stats);
+                return Ok(ControlFlow::Break(()));
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(archive::SkippedEntry::Unsafe(name)) => {
+                    notice!(self.options, "⚠️  条目路径不安全，跳过: {}", name);
+                    stats.increment_skipped(SkipReason::Filtered);
+                    return Ok(ControlFlow::Continue(()));
+                }
+                Err(archive::SkippedEntry::TooLarge(path)) => {
+                    let input = archive.join(path);
+                    if let Some(progress) = &mut progress {
+                        progress.start(&input, &stats);
+                    }
+                    notice!(self.options, "⏭️  文件过大，跳过: {}", input.display());
+                    stats.increment_filtered(SkipReason::TooLarge);
+                    return Ok(ControlFlow::Continue(()));
+                }
+            };
+            // 消息中的输入路径写成 压缩包/条目
+            let input = archive.join(&entry.path);
+
+            let Ok(detected) = image::guess_format(&entry.data) else {
+                if self.options.copy_unconverted {
+                    let parent = self.mirrored_dir(entry.path.parent().unwrap_or(Path::new("")));
+                    let mut copied = base.join(parent).join(entry.path.file_name().unwrap_or_default());
+                    if !self.options.dry_run {
+                        self.ensure_output_directory(&copied)?;
+                        self.write_output(&copied, &entry.data)?;
+                        copied = Self::pack_output(zip.as_deref_mut(), &copied)?;
+                    }
+                    notice!(self.options, "📄 复制: {} -> {}", input.display(), copied.display());
+                } else {
+                    stats.increment_filtered(SkipReason::NotAnImage);
+                }
+                return Ok(ControlFlow::Continue(()));
+            };
+            if let Some(progress) = &mut progress {
+                progress.start(&input, &stats);
+            }
+            let detected = detected.extensions_str().first().map(|extension| utils::canonical_format(extension));
+            if let Err(e) = self.check_format(&input, detected.as_deref()) {
+                return self.record_rejected(&mut stats, e, None).map(ControlFlow::Continue);
+            }
+
+            // a.png和a.jpg换成同一个扩展名后加上序号区分，不互相覆盖
+            let file_name = utils::change_extension(&entry.path, &self.output_extension(&format));
+            let parent = self.mirrored_dir(entry.path.parent().unwrap_or(Path::new("")));
+            let output_file = Self::unique_output(base.join(parent).join(file_name), &mut used_outputs);
+            if self.options.dry_run {
+                notice!(self.options, "🔍 将转换: {} -> {}", input.display(), output_file.display());
+                stats.increment_planned(&format, None);
+                return Ok(ControlFlow::Continue(()));
+            }
+            self.ensure_output_directory(&output_file)?;
+
+            let converted = self
+                .decode_memory(&entry.data)
+                .with_context(|| format!("无法打开图像: {}", input.display()))
+                .and_then(|(img, orientation)| {
+                    let img = self.profiler.time(Stage::Transform, || self.apply_transforms(img, orientation));
+                    self.encode_decoded(&input, img, &output_file, &format, quality)
+                })
+                .and_then(|()| self.verify_output(&output_file, &format))
+                .map(|()| fs::metadata(&output_file).map(|metadata| metadata.len()).unwrap_or_default());
+            let size = match converted {
+                Ok(size) if self.options.max_total_bytes.is_some_and(|cap| stats.written_bytes + size > cap) => {
+                    fs::remove_file(&output_file)
+                        .map_err(|source| PixForgeError::Io { path: output_file.clone(), source })?;
+                    notice!(self.options, "⏹️  写出 {} 会超出输出总大小上限，已删除", output_file.display());
+                    self.report_archive_bytes_cap(&stats);
+                    return Ok(ControlFlow::Break(()));
+                }
+                Ok(size) => size,
+                Err(e) => {
+                    notice!(self.options, "⚠️  跳过: {:#}", e);
+                    stats.increment_failed();
+                    self.stop_on_failure(e)?;
+                    return Ok(ControlFlow::Continue(()));
+                }
+            };
+            match Self::pack_output(zip.as_deref_mut(), &output_file) {
+                Ok(written) => {
+                    stats.read_bytes += entry.data.len() as u64;
+                    stats.written_bytes += size;
+                    notice!(self.options, "✅ 转换: {} -> {}", input.display(), written.display());
+                    stats.increment_converted(&format);
+                }
+                Err(e) => {
+                    notice!(self.options, "⚠️  跳过: {:#}", e);
+                    stats.increment_failed();
+                    self.stop_on_failure(e)?;
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+        self.finish_progress(progress.as_mut(), &stats);
+
+        stats.print_summary(&self.options);
+        self.print_profile();
+        Ok(stats)
+    }
+
+    /// 批量转换的主体，单个文件失败只报告并跳过，返回本次的统计
+    ///
+    /// `zip` 不为空时 `output_dir` 是它的暂存目录，每个写出的文件立即移入压缩包
+    fn convert_entries(
+        &self,
+        input_dir: &Path,
+        output_dir: &Path,
+        target_format: &str,
+        quality: f32,
+        mut zip: Option<&mut archive::ZipOutput>,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<ConversionStats> {
+        self.profiler.reset();
+        let mut stats = ConversionStats::new(self.options.dry_run);
+        let default_output_dir = self.format_output_dir(output_dir, target_format);
+        let mut state = match &self.options.state_file {
+            Some(path) => Some(ResumeState::open(path, self.options.resume)?),
+            None => None,
+        };
         
-        println!("🔄 开始批量转换...");
+
+        if self.options.validate_first {
+            self.validate_inputs(self.walk_inputs(input_dir))?;
+        }
+        let mut progress = self.options.progress_json.then(BatchProgress::default);
+        let mut entries: Box<dyn Iterator<Item = walkdir::DirEntry>> = if self.options.stream && !self.options.in_place {
+            Box::new(self.walk_inputs(input_dir))
+        } else {
+            // 原地转换会在输入目录中新建文件，先列出全部条目，避免再次处理刚写出的输出
+            let listed = self.walk_inputs(input_dir).collect::<Vec<_>>();
+            if let Some(progress) = &mut progress {
+                progress.total = Some(listed.iter().filter(|entry| self.is_batch_input(entry.path())).count());
+            }
+            Box::new(listed.into_iter())
+        };
+
+        // 确保输出目录存在，原地转换时不使用输出目录，预演时不创建
+        if !self.options.in_place && !self.options.dry_run {
+            fs::create_dir_all(&default_output_dir)
+                .map_err(|source| PixForgeError::Io { path: default_output_dir.clone(), source })?;
+        }
+        if self.options.clean_temp && !self.options.dry_run {
+            let temp_dir = if self.options.in_place { input_dir } else { default_output_dir.as_path() };
+            let removed = utils::clean_temp_files(temp_dir, utils::STALE_TEMP_AGE)
+                .with_context(|| format!("无法清理临时文件: {}", temp_dir.display()))?;
+            if removed > 0 {
+                notice!(self.options, "🧹 已清理 {} 个残留的临时文件", removed);
+            }
+        }
+
+        notice!(self.options, "🔄 开始批量转换...");
+        let mut used_outputs = HashSet::new();
+        let mut recipe_matched = HashSet::new();
         
-        for entry in WalkDir::new(input_dir).into_iter().filter_map(Result::ok) {
+        while let Some(entry) = entries.next() {
+            // 上一个文件的各个分支都以 continue 结束，在这里按统计的变化输出它的进度
+            self.finish_progress(progress.as_mut(), &stats);
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                notice!(self.options, "⏹️  已取消，停止转换");
+                break;
+            }
+            if self.limit_reached(&stats) {
+                notice!(self.options, "⏹️  已达到数量上限 {}，停止转换", self.options.limit.unwrap_or_default());
+                break;
+            }
+            if self.options.max_total_bytes.is_some_and(|cap| stats.written_bytes >= cap) {
+                self.report_total_bytes_cap(&stats, std::iter::once(entry).chain(entries));
+                break;
+            }
+
             let path = entry.path();
+            if let Some(progress) = &mut progress {
+                if self.is_batch_input(path) {
+                    progress.start(path, &stats);
+                }
+            }
+
+            if path.is_file() && self.exceeds_max_file_size(path) {
+                notice!(self.options, "⏭️  文件过大，跳过: {}", path.display());
+                stats.increment_filtered(SkipReason::TooLarge);
+                continue;
+            }
             
-            if path.is_file() && utils::is_image_file(path) {
-                let relative_path = path.strip_prefix(input_dir)?;
-                let output_file = output_dir.join(utils::change_extension(relative_path, target_format));
+            if self.is_batch_input(path) {
+                if state.as_ref().is_some_and(|state| state.is_completed(path)) {
+                    notice!(self.options, "⏩ 上次已完成，跳过: {}", path.display());
+                    stats.skip_reasons.record(SkipReason::AlreadyExists);
+                    continue;
+                }
+
+                if let Err(e) = self.check_rejected(path) {
+                    let output_file = self.default_output(path, input_dir, output_dir, target_format);
+                    self.record_rejected(&mut stats, e, Some((path, &output_file)))?;
+                    continue;
+                }
+
+                // 附属设置文件可以覆盖这个文件的目标格式和质量
+                let sidecar = match sidecar::load(path) {
+                    Ok(sidecar) => sidecar.unwrap_or_default(),
+                    Err(e) => {
+                        notice!(self.options, "⚠️  跳过: {:#}", e);
+                        stats.increment_failed();
+                        if !self.options.dry_run {
+                            let output_file = self.default_output(path, input_dir, output_dir, target_format);
+                            self.run_hook(self.options.on_failure.as_ref(), path, &output_file);
+                        }
+                        continue;
+                    }
+                };
+                let target_format = sidecar.format.as_deref().unwrap_or(target_format);
+                let quality = sidecar.quality.unwrap_or_else(|| self.effective_quality(path, quality));
+
+                let output_file = if self.options.in_place {
+                    self.in_place_output(path, target_format)
+                } else {
+                    let relative_path = path.strip_prefix(input_dir)?;
+                    let file_name = utils::change_extension(relative_path, &self.output_extension(target_format));
+                    let base = self.format_output_dir(output_dir, target_format);
+                    match self.options.collapse_depth {
+                        Some(depth) => Self::unique_output(
+                            base.join(self.mirrored_dir(&Self::collapsed_parent(relative_path, depth))).join(file_name),
+                            &mut used_outputs,
+                        ),
+                        None => base.join(file_name),
+                    }
+                };
+                let recipe_entry = self.options.recipe.as_ref().and_then(|recipe| {
+                    let (key, entry) = recipe.lookup(path.strip_prefix(input_dir).unwrap_or(path))?;
+                    recipe_matched.insert(key.to_string());
+                    Some(entry)
+                });
+                let with_recipe;
+                let converter = match recipe_entry {
+                    Some(entry) => {
+                        with_recipe = self.with_recipe(entry);
+                        &with_recipe
+                    }
+                    None => self,
+                };
+
+                if self.options.dry_run {
+                    match converter.dry_run_file(path, &output_file, target_format, quality) {
+                        Ok(size) => stats.increment_planned(target_format, size),
+                        Err(e) => {
+                            let e = anyhow::Error::from(Self::classify_failure(e, path, target_format));
+                            notice!(self.options, "⚠️  跳过: {:#}", e);
+                            stats.increment_failed();
+                            self.stop_on_failure(e)?;
+                        }
+                    }
+                    continue;
+                }
+
+                if !self.prepare_in_place(path, &output_file, target_format)? {
+                    stats.increment_filtered(SkipReason::AlreadyExists);
+                    continue;
+                }
                 
                 // 确保输出子目录存在
                 self.ensure_output_directory(&output_file)?;
+                // 输出可能覆盖源文件，转换前记下输入大小
+                let input_size = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or_default();
                 
-                match self.convert_image(path, &output_file, target_format, quality) {
-                    Ok(_) => {
-                        println!("✅ 转换: {} -> {}", path.display(), output_file.display());
-                        stats.increment_converted();
+                match converter.convert_image(path, &output_file, target_format, quality) {
+                    // 按哈希命名时同名条目就是相同的内容，已经写入的压缩包中不会再出现在暂存目录里
+                    Ok(Some(written))
+                        if self.options.name_by_hash && zip.as_ref().is_some_and(|zip| zip.contains(&written)) =>
+                    {
+                        fs::remove_file(&written).map_err(|source| PixForgeError::Io { path: written.clone(), source })?;
+                        let entry = zip.as_ref().and_then(|zip| {
+                            written.strip_prefix(zip.staging_dir()).ok().map(|name| zip.path().join(name))
+                        });
+                        let existing = entry.as_deref().unwrap_or(&written);
+                        notice!(self.options, "♻️  相同内容的输出已存在，跳过写出: {} -> {}", path.display(), existing.display());
+                        stats.increment_skipped(SkipReason::AlreadyExists);
+                        Self::record_completed(state.as_mut(), path)?;
+                    }
+                    Ok(Some(written)) => {
+                        let size = fs::metadata(&written).map(|metadata| metadata.len()).unwrap_or_default();
+                        let over_cap = self.options.max_total_bytes.is_some_and(|cap| stats.written_bytes + size > cap);
+                        // 输出覆盖了源文件时无法撤销，照常计入
+                        if over_cap && !utils::same_file(path, &written) {
+                            fs::remove_file(&written)
+                                .map_err(|source| PixForgeError::Io { path: written.clone(), source })?;
+                            notice!(self.options, "⏹️  写出 {} 会超出输出总大小上限，已删除", written.display());
+                            self.report_total_bytes_cap(&stats, std::iter::once(entry).chain(entries));
+                            break;
+                        }
+                        stats.read_bytes += input_size;
+                        stats.written_bytes += size;
+                        self.replace_original(path, &written)?;
+                        let written = match Self::pack_output(zip.as_deref_mut(), &written) {
+                            Ok(written) => written,
+                            Err(e) => {
+                                notice!(self.options, "⚠️  跳过: {:#}", e);
+                                stats.increment_failed();
+                                self.run_hook(self.options.on_failure.as_ref(), path, &written);
+                                self.stop_on_failure(e)?;
+                                continue;
+                            }
+                        };
+                        notice!(self.options, "✅ 转换: {} -> {}", path.display(), written.display());
+                        stats.increment_converted(target_format);
+                        self.run_hook(self.options.on_success.as_ref(), path, &written);
+                        Self::record_completed(state.as_mut(), path)?;
+                    }
+                    Ok(None) => {
+                        stats.increment_skipped(lock(&converter.skip_reason).take().unwrap_or(SkipReason::Filtered));
+                        Self::record_completed(state.as_mut(), path)?;
                     }
                     Err(e) => {
-                        println!("⚠️  跳过: {} ({})", path.display(), e);
-                        stats.increment_skipped();
+                        let e = anyhow::Error::from(Self::classify_failure(e, path, target_format));
+                        notice!(self.options, "⚠️  跳过: {:#}", e);
+                        stats.increment_failed();
+                        self.run_hook(self.options.on_failure.as_ref(), path, &output_file);
+                        self.stop_on_failure(e)?;
                     }
                 }
+            } else if path.is_file() {
+                stats.skip_reasons.record(SkipReason::NotAnImage);
             }
         }
+        self.finish_progress(progress.as_mut(), &stats);
         
-        stats.print_summary();
-        Ok(())
+        if let Some(recipe) = &self.options.recipe {
+            let unmatched: Vec<&str> = recipe.names().filter(|name| !recipe_matched.contains(*name)).collect();
+            if !unmatched.is_empty() {
+                notice!(self.options, "⚠️  处理方案中有 {} 个文件没有找到: {}", unmatched.len(), unmatched.join(", "));
+            }
+        }
+
+        stats.print_summary(&self.options);
+        self.print_profile();
+        Ok(stats)
     }
     
-    /// 核心图像转换逻辑
-    fn convert_image(
-        &self, 
-        input: &Path, 
-        output: &Path, 
-        target_format: &str, 
-        quality: u8
-    ) -> Result<()> {
-        // SVG特殊处理
-        if utils::get_extension(input).to_lowercase() == "svg" {
-            anyhow::bail!("当前版本不支持SVG转换");
+    /// 用处理方案替换变换选项的转换器，其余选项和计时器与当前转换器相同
+    fn with_recipe(&self, entry: &RecipeEntry) -> ImageConverter {
+        let mut options = ConvertOptions { recipe: None, ..self.options.clone() };
+        if entry.crop.is_some() {
+            options.crop = entry.crop;
         }
-        
-        let img = image::open(input)
-            .with_context(|| format!("无法打开图像: {}", input.display()))?;
-        
-        let (width, height) = (img.width(), img.height());
-        let color_type = img.color();
-        let image_type = self.analyze_image_type(&img);
-
-        match target_format.to_lowercase().as_str() {
-            "jpeg" | "jpg" => self.convert_to_jpeg(&img, output, quality)?,
-            "webp" => self.convert_to_webp(&img, output, quality)?,
-            "png" => self.convert_to_png(&img, output, quality, image_type, color_type)?,
-            "gif" => self.convert_to_gif(&img, output)?,
-            "ico" => self.convert_to_ico(&img, output, width, height)?,
-            _ => anyhow::bail!("不支持的目标格式: {}", target_format),
+        if let Some(rotate) = entry.rotate {
+            options.rotate = rotate;
+        }
+        if entry.resize.is_some() {
+            options.resize = entry.resize;
+        }
+        ImageConverter {
+            options,
+            profiler: Arc::clone(&self.profiler),
+            quality: self.quality,
+            estimated_size: Mutex::new(None),
+            skip_reason: Mutex::new(None),
+            palette: Arc::clone(&self.palette),
+            backdrop: Arc::clone(&self.backdrop),
+            progress_writer: Mutex::new(None),
         }
-        
-        Ok(())
     }
 
-    /// 分析图像类型
-    fn analyze_image_type(&self, img: &image::DynamicImage) -> ImageType {
-        let (width, height) = (img.width(), img.height());
+    /// 按WalkDir产生的顺序逐个返回批量转换要遍历的条目，不在内存中保留整个目录树
+    ///
+    /// 限制数量时按文件名排序，保证"前N个"是确定的；折叠目录时保证重名文件的序号是确定的。
+    /// 排序需要先读出一个目录的全部条目，这时内存占用取决于最大的单个目录，而不是整个目录树
+    fn walk_inputs(&self, input_dir: &Path) -> impl Iterator<Item = walkdir::DirEntry> + '_ {
+        let walker = if self.options.limit.is_some() || self.options.collapse_depth.is_some() {
+            WalkDir::new(input_dir).sort_by_file_name()
+        } else {
+            WalkDir::new(input_dir)
+        };
+        walker.into_iter().filter_entry(|entry| !self.is_excluded_dir(entry)).filter_map(Result::ok)
+    }
 
-        // 小尺寸图像通常是图标或者简单图形
-        if width <= 64 && height <= 64 {
-            return ImageType::SimpleGraphics;
+    /// 是否为 `--exclude-dir` 指定名称的子目录，输入目录本身不排除
+    fn is_excluded_dir(&self, entry: &walkdir::DirEntry) -> bool {
+        entry.depth() > 0
+            && entry.file_type().is_dir()
+            && self.options.exclude_dirs.iter().any(|name| entry.file_name() == name.as_str())
+    }
+
+    /// 相对路径中保留的前 `depth` 层目录
+    fn collapsed_parent(relative_path: &Path, depth: usize) -> PathBuf {
+        relative_path
+            .parent()
+            .map(|parent| parent.components().take(depth).collect())
+            .unwrap_or_default()
+    }
+
+    /// 按 `subpath_case` 处理输出中保留的源目录路径
+    fn mirrored_dir(&self, dir: &Path) -> PathBuf {
+        match self.options.subpath_case {
+            SubpathCase::Preserve => dir.to_path_buf(),
+            SubpathCase::Lower => dir
+                .components()
+                .map(|component| match component {
+                    std::path::Component::Normal(name) => PathBuf::from(name.to_string_lossy().to_lowercase()),
+                    other => PathBuf::from(other.as_os_str()),
+                })
+                .collect(),
         }
+    }
 
-        // 分析图像变化模式
-        let sample_size = (width.min(height) / 4).max(10) as usize;
-        let rgba_img = img.to_rgba8();
+    /// 本次转换中已用过的输出路径加 `_1`、`_2` 等序号，避免折叠后的文件互相覆盖
+    fn unique_output(path: PathBuf, used: &mut HashSet<PathBuf>) -> PathBuf {
+        if used.insert(path.clone()) {
+            return path;
+        }
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let extension = utils::get_extension(&path);
+        (1..)
+            .map(|n| path.with_file_name(format!("{}_{}.{}", stem, n, extension)))
+            .find(|candidate| used.insert(candidate.clone()))
+            .unwrap_or(path)
+    }
 
-        let mut horizontal_variation = 0u64;
-        let mut vertical_variation = 0u64;
-        let mut sample_count = 0u32;
+    /// 还没有确定输出路径就失败时交给失败钩子的输出路径：不考虑附属设置文件和目录折叠的默认位置
+    fn default_output(&self, input: &Path, input_dir: &Path, output_dir: &Path, target_format: &str) -> PathBuf {
+        if self.options.in_place {
+            return self.in_place_output(input, target_format);
+        }
+        let relative_path = input.strip_prefix(input_dir).unwrap_or(input);
+        let file_name = utils::change_extension(relative_path, &self.output_extension(target_format));
+        self.format_output_dir(output_dir, target_format).join(file_name)
+    }
 
-        // 采样分析水平和垂直方向的变化
-        for y in (0..height).step_by((height as usize / sample_size).max(1)) {
-            for x in (1..width).step_by((width as usize / sample_size).max(1)) {
-                if let Some(current) = rgba_img.get_pixel_checked(x, y) {
-                    if let Some(left) = rgba_img.get_pixel_checked(x - 1, y) {
-                        horizontal_variation += Self::pixel_difference(current, left) as u64;
-                        sample_count += 1;
-                    }
-                }
-            }
+    /// 原地转换的输出路径：与源文件同目录，只替换扩展名
+    fn in_place_output(&self, input: &Path, target_format: &str) -> PathBuf {
+        input.with_extension(self.output_extension(target_format))
+    }
+
+    /// 原地转换前的检查，返回 `false` 表示跳过该文件
+    ///
+    /// 格式和扩展名都不变时没有必要转换，除非指定了 `--force`；
+    /// 输出会覆盖源文件且需要备份时，先把源文件复制为 `.bak`
+    fn prepare_in_place(&self, input: &Path, output: &Path, target_format: &str) -> Result<bool> {
+        if !self.options.in_place || output != input {
+            return Ok(true);
         }
 
-        for y in (1..height).step_by((height as usize / sample_size).max(1)) {
-            for x in (0..width).step_by((width as usize / sample_size).max(1)) {
-                if let Some(current) = rgba_img.get_pixel_checked(x, y) {
-                    if let Some(up) = rgba_img.get_pixel_checked(x, y - 1) {
-                        vertical_variation += Self::pixel_difference(current, up) as u64;
-                    }
-                }
-            }
+        let detected = self.source_format(input).unwrap_or_default();
+        if !self.options.force && utils::canonical_format(&detected) == utils::canonical_format(target_format) {
+            notice!(self.options, "⏭️  格式和扩展名都没有变化，跳过 (使用 --force 强制转换): {}", input.display());
+            return Ok(false);
         }
 
-        if sample_count == 0 {
-            return ImageType::SimpleGraphics;
+        if self.options.backup {
+            let backup = Self::backup_path(input);
+            fs::copy(input, &backup).with_context(|| format!("无法创建备份: {}", backup.display()))?;
         }
+        Ok(true)
+    }
 
-        let avg_horizontal = horizontal_variation / sample_count as u64;
-        let avg_vertical = vertical_variation / sample_count as u64;
+    /// 原地转换成功后处理源文件：`--backup` 时改名为 `.bak`，否则删除
+    ///
+    /// 写出的文件就是源文件本身时（覆盖或保留原文件）不做任何处理
+    fn replace_original(&self, input: &Path, written: &Path) -> Result<()> {
+        if !self.options.in_place || utils::same_file(input, written) {
+            return Ok(());
+        }
 
-        // 根据方向性变化选择类型
-        if avg_horizontal < avg_vertical / 2 {
-            ImageType::HorizontalGraphics
-        } else if avg_vertical < avg_horizontal / 2 {
-            ImageType::VerticalPattern
-        } else if avg_horizontal < 10 && avg_vertical < 10 {
-            ImageType::SmoothPhoto
-        } else if avg_horizontal > 50 && avg_vertical > 50 {
-            // 高变化率的复杂内容使用自适应过滤器
-            ImageType::Mixed
+        if self.options.backup {
+            let backup = Self::backup_path(input);
+            fs::rename(input, &backup).with_context(|| format!("无法创建备份: {}", backup.display()))?;
+            notice!(self.options, "🗂️  原文件已备份: {}", backup.display());
         } else {
-            ImageType::ComplexGeometry
+            fs::remove_file(input).with_context(|| format!("无法删除原文件: {}", input.display()))?;
+            notice!(self.options, "🗑️  已删除原文件: {}", input.display());
         }
+        Ok(())
     }
 
-    /// 计算两个像素间的差异
-    fn pixel_difference(p1: &image::Rgba<u8>, p2: &image::Rgba<u8>) -> u32 {
-        let r_diff = (p1[0] as i32 - p2[0] as i32).abs() as u32;
-        let g_diff = (p1[1] as i32 - p2[1] as i32).abs() as u32;
-        let b_diff = (p1[2] as i32 - p2[2] as i32).abs() as u32;
-        let a_diff = (p1[3] as i32 - p2[3] as i32).abs() as u32;
-        r_diff + g_diff + b_diff + a_diff
+    /// 备份文件路径：在完整文件名后追加 `.bak`
+    fn backup_path(input: &Path) -> PathBuf {
+        let mut name = input.file_name().unwrap_or_default().to_os_string();
+        name.push(".bak");
+        input.with_file_name(name)
+    }
+
+    /// 文件大小超过 `--skip-larger-than`，不读取文件内容
+    fn exceeds_max_file_size(&self, path: &Path) -> bool {
+        match self.options.max_file_size {
+            Some(max) => fs::metadata(path).is_ok_and(|metadata| metadata.len() > max),
+            None => false,
+        }
+    }
+
+    /// 开启 `--profile` 时输出各阶段耗时
+    fn print_profile(&self) {
+        if let Some(report) = self.profiler.report() {
+            notice!(self.options, "{}", report);
+        }
+    }
+
+    /// 在状态文件中记录已完成的输入，失败的输入不记录，续传时会重试
+    fn record_completed(state: Option<&mut ResumeState>, input: &Path) -> Result<()> {
+        match state {
+            Some(state) => state.record(input),
+            None => Ok(()),
+        }
+    }
+
+    /// 批量转换的输出根目录，按格式分目录时为 `output/<格式>/`
+    ///
+    /// 目录名使用格式的规范名称，jpg和jpeg写到同一个 `jpeg/` 目录；文件在该目录下的布局与不分目录时相同
+    fn format_output_dir(&self, output_dir: &Path, target_format: &str) -> std::path::PathBuf {
+        if self.options.split_by_format {
+            output_dir.join(utils::canonical_format(target_format))
+        } else {
+            output_dir.to_path_buf()
+        }
+    }
+
+    /// 生成转换失败的上下文描述，包含检测到的源格式与目标格式
+    fn failure_context(input: &Path, target_format: &str) -> String {
+        let detected = utils::detect_image_format_by_content(input)
+            .unwrap_or_else(|| "未知".to_string());
+        format!(
+            "转换失败: {} (检测到的格式: {}, 目标格式: {})",
+            input.display(), detected, target_format.to_lowercase()
+        )
     }
     
-    /// 转换为JPEG格式
-    fn convert_to_jpeg(&self, img: &image::DynamicImage, output: &Path, quality: u8) -> Result<()> {
-        let output_file = File::create(output)
-            .with_context(|| format!("无法创建输出文件: {}", output.display()))?;
-        
-        let encoder = JpegEncoder::new_with_quality(output_file, quality);
-        let rgb_img = img.to_rgb8(); // JPEG不支持透明度
-        
-        rgb_img.write_with_encoder(encoder)
-            .with_context(|| format!("JPEG编码失败: {}", output.display()))?;
-        
-        Ok(())
+    /// 将转换失败的错误分类，未分类的错误附上检测到的源格式与目标格式
+    fn classify_failure(error: anyhow::Error, input: &Path, target_format: &str) -> PixForgeError {
+        PixForgeError::from_anyhow(error.context(Self::failure_context(input, target_format)))
     }
     
-    /// 转换为WebP格式（使用webp 0.3.0）
-    fn convert_to_webp(&self, img: &image::DynamicImage, output: &Path, quality: u8) -> Result<()> {
-        let rgba_img = img.to_rgba8();
-        let (width, height) = (rgba_img.width(), rgba_img.height());
-        
-        // 使用webp crate进行编码
-        let encoder = webp::Encoder::from_rgba(&rgba_img, width, height);
-        let encoded_data = encoder.encode(quality as f32);
-        
-        fs::write(output, &*encoded_data)
-            .with_context(|| format!("WebP保存失败: {}", output.display()))?;
-        
+    /// 按文件内容检测到的格式在 `reject_formats` 中或不在 `allow_formats` 中时返回错误
+    fn check_rejected(&self, input: &Path) -> Result<(), PixForgeError> {
+        if self.options.reject_formats.is_empty() && self.options.allow_formats.is_empty() {
+            return Ok(());
+        }
+        self.check_format(input, self.source_format(input).as_deref())
+    }
+
+    /// 按检测到的格式 (`None` 表示无法识别) 检查 `reject_formats` 和 `allow_formats`
+    fn check_format(&self, input: &Path, detected: Option<&str>) -> Result<(), PixForgeError> {
+        let canonical = detected.map(utils::canonical_format);
+        let listed = |formats: &[String]| {
+            formats.iter().any(|format| Some(utils::canonical_format(format)) == canonical)
+        };
+        if let Some(detected) = detected {
+            if listed(&self.options.reject_formats) {
+                return Err(PixForgeError::RejectedFormat { path: input.to_path_buf(), format: detected.to_string() });
+            }
+        }
+        if !self.options.allow_formats.is_empty() && !listed(&self.options.allow_formats) {
+            return Err(PixForgeError::DisallowedFormat {
+                path: input.to_path_buf(),
+                format: detected.unwrap_or("未知").to_string(),
+                allowed: self.options.allow_formats.join(", "),
+            });
+        }
         Ok(())
     }
-    
-    /// 转换为PNG格式
-    fn convert_to_png(
-        &self, 
-        img: &image::DynamicImage, 
-        output: &Path, 
-        quality: u8,
-        image_type: ImageType,
-        color_type: ColorType
+
+    /// 批量转换时被拒绝的输入记入统计：设置 `skip_disallowed` 时不在允许列表中的计为跳过，其余计为失败
+    ///
+    /// 计为失败时对 `failure_hook` 中的输入和输出执行失败钩子；压缩包条目不执行钩子，传 `None`
+    fn record_rejected(
+        &self,
+        stats: &mut ConversionStats,
+        error: PixForgeError,
+        failure_hook: Option<(&Path, &Path)>,
     ) -> Result<()> {
-        let output_file = File::create(output)
-            .with_context(|| format!("无法创建输出文件: {}", output.display()))?;
+        if self.options.skip_disallowed && matches!(error, PixForgeError::DisallowedFormat { .. }) {
+            notice!(self.options, "⏭️  跳过: {}", error);
+            stats.increment_skipped(SkipReason::Filtered);
+            return Ok(());
+        }
+        notice!(self.options, "🚫 {}", error);
+        stats.increment_failed();
+        if let Some((input, output)) = failure_hook.filter(|_| !self.options.dry_run) {
+            self.run_hook(self.options.on_failure.as_ref(), input, output);
+        }
+        self.stop_on_failure(error.into())
+    }
 
-        let filter_type = self.get_optimal_filter_type(image_type);
+    /// 批量转换是否处理这个文件：可识别的图像或交给后备解码命令的文件
+    fn is_batch_input(&self, path: &Path) -> bool {
+        path.is_file() && (self.is_image_input(path) || self.needs_fallback(path))
+    }
 
-        // 根据质量参数调整PNG压缩级别
-        let compression_level = self.get_png_compression_level(quality);
-        let encoder = PngEncoder::new_with_quality(
-            output_file, 
-            compression_level,
-            filter_type
-        );
+    /// 输出正在处理的文件的进度行，写出失败只提示，不影响转换
+    fn finish_progress(&self, progress: Option<&mut BatchProgress>, stats: &ConversionStats) {
+        let Some(event) = progress.and_then(|progress| progress.finish(stats)) else {
+            return;
+        };
+        let line = serde_json::to_string(&event).unwrap_or_default();
+        let written = match lock(&self.progress_writer).as_mut() {
+            Some(writer) => writeln!(writer, "{}", line).and_then(|()| writer.flush()),
+            None => writeln!(std::io::stdout(), "{}", line),
+        };
+        if let Err(e) = written {
+            eprintln!("⚠️  无法写出进度: {}", e);
+        }
+    }
 
-        // 保持原有颜色类型以避免不必要的转换
-        self.encode_png_with_optimal_color_type(img, encoder, color_type)?;
-        
+    /// 设置 `fail_fast` 时把失败原因作为批量转换的错误返回，停止处理后续文件
+    fn stop_on_failure(&self, error: anyhow::Error) -> Result<()> {
+        if self.options.fail_fast {
+            notice!(self.options, "⏹️  遇到失败，停止批量转换");
+            return Err(error);
+        }
         Ok(())
     }
 
-    /// 根据图像类型选择最优过滤器
-    fn get_optimal_filter_type(&self, image_type: ImageType) -> image::codecs::png::FilterType {
-        use image::codecs::png::FilterType;
+    /// 转换前逐个解码全部输入，有任何一个失败就列出失败的文件并中止，不写出任何输出
+    ///
+    /// 交给后备解码命令的输入和超过大小上限的文件不校验
+    fn validate_inputs(&self, entries: impl Iterator<Item = walkdir::DirEntry>) -> Result<()> {
+        notice!(self.options, "🔎 预先校验全部输入...");
+        let mut checked = 0;
+        let mut failures = Vec::new();
+        for entry in entries {
+            let path = entry.path();
+            if !(path.is_file() && self.is_image_input(path) && !self.exceeds_max_file_size(path)) {
+                continue;
+            }
+            checked += 1;
+            if let Err(e) = self.open_image(path) {
+                failures.push((entry.into_path(), e));
+            }
+        }
 
-        match image_type {
-            ImageType::SimpleGraphics => FilterType::NoFilter,
-            ImageType::HorizontalGraphics => FilterType::Sub,
-            ImageType::VerticalPattern => FilterType::Up,
-            ImageType::SmoothPhoto => FilterType::Avg,
-            ImageType::ComplexGeometry => FilterType::Paeth,
-            ImageType::Mixed => FilterType::Adaptive
+        if failures.is_empty() {
+            notice!(self.options, "✅ {} 个输入全部通过校验", checked);
+            return Ok(());
         }
+        for (path, e) in &failures {
+            notice!(self.options, "❌ 校验失败: {} ({:#})", path.display(), e);
+        }
+        anyhow::bail!("{} 个输入无法解码，已中止，没有转换任何文件", failures.len())
     }
-    
-    /// 转换为GIF格式
-    fn convert_to_gif(&self, img: &image::DynamicImage, output: &Path) -> Result<()> {
-        img.save_with_format(output, ImageFormat::Gif)
-            .with_context(|| format!("GIF保存失败: {}", output.display()))?;
-        
-        Ok(())
+
+    /// 报告输出总大小上限：已转换的文件数和剩余没有转换的图像数
+    fn report_total_bytes_cap(&self, stats: &ConversionStats, remaining: impl Iterator<Item = walkdir::DirEntry>) {
+        let remaining = remaining
+            .filter(|entry| {
+                let path = entry.path();
+                path.is_file() && (self.is_image_input(path) || self.needs_fallback(path))
+            })
+            .count();
+        notice!(self.options, 
+            "📦 已达到输出总大小上限 {} 字节: 已写出 {} 字节，转换 {} 个文件，剩余 {} 个未转换",
+            self.options.max_total_bytes.unwrap_or_default(),
+            stats.written_bytes,
+            stats.converted,
+            remaining
+        );
+    }
+
+    /// 压缩包输入时报告输出总大小上限，剩余的条目不再解压，不统计数量
+    fn report_archive_bytes_cap(&self, stats: &ConversionStats) {
+        notice!(self.options, 
+            "📦 已达到输出总大小上限 {} 字节: 已写出 {} 字节，转换 {} 个文件，压缩包中剩余的条目未转换",
+            self.options.max_total_bytes.unwrap_or_default(),
+            stats.written_bytes,
+            stats.converted
+        );
+    }
+
+    /// 检查批量转换是否已达到数量上限
+    fn limit_reached(&self, stats: &ConversionStats) -> bool {
+        let Some(limit) = self.options.limit else {
+            return false;
+        };
+
+        let counted = match self.options.limit_mode {
+            LimitMode::Converted => stats.converted,
+            LimitMode::Attempted => stats.converted + stats.skipped,
+        };
+        counted as usize >= limit
     }
     
-    /// 转换为ICO格式
-    fn convert_to_ico(
-        &self, 
-        img: &image::DynamicImage, 
-        output: &Path, 
-        width: u32, 
-        height: u32
-    ) -> Result<()> {
-        // ICO格式尺寸限制处理
-        if width > 256 || height > 256 {
-            let resized = img.resize(256, 256, image::imageops::FilterType::Lanczos3);
-            resized.save_with_format(output, ImageFormat::Ico)
-        } else {
-            img.save_with_format(output, ImageFormat::Ico)
+    /// 执行转换钩子，钩子失败只报告，不影响转换结果
+    fn run_hook(&self, hook: Option<&HookCommand>, input: &Path, output: &Path) {
+        if let Some(hook) = hook {
+            let result = if self.options.progress_json { hook.run_to_stderr(input, output) } else { hook.run(input, output) };
+            if let Err(e) = result {
+                notice!(self.options, "⚠️  钩子命令失败: {} ({})", input.display(), e);
+            }
         }
-        .with_context(|| format!("ICO保存失败: {}", output.display()))?;
-        
-        Ok(())
     }
     
-    /// 确定输出文件路径
-    fn determine_output_path(&self, input: &Path, output: &Path, target_format: &str) -> std::path::PathBuf {
-        if output.is_dir() {
-            let filename = utils::change_extension(input, target_format);
-            output.join(filename)
-        } else {
-            output.to_path_buf()
+    /// 转换单个文件，返回实际写出的文件
+    ///
+    /// 开启 `name_by_hash` 时先写到临时文件，再按内容的哈希改名；相同内容的输出已存在时删除临时文件，返回 `None`
+    fn convert_image(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_format: &str,
+        quality: f32
+    ) -> Result<Option<PathBuf>> {
+        if !self.options.name_by_hash {
+            return self.write_converted(input, output, target_format, quality);
+        }
+
+        let temp = utils::temp_path(output);
+        let written = match self.write_converted(input, &temp, target_format, quality) {
+            Ok(Some(written)) => written,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            }
+        };
+        // 保留原文件时写出的是换回源扩展名的副本
+        let extension = if written == temp { output.extension() } else { written.extension() };
+        let data = fs::read(&written).with_context(|| format!("无法读取输出文件: {}", written.display()))?;
+        let mut hashed = output.with_file_name(sha256_hex(&data));
+        if let Some(extension) = extension {
+            hashed.set_extension(extension);
+        }
+
+        if hashed.exists() {
+            fs::remove_file(&written)?;
+            notice!(self.options, "♻️  相同内容的输出已存在，跳过写出: {} -> {}", input.display(), hashed.display());
+            *lock(&self.skip_reason) = Some(SkipReason::AlreadyExists);
+            return Ok(None);
         }
+        fs::rename(&written, &hashed).with_context(|| format!("无法写入输出文件: {}", hashed.display()))?;
+        Ok(Some(hashed))
     }
-    
-    /// 确保输出目录存在
-    fn ensure_output_directory(&self, output_file: &Path) -> Result<()> {
-        if let Some(parent) = output_file.parent() {
-            fs::create_dir_all(parent)?;
+
+    /// 转换单个文件并写到 `output`，返回实际写出的文件
+    ///
+    /// 开启 `--skip-larger` 时先编码到临时文件，输出比源文件大时按设置
+    /// 改为复制源文件或不写出，不写出时返回 `None`
+    fn write_converted(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_format: &str,
+        quality: f32
+    ) -> Result<Option<PathBuf>> {
+        let decoded = self.fallback_decode(input, target_format)?;
+        let source = decoded.as_ref().map_or(input, DecodedPng::path);
+
+        if self.options.pdf_all_pages && utils::detect_image_format_by_content(source).as_deref() == Some("pdf") {
+            return self.convert_pdf_pages(source, output, target_format, quality).map(Some);
         }
+
+        let Some(action) = self.options.skip_larger else {
+            self.encode_file(source, output, target_format, quality)?;
+            self.verify_output(output, target_format)?;
+            return Ok(Some(output.to_path_buf()));
+        };
+
+        let temp = utils::temp_path(output);
+        let encoded = self
+            .encode_file(source, &temp, target_format, quality)
+            .and_then(|()| self.verify_output(&temp, target_format));
+        if let Err(e) = encoded {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+
+        let source_size = fs::metadata(input)?.len();
+        let output_size = fs::metadata(&temp)?.len();
+        if output_size <= source_size {
+            fs::rename(&temp, output)
+                .with_context(|| format!("无法写入输出文件: {}", output.display()))?;
+            return Ok(Some(output.to_path_buf()));
+        }
+        fs::remove_file(&temp)?;
+
+        match action {
+            LargerAction::Keep => {
+                let kept = Self::kept_original_path(input, output, target_format);
+                if !utils::same_file(input, &kept) {
+                    fs::copy(input, &kept)
+                        .with_context(|| format!("无法复制源文件: {}", kept.display()))?;
+                }
+                notice!(self.options, 
+                    "↩️  输出比源文件大 ({} > {} 字节)，保留原文件: {}",
+                    output_size, source_size, input.display()
+                );
+                Ok(Some(kept))
+            }
+            LargerAction::Skip => {
+                notice!(self.options, 
+                    "⏭️  输出比源文件大 ({} > {} 字节)，跳过: {}",
+                    output_size, source_size, input.display()
+                );
+                *lock(&self.skip_reason) = Some(SkipReason::TooLarge);
+                Ok(None)
+            }
+        }
+    }
+
+    /// 检查写出的文件能按内容识别为目标格式，并能读出文件头中的尺寸
+    ///
+    /// 只读取文件头，不完整解码；JPEG XL只检查签名
+    fn verify_output(&self, output: &Path, target_format: &str) -> Result<()> {
+        if self.options.no_verify_output {
+            return Ok(());
+        }
+        let target = utils::canonical_format(target_format);
+        let detected = utils::detect_image_format_by_content(output);
+        let matches_target = match detected.as_deref() {
+            Some(format) if target == "pnm" => matches!(format, "ppm" | "pgm" | "pbm"),
+            Some(format) => utils::canonical_format(format) == target,
+            None => false,
+        };
+        if !matches_target {
+            anyhow::bail!(
+                "输出校验失败: {} 不是有效的{}文件 (检测到的格式: {})",
+                output.display(), target, detected.as_deref().unwrap_or("未知")
+            );
+        }
+        if target == "jxl" {
+            return Ok(());
+        }
+
+        let reader = image::ImageReader::open(output)
+            .with_context(|| format!("输出校验时无法打开: {}", output.display()))?;
+        let reader = if target == "tga" {
+            let mut reader = reader;
+            reader.set_format(ImageFormat::Tga);
+            reader
+        } else {
+            reader.with_guessed_format()?
+        };
+        reader
+            .into_dimensions()
+            .with_context(|| format!("输出校验失败: 无法读取{}文件头: {}", target, output.display()))?;
         Ok(())
     }
-    
-    /// 获取PNG压缩级别
-    fn get_png_compression_level(&self, quality: u8) -> image::codecs::png::CompressionType {
-        match quality {
-            0..=20 => image::codecs::png::CompressionType::Fast,
-            21..=60 => image::codecs::png::CompressionType::Default,
-            61..=80 => image::codecs::png::CompressionType::Best,
-            _ => image::codecs::png::CompressionType::Best,
+
+    /// 输入过滤：是否按图像文件转换
+    ///
+    /// 设置 `trust_extensions` 时只看扩展名，省去每个文件一次打开和读取
+    pub fn is_image_input(&self, path: &Path) -> bool {
+        if self.options.input_format.is_some() {
+            return path.is_file();
+        }
+        if self.options.trust_extensions {
+            return path.is_file() && utils::has_potential_image_extension(path);
         }
+        utils::is_image_file_with(path, self.options.any_extension)
     }
-    
-    /// 使用最优颜色类型编码PNG
-    fn encode_png_with_optimal_color_type(
-        &self,
-        img: &image::DynamicImage,
-        encoder: PngEncoder<File>,
-        color_type: ColorType
-    ) -> Result<()> {
-        match color_type {
-            ColorType::L8 => {
-                let luma_img = img.as_luma8()
-                    .map(|img| img.clone())
-                    .unwrap_or_else(|| img.to_luma8());
-                luma_img.write_with_encoder(encoder)?;
-            },
-            ColorType::La8 => {
-                let luma_alpha_img = img.as_luma_alpha8()
-                    .map(|img| img.clone())
-                    .unwrap_or_else(|| img.to_luma_alpha8());
-                luma_alpha_img.write_with_encoder(encoder)?;
-            },
-            ColorType::Rgb8 => {
-                let rgb_img = img.as_rgb8()
-                    .map(|img| img.clone())
-                    .unwrap_or_else(|| img.to_rgb8());
-                rgb_img.write_with_encoder(encoder)?;
-            },
-            ColorType::Rgba8 => {
-                let rgba_img = img.as_rgba8()
-                    .map(|img| img.clone())
-                    .unwrap_or_else(|| img.to_rgba8());
-                rgba_img.write_with_encoder(encoder)?;
-            },
-            _ => {
-                let rgba_img = img.to_rgba8();
-                rgba_img.write_with_encoder(encoder)?;
+
+    /// 输入的格式：指定了 `input_format` 时直接使用，否则按文件内容识别
+    fn source_format(&self, input: &Path) -> Option<String> {
+        self.options.input_format.clone().or_else(|| utils::detect_image_format_by_content(input))
+    }
+
+    /// 输入是否交给后备解码命令：设置了命令且输入不是可识别的图像或是SVG，附属设置文件除外
+    fn needs_fallback(&self, input: &Path) -> bool {
+        self.options.fallback_decoder.is_some()
+            && !input.to_string_lossy().ends_with(sidecar::SIDECAR_SUFFIX)
+            && (utils::get_extension(input).eq_ignore_ascii_case("svg")
+                || !self.is_image_input(input))
+    }
+
+    /// 需要时用后备解码命令把输入转换为临时PNG，命令失败归为解码失败
+    fn fallback_decode(&self, input: &Path, target_format: &str) -> Result<Option<DecodedPng>> {
+        let Some(decoder) = &self.options.fallback_decoder else {
+            return Ok(None);
+        };
+        if !self.needs_fallback(input) {
+            return Ok(None);
+        }
+        let decoder = decoder.clone();
+        let decode = move |_: &ImageConverter, input: &Path| decoder.decode(input);
+        let decoded = self.profiler.time(Stage::Decode, || self.decode_with_timeout(input, decode)).map_err(|source| {
+            PixForgeError::DecodeFailed {
+                path: input.to_path_buf(),
+                detected: self.source_format(input).unwrap_or_else(|| "未知".to_string()),
+                target: target_format.to_lowercase(),
+                source: source.into(),
             }
+        })?;
+        Ok(Some(decoded))
+    }
+
+    /// 把PDF的每一页转换为单独的文件 (`name_p1.png`、`name_p2.png` ...)，返回第一页的输出路径
+    ///
+    /// 多页输出不做 `--skip-larger` 比较
+    fn convert_pdf_pages(&self, input: &Path, output: &Path, target_format: &str, quality: f32) -> Result<PathBuf> {
+        let format = target_format.to_lowercase();
+        if !TARGET_FORMATS.contains(&format.as_str()) {
+            return Err(PixForgeError::UnsupportedTargetFormat(target_format.to_string()).into());
         }
-        Ok(())
+
+        let pages = self
+            .profiler
+            .time(Stage::Decode, || pdf::render(input, None, self.pdf_dpi()))
+            .map_err(|source| PixForgeError::DecodeFailed {
+                path: input.to_path_buf(),
+                detected: "pdf".to_string(),
+                target: format.clone(),
+                source: source.into(),
+            })?;
+
+        let mut first = None;
+        for (index, page) in pages.into_iter().enumerate() {
+            let page_output = Self::page_output(output, index + 1);
+            let page = self.profiler.time(Stage::Transform, || self.apply_transforms(page, Reorient::IDENTITY));
+            self.encode_decoded(input, page, &page_output, &format, quality)?;
+            self.verify_output(&page_output, &format)?;
+            notice!(self.options, "📄 第 {} 页 -> {}", index + 1, page_output.display());
+            first.get_or_insert(page_output);
+        }
+        Ok(first.unwrap_or_else(|| output.to_path_buf()))
     }
-}
 
-/// 转换统计信息
-#[derive(Debug)]
-struct ConversionStats {
-    converted: u32,
-    skipped: u32,
-}
+    /// 多页输出的文件名，在扩展名前加 `_p页码`
+    fn page_output(output: &Path, page: usize) -> PathBuf {
+        let stem = output.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        match output.extension() {
+            Some(extension) => output.with_file_name(format!("{}_p{}.{}", stem, page, extension.to_string_lossy())),
+            None => output.with_file_name(format!("{}_p{}", stem, page)),
+        }
+    }
 
-impl ConversionStats {
-    fn new() -> Self {
-        Self {
-            converted: 0,
-            skipped: 0,
+    fn pdf_dpi(&self) -> f32 {
+        self.options.pdf_dpi.unwrap_or(pdf::DEFAULT_DPI)
+    }
+
+    /// 写出编码结果；预演时只记录字节数，不写文件
+    fn write_output(&self, output: &Path, encoded: &[u8]) -> Result<()> {
+        if self.options.dry_run {
+            *lock(&self.estimated_size) = Some(encoded.len() as u64);
+            return Ok(());
         }
+        self.profiler
+            .time(Stage::Write, || fs::write(output, encoded))
+            .map_err(|source| PixForgeError::Io { path: output.to_path_buf(), source })?;
+        Ok(())
     }
-    
-    fn increment_converted(&mut self) {
-        self.converted += 1;
+
+    /// 预演单个文件：列出输入和输出，需要时在内存中编码并返回输出大小
+    ///
+    /// 估算的是编码结果本身，不考虑 `--skip-larger`
+    fn dry_run_file(&self, input: &Path, output: &Path, target_format: &str, quality: f32) -> Result<Option<u64>> {
+        if !self.options.estimate_sizes {
+            notice!(self.options, "🔍 将转换: {} -> {}", input.display(), output.display());
+            return Ok(None);
+        }
+
+        let decoded = self.fallback_decode(input, target_format)?;
+        let source = decoded.as_ref().map_or(input, DecodedPng::path);
+        *lock(&self.estimated_size) = None;
+        self.encode_file(source, output, target_format, quality)?;
+        let size = lock(&self.estimated_size).take().unwrap_or_default();
+        notice!(self.options, "📏 将转换: {} -> {} ({} 字节)", input.display(), output.display(), size);
+        Ok(Some(size))
     }
-    
-    fn increment_skipped(&mut self) {
-        self.skipped += 1;
+
+    /// 保留原文件时的输出路径
+    ///
+    /// 格式相同时就是原输出路径，格式不同时换回源文件的扩展名，保证扩展名与内容一致
+    fn kept_original_path(input: &Path, output: &Path, target_format: &str) -> PathBuf {
+        let detected = utils::detect_image_format_by_content(input).unwrap_or_default();
+        if utils::canonical_format(&detected) == utils::canonical_format(target_format) {
+            output.to_path_buf()
+        } else {
+            output.with_extension(utils::get_extension(input))
+        }
     }
-    
-    fn print_summary(&self) {
-        if self.converted == 0 && self.skipped > 0 {
-            println!("❌ 没有图片被转换。全部 {} 个文件被跳过。", self.skipped);
+
+    /// 核心图像转换逻辑
+    fn encode_file(
+        &self, 
+        input: &Path, 
+        output: &Path, 
+        target_format: &str, 
+        quality: f32
+    ) -> Result<()> {
+        // SVG特殊处理
+        if utils::get_extension(input).to_lowercase() == "svg" {
+            return Err(PixForgeError::UnsupportedSourceFormat { path: input.to_path_buf() }.into());
+        }
+        
+        let format = target_format.to_lowercase();
+        if !TARGET_FORMATS.contains(&format.as_str()) {
+            return Err(PixForgeError::UnsupportedTargetFormat(target_format.to_string()).into());
+        }
+
+        // JPEG只做旋转/翻转时走无损路径
+        if self.try_lossless_jpeg(input, output, &format)? {
+            return Ok(());
+        }
+        if self.try_animation(input, output, &format)? {
+            return Ok(());
+        }
+        if self.try_passthrough(input, output, &format)? {
+            return Ok(());
+        }
+
+        let decode_failed = |source: anyhow::Error| PixForgeError::DecodeFailed {
+            path: input.to_path_buf(),
+            detected: self.source_format(input).unwrap_or_else(|| "未知".to_string()),
+            target: format.clone(),
+            source: source.into(),
+        };
+        let streamed = self.profiler.time(Stage::Decode, || self.try_stream_tiff(input)).map_err(decode_failed)?;
+        let img = match streamed {
+            // 流式路径已经完成了缩放
+            Some(img) => img,
+            None => {
+                let decode = |converter: &ImageConverter, input: &Path| converter.open_image(input);
+                let (img, exif_orientation) = self.profiler
+                    .time(Stage::Decode, || self.decode_with_timeout(input, decode))
+                    .map_err(decode_failed)?;
+                self.profiler.time(Stage::Transform, || self.apply_transforms(img, exif_orientation))
+            }
+        };
+
+        self.encode_decoded(input, img, output, &format, quality)
+    }
+
+    /// 对解码并完成几何变换的图像执行像素处理、编码和写出
+    ///
+    /// `format` 为已检查过的小写目标格式
+    fn encode_decoded(
+        &self,
+        input: &Path,
+        mut img: image::DynamicImage,
+        output: &Path,
+        format: &str,
+        quality: f32,
+    ) -> Result<()> {
+        if self.options.swap_rb {
+            self.profiler.time(Stage::Transform, || transform::swap_red_blue(&mut img));
+        }
+        
+        let img = match self.options.extract {
+            Some(channel) => self.profiler.time(Stage::Transform, || {
+                transform::extract_channel(&img, channel, self.options.missing_alpha)
+            })?,
+            None => img,
+        };
+        
+        // farbfeld保持16位，其余格式都会降到8位
+        let reduces_depth = !matches!(format, "farbfeld" | "ff");
+        let img = if self.options.tone_map && reduces_depth && Self::is_high_bit_depth(&img) {
+            self.profiler.time(Stage::Transform, || transform::tone_map(&img))
+        } else {
+            img
+        };
+        
+        if self.options.color_count {
+            let (count, grayscale) = self.profiler.time(Stage::Analyze, || {
+                (colors::count_colors(&img), colors::is_effectively_grayscale(&img))
+            });
+            let verdict = if grayscale { "，实际为灰度" } else { "" };
+            notice!(self.options, "🎨 {}: {} 种颜色{}", input.display(), count, verdict);
+        }
+
+        let img = if self.options.auto_grayscale
+            && img.color().has_color()
+            && self.profiler.time(Stage::Analyze, || colors::is_effectively_grayscale(&img))
+        {
+            if self.options.verbose {
+                notice!(self.options, "⚫ 颜色通道完全相同，自动转为灰度: {}", input.display());
+            }
+            self.profiler.time(Stage::Transform, || colors::to_grayscale(&img))
         } else {
-            println!("🎉 转换完成: {} 个转换成功, {} 个跳过", self.converted, self.skipped);
+            img
+        };
+
+        if self.options.extract_colors {
+            let summary = self.profiler.time(Stage::Analyze, || colors::summarize(&img, colors::PALETTE_SIZE));
+            if let Some(summary) = summary {
+                notice!(self.options, "🎨 {}: {}", input.display(), summary);
+            }
+        }
+
+        let color_type = img.color();
+        let (image_type, variation) = self.profiler.time(Stage::Analyze, || self.analyze_image_type(&img));
+        if self.options.trace {
+            let source = self.source_format(input).unwrap_or_else(|| "未知".to_string());
+            self.trace(input, format_args!("源格式 {}, 尺寸 {}x{}, 颜色 {:?}", source, img.width(), img.height(), color_type));
+            match variation {
+                Some((horizontal, vertical)) => self.trace(
+                    input,
+                    format_args!("内容类型 {:?} (水平变化 {}, 垂直变化 {})", image_type, horizontal, vertical),
+                ),
+                None => self.trace(input, format_args!("内容类型 {:?} (尺寸不超过64x64，未采样)", image_type)),
+            }
+            match format {
+                "png" => self.trace(
+                    input,
+                    format_args!(
+                        "PNG过滤器 {:?}, 压缩级别 {:?}",
+                        self.get_optimal_filter_type(image_type),
+                        QualityProfile::new(quality).png_compression()
+                    ),
+                ),
+                "jpeg" | "jpg" => {
+                    let quality = self.size_capped_quality(quality, img.width(), img.height());
+                    self.trace(input, format_args!("JPEG质量 {}", QualityProfile::new(quality).jpeg()))
+                }
+                "webp" if self.webp_lossless(image_type) => match self.options.webp_near_lossless {
+                    Some(level) => self.trace(input, format_args!("WebP近无损 {}", level)),
+                    None => self.trace(input, format_args!("WebP无损")),
+                },
+                "webp" => {
+                    let quality = self.effective_webp_quality(quality, image_type);
+                    let quality = self.size_capped_quality(quality, img.width(), img.height());
+                    self.trace(input, format_args!("WebP质量 {}", quality))
+                }
+                _ => {}
+            }
+        }
+        let webp_lossless = format == "webp" && self.webp_lossless(image_type);
+        if format == "webp" && self.options.auto_webp_mode && self.options.verbose {
+            let mode = if webp_lossless { "无损" } else { "有损" };
+            notice!(self.options, "🧩 WebP编码方式: {} (内容类型 {:?}): {}", mode, image_type, input.display());
+        }
+
+        // ICO格式尺寸限制处理
+        // 多尺寸ICO的每一帧都从原图缩放，这里不预先缩小
+        let mut img = if format == "ico" && self.options.ico_sizes.is_empty() { Self::fit_ico_size(img) } else { img };
+
+        // 二值化放在所有缩放之后，避免插值重新产生半透明边缘
+        if let Some(threshold) = self.options.alpha_threshold {
+            self.profiler.time(Stage::Transform, || transform::threshold_alpha(&mut img, threshold));
         }
+        // 背景最后垫在下面，不参与缩放和二值化
+        let (img, color_type) = match &self.options.flatten {
+            Some(backdrop) if img.color().has_alpha() => {
+                let flattened = self.profiler.time(Stage::Transform, || self.flatten(&img, backdrop))?;
+                let color_type = flattened.color();
+                (flattened, color_type)
+            }
+            _ => (img, color_type),
+        };
+
+        let encode_as = |quality: f32, lossless: bool| match format {
+            "jpeg" | "jpg" => {
+                let quality = self.size_capped_quality(quality, img.width(), img.height());
+                self.convert_to_jpeg(&img, output, QualityProfile::new(quality))
+            }
+            "webp" => {
+                let quality = self.effective_webp_quality(quality, image_type);
+                let quality = self.size_capped_quality(quality, img.width(), img.height());
+                self.convert_to_webp(&img, QualityProfile::new(quality), lossless)
+            }
+            "png" => self.convert_to_png(&img, output, QualityProfile::new(quality), image_type, color_type),
+            "gif" => self.convert_to_gif(&img, output),
+            "ico" => self.convert_to_ico(&img, output),
+            "farbfeld" | "ff" => self.convert_to_farbfeld(&img, output),
+            "tga" => self.convert_to_tga(&img, output),
+            "pnm" | "ppm" | "pgm" | "pbm" => self.convert_to_pnm(&img, output, format),
+            "jxl" => self.convert_to_jxl(&img, output),
+            _ => unreachable!("目标格式已在解码前检查"),
+        };
+        let encode = |quality: f32| encode_as(quality, false);
+        let encode_failed = |source: anyhow::Error| PixForgeError::EncodeFailed {
+            path: output.to_path_buf(),
+            format: format.to_string(),
+            source: source.into(),
+        };
+        // 同时返回WebP是否实际按无损编码，无损校验据此决定是否校验
+        let (encoded, webp_lossless) = self.profiler.time(Stage::Encode, || match self.options.max_bytes {
+            Some(budget) if LOSSY_FORMATS.contains(&format) => {
+                // 无损WebP超出预算时改用有损编码在预算内搜索质量
+                if webp_lossless {
+                    let data = encode_as(quality, true)?;
+                    if data.len() as u64 <= budget {
+                        return Ok((data, true));
+                    }
+                    notice!(self.options, "⚠️  无损WebP超出字节预算，改用有损编码: {}", input.display());
+                }
+                self.encode_within_budget(input, budget, quality, encode).map(|data| (data, false))
+            }
+            _ => encode_as(quality, webp_lossless).map(|data| (data, webp_lossless)),
+        }).map_err(encode_failed)?;
+
+        let encoded = if self.options.optimize {
+            self.profiler
+                .time(Stage::Encode, || Self::optimize_encoded(format, encoded))
+                .map_err(encode_failed)?
+        } else {
+            encoded
+        };
+        let encoded = self.with_restart_markers(format, encoded).map_err(encode_failed)?;
+
+        self.trace(input, format_args!("输出 {} 字节 -> {}", encoded.len(), output.display()));
+        self.write_output(output, &encoded)?;
+        self.profiler.finish_file();
+
+        if self.options.verify_lossless && !self.options.dry_run {
+            self.verify_encoded(&img, output, format, webp_lossless)?;
+        }
+        
+        Ok(())
+    }
+
+    /// 输出一行决策跟踪，写到跟踪文件 (追加) 或标准错误
+    fn trace(&self, input: &Path, message: std::fmt::Arguments) {
+        if !self.options.trace {
+            return;
+        }
+        let line = format!("[trace] {}: {}", input.display(), message);
+        let Some(path) = &self.options.trace_file else {
+            eprintln!("{}", line);
+            return;
+        };
+        let written = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = written {
+            eprintln!("⚠️  无法写入跟踪文件 {}: {}", path.display(), e);
+        }
+    }
+
+    /// 开启 `source_quality` 且源文件是JPEG时使用估计的源质量，估计失败时保持传入的质量
+    fn effective_quality(&self, input: &Path, quality: f32) -> f32 {
+        if !self.options.source_quality
+            || self.source_format(input).as_deref() != Some("jpeg")
+        {
+            return quality;
+        }
+        jpeg_quality::estimate_file_quality(input).ok().flatten().unwrap_or(quality)
+    }
+
+    /// 按实际执行的编码校验输出：只校验无损编码，并与写入文件的像素比较
+    ///
+    /// PNM按子类型写入RGB或灰度，多尺寸ICO逐帧与缩放后的帧比较；
+    /// `webp_lossless` 是这次WebP是否按无损编码，近无损和超出预算改用的有损编码不校验
+    fn verify_encoded(&self, img: &image::DynamicImage, output: &Path, format: &str, webp_lossless: bool) -> Result<()> {
+        match format {
+            "png" | "farbfeld" | "ff" | "tga" | "jxl" => Self::verify_lossless(img, output),
+            "ico" if self.options.ico_sizes.is_empty() => Self::verify_lossless(img, output),
+            "ico" => {
+                let data = fs::read(output).with_context(|| format!("无损校验时无法读取: {}", output.display()))?;
+                for &size in &self.options.ico_sizes {
+                    let sharpen = self.options.ico_sharpen && size <= ico::SHARPEN_MAX_SIZE;
+                    let expected = image::DynamicImage::ImageRgba8(ico::square_frame(img, size, sharpen));
+                    let decoded = ico::decode_ico_bytes(&data, IcoPick::Exact(size, size))
+                        .with_context(|| format!("无损校验时无法解码 {}x{} 帧: {}", size, size, output.display()))?;
+                    Self::compare_lossless(&expected, &decoded)?;
+                }
+                Ok(())
+            }
+            "pnm" | "ppm" | "pgm" => {
+                let gray = format == "pgm" || (format == "pnm" && !img.color().has_color());
+                let expected = if gray {
+                    image::DynamicImage::ImageLuma8(img.to_luma8())
+                } else {
+                    image::DynamicImage::ImageRgb8(img.to_rgb8())
+                };
+                Self::verify_lossless(&expected, output)
+            }
+            "webp" if webp_lossless && self.options.webp_near_lossless.is_none() => {
+                let expected = if self.options.webp_no_alpha || Self::is_opaque(img) {
+                    image::DynamicImage::ImageRgb8(img.to_rgb8())
+                } else {
+                    image::DynamicImage::ImageRgba8(img.to_rgba8())
+                };
+                Self::verify_lossless(&expected, output)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 校验无损输出与编码前的图像逐像素一致
+    ///
+    /// 重新解码输出文件，统一按16位RGBA比较，因此位深或颜色类型的意外降级也会被发现
+    fn verify_lossless(expected: &image::DynamicImage, output: &Path) -> Result<()> {
+        let decoded = if utils::detect_image_format_by_content(output).as_deref() == Some("jxl") {
+            jxl::decode(output)
+        } else {
+            image::open(output).map_err(anyhow::Error::from)
+        };
+        let decoded = decoded.with_context(|| format!("无损校验时无法重新解码: {}", output.display()))?;
+        Self::compare_lossless(expected, &decoded)
+    }
+
+    /// 比较重新解码的图像与编码前的图像，报告尺寸或首个像素差异
+    fn compare_lossless(expected: &image::DynamicImage, decoded: &image::DynamicImage) -> Result<()> {
+        if decoded.dimensions() != expected.dimensions() {
+            anyhow::bail!(
+                "无损校验失败: 尺寸不一致 ({}x{} -> {}x{})",
+                expected.width(), expected.height(), decoded.width(), decoded.height()
+            );
+        }
+
+        let expected = expected.to_rgba16();
+        let decoded = decoded.to_rgba16();
+        if let Some((x, y, _)) = expected
+            .enumerate_pixels()
+            .find(|(x, y, pixel)| decoded.get_pixel(*x, *y) != *pixel)
+        {
+            anyhow::bail!("无损校验失败: 输出像素与源图像不一致，首个差异位于 ({}, {})", x, y);
+        }
+
+        Ok(())
+    }
+
+    /// JPEG到JPEG且只有旋转/翻转时，直接在DCT系数上无损完成
+    ///
+    /// 返回是否已经写出结果；尺寸不满足MCU对齐或没有需要执行的旋转时回退到常规路径
+    fn try_lossless_jpeg(&self, input: &Path, output: &Path, format: &str) -> Result<bool> {
+        if !matches!(format, "jpeg" | "jpg") || self.has_pixel_transforms() || self.options.resize_if_larger.is_some() {
+            return Ok(false);
+        }
+        if !self.options.auto_orient
+            && self.options.rotate.is_identity()
+            && self.options.normalize_orientation.is_none()
+        {
+            return Ok(false);
+        }
+        if self.source_format(input).as_deref() != Some("jpeg") {
+            return Ok(false);
+        }
+
+        let data = fs::read(input)
+            .with_context(|| format!("无法读取文件: {}", input.display()))?;
+        let mut decoder = image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(&data))
+            .with_context(|| format!("无法打开图像: {}", input.display()))?;
+        let exif_orientation = if self.options.auto_orient {
+            decoder.orientation().map(Reorient::from_orientation).unwrap_or_default()
+        } else {
+            Reorient::IDENTITY
+        };
+
+        let mut reorient = exif_orientation.then(self.options.rotate);
+        if let Some(target) = self.options.normalize_orientation {
+            let (width, height) = decoder.dimensions();
+            let (width, height) = if reorient.swaps_axes() { (height, width) } else { (width, height) };
+            reorient = reorient.then(target.correction(width, height));
+        }
+        if reorient.is_identity() {
+            return Ok(false);
+        }
+
+        // 先完整解码一次：libjpeg对截断的扫描数据只给出警告并补全，损坏的文件应当报错而不是被无损变换
+        image::DynamicImage::from_decoder(decoder)
+            .with_context(|| format!("无法打开图像: {}", input.display()))?;
+
+        match jpegtran::transform(&data, reorient)? {
+            Some(transformed) => {
+                let transformed = self.with_restart_markers(format, transformed)?;
+                self.write_output(output, &transformed)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// 指定 `--fps`、源文件是动画且目标为GIF时保留动画：按帧率重采样后逐帧应用旋转、裁剪和缩放
+    ///
+    /// 其余情况返回 `false`，按静态图像转换
+    fn try_animation(&self, input: &Path, output: &Path, format: &str) -> Result<bool> {
+        let Some(fps) = self.options.fps else {
+            return Ok(false);
+        };
+        if format != "gif" {
+            return Ok(false);
+        }
+
+        let detected = self.source_format(input).unwrap_or_default();
+        let Some(frames) = self.profiler.time(Stage::Decode, || animation::decode_frames(input, &detected))? else {
+            return Ok(false);
+        };
+        let source_count = frames.len();
+        let orientation = if self.options.auto_orient { Self::read_orientation(input) } else { Reorient::IDENTITY };
+
+        let frames: Vec<_> = self.profiler.time(Stage::Transform, || {
+            animation::resample_frames(frames, fps)
+                .into_iter()
+                .map(|frame| {
+                    let delay = frame.delay();
+                    let img = image::DynamicImage::ImageRgba8(frame.into_buffer());
+                    let mut img = self.apply_transforms(img, orientation);
+                    if let Some(threshold) = self.options.alpha_threshold {
+                        transform::threshold_alpha(&mut img, threshold);
+                    }
+                    image::Frame::from_parts(img.to_rgba8(), 0, 0, delay)
+                })
+                .collect()
+        });
+        let frame_count = frames.len();
+
+        let encoded = self
+            .profiler
+            .time(Stage::Encode, || animation::encode_gif(frames))
+            .with_context(|| format!("GIF编码失败: {}", output.display()))?;
+        self.write_output(output, &encoded)?;
+        self.profiler.finish_file();
+
+        notice!(self.options, "🎞️  动画重采样到 {} fps: {} 帧 -> {} 帧", fps, source_count, frame_count);
+        Ok(true)
+    }
+
+    /// `--resize-if-larger` 下图像未超出尺寸、格式也相同时直接复制源文件，避免重新压缩
+    ///
+    /// 只读取文件头获取尺寸；有其它改变像素或方向的设置时不走此路径
+    fn try_passthrough(&self, input: &Path, output: &Path, format: &str) -> Result<bool> {
+        let Some((max_width, max_height)) = self.options.resize_if_larger else {
+            return Ok(false);
+        };
+        let other_transforms = self.has_pixel_transforms()
+            || self.options.auto_orient
+            || !self.options.rotate.is_identity()
+            || self.options.normalize_orientation.is_some()
+            || self.options.optimize
+            || self.options.jpeg_restart.is_some()
+            || self.options.max_bytes.is_some();
+        if other_transforms {
+            return Ok(false);
+        }
+
+        let detected = self.source_format(input).unwrap_or_default();
+        if utils::canonical_format(&detected) != utils::canonical_format(format) {
+            return Ok(false);
+        }
+        let Ok((width, height)) = image::ImageReader::open(input)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(image::ImageError::from)
+            .and_then(|reader| reader.into_dimensions())
+        else {
+            return Ok(false);
+        };
+        if width > max_width || height > max_height {
+            return Ok(false);
+        }
+
+        if self.options.dry_run {
+            *lock(&self.estimated_size) = Some(fs::metadata(input)?.len());
+        } else if !utils::same_file(input, output) {
+            fs::copy(input, output).with_context(|| format!("无法复制源文件: {}", output.display()))?;
+        }
+        self.profiler.finish_file();
+        Ok(true)
+    }
+
+    /// 只缩小TIFF时按条带流式解码，避免整幅图像载入内存
+    fn try_stream_tiff(&self, input: &Path) -> Result<Option<image::DynamicImage>> {
+        let Some(resize) = self.options.resize else {
+            return Ok(None);
+        };
+        if self.options.crop.is_some()
+            || self.options.aspect.is_some()
+            || self.options.pad_square.is_some()
+            || self.options.auto_orient
+            || !self.options.rotate.is_identity()
+            || self.options.normalize_orientation.is_some()
+        {
+            return Ok(None);
+        }
+        if self.source_format(input).as_deref() != Some("tiff") {
+            return Ok(None);
+        }
+
+        tiff_stream::decode_downscaled(input, &resize)
+    }
+
+    /// 是否有旋转/翻转之外改变像素的变换
+    ///
+    /// 不含 `resize_if_larger`：它只在图像超出尺寸时缩小，由调用方按尺寸判断
+    fn has_pixel_transforms(&self) -> bool {
+        self.options.crop.is_some()
+            || self.options.deskew
+            || self.options.aspect.is_some()
+            || self.options.pad_square.is_some()
+            || self.options.resize.is_some()
+            || self.options.extract.is_some()
+            || self.options.swap_rb
+            || self.options.alpha_threshold.is_some()
+            || self.options.flatten.is_some()
+            || self.options.tone_map
+            || self.options.auto_grayscale
+            || self.options.palette_from.is_some()
+    }
+
+    /// 在编码前对解码后的图像执行变换
+    ///
+    /// 顺序为：EXIF方向校正 -> 裁剪 -> 旋转 -> 倾斜校正 -> 统一方向 -> 宽高比裁剪 -> 填充正方形 -> 按上限缩小 -> 缩放
+    fn apply_transforms(&self, img: image::DynamicImage, exif_orientation: Reorient) -> image::DynamicImage {
+        let img = match self.options.crop {
+            Some(crop) => self.options.rotate.apply(crop.apply(&exif_orientation.apply(img))),
+            None => exif_orientation.then(self.options.rotate).apply(img),
+        };
+        let img = if self.options.deskew {
+            let (img, angle) = deskew::deskew(img);
+            if angle != 0.0 {
+                notice!(self.options, "📐 倾斜校正: {:.2}°", angle);
+            }
+            img
+        } else {
+            img
+        };
+        let img = match self.options.normalize_orientation {
+            Some(target) => target.correction(img.width(), img.height()).apply(img),
+            None => img,
+        };
+
+        let img = match self.options.aspect {
+            Some(aspect) => transform::crop_to_aspect(img, aspect),
+            None => img,
+        };
+
+        let img = match self.options.pad_square {
+            Some(background) => transform::pad_to_square(img, background),
+            None => img,
+        };
+
+        let img = match self.options.resize_if_larger {
+            Some((width, height)) if img.width() > width || img.height() > height => {
+                Resize { width, height, mode: transform::FitMode::Fit }.apply(img)
+            }
+            _ => img,
+        };
+
+        match &self.options.resize {
+            Some(resize) if self.options.no_upscale && resize.would_upscale(img.width(), img.height()) => img,
+            Some(resize) => resize.apply(img),
+            None => img,
+        }
+    }
+
+    /// 解码输入图像
+    ///
+    /// ICO按帧选择策略挑选一帧，其它格式按内容识别后解码；
+    /// 开启自动方向校正时同时返回EXIF中的方向
+    fn open_image(&self, input: &Path) -> Result<(image::DynamicImage, Reorient)> {
+        if self.source_format(input).as_deref() == Some("ico") {
+            let img = ico::decode_ico(input, self.options.ico_pick)
+                .with_context(|| format!("无法打开图像: {}", input.display()))?;
+            return Ok((img, Reorient::IDENTITY));
+        }
+        if self.source_format(input).as_deref() == Some("pdf") {
+            let mut pages = pdf::render(input, Some(0), self.pdf_dpi())
+                .with_context(|| format!("无法打开图像: {}", input.display()))?;
+            return Ok((pages.remove(0), Reorient::IDENTITY));
+        }
+        // 解码器已经按JPEG XL自身的方向信息旋转
+        if self.source_format(input).as_deref() == Some("jxl") {
+            let img = jxl::decode(input).with_context(|| format!("无法打开图像: {}", input.display()))?;
+            return Ok((img, Reorient::IDENTITY));
+        }
+
+        let mut reader = image::ImageReader::open(input)
+            .and_then(|reader| reader.with_guessed_format())
+            .with_context(|| format!("无法打开图像: {}", input.display()))?;
+        // TGA没有文件头魔数，扩展名不是 .tga 时image无法自行识别；指定了输入格式时不按内容猜测
+        match self.options.input_format.as_deref() {
+            Some(format) => reader.set_format(input_image_format(format)?),
+            None if self.source_format(input).as_deref() == Some("tga") => reader.set_format(ImageFormat::Tga),
+            None => {}
+        }
+        let mut decoder = reader
+            .into_decoder()
+            .with_context(|| format!("无法打开图像: {}", input.display()))?;
+
+        let orientation = if self.options.auto_orient {
+            decoder.orientation().map(Reorient::from_orientation).unwrap_or_default()
+        } else {
+            Reorient::IDENTITY
+        };
+
+        let img = image::DynamicImage::from_decoder(decoder)
+            .with_context(|| format!("无法打开图像: {}", input.display()))?;
+        Ok((img, orientation))
+    }
+
+    /// 读取文件元数据中的方向 (JPEG、WebP和TIFF的EXIF等)，无法读取时按无需旋转处理
+    fn read_orientation(input: &Path) -> Reorient {
+        image::ImageReader::open(input)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(image::ImageError::from)
+            .and_then(|reader| reader.into_decoder())
+            .and_then(|mut decoder| decoder.orientation())
+            .map(Reorient::from_orientation)
+            .unwrap_or_default()
+    }
+
+    /// 设置了 `decode_timeout` 时在单独的线程中解码，超时后放弃等待并返回错误
+    ///
+    /// 线程中使用按相同选项新建的转换器。Rust无法从外部终止线程，超时的线程会在后台继续运行到解码结束，
+    /// 结果被丢弃；卡死在C解码器或外部命令中的线程会一直占用资源直到进程退出，这种情况需要在进程层面处理，
+    /// 例如给整个批次设置超时，重启后用 `--resume` 续传
+    fn decode_with_timeout<T: Send + 'static>(
+        &self,
+        input: &Path,
+        decode: impl FnOnce(&ImageConverter, &Path) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let Some(timeout) = self.options.decode_timeout else {
+            return decode(self, input);
+        };
+
+        let options = self.options.clone();
+        let path = input.to_path_buf();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("pixforge-decode".to_string())
+            .spawn(move || {
+                // 超时后接收端已经丢弃，发送失败时直接丢弃结果
+                let _ = sender.send(decode(&ImageConverter::with_options(options), &path));
+            })
+            .context("无法启动解码线程")?;
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                anyhow::bail!("解码超过 {} 秒，已放弃: {}", timeout.as_secs_f64(), input.display())
+            }
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("解码线程异常退出: {}", input.display()),
+        }
+    }
+
+    /// 从内存中解码图像，格式按内容识别，开启 `auto_orient` 时同样读取EXIF方向
+    fn decode_memory(&self, data: &[u8]) -> Result<(image::DynamicImage, Reorient)> {
+        let mut decoder = image::ImageReader::new(std::io::Cursor::new(data)).with_guessed_format()?.into_decoder()?;
+        let orientation = if self.options.auto_orient {
+            decoder.orientation().map(Reorient::from_orientation).unwrap_or_default()
+        } else {
+            Reorient::IDENTITY
+        };
+        Ok((image::DynamicImage::from_decoder(decoder)?, orientation))
+    }
+
+    /// 分析图像类型，同时返回采样点的平均水平变化和垂直变化；直接按尺寸判定时没有变化数据
+    fn analyze_image_type(&self, img: &image::DynamicImage) -> (ImageType, Option<(u64, u64)>) {
+        let (width, height) = (img.width(), img.height());
+
+        // 小尺寸图像通常是图标或者简单图形
+        if width <= 64 && height <= 64 {
+            return (ImageType::SimpleGraphics, None);
+        }
+
+        // 指定 --analysis-samples 时取固定数量的采样点，否则按图像尺寸采样
+        let variation = match self.options.analysis_samples {
+            Some(budget) => Self::budget_variation(img, budget),
+            None => Self::grid_variation(img),
+        };
+        let Some((avg_horizontal, avg_vertical)) = variation else {
+            return (ImageType::SimpleGraphics, None);
+        };
+
+        // 根据方向性变化选择类型
+        let image_type = if avg_horizontal < avg_vertical / 2 {
+            ImageType::HorizontalGraphics
+        } else if avg_vertical < avg_horizontal / 2 {
+            ImageType::VerticalPattern
+        } else if avg_horizontal < 10 && avg_vertical < 10 {
+            ImageType::SmoothPhoto
+        } else if avg_horizontal > 50 && avg_vertical > 50 {
+            // 高变化率的复杂内容使用自适应过滤器
+            ImageType::Mixed
+        } else {
+            ImageType::ComplexGeometry
+        };
+        (image_type, Some((avg_horizontal, avg_vertical)))
+    }
+
+    /// 按图像尺寸确定采样间隔，返回平均水平变化和垂直变化；没有可比较的像素时返回 `None`
+    fn grid_variation(img: &image::DynamicImage) -> Option<(u64, u64)> {
+        let (width, height) = (img.width(), img.height());
+        let sample_size = (width.min(height) / 4).max(10) as usize;
+        let rgba_img = img.to_rgba8();
+
+        let mut horizontal_variation = 0u64;
+        let mut vertical_variation = 0u64;
+        let mut sample_count = 0u32;
+
+        // 采样分析水平和垂直方向的变化
+        for y in (0..height).step_by((height as usize / sample_size).max(1)) {
+            for x in (1..width).step_by((width as usize / sample_size).max(1)) {
+                if let Some(current) = rgba_img.get_pixel_checked(x, y) {
+                    if let Some(left) = rgba_img.get_pixel_checked(x - 1, y) {
+                        horizontal_variation += Self::pixel_difference(current, left) as u64;
+                        sample_count += 1;
+                    }
+                }
+            }
+        }
+
+        for y in (1..height).step_by((height as usize / sample_size).max(1)) {
+            for x in (0..width).step_by((width as usize / sample_size).max(1)) {
+                if let Some(current) = rgba_img.get_pixel_checked(x, y) {
+                    if let Some(up) = rgba_img.get_pixel_checked(x, y - 1) {
+                        vertical_variation += Self::pixel_difference(current, up) as u64;
+                    }
+                }
+            }
+        }
+
+        if sample_count == 0 {
+            return None;
+        }
+        Some((horizontal_variation / sample_count as u64, vertical_variation / sample_count as u64))
+    }
+
+    /// 在整幅图像上均匀取约 `budget` 个采样点，比较每个点与左侧、上方像素的差异；
+    /// 只读取采样点附近的像素，不转换整幅图像
+    fn budget_variation(img: &image::DynamicImage, budget: usize) -> Option<(u64, u64)> {
+        let (columns, rows) = Self::budget_grid(img.width(), img.height(), budget)?;
+        let (width, height) = (img.width(), img.height());
+
+        let mut horizontal_variation = 0u64;
+        let mut vertical_variation = 0u64;
+        for row in 0..rows {
+            // 取每个网格单元的中心，坐标从1开始保证左侧和上方像素存在
+            let y = 1 + ((2 * u64::from(row) + 1) * u64::from(height - 1) / (2 * u64::from(rows))) as u32;
+            for column in 0..columns {
+                let x = 1 + ((2 * u64::from(column) + 1) * u64::from(width - 1) / (2 * u64::from(columns))) as u32;
+                let current = img.get_pixel(x, y);
+                horizontal_variation += Self::pixel_difference(&current, &img.get_pixel(x - 1, y)) as u64;
+                vertical_variation += Self::pixel_difference(&current, &img.get_pixel(x, y - 1)) as u64;
+            }
+        }
+
+        let sample_count = u64::from(columns) * u64::from(rows);
+        Some((horizontal_variation / sample_count, vertical_variation / sample_count))
+    }
+
+    /// 固定采样点数对应的网格列数和行数，按宽高比分配；单行或单列的图像无法比较两个方向
+    fn budget_grid(width: u32, height: u32, budget: usize) -> Option<(u32, u32)> {
+        if width < 2 || height < 2 {
+            return None;
+        }
+        let budget = budget.max(1) as f64;
+        let columns = ((budget * f64::from(width) / f64::from(height)).sqrt().round() as u32).clamp(1, width - 1);
+        let rows = ((budget / f64::from(columns)).round() as u32).clamp(1, height - 1);
+        Some((columns, rows))
+    }
+
+    /// 计算两个像素间的差异
+    fn pixel_difference(p1: &image::Rgba<u8>, p2: &image::Rgba<u8>) -> u32 {
+        let r_diff = (p1[0] as i32 - p2[0] as i32).unsigned_abs();
+        let g_diff = (p1[1] as i32 - p2[1] as i32).unsigned_abs();
+        let b_diff = (p1[2] as i32 - p2[2] as i32).unsigned_abs();
+        let a_diff = (p1[3] as i32 - p2[3] as i32).unsigned_abs();
+        r_diff + g_diff + b_diff + a_diff
+    }
+    
+    /// 计算WebP实际使用的质量
+    ///
+    /// 开启自适应质量时，平坦的图形降低质量，细节丰富的照片提高质量，
+    /// 调整幅度围绕用户给定的基准值
+    fn effective_webp_quality(&self, quality: f32, image_type: ImageType) -> f32 {
+        if !self.options.adaptive_quality {
+            return quality;
+        }
+
+        let offset = match image_type {
+            ImageType::SimpleGraphics => -15.0,
+            ImageType::HorizontalGraphics => -10.0,
+            ImageType::VerticalPattern | ImageType::ComplexGeometry => 0.0,
+            ImageType::SmoothPhoto => 5.0,
+            ImageType::Mixed => 10.0,
+        };
+        (quality + offset).clamp(0.0, 100.0)
+    }
+
+    /// 开启自适应质量时，按输出的像素数把质量限制在 [`SMALL_OUTPUT_QUALITY_CAPS`] 的上限以内
+    fn size_capped_quality(&self, quality: f32, width: u32, height: u32) -> f32 {
+        if !self.options.adaptive_quality {
+            return quality;
+        }
+
+        let pixels = u64::from(width) * u64::from(height);
+        SMALL_OUTPUT_QUALITY_CAPS
+            .iter()
+            .find(|&&(max_pixels, _)| pixels <= max_pixels)
+            .map_or(quality, |&(_, cap)| quality.min(cap))
+    }
+
+    /// 开启 `--auto-webp-mode` 时，平坦图形和单方向变化的图案使用无损WebP，照片和复杂内容保持有损；
+    /// 指定 `webp_near_lossless` 时总是无损
+    fn webp_lossless(&self, image_type: ImageType) -> bool {
+        if self.options.webp_near_lossless.is_some() {
+            return true;
+        }
+        self.options.auto_webp_mode
+            && matches!(
+                image_type,
+                ImageType::SimpleGraphics | ImageType::HorizontalGraphics | ImageType::VerticalPattern
+            )
+    }
+
+    /// 转换为JPEG格式
+    fn convert_to_jpeg(&self, img: &image::DynamicImage, output: &Path, quality: QualityProfile) -> Result<Vec<u8>> {
+        if let Some(subsampling) = self.options.jpeg_subsampling {
+            return Self::convert_to_jpeg_with_subsampling(img, output, quality.jpeg(), subsampling);
+        }
+
+        let mut buffer = Vec::new();
+        let encoder = JpegEncoder::new_with_quality(&mut buffer, quality.jpeg());
+        // JPEG不支持透明度；灰度源编码为单分量JPEG，不复制成三个相同的通道
+        let encoded = if img.color().has_color() {
+            img.to_rgb8().write_with_encoder(encoder)
+        } else {
+            img.to_luma8().write_with_encoder(encoder)
+        };
+        encoded.with_context(|| format!("JPEG编码失败: {}", output.display()))?;
+        
+        Ok(buffer)
+    }
+
+    /// 使用mozjpeg编码JPEG，可以指定色度抽样
+    ///
+    /// libjpeg的错误通过展开栈传递，release构建设置了 `panic = "abort"`，无法在这里捕获，
+    /// 因此编码前先检查mozjpeg会拒绝的尺寸；质量和抽样因子总在有效范围内
+    fn convert_to_jpeg_with_subsampling(
+        img: &image::DynamicImage,
+        output: &Path,
+        quality: u8,
+        subsampling: ChromaSubsampling,
+    ) -> Result<Vec<u8>> {
+        // JPEG不支持透明度；灰度源编码为单分量JPEG，没有色度可抽样
+        let grayscale = !img.color().has_color();
+        let (pixels, color_space) = if grayscale {
+            (img.to_luma8().into_raw(), mozjpeg::ColorSpace::JCS_GRAYSCALE)
+        } else {
+            (img.to_rgb8().into_raw(), mozjpeg::ColorSpace::JCS_RGB)
+        };
+
+        let (width, height) = img.dimensions();
+        if !(1..=JPEG_MAX_DIMENSION).contains(&width) || !(1..=JPEG_MAX_DIMENSION).contains(&height) {
+            anyhow::bail!(
+                "JPEG编码失败: 尺寸 {}x{} 超出JPEG支持的范围 (每边1-{}): {}",
+                width, height, JPEG_MAX_DIMENSION, output.display()
+            );
+        }
+
+        let encode = || -> std::io::Result<Vec<u8>> {
+            let mut compress = mozjpeg::Compress::new(color_space);
+            compress.set_size(width as usize, height as usize);
+            compress.set_quality(quality as f32);
+            if !grayscale {
+                let chroma = subsampling.chroma_pixel_size();
+                compress.set_chroma_sampling_pixel_sizes(chroma, chroma);
+            }
+
+            let mut started = compress.start_compress(Vec::new())?;
+            started.write_scanlines(&pixels)?;
+            started.finish()
+        };
+        encode().with_context(|| format!("JPEG编码失败: {}", output.display()))
+    }
+    
+    /// 每个通道超过8位
+    fn is_high_bit_depth(img: &image::DynamicImage) -> bool {
+        let color = img.color();
+        color.bytes_per_pixel() / color.channel_count() > 1
+    }
+
+    /// 图像没有透明通道，或者所有像素都完全不透明
+    fn is_opaque(img: &image::DynamicImage) -> bool {
+        if !img.color().has_alpha() {
+            return true;
+        }
+        match img {
+            image::DynamicImage::ImageRgba8(buffer) => buffer.pixels().all(|p| p[3] == u8::MAX),
+            image::DynamicImage::ImageLumaA8(buffer) => buffer.pixels().all(|p| p[1] == u8::MAX),
+            _ => img.to_rgba16().pixels().all(|p| p[3] == u16::MAX),
+        }
+    }
+
+    /// 转换为WebP格式（使用webp 0.3.0）
+    ///
+    /// `lossless` 为真时使用无损编码，忽略质量；指定了 `webp_near_lossless` 时无损编码前先做近无损预处理
+    fn convert_to_webp(&self, img: &image::DynamicImage, quality: QualityProfile, lossless: bool) -> Result<Vec<u8>> {
+        let (width, height) = (img.width(), img.height());
+        let quality = quality.webp();
+        let encode = |encoder: webp::Encoder| -> Result<webp::WebPMemory> {
+            match self.options.webp_near_lossless {
+                Some(level) if lossless => {
+                    let mut config = webp::WebPConfig::new().map_err(|()| anyhow::anyhow!("无法创建WebP编码配置"))?;
+                    config.lossless = 1;
+                    config.alpha_compression = 0;
+                    config.near_lossless = i32::from(level);
+                    encoder.encode_advanced(&config).map_err(|e| anyhow::anyhow!("WebP近无损编码失败: {:?}", e))
+                }
+                _ if lossless => Ok(encoder.encode_lossless()),
+                _ => Ok(encoder.encode(quality)),
+            }
+        };
+
+        // 使用webp crate进行编码，没有有效透明度时按RGB编码
+        let encoded_data = if self.options.webp_no_alpha || Self::is_opaque(img) {
+            let rgb_img = img.to_rgb8();
+            encode(webp::Encoder::from_rgb(&rgb_img, width, height))?
+        } else {
+            let rgba_img = img.to_rgba8();
+            encode(webp::Encoder::from_rgba(&rgba_img, width, height))?
+        };
+        
+        Ok(encoded_data.to_vec())
+    }
+    
+    /// 在字节预算内寻找最高质量
+    ///
+    /// 先按传入的质量编码，超出预算时在 `[最低质量, 传入质量)` 中二分查找能满足预算的
+    /// 最高整数质量。最低质量优先于预算：最低质量下仍超出预算时按最低质量写出并警告，
+    /// 而不是继续降低质量
+    fn encode_within_budget(
+        &self,
+        input: &Path,
+        budget: u64,
+        quality: f32,
+        encode: impl Fn(f32) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let data = encode(quality)?;
+        if data.len() as u64 <= budget {
+            return Ok(data);
+        }
+
+        let floor = self.options.min_quality.unwrap_or(1.0).min(quality);
+        let (mut low, mut high) = (floor.ceil() as i64, quality.ceil() as i64 - 1);
+        let mut best = None;
+        while low <= high {
+            let middle = (low + high) / 2;
+            let data = encode(middle as f32)?;
+            if data.len() as u64 <= budget {
+                best = Some(data);
+                low = middle + 1;
+            } else {
+                high = middle - 1;
+            }
+        }
+
+        match best {
+            Some(data) => Ok(data),
+            None => {
+                let data = encode(floor)?;
+                notice!(self.options, 
+                    "⚠️  最低质量 {} 下仍超出预算 ({} > {} 字节)，按最低质量写出: {}",
+                    floor, data.len(), budget, input.display()
+                );
+                Ok(data)
+            }
+        }
+    }
+
+    /// 指定 `jpeg_restart` 时给JPEG输出插入重启标记，其他格式保持原样
+    fn with_restart_markers(&self, format: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self.options.jpeg_restart {
+            Some(rows) if matches!(format, "jpeg" | "jpg") => jpegtran::set_restart_interval(&data, rows),
+            _ => Ok(data),
+        }
+    }
+
+    /// 对编码结果做格式相关的无损优化，只在结果更小时采用
+    fn optimize_encoded(format: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+        let optimized = match format {
+            "png" => Self::optimize_png(&data)?,
+            "jpeg" | "jpg" => jpegtran::optimize(&data)?,
+            _ => return Ok(data),
+        };
+        Ok(if optimized.len() < data.len() { optimized } else { data })
+    }
+
+    #[cfg(feature = "optimize")]
+    fn optimize_png(data: &[u8]) -> Result<Vec<u8>> {
+        oxipng::optimize_from_memory(data, &oxipng::Options::default()).context("PNG优化失败")
+    }
+
+    /// 未启用 `optimize` 特性时PNG保持原样
+    #[cfg(not(feature = "optimize"))]
+    fn optimize_png(data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    /// 转换为PNG格式
+    ///
+    /// 输出是确定的：编码器只写入IHDR/PLTE/tRNS/IDAT/IEND等与像素相关的块，
+    /// 不写tIME等随时间变化的块，相同输入和参数总是得到逐字节相同的文件
+    fn convert_to_png(
+        &self, 
+        img: &image::DynamicImage, 
+        output: &Path, 
+        quality: QualityProfile,
+        image_type: ImageType,
+        color_type: ColorType
+    ) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let filter_type = self.get_optimal_filter_type(image_type);
+
+        let encoder = PngEncoder::new_with_quality(
+            &mut buffer, 
+            quality.png_compression(),
+            filter_type
+        );
+
+        // 保持原有颜色类型以避免不必要的转换
+        self.encode_png_with_optimal_color_type(img, encoder, color_type)
+            .with_context(|| format!("PNG编码失败: {}", output.display()))?;
+        
+        Ok(buffer)
+    }
+
+    /// 根据图像类型选择最优过滤器
+    fn get_optimal_filter_type(&self, image_type: ImageType) -> image::codecs::png::FilterType {
+        use image::codecs::png::FilterType;
+
+        match image_type {
+            ImageType::SimpleGraphics => FilterType::NoFilter,
+            ImageType::HorizontalGraphics => FilterType::Sub,
+            ImageType::VerticalPattern => FilterType::Up,
+            ImageType::SmoothPhoto => FilterType::Avg,
+            ImageType::ComplexGeometry => FilterType::Paeth,
+            ImageType::Mixed => FilterType::Adaptive
+        }
+    }
+    
+    /// 按image内置编码器编码到内存
+    fn encode_with_format(img: &image::DynamicImage, format: ImageFormat) -> image::ImageResult<Vec<u8>> {
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut buffer, format)?;
+        Ok(buffer.into_inner())
+    }
+
+    /// 转换为GIF格式
+    fn convert_to_gif(&self, img: &image::DynamicImage, output: &Path) -> Result<Vec<u8>> {
+        if let Some(palette) = self.shared_palette()? {
+            return palette.encode_gif(img).with_context(|| format!("GIF编码失败: {}", output.display()));
+        }
+        Self::encode_with_format(img, ImageFormat::Gif)
+            .with_context(|| format!("GIF编码失败: {}", output.display()))
+    }
+    
+    /// `palette_from` 的共享调色板，参考图像只在第一次用到时解码和量化
+    fn shared_palette(&self) -> Result<Option<&Palette>> {
+        let Some(reference) = &self.options.palette_from else {
+            return Ok(None);
+        };
+        if let Some(palette) = self.palette.get() {
+            return Ok(Some(palette));
+        }
+        let img = self.decode(reference).with_context(|| format!("读取调色板参考图像失败: {}", reference.display()))?;
+        let palette = Palette::from_image(&img).with_context(|| format!("生成调色板失败: {}", reference.display()))?;
+        Ok(Some(self.palette.get_or_init(|| palette)))
+    }
+
+    /// 把图像合成到 `flatten` 的背景上，背景图像只在第一次用到时解码
+    fn flatten(&self, img: &image::DynamicImage, backdrop: &Backdrop) -> Result<image::DynamicImage> {
+        let (width, height) = (img.width(), img.height());
+        let canvas = match backdrop {
+            Backdrop::Color(color) => image::RgbaImage::from_pixel(width, height, *color),
+            Backdrop::Checker => transform::checkerboard(width, height),
+            Backdrop::Image(path, layout) => {
+                let pattern = match self.backdrop.get() {
+                    Some(pattern) => pattern,
+                    None => {
+                        let pattern = self.decode(path).with_context(|| format!("读取背景图像失败: {}", path.display()))?;
+                        self.backdrop.get_or_init(|| pattern)
+                    }
+                };
+                layout.fill(pattern, width, height)
+            }
+        };
+        Ok(transform::flatten(img, canvas))
+    }
+
+    /// 将超出ICO尺寸上限的图像缩小到256以内
+    fn fit_ico_size(img: image::DynamicImage) -> image::DynamicImage {
+        if img.width() > 256 || img.height() > 256 {
+            img.resize(256, 256, image::imageops::FilterType::Lanczos3)
+        } else {
+            img
+        }
+    }
+    
+    /// 转换为ICO格式，指定了 `ico_sizes` 时输出多尺寸ICO
+    fn convert_to_ico(&self, img: &image::DynamicImage, output: &Path) -> Result<Vec<u8>> {
+        if !self.options.ico_sizes.is_empty() {
+            return ico::encode_multi_size(img, &self.options.ico_sizes, self.options.ico_sharpen)
+                .with_context(|| format!("ICO编码失败: {}", output.display()));
+        }
+        Self::encode_with_format(img, ImageFormat::Ico)
+            .with_context(|| format!("ICO编码失败: {}", output.display()))
+    }
+    
+    /// 转换为farbfeld格式
+    ///
+    /// farbfeld只支持16位RGBA，8位图像会被无损扩展，16位图像保持原精度
+    fn convert_to_farbfeld(&self, img: &image::DynamicImage, output: &Path) -> Result<Vec<u8>> {
+        let img = image::DynamicImage::ImageRgba16(img.to_rgba16());
+        Self::encode_with_format(&img, ImageFormat::Farbfeld)
+            .with_context(|| format!("farbfeld编码失败: {}", output.display()))
+    }
+    
+    /// 转换为TGA格式
+    ///
+    /// image的编码器不写文件尾，这里追加TGA 2.0文件尾（扩展区和开发者区偏移均为0），
+    /// 使输出可以按内容识别
+    fn convert_to_tga(&self, img: &image::DynamicImage, output: &Path) -> Result<Vec<u8>> {
+        let mut data = Self::encode_with_format(img, ImageFormat::Tga)
+            .with_context(|| format!("TGA编码失败: {}", output.display()))?;
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(utils::TGA_FOOTER_SIGNATURE);
+        Ok(data)
+    }
+
+    /// 转换为PNM格式 (二进制编码)
+    ///
+    /// ppm输出RGB，pgm输出灰度，pbm按亮度阈值输出黑白；
+    /// pnm按源图像是否为灰度选择pgm或ppm
+    fn convert_to_pnm(&self, img: &image::DynamicImage, output: &Path, format: &str) -> Result<Vec<u8>> {
+        let encoding = SampleEncoding::Binary;
+        let subtype = match format {
+            "ppm" => PnmSubtype::Pixmap(encoding),
+            "pgm" => PnmSubtype::Graymap(encoding),
+            "pbm" => PnmSubtype::Bitmap(encoding),
+            _ if !img.color().has_color() => PnmSubtype::Graymap(encoding),
+            _ => PnmSubtype::Pixmap(encoding),
+        };
+
+        let (width, height) = img.dimensions();
+        let mut data = Vec::new();
+        let mut encoder = PnmEncoder::new(&mut data).with_subtype(subtype);
+        let result = match subtype {
+            PnmSubtype::Pixmap(_) => encoder.encode(img.to_rgb8().as_raw().as_slice(), width, height, ExtendedColorType::Rgb8),
+            PnmSubtype::Graymap(_) => encoder.encode(img.to_luma8().as_raw().as_slice(), width, height, ExtendedColorType::L8),
+            _ => {
+                // 每个像素一个字节的1位灰度：0为黑，1为白
+                let mut bits = img.to_luma8();
+                bits.pixels_mut().for_each(|pixel| pixel[0] = u8::from(pixel[0] >= 128));
+                encoder.encode(bits.as_raw().as_slice(), width, height, ExtendedColorType::L1)
+            }
+        };
+        result.with_context(|| format!("PNM编码失败: {}", output.display()))?;
+        Ok(data)
+    }
+
+    /// 转换为JPEG XL格式
+    ///
+    /// 需要 `jxl` 特性；当前的编码器只支持无损，未指定 `--jxl-lossless` 时报错而不是静默忽略质量
+    fn convert_to_jxl(&self, img: &image::DynamicImage, output: &Path) -> Result<Vec<u8>> {
+        if !self.options.jxl_lossless {
+            anyhow::bail!("JPEG XL目前只支持无损编码，请指定 --jxl-lossless");
+        }
+        jxl::encode_lossless(img).with_context(|| format!("JPEG XL编码失败: {}", output.display()))
+    }
+
+    /// 确定输出文件路径
+    fn determine_output_path(&self, input: &Path, output: &Path, target_format: &str) -> std::path::PathBuf {
+        if output.is_dir() {
+            let filename = utils::change_extension(input, &self.output_extension(target_format));
+            output.join(filename)
+        } else {
+            output.to_path_buf()
+        }
+    }
+    
+    /// 输出文件使用的扩展名
+    fn output_extension(&self, target_format: &str) -> String {
+        match &self.options.extension {
+            Some(extension) => extension.trim_start_matches('.').to_string(),
+            None => utils::default_extension(target_format),
+        }
+    }
+
+    /// 确保输出目录存在
+    fn ensure_output_directory(&self, output_file: &Path) -> Result<()> {
+        if let Some(parent) = output_file.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|source| PixForgeError::Io { path: parent.to_path_buf(), source })?;
+        }
+        Ok(())
+    }
+    
+    /// 使用最优颜色类型编码PNG
+    fn encode_png_with_optimal_color_type(
+        &self,
+        img: &image::DynamicImage,
+        encoder: PngEncoder<&mut Vec<u8>>,
+        color_type: ColorType
+    ) -> Result<()> {
+        match color_type {
+            ColorType::L8 => {
+                let luma_img = img.as_luma8()
+                    .cloned()
+                    .unwrap_or_else(|| img.to_luma8());
+                luma_img.write_with_encoder(encoder)?;
+            },
+            ColorType::La8 => {
+                let luma_alpha_img = img.as_luma_alpha8()
+                    .cloned()
+                    .unwrap_or_else(|| img.to_luma_alpha8());
+                luma_alpha_img.write_with_encoder(encoder)?;
+            },
+            ColorType::Rgb8 => {
+                let rgb_img = img.as_rgb8()
+                    .cloned()
+                    .unwrap_or_else(|| img.to_rgb8());
+                rgb_img.write_with_encoder(encoder)?;
+            },
+            ColorType::Rgba8 => {
+                let rgba_img = img.as_rgba8()
+                    .cloned()
+                    .unwrap_or_else(|| img.to_rgba8());
+                rgba_img.write_with_encoder(encoder)?;
+            },
+            _ => {
+                let rgba_img = img.to_rgba8();
+                rgba_img.write_with_encoder(encoder)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for ImageConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `ImageConverter` 的构建器
+///
+/// 适合库调用方反复使用同一组设置：
+/// `ImageConverter::builder().quality(90.0).resize(resize).build()`
+#[derive(Debug, Clone)]
+pub struct ImageConverterBuilder {
+    options: ConvertOptions,
+    quality: f32,
+}
+
+impl ImageConverterBuilder {
+    pub fn new() -> Self {
+        Self { options: ConvertOptions::default(), quality: DEFAULT_QUALITY }
+    }
+
+    /// 整体替换转换选项，之后的设置方法在此基础上修改
+    pub fn options(mut self, options: ConvertOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// 质量 (0-100)
+    pub fn quality(mut self, quality: f32) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// 缩放目标
+    pub fn resize(mut self, resize: Resize) -> Self {
+        self.options.resize = Some(resize);
+        self
+    }
+
+    /// 缩放前居中裁剪的宽高比
+    pub fn aspect(mut self, width: u32, height: u32) -> Self {
+        self.options.aspect = Some((width, height));
+        self
+    }
+
+    /// 源图像小于缩放目标时不放大
+    pub fn no_upscale(mut self, no_upscale: bool) -> Self {
+        self.options.no_upscale = no_upscale;
+        self
+    }
+
+    /// 旋转/翻转
+    pub fn rotate(mut self, rotate: Reorient) -> Self {
+        self.options.rotate = rotate;
+        self
+    }
+
+    /// 按EXIF方向自动旋转
+    pub fn auto_orient(mut self, auto_orient: bool) -> Self {
+        self.options.auto_orient = auto_orient;
+        self
+    }
+
+    /// 输入过滤：跳过超过该字节数的文件
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.options.max_file_size = Some(bytes);
+        self
+    }
+
+    /// 输入过滤：不检查扩展名，只按魔数识别图像
+    pub fn any_extension(mut self, any_extension: bool) -> Self {
+        self.options.any_extension = any_extension;
+        self
+    }
+
+    /// 输入过滤：只按扩展名识别图像，不读取文件头
+    pub fn trust_extensions(mut self, trust_extensions: bool) -> Self {
+        self.options.trust_extensions = trust_extensions;
+        self
+    }
+
+    pub fn build(self) -> ImageConverter {
+        ImageConverter { quality: self.quality, ..ImageConverter::with_options(self.options) }
+    }
+}
+
+impl Default for ImageConverterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 小写十六进制的SHA-256摘要，用于 `name_by_hash` 的文件名
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 锁住转换器内部的状态；持锁时发生的panic不影响之后的使用
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// 批量转换的逐文件进度：记下文件开始处理时的统计，处理完后按统计的变化得出结果
+#[derive(Debug, Default)]
+struct BatchProgress {
+    total: Option<usize>,
+    /// 已经输出的进度行数
+    index: usize,
+    /// 正在处理的文件和开始时的转换数、失败数、已写出字节数
+    current: Option<(PathBuf, u32, u32, u64)>,
+}
+
+impl BatchProgress {
+    fn start(&mut self, path: &Path, stats: &ConversionStats) {
+        self.current = Some((path.to_path_buf(), stats.converted, stats.failed, stats.written_bytes));
+    }
+
+    /// 正在处理的文件的进度，没有正在处理的文件时为 `None`
+    fn finish(&mut self, stats: &ConversionStats) -> Option<ProgressEvent> {
+        let (path, converted, failed, written_bytes) = self.current.take()?;
+        let status = if stats.converted > converted {
+            if stats.dry_run { ProgressStatus::Planned } else { ProgressStatus::Converted }
+        } else if stats.failed > failed {
+            ProgressStatus::Failed
+        } else {
+            ProgressStatus::Skipped
+        };
+        self.index += 1;
+        Some(ProgressEvent {
+            index: self.index,
+            total: self.total,
+            path: path.display().to_string(),
+            status,
+            bytes_out: stats.written_bytes - written_bytes,
+        })
+    }
+}
+
+/// 转换统计信息
+#[derive(Debug)]
+struct ConversionStats {
+    converted: u32,
+    skipped: u32,
+    /// 被输入过滤条件排除、没有尝试转换的文件
+    filtered: u32,
+    /// 按目标格式统计的转换成功数，附属设置文件可以让同一批文件输出不同格式
+    by_format: BTreeMap<String, u32>,
+    /// 预演模式，`converted` 统计的是将要转换的文件
+    dry_run: bool,
+    /// 预演时估算的输出总字节数
+    estimated_bytes: Option<u64>,
+    /// 已写出的输出总字节数
+    written_bytes: u64,
+    /// 转换失败的文件，同时计入 `skipped`
+    failed: u32,
+    /// 转换成功的输入文件总字节数
+    read_bytes: u64,
+    /// 没有转换的文件按原因的分类
+    skip_reasons: SkipBreakdown,
+}
+
+impl ConversionStats {
+    fn new(dry_run: bool) -> Self {
+        Self {
+            converted: 0,
+            skipped: 0,
+            filtered: 0,
+            by_format: BTreeMap::new(),
+            dry_run,
+            estimated_bytes: None,
+            written_bytes: 0,
+            failed: 0,
+            read_bytes: 0,
+            skip_reasons: SkipBreakdown::default(),
+        }
+    }
+    
+    fn increment_converted(&mut self, target_format: &str) {
+        self.converted += 1;
+        *self.by_format.entry(utils::canonical_format(target_format)).or_default() += 1;
+    }
+
+    /// 预演时记录一个将要转换的文件及其估算大小
+    fn increment_planned(&mut self, target_format: &str, size: Option<u64>) {
+        self.increment_converted(target_format);
+        if let Some(size) = size {
+            *self.estimated_bytes.get_or_insert(0) += size;
+        }
+    }
+    
+    fn increment_skipped(&mut self, reason: SkipReason) {
+        self.skipped += 1;
+        self.skip_reasons.record(reason);
+    }
+
+    fn increment_failed(&mut self) {
+        self.increment_skipped(SkipReason::Failed);
+        self.failed += 1;
+    }
+
+    fn increment_filtered(&mut self, reason: SkipReason) {
+        self.filtered += 1;
+        self.skip_reasons.record(reason);
+    }
+
+    fn summary_values(&self) -> SummaryValues {
+        SummaryValues {
+            converted: self.converted,
+            skipped: self.skipped,
+            failed: self.failed,
+            total: self.converted + self.skipped + self.filtered,
+            bytes_in: self.read_bytes,
+            bytes_out: self.written_bytes,
+            skip_reasons: self.skip_reasons,
+        }
+    }
+    
+    /// 打印统计摘要，指定了模板或JSON时只输出一行
+    fn print_summary(&self, options: &ConvertOptions) {
+        if options.summary_json {
+            notice!(options, "{}", serde_json::to_string(&self.summary_values()).unwrap_or_default());
+            return;
+        }
+        if let Some(template) = &options.summary_template {
+            notice!(options, "{}", template.render(&self.summary_values()));
+            return;
+        }
+        if self.dry_run {
+            notice!(options, "🔍 预演完成: {} 个文件将被转换, {} 个跳过", self.converted, self.skipped);
+        } else if self.converted == 0 && self.skipped > 0 {
+            notice!(options, "❌ 没有图片被转换。全部 {} 个文件被跳过。", self.skipped);
+        } else {
+            notice!(options, "🎉 转换完成: {} 个转换成功, {} 个跳过", self.converted, self.skipped);
+        }
+        if !self.by_format.is_empty() {
+            let breakdown: Vec<String> = self
+                .by_format
+                .iter()
+                .map(|(format, count)| format!("{} {}", format, count))
+                .collect();
+            notice!(options, "📊 按格式: {}", breakdown.join(", "));
+        }
+        if let Some(total) = self.estimated_bytes {
+            notice!(options, "📦 预计输出总大小: {} 字节", total);
+        }
+        if self.filtered > 0 {
+            notice!(options, "🔍 {} 个文件被过滤条件排除", self.filtered);
+        }
+        let reasons = self.skip_reasons.nonzero();
+        if !reasons.is_empty() {
+            let breakdown: Vec<String> = reasons.iter().map(|(reason, count)| format!("{} {}", reason, count)).collect();
+            notice!(options, "⏭️  未转换原因: {}", breakdown.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 7) as u8, (y * 5) as u8, 128, 255])
+        }))
+    }
+
+    #[test]
+    fn test_verify_lossless_accepts_identical_png() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("source.png");
+        gradient(40, 30).save(&input).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            verify_lossless: true,
+            ..Default::default()
+        });
+        let output = dir.path().join("out.png");
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+    }
+
+    #[test]
+    fn test_verify_lossless_detects_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.png");
+        let mut written = gradient(40, 30).to_rgba8();
+        written.put_pixel(12, 7, Rgba([0, 0, 0, 0]));
+        written.save(&output).unwrap();
+
+        let err = ImageConverter::verify_lossless(&gradient(40, 30), &output).unwrap_err();
+        assert!(err.to_string().contains("(12, 7)"));
+    }
+
+    #[test]
+    fn test_verify_lossless_detects_bit_depth_loss() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.png");
+        let source = DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(
+            4, 4, Rgba([0x1234u16, 0x5678, 0x9abc, 0xffff])
+        ));
+        source.to_rgba8().save(&output).unwrap();
+
+        assert!(ImageConverter::verify_lossless(&source, &output).is_err());
+    }
+
+    #[test]
+    fn test_verify_lossless_follows_actual_encode() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("source.png");
+        gradient(40, 30).save(&input).unwrap();
+        let options = ConvertOptions { verify_lossless: true, ..Default::default() };
+
+        // 多尺寸ICO逐帧与缩放后的帧比较，不与未缩放的源图像比较
+        let converter = ImageConverter::with_options(ConvertOptions { ico_sizes: vec![16, 32], ..options.clone() });
+        converter.convert_single_file(&input, &dir.path().join("icon.ico"), "ico", 80.0).unwrap();
+
+        let converter = ImageConverter::with_options(options.clone());
+        converter.convert_single_file(&input, &dir.path().join("out.ppm"), "ppm", 80.0).unwrap();
+
+        // 有损写出的WebP：按无损编码校验时发现差异，有损编码时不校验
+        let webp = dir.path().join("out.webp");
+        ImageConverter::new().convert_single_file(&input, &webp, "webp", 20.0).unwrap();
+        let img = gradient(40, 30);
+        assert!(converter.verify_encoded(&img, &webp, "webp", true).is_err());
+        converter.verify_encoded(&img, &webp, "webp", false).unwrap();
+        let near = ImageConverter::with_options(ConvertOptions { webp_near_lossless: Some(60), ..options });
+        near.verify_encoded(&img, &webp, "webp", true).unwrap();
+    }
+
+    #[test]
+    fn test_verify_output_flags_invalid_encoder_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let converter = ImageConverter::new();
+
+        // 模拟编码器写出的异常数据
+        let garbage = dir.path().join("garbage.png");
+        std::fs::write(&garbage, b"definitely not an image").unwrap();
+        let err = converter.verify_output(&garbage, "png").unwrap_err();
+        assert!(err.to_string().contains("输出校验失败"), "{}", err);
+
+        let truncated = dir.path().join("truncated.png");
+        std::fs::write(&truncated, b"\x89PNG\r\n\x1a\n\0\0").unwrap();
+        assert!(converter.verify_output(&truncated, "png").is_err());
+
+        let jpeg = dir.path().join("actually-jpeg.png");
+        gradient(8, 8).to_rgb8().save_with_format(&jpeg, ImageFormat::Jpeg).unwrap();
+        let err = converter.verify_output(&jpeg, "png").unwrap_err();
+        assert!(err.to_string().contains("检测到的格式: jpeg"), "{}", err);
+        converter.verify_output(&jpeg, "jpg").unwrap();
+
+        let unchecked = ImageConverter::with_options(ConvertOptions { no_verify_output: true, ..Default::default() });
+        unchecked.verify_output(&garbage, "png").unwrap();
+    }
+
+    #[test]
+    fn test_verify_output_accepts_every_target_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("source.png");
+        gradient(16, 16).save(&input).unwrap();
+
+        for format in TARGET_FORMATS.iter().filter(|format| **format != "jxl") {
+            let output = dir.path().join(format!("out.{}", format));
+            ImageConverter::new().convert_single_file(&input, &output, format, 80.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_failure_mentions_detected_and_target_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let broken = dir.path().join("broken.png");
+        std::fs::write(&broken, b"\x89PNG\r\n\x1a\ntruncated").unwrap();
+
+        let err = ImageConverter::new()
+            .convert_single_file(&broken, &dir.path().join("out.webp"), "WebP", 80.0)
+            .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("检测到的格式: png"), "{}", message);
+        assert!(message.contains("目标格式: webp"), "{}", message);
+    }
+
+    #[test]
+    fn test_errors_are_classified() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.png");
+        gradient(16, 16).save(&source).unwrap();
+        let output = dir.path().join("out");
+        let converter = ImageConverter::new();
+
+        let text = dir.path().join("notes.txt");
+        std::fs::write(&text, "hello").unwrap();
+        let err = converter.convert_single_file(&text, &output, "png", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::UnsupportedSourceFormat { path } if path == text));
+
+        let err = converter.convert_single_file(&source, &output, "bmp", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::UnsupportedTargetFormat(format) if format == "bmp"));
+
+        let broken = dir.path().join("broken.png");
+        std::fs::write(&broken, b"\x89PNG\r\n\x1a\ntruncated").unwrap();
+        let err = converter.convert_single_file(&broken, &output, "webp", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::DecodeFailed { detected, .. } if detected == "png"));
+        let err = converter.decode(&broken).unwrap_err();
+        assert!(matches!(err, PixForgeError::DecodeFailed { detected, .. } if detected == "png"));
+
+        // 输出目录的父路径是普通文件，无法创建
+        let blocker = dir.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let err = converter.convert_single_file(&source, &blocker.join("out.png"), "png", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::Io { .. }));
+
+        let limited = ImageConverter::builder().max_file_size(10).build();
+        let err = limited.convert_single_file(&source, &output, "png", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::TooLarge { limit: 10, size, .. } if size > 10));
+    }
+
+    #[test]
+    fn test_unclassified_error_keeps_failure_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("opaque.png");
+        image::RgbImage::new(8, 8).save(&input).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            extract: Some(Channel::Alpha),
+            ..Default::default()
+        });
+        let err = converter.convert_single_file(&input, dir.path(), "png", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::Other(_)));
+        assert!(err.to_string().contains("目标格式: png"), "{}", err);
+    }
+
+    #[test]
+    fn test_fps_downsamples_animated_gif() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("anim.gif");
+        let frames = (0..20u32).map(|i| {
+            let buffer = RgbaImage::from_pixel(16, 16, Rgba([(i * 12) as u8, 0, 0, 255]));
+            image::Frame::from_parts(buffer, 0, 0, image::Delay::from_numer_denom_ms(20, 1))
+        });
+        std::fs::write(&input, animation::encode_gif(frames.collect()).unwrap()).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            fps: Some(10.0),
+            resize: Some("8x8".parse().unwrap()),
+            ..Default::default()
+        });
+        let output = dir.path().join("out.gif");
+        converter.convert_single_file(&input, &output, "gif", 80.0).unwrap();
+
+        let frames = animation::decode_frames(&output, "gif").unwrap().unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|frame| frame.delay().numer_denom_ms() == (100, 1)));
+        assert_eq!(frames[0].buffer().dimensions(), (8, 8));
+    }
+
+    #[test]
+    fn test_ppm_round_trip_preserves_pixels() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.png");
+        let source = image::RgbImage::from_fn(24, 16, |x, y| image::Rgb([(x * 10) as u8, (y * 15) as u8, (x ^ y) as u8]));
+        source.save(&input).unwrap();
+
+        let converter = ImageConverter::new();
+        let ppm = dir.path().join("photo.ppm");
+        converter.convert_single_file(&input, &ppm, "ppm", 80.0).unwrap();
+        assert_eq!(utils::detect_image_format_by_content(&ppm).as_deref(), Some("ppm"));
+
+        let png = dir.path().join("back.png");
+        converter.convert_single_file(&ppm, &png, "png", 80.0).unwrap();
+        assert_eq!(image::open(&png).unwrap().to_rgb8(), source);
+    }
+
+    #[test]
+    fn test_pbm_thresholds_luma() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("mask.png");
+        image::GrayImage::from_fn(8, 2, |x, _| image::Luma([if x < 4 { 10 } else { 240 }])).save(&input).unwrap();
+
+        let pbm = dir.path().join("mask.pbm");
+        ImageConverter::new().convert_single_file(&input, &pbm, "pbm", 80.0).unwrap();
+        assert_eq!(utils::detect_image_format_by_content(&pbm).as_deref(), Some("pbm"));
+
+        let decoded = image::open(&pbm).unwrap().to_luma8();
+        assert_eq!(decoded.get_pixel(0, 0)[0], 0);
+        assert_eq!(decoded.get_pixel(7, 1)[0], 255);
+    }
+
+    #[test]
+    fn test_normalize_orientation_rotates_portrait() {
+        let dir = tempfile::tempdir().unwrap();
+        let portrait = dir.path().join("portrait.png");
+        let landscape = dir.path().join("landscape.png");
+        gradient(30, 50).save(&portrait).unwrap();
+        gradient(50, 30).save(&landscape).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            normalize_orientation: Some(OrientationTarget::Landscape),
+            ..Default::default()
+        });
+        let output = dir.path().join("out");
+        converter.convert_directory(dir.path(), &output, "png", 80.0).unwrap();
+
+        let rotated = image::open(output.join("portrait.png")).unwrap();
+        assert_eq!(rotated.dimensions(), (50, 30));
+        // 顺时针旋转：源图左下角转到左上角
+        assert_eq!(rotated.get_pixel(0, 0), gradient(30, 50).get_pixel(0, 49));
+        assert_eq!(image::image_dimensions(output.join("landscape.png")).unwrap(), (50, 30));
+    }
+
+    #[test]
+    fn test_normalize_orientation_lossless_jpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("tall.jpg");
+        rgb_jpeg(&input, 32, 48);
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            normalize_orientation: Some(OrientationTarget::Landscape),
+            ..Default::default()
+        });
+        let output = dir.path().join("wide.jpg");
+        converter.convert_single_file(&input, &output, "jpeg", 80.0).unwrap();
+        assert_eq!(image::image_dimensions(&output).unwrap(), (48, 32));
+    }
+
+    #[test]
+    fn test_sidecar_overrides_format_and_quality() {
+        let input = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.png", "c.png"] {
+            gradient(16, 16).save(input.path().join(name)).unwrap();
+        }
+        std::fs::write(input.path().join("b.png.pixforge"), "format=webp,quality=95\n").unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        ImageConverter::new().convert_directory(input.path(), output.path(), "jpeg", 80.0).unwrap();
+
+        let mut names: Vec<String> = std::fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a.jpg", "b.webp", "c.jpg"]);
+        assert_eq!(utils::detect_image_format_by_content(&output.path().join("b.webp")).as_deref(), Some("webp"));
+    }
+
+    #[test]
+    fn test_collapse_depth_keeps_top_levels() {
+        let input = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(input.path().join("a/b/c")).unwrap();
+        for name in ["top.png", "a/one.png", "a/b/one.png", "a/b/two.png", "a/b/c/three.png"] {
+            gradient(8, 8).save(input.path().join(name)).unwrap();
+        }
+
+        let output = tempfile::tempdir().unwrap();
+        let converter = ImageConverter::with_options(ConvertOptions {
+            collapse_depth: Some(1),
+            ..Default::default()
+        });
+        converter.convert_directory(input.path(), output.path(), "png", 80.0).unwrap();
+
+        let mut files: Vec<String> = WalkDir::new(output.path())
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.path().strip_prefix(output.path()).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["a/one.png", "a/one_1.png", "a/three.png", "a/two.png", "top.png"]);
+    }
+
+    #[test]
+    fn test_recipe_applies_to_listed_file_only() {
+        let input = tempfile::tempdir().unwrap();
+        std::fs::create_dir(input.path().join("sub")).unwrap();
+        for name in ["a.png", "b.png", "sub/c.png"] {
+            gradient(40, 30).save(input.path().join(name)).unwrap();
+        }
+        let recipe = Recipe::parse(
+            r#"{
+                "b.png": { "crop": { "x": 5, "y": 5, "width": 20, "height": 10 }, "rotate": 90 },
+                "sub/c.png": { "resize": "8x8!" },
+                "missing.png": { "rotate": 180 }
+            }"#,
+        )
+        .unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let converter = ImageConverter::with_options(ConvertOptions {
+            recipe: Some(Arc::new(recipe)),
+            resize: Some(Resize { width: 20, height: 20, mode: transform::FitMode::Fit }),
+            ..Default::default()
+        });
+        converter.convert_directory(input.path(), output.path(), "png", 80.0).unwrap();
+
+        // 未列出的文件使用命令行设置
+        assert_eq!(image::image_dimensions(output.path().join("a.png")).unwrap(), (20, 15));
+        // 裁剪后顺时针旋转，缩放沿用命令行设置
+        let b = image::open(output.path().join("b.png")).unwrap();
+        assert_eq!(b.dimensions(), (10, 20));
+        assert_eq!(b.to_rgba8().get_pixel(9, 0), gradient(40, 30).to_rgba8().get_pixel(5, 5));
+        assert_eq!(image::image_dimensions(output.path().join("c.png")).unwrap(), (8, 8));
+    }
+
+    #[test]
+    fn test_estimated_size_matches_real_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.png");
+        gradient(64, 48).save(&input).unwrap();
+        let output = dir.path().join("photo.webp");
+
+        let estimating = ImageConverter::with_options(ConvertOptions {
+            dry_run: true,
+            estimate_sizes: true,
+            ..Default::default()
+        });
+        let estimated = estimating.dry_run_file(&input, &output, "webp", 75.0).unwrap().unwrap();
+        estimating.convert_single_file(&input, &output, "webp", 75.0).unwrap();
+        assert!(!output.exists());
+
+        ImageConverter::new().convert_single_file(&input, &output, "webp", 75.0).unwrap();
+        assert_eq!(estimated, std::fs::metadata(&output).unwrap().len());
+    }
+
+    #[test]
+    fn test_dry_run_writes_nothing() {
+        let input = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.png"] {
+            gradient(8, 8).save(input.path().join(name)).unwrap();
+        }
+        let output = input.path().join("never-created");
+        let converter = ImageConverter::with_options(ConvertOptions { dry_run: true, ..Default::default() });
+
+        let stats = converter.convert_entries(input.path(), &output, "jpeg", 80.0, None, None).unwrap();
+        assert_eq!(stats.converted, 2);
+        assert!(stats.estimated_bytes.is_none());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_stats_count_each_target_format() {
+        let input = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.png", "c.png", "d.png", "e.png"] {
+            gradient(8, 8).save(input.path().join(name)).unwrap();
+        }
+        std::fs::write(input.path().join("b.png.pixforge"), "format=png").unwrap();
+        std::fs::write(input.path().join("c.png.pixforge"), "format=jpg").unwrap();
+        std::fs::write(input.path().join("d.png.pixforge"), "format=jpeg").unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let stats = ImageConverter::new()
+            .convert_entries(input.path(), output.path(), "webp", 80.0, None, None)
+            .unwrap();
+        assert_eq!(stats.converted, 5);
+        let counts: Vec<(&str, u32)> = stats.by_format.iter().map(|(format, count)| (format.as_str(), *count)).collect();
+        assert_eq!(counts, vec![("jpeg", 2), ("png", 1), ("webp", 2)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exclude_dir_is_never_visited() {
+        let input = tempfile::tempdir().unwrap();
+        for dir in ["node_modules/pkg", "photos/thumbnails", "photos/node_modules_backup"] {
+            std::fs::create_dir_all(input.path().join(dir)).unwrap();
+        }
+        for name in ["top.png", "node_modules/pkg/logo.png", "photos/thumbnails/small.png", "photos/node_modules_backup/kept.png"] {
+            gradient(8, 8).save(input.path().join(name)).unwrap();
+        }
+        std::fs::write(input.path().join("node_modules/broken.png"), b"\x89PNG\r\n\x1a\nbroken").unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let log = output.path().join("visited.log");
+        let hook = format!("echo {{input}} >> '{}'", log.display());
+        let converter = ImageConverter::with_options(ConvertOptions {
+            exclude_dirs: vec!["node_modules".to_string(), "thumbnails".to_string()],
+            on_success: Some(HookCommand::new(hook.clone())),
+            on_failure: Some(HookCommand::new(hook)),
+            ..Default::default()
+        });
+        converter.convert_directory(input.path(), &output.path().join("out"), "webp", 80.0).unwrap();
+
+        let mut visited: Vec<String> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|line| Path::new(line).strip_prefix(input.path()).unwrap().to_string_lossy().into_owned())
+            .collect();
+        visited.sort();
+        assert_eq!(visited, vec!["photos/node_modules_backup/kept.png", "top.png"]);
+    }
+
+    #[cfg(feature = "jxl")]
+    #[test]
+    fn test_jxl_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.png");
+        gradient(40, 24).save(&input).unwrap();
+
+        let jxl = dir.path().join("photo.jxl");
+        let lossy = ImageConverter::new();
+        assert!(lossy.convert_single_file(&input, &jxl, "jxl", 80.0).is_err());
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            jxl_lossless: true,
+            verify_lossless: true,
+            ..Default::default()
+        });
+        converter.convert_single_file(&input, &jxl, "jxl", 80.0).unwrap();
+        assert_eq!(utils::detect_image_format_by_content(&jxl).as_deref(), Some("jxl"));
+
+        let png = dir.path().join("back.png");
+        converter.convert_single_file(&jxl, &png, "png", 80.0).unwrap();
+        assert_eq!(image::open(&png).unwrap().to_rgba8(), gradient(40, 24).to_rgba8());
+    }
+
+    #[cfg(not(feature = "jxl"))]
+    #[test]
+    fn test_jxl_requires_feature() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.png");
+        gradient(8, 8).save(&input).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions { jxl_lossless: true, ..Default::default() });
+        let err = converter.convert_single_file(&input, dir.path(), "jxl", 80.0).unwrap_err();
+        assert!(format!("{:#}", anyhow::Error::from(err)).contains("--features jxl"));
+    }
+
+    #[cfg(not(feature = "pdf"))]
+    #[test]
+    fn test_pdf_requires_feature() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("report.pdf");
+        std::fs::write(&input, b"%PDF-1.4\n%%EOF\n").unwrap();
+
+        let err = ImageConverter::new().convert_single_file(&input, dir.path(), "png", 80.0).unwrap_err();
+        assert!(format!("{:#}", anyhow::Error::from(err)).contains("--features pdf"));
+    }
+
+    #[cfg(feature = "pdf")]
+    #[test]
+    fn test_pdf_pages_are_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("report.pdf");
+        std::fs::write(&input, crate::pdf::tests::one_page_pdf(72, 72)).unwrap();
+        let output = dir.path().join("out");
+        std::fs::create_dir(&output).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            pdf_dpi: Some(72.0),
+            pdf_all_pages: true,
+            ..Default::default()
+        });
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+        assert_eq!(image::open(output.join("report_p1.png")).unwrap().dimensions(), (72, 72));
+        assert!(!output.join("report.png").exists());
+    }
+
+    #[test]
+    fn test_max_total_bytes_stops_before_exceeding_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        for index in 0..5 {
+            gradient(16, 16).save(input.join(format!("{}.png", index))).unwrap();
+        }
+        let probe = dir.path().join("probe");
+        std::fs::create_dir(&probe).unwrap();
+        ImageConverter::new().convert_single_file(&input.join("0.png"), &probe, "webp", 80.0).unwrap();
+        let size = std::fs::metadata(probe.join("0.webp")).unwrap().len();
+
+        let output = dir.path().join("out");
+        let converter = ImageConverter::with_options(ConvertOptions {
+            max_total_bytes: Some(size * 2 + size / 2),
+            ..Default::default()
+        });
+        let stats = converter.convert_entries(&input, &output, "webp", 80.0, None, None).unwrap();
+        assert_eq!(stats.converted, 2);
+        assert_eq!(stats.written_bytes, size * 2);
+        assert_eq!(std::fs::read_dir(&output).unwrap().count(), 2);
+
+        // 恰好达到上限时不再开始新的转换
+        let exact = dir.path().join("exact");
+        let converter = ImageConverter::with_options(ConvertOptions { max_total_bytes: Some(size * 3), ..Default::default() });
+        assert_eq!(converter.convert_entries(&input, &exact, "webp", 80.0, None, None).unwrap().converted, 3);
+    }
+
+    #[test]
+    fn test_limit_caps_directory_conversion() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        for index in 0..5 {
+            gradient(8, 8).save(input.join(format!("{}.png", index))).unwrap();
+        }
+        std::fs::write(input.join("0_broken.png"), b"\x89PNG\r\n\x1a\nbroken").unwrap();
+
+        let converted = |mode: LimitMode| {
+            let output = tempfile::tempdir().unwrap();
+            let converter = ImageConverter::with_options(ConvertOptions {
+                limit: Some(2),
+                limit_mode: mode,
+                ..Default::default()
+            });
+            converter.convert_directory(&input, output.path(), "webp", 80.0).unwrap();
+
+            let mut names: Vec<String> = std::fs::read_dir(output.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // 按文件名排序后，损坏文件排在第二个被尝试
+        assert_eq!(converted(LimitMode::Converted), vec!["0.webp", "1.webp"]);
+        assert_eq!(converted(LimitMode::Attempted), vec!["0.webp"]);
+    }
+
+    #[test]
+    fn test_split_by_format_subdirectories() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir_all(input.join("nested")).unwrap();
+        gradient(8, 8).save(input.join("a.png")).unwrap();
+        gradient(8, 8).save(input.join("nested/b.png")).unwrap();
+
+        let output = dir.path().join("out");
+        let converter = ImageConverter::with_options(ConvertOptions {
+            split_by_format: true,
+            ..Default::default()
+        });
+        converter.convert_directory(&input, &output, "webp", 80.0).unwrap();
+        converter.convert_directory(&input, &output, "png", 80.0).unwrap();
+
+        for file in ["webp/a.webp", "webp/b.webp", "png/a.png", "png/b.png"] {
+            assert!(output.join(file).is_file(), "缺少 {}", file);
+        }
+        assert!(!output.join("a.webp").exists());
+
+        // 同一格式的不同写法共用一个目录
+        converter.convert_directory(&input, &output, "jpg", 80.0).unwrap();
+        converter.convert_directory(&input, &output, "JPEG", 80.0).unwrap();
+        assert!(output.join("jpeg/a.jpg").is_file());
+        let mut dirs: Vec<_> = std::fs::read_dir(&output).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        dirs.sort();
+        assert_eq!(dirs, ["jpeg", "png", "webp"]);
+    }
+
+    #[test]
+    fn test_resume_after_interrupted_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        for index in 0..4 {
+            gradient(8, 8).save(input.join(format!("{}.png", index))).unwrap();
+        }
+        let output = dir.path().join("out");
+        let state_file = dir.path().join("state.jsonl");
+
+        // 用数量上限模拟第一次运行在两个文件后被打断
+        let interrupted = ImageConverter::with_options(ConvertOptions {
+            limit: Some(2),
+            state_file: Some(state_file.clone()),
+            ..Default::default()
+        });
+        interrupted.convert_directory(&input, &output, "webp", 80.0).unwrap();
+
+        // 删除已有输出，续传时已完成的文件不应被重新生成
+        for index in 0..2 {
+            std::fs::remove_file(output.join(format!("{}.webp", index))).unwrap();
+        }
+        let resumed = ImageConverter::with_options(ConvertOptions {
+            state_file: Some(state_file.clone()),
+            resume: true,
+            ..Default::default()
+        });
+        resumed.convert_directory(&input, &output, "webp", 80.0).unwrap();
+
+        let exists = |index: usize| output.join(format!("{}.webp", index)).exists();
+        assert_eq!((0..4).map(exists).collect::<Vec<_>>(), vec![false, false, true, true]);
+
+        // 修改过的输入会被重新处理
+        gradient(16, 16).save(input.join("0.png")).unwrap();
+        resumed.convert_directory(&input, &output, "webp", 80.0).unwrap();
+        assert!(exists(0));
+        assert!(!exists(1));
+    }
+
+    #[test]
+    fn test_outputs_match_reference_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(100, 40).save(input.join("wide.png")).unwrap();
+        gradient(30, 90).save(input.join("tall.png")).unwrap();
+
+        let reference = dir.path().join("reference.png");
+        gradient(32, 24).save(&reference).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            resize: Some(Resize::from_reference(&reference, crate::transform::FitMode::Fill).unwrap()),
+            ..Default::default()
+        });
+        let output = dir.path().join("out");
+        converter.convert_directory(&input, &output, "png", 80.0).unwrap();
+
+        for name in ["wide.png", "tall.png"] {
+            assert_eq!(image::image_dimensions(output.join(name)).unwrap(), (32, 24));
+        }
+    }
+
+    #[test]
+    fn test_aspect_crop_then_resize() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("wide.png");
+        gradient(400, 200).save(&input).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            aspect: Some((1, 1)),
+            resize: Some("64x64".parse().unwrap()),
+            ..Default::default()
+        });
+        let output = dir.path().join("thumb.png");
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+
+        assert_eq!(image::image_dimensions(&output).unwrap(), (64, 64));
+    }
+
+    #[test]
+    fn test_no_upscale_keeps_small_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("small.png");
+        let large = dir.path().join("large.png");
+        gradient(100, 100).save(&small).unwrap();
+        gradient(1000, 800).save(&large).unwrap();
+
+        // 即使使用 ! 强制精确尺寸，也不放大
+        let converter = ImageConverter::with_options(ConvertOptions {
+            resize: Some("500x500!".parse().unwrap()),
+            no_upscale: true,
+            ..Default::default()
+        });
+
+        let output = dir.path().join("small_out.png");
+        converter.convert_single_file(&small, &output, "png", 80.0).unwrap();
+        assert_eq!(image::image_dimensions(&output).unwrap(), (100, 100));
+
+        let output = dir.path().join("large_out.png");
+        converter.convert_single_file(&large, &output, "png", 80.0).unwrap();
+        assert_eq!(image::image_dimensions(&output).unwrap(), (500, 500));
+    }
+
+    fn rgb_jpeg(path: &Path, width: u32, height: u32) {
+        let img = image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 3) as u8, (y * 3) as u8, ((x * y) % 251) as u8])
+        });
+        img.save(path).unwrap();
+    }
+
+    #[test]
+    fn test_jpeg_rotation_uses_lossless_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.jpg");
+        rgb_jpeg(&input, 64, 48);
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            rotate: Reorient::from_degrees(90).unwrap(),
+            ..Default::default()
+        });
+        let output = dir.path().join("rotated.jpg");
+        // 无损路径不使用质量参数，极低的质量也不会影响画质
+        converter.convert_single_file(&input, &output, "jpeg", 1.0).unwrap();
+
+        let expected = image::open(&input).unwrap().rotate90().to_rgb8();
+        let actual = image::open(&output).unwrap().to_rgb8();
+        assert_eq!(actual.dimensions(), (48, 64));
+        let diff: u64 = expected
+            .as_raw()
+            .iter()
+            .zip(actual.as_raw())
+            .map(|(a, b)| (*a as i32 - *b as i32).unsigned_abs() as u64)
+            .sum();
+        assert!((diff as f64 / expected.as_raw().len() as f64) < 1.0);
+    }
+
+    #[test]
+    fn test_jpeg_rotation_falls_back_when_unaligned() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.jpg");
+        rgb_jpeg(&input, 50, 30);
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            rotate: Reorient::from_degrees(270).unwrap(),
+            ..Default::default()
+        });
+        let output = dir.path().join("rotated.jpg");
+        converter.convert_single_file(&input, &output, "jpeg", 90.0).unwrap();
+
+        assert_eq!(image::image_dimensions(&output).unwrap(), (30, 50));
+    }
+
+    #[test]
+    fn test_jpeg_subsampling_tradeoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("text.png");
+        // 白底上的红蓝细笔画，模拟彩色文字
+        let source = image::RgbImage::from_fn(128, 64, |x, y| match (x % 4, y % 8) {
+            (0, _) => image::Rgb([220, 0, 0]),
+            (_, 0) => image::Rgb([0, 0, 220]),
+            _ => image::Rgb([255, 255, 255]),
+        });
+        source.save(&input).unwrap();
+
+        let encode = |subsampling: ChromaSubsampling| {
+            let output = dir.path().join(format!("{:?}.jpg", subsampling));
+            let converter = ImageConverter::with_options(ConvertOptions {
+                jpeg_subsampling: Some(subsampling),
+                ..Default::default()
+            });
+            converter.convert_single_file(&input, &output, "jpeg", 90.0).unwrap();
+
+            let decoded = image::open(&output).unwrap().to_rgb8();
+            let error: u64 = decoded
+                .as_raw()
+                .iter()
+                .zip(source.as_raw())
+                .map(|(a, b)| (*a as i32 - *b as i32).unsigned_abs() as u64)
+                .sum();
+            (std::fs::metadata(&output).unwrap().len(), error)
+        };
+
+        let (full_size, full_error) = encode(ChromaSubsampling::Yuv444);
+        let (half_size, half_error) = encode(ChromaSubsampling::Yuv420);
+        assert!(full_size > half_size, "4:4:4 {} 字节, 4:2:0 {} 字节", full_size, half_size);
+        assert!(full_error < half_error, "4:4:4 误差 {}, 4:2:0 误差 {}", full_error, half_error);
+    }
+
+    #[test]
+    fn test_jpeg_subsampling_rejects_oversized_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("strip.png");
+        image::GrayImage::new(JPEG_MAX_DIMENSION + 1, 1).save(&input).unwrap();
+
+        // 超出libjpeg限制的尺寸在调用mozjpeg之前报错，不会因为 panic = "abort" 终止进程
+        let options = ConvertOptions { jpeg_subsampling: Some(ChromaSubsampling::Yuv444), ..Default::default() };
+        let converter = ImageConverter::with_options(options);
+        let err = converter.convert_single_file(&input, &dir.path().join("strip.jpg"), "jpeg", 80.0).unwrap_err();
+        let source = std::error::Error::source(&err).map(ToString::to_string).unwrap_or_default();
+        assert!(matches!(err, PixForgeError::EncodeFailed { .. }) && source.contains("65501x1"), "{}", source);
+    }
+
+    #[test]
+    fn test_chroma_subsampling_from_str() {
+        assert_eq!("444".parse::<ChromaSubsampling>(), Ok(ChromaSubsampling::Yuv444));
+        assert_eq!("4:2:0".parse::<ChromaSubsampling>(), Ok(ChromaSubsampling::Yuv420));
+        assert!("411".parse::<ChromaSubsampling>().is_err());
+    }
+
+    /// 噪声图像：PNG压缩几乎无效，用最低压缩级别保存后重新编码会变大
+    fn write_noise_png(path: &Path) {
+        let mut state = 12345u32;
+        let img = image::RgbImage::from_fn(64, 64, |_, _| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let [a, b, c, _] = state.to_le_bytes();
+            image::Rgb([a, b, c])
+        });
+        let file = std::fs::File::create(path).unwrap();
+        let encoder = PngEncoder::new_with_quality(
+            file,
+            image::codecs::png::CompressionType::Fast,
+            image::codecs::png::FilterType::NoFilter,
+        );
+        img.write_with_encoder(encoder).unwrap();
+    }
+
+    #[test]
+    fn test_skip_larger_keeps_or_skips_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("noise.png");
+        write_noise_png(&input);
+
+        // 作为对照，正常转换确实会变大
+        let plain = dir.path().join("plain.jpg");
+        ImageConverter::new().convert_single_file(&input, &plain, "jpeg", 100.0).unwrap();
+        assert!(std::fs::metadata(&plain).unwrap().len() > std::fs::metadata(&input).unwrap().len());
+
+        let convert = |action: LargerAction, output: &Path| {
+            let converter = ImageConverter::with_options(ConvertOptions {
+                skip_larger: Some(action),
+                ..Default::default()
+            });
+            converter.convert_single_file(&input, output, "jpeg", 100.0).unwrap();
+        };
+
+        // 格式不同时复制源文件并保留其扩展名
+        convert(LargerAction::Keep, &dir.path().join("kept.jpg"));
+        assert!(!dir.path().join("kept.jpg").exists());
+        assert_eq!(std::fs::read(dir.path().join("kept.png")).unwrap(), std::fs::read(&input).unwrap());
+
+        convert(LargerAction::Skip, &dir.path().join("skipped.jpg"));
+        assert!(!dir.path().join("skipped.jpg").exists());
+        assert!(!dir.path().join("skipped.png").exists());
+
+        // 不会留下临时文件
+        let leftovers = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with("pixforge-tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn test_skip_larger_writes_smaller_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("noise.png");
+        write_noise_png(&input);
+
+        let output = dir.path().join("small.jpg");
+        let converter = ImageConverter::with_options(ConvertOptions {
+            skip_larger: Some(LargerAction::Skip),
+            ..Default::default()
+        });
+        converter.convert_single_file(&input, &output, "jpeg", 30.0).unwrap();
+        assert!(std::fs::metadata(&output).unwrap().len() < std::fs::metadata(&input).unwrap().len());
+    }
+
+    #[test]
+    fn test_concurrent_workers_leave_no_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("noise.png");
+        write_noise_png(&input);
+        let output_dir = dir.path().join("out");
+        std::fs::create_dir(&output_dir).unwrap();
+
+        // 每个线程有自己的转换器，所有线程写入同一目录，并且都写同一个 shared.jpg
+        let workers: Vec<_> = (0..8)
+            .map(|worker| {
+                let (input, output_dir) = (input.clone(), output_dir.clone());
+                std::thread::spawn(move || {
+                    let converter = ImageConverter::with_options(ConvertOptions {
+                        skip_larger: Some(LargerAction::Skip),
+                        ..Default::default()
+                    });
+                    for index in 0..4 {
+                        let output = output_dir.join(format!("{}_{}.jpg", worker, index));
+                        converter.convert_single_file(&input, &output, "jpeg", 30.0).unwrap();
+                        converter.convert_single_file(&input, &output_dir.join("shared.jpg"), "jpeg", 30.0).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let names: Vec<String> = std::fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 8 * 4 + 1, "{:?}", names);
+        assert!(names.iter().all(|name| !utils::is_temp_file(Path::new(name))), "{:?}", names);
+        assert!(image::open(output_dir.join("shared.jpg")).is_ok());
+    }
+
+    #[test]
+    fn test_clean_temp_removes_leftovers_before_batch() {
+        let input = tempfile::tempdir().unwrap();
+        gradient(8, 8).save(input.path().join("a.png")).unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let leftover = utils::temp_path(&output.path().join("a.png"));
+        std::fs::write(&leftover, b"partial").unwrap();
+        let last_week = std::time::SystemTime::now() - Duration::from_secs(7 * 24 * 60 * 60);
+        std::fs::File::options().write(true).open(&leftover).unwrap().set_modified(last_week).unwrap();
+        // 另一个进程正在写入的临时文件
+        let live = utils::temp_path(&output.path().join("b.png"));
+        std::fs::write(&live, b"writing").unwrap();
+
+        ImageConverter::new().convert_directory(input.path(), output.path(), "png", 80.0).unwrap();
+        assert!(leftover.exists());
+
+        let converter = ImageConverter::with_options(ConvertOptions { clean_temp: true, ..Default::default() });
+        converter.convert_directory(input.path(), output.path(), "png", 80.0).unwrap();
+        assert!(!leftover.exists());
+        assert!(live.exists());
+        assert!(output.path().join("a.png").exists());
+    }
+
+    #[test]
+    fn test_auto_webp_mode_picks_lossless_for_graphics() {
+        let dir = tempfile::tempdir().unwrap();
+        let logo = dir.path().join("logo.png");
+        image::RgbImage::from_fn(64, 64, |x, y| {
+            if (16..48).contains(&x) && (16..48).contains(&y) { image::Rgb([200, 30, 30]) } else { image::Rgb([255; 3]) }
+        })
+        .save(&logo)
+        .unwrap();
+        let photo = dir.path().join("photo.png");
+        let mut state = 7u32;
+        image::RgbImage::from_fn(160, 120, |_, _| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            image::Rgb([(state >> 16) as u8, (state >> 8) as u8, (state >> 24) as u8])
+        })
+        .save(&photo)
+        .unwrap();
+        let output = dir.path().join("out");
+        std::fs::create_dir(&output).unwrap();
+
+        // WebP文件头之后的第一个块：无损为 VP8L，有损为 "VP8 "
+        let chunk = |name: &str| std::fs::read(output.join(name)).unwrap()[12..16].to_vec();
+        let converter = ImageConverter::with_options(ConvertOptions { auto_webp_mode: true, ..Default::default() });
+        assert_eq!(converter.analyze_image_type(&image::open(&logo).unwrap()).0, ImageType::SimpleGraphics);
+        converter.convert_single_file(&logo, &output, "webp", 80.0).unwrap();
+        assert_eq!(chunk("logo.webp"), b"VP8L");
+        assert_eq!(image::open(output.join("logo.webp")).unwrap().to_rgb8(), image::open(&logo).unwrap().to_rgb8());
+
+        assert_eq!(converter.analyze_image_type(&image::open(&photo).unwrap()).0, ImageType::Mixed);
+        converter.convert_single_file(&photo, &output, "webp", 80.0).unwrap();
+        assert_eq!(chunk("photo.webp"), b"VP8 ");
+
+        // 未开启时图形也按有损编码
+        ImageConverter::new().convert_single_file(&logo, &output, "webp", 80.0).unwrap();
+        assert_eq!(chunk("logo.webp"), b"VP8 ");
+    }
+
+    #[test]
+    fn test_opaque_webp_has_no_alpha() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("opaque.png");
+        // RGBA存储但完全不透明
+        gradient(64, 64).save(&input).unwrap();
+
+        let output = dir.path().join("opaque.webp");
+        ImageConverter::new().convert_single_file(&input, &output, "webp", 80.0).unwrap();
+        assert!(!image::open(&output).unwrap().color().has_alpha());
+
+        let rgba = gradient(64, 64).to_rgba8();
+        let forced = webp::Encoder::from_rgba(&rgba, 64, 64).encode(80.0);
+        assert!(std::fs::metadata(&output).unwrap().len() <= forced.len() as u64);
+    }
+
+    #[test]
+    fn test_webp_no_alpha_drops_translucency() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("translucent.png");
+        RgbaImage::from_fn(64, 64, |x, y| Rgba([(x * 3) as u8, (y * 3) as u8, 90, ((x * y) % 256) as u8]))
+            .save(&input)
+            .unwrap();
+
+        let with_alpha = dir.path().join("with_alpha.webp");
+        ImageConverter::new().convert_single_file(&input, &with_alpha, "webp", 80.0).unwrap();
+        assert!(image::open(&with_alpha).unwrap().color().has_alpha());
+
+        let without_alpha = dir.path().join("without_alpha.webp");
+        let converter = ImageConverter::with_options(ConvertOptions {
+            webp_no_alpha: true,
+            ..Default::default()
+        });
+        converter.convert_single_file(&input, &without_alpha, "webp", 80.0).unwrap();
+        assert!(!image::open(&without_alpha).unwrap().color().has_alpha());
+        assert!(std::fs::metadata(&without_alpha).unwrap().len() < std::fs::metadata(&with_alpha).unwrap().len());
+    }
+
+    #[test]
+    fn test_profile_accumulates_stages() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        for index in 0..3 {
+            gradient(32, 32).save(input.join(format!("{}.png", index))).unwrap();
+        }
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            profile: true,
+            ..Default::default()
+        });
+        converter.convert_directory(&input, &dir.path().join("out"), "webp", 80.0).unwrap();
+
+        let report = converter.profiler.report().unwrap();
+        assert!(report.contains("3 个文件"), "{}", report);
+        for stage in ["解码", "分析", "编码", "写入"] {
+            assert!(report.contains(stage), "{}", report);
+        }
+
+        // 同一个转换器再次转换时重新计数，不累计上一次的结果
+        converter.convert_single_file(&input.join("0.png"), &dir.path().join("single.webp"), "webp", 80.0).unwrap();
+        assert!(converter.profiler.report().unwrap().contains("1 个文件"));
+        assert!(ImageConverter::new().profiler.report().is_none());
+    }
+
+    /// 4x4的DXT1纹理，整块为纯红色
+    fn red_dds() -> Vec<u8> {
+        let mut data = b"DDS ".to_vec();
+        for value in [124u32, 0x1007, 4, 4, 8, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0; 44]);
+        data.extend_from_slice(&32u32.to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(b"DXT1");
+        data.extend_from_slice(&[0; 20]);
+        data.extend_from_slice(&0x1000u32.to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        // color0 = 红 (RGB565)，color1 = 蓝，索引全为0
+        data.extend_from_slice(&[0x00, 0xF8, 0x1F, 0x00, 0, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_dds_texture_to_png() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("texture.dds");
+        std::fs::write(&input, red_dds()).unwrap();
+        assert_eq!(utils::detect_image_format_by_content(&input).as_deref(), Some("dds"));
+
+        let output = dir.path().join("texture.png");
+        ImageConverter::new().convert_single_file(&input, &output, "png", 80.0).unwrap();
+        let img = image::open(&output).unwrap().to_rgba8();
+        assert_eq!(img.dimensions(), (4, 4));
+        assert!(img.pixels().all(|pixel| *pixel == Rgba([255, 0, 0, 255])));
+    }
+
+    #[test]
+    fn test_tga_round_trip_is_detected_by_footer() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("sprite.png");
+        gradient(12, 9).save(&source).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions { verify_lossless: true, ..Default::default() });
+        let tga = dir.path().join("sprite.tga");
+        converter.convert_single_file(&source, &tga, "tga", 80.0).unwrap();
+
+        // 去掉扩展名后仍能按文件尾识别并解码
+        let renamed = dir.path().join("sprite.bin");
+        std::fs::rename(&tga, &renamed).unwrap();
+        assert_eq!(utils::detect_image_format_by_content(&renamed).as_deref(), Some("tga"));
+
+        let any = ImageConverter::with_options(ConvertOptions { any_extension: true, ..Default::default() });
+        let back = dir.path().join("back.png");
+        any.convert_single_file(&renamed, &back, "png", 80.0).unwrap();
+        assert_eq!(image::open(&back).unwrap().to_rgba8(), gradient(12, 9).to_rgba8());
+    }
+
+    #[test]
+    fn test_input_format_overrides_detection() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("sprite.png");
+        gradient(12, 9).save(&source).unwrap();
+        let tga = dir.path().join("sprite.tga");
+        ImageConverter::new().convert_single_file(&source, &tga, "tga", 80.0).unwrap();
+
+        // 去掉TGA 2.0的文件尾后只剩没有魔数的旧式TGA，换了扩展名就无法识别
+        let mut data = std::fs::read(&tga).unwrap();
+        data.truncate(data.len() - 26);
+        let headerless = dir.path().join("sprite.bin");
+        std::fs::write(&headerless, data).unwrap();
+        assert_eq!(utils::detect_image_format_by_content(&headerless), None);
+        let back = dir.path().join("back.png");
+        let any = ImageConverter::with_options(ConvertOptions { any_extension: true, ..Default::default() });
+        assert!(matches!(
+            any.convert_single_file(&headerless, &back, "png", 80.0),
+            Err(PixForgeError::UnsupportedSourceFormat { .. })
+        ));
+
+        let forced = ImageConverter::with_options(ConvertOptions {
+            input_format: Some("tga".to_string()),
+            ..Default::default()
+        });
+        forced.convert_single_file(&headerless, &back, "png", 80.0).unwrap();
+        assert_eq!(image::open(&back).unwrap().to_rgba8(), gradient(12, 9).to_rgba8());
+
+        for format in INPUT_FORMATS.iter().filter(|format| !matches!(**format, "ico" | "jxl" | "pdf")) {
+            assert!(input_image_format(format).is_ok(), "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_in_place_deletes_or_backs_up_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.png");
+        let second = dir.path().join("second.png");
+        gradient(8, 8).save(&first).unwrap();
+        gradient(8, 8).save(&second).unwrap();
+        let original = std::fs::read(&second).unwrap();
+
+        let delete = ImageConverter::with_options(ConvertOptions { in_place: true, ..Default::default() });
+        delete.convert_single_file(&first, Path::new("ignored"), "webp", 80.0).unwrap();
+        assert!(!first.exists());
+        assert!(dir.path().join("first.webp").exists());
+
+        let backup = ImageConverter::with_options(ConvertOptions { in_place: true, backup: true, ..Default::default() });
+        backup.convert_single_file(&second, Path::new("ignored"), "webp", 80.0).unwrap();
+        assert!(!second.exists());
+        assert!(dir.path().join("second.webp").exists());
+        assert_eq!(std::fs::read(dir.path().join("second.png.bak")).unwrap(), original);
+        assert!(!Path::new("ignored").exists());
+    }
+
+    #[test]
+    fn test_in_place_requires_force_when_nothing_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        gradient(8, 8).save(dir.path().join("a.png")).unwrap();
+        let original = std::fs::read(dir.path().join("a.png")).unwrap();
+
+        let options = ConvertOptions { in_place: true, backup: true, ..Default::default() };
+        let converter = ImageConverter::with_options(options.clone());
+        converter.convert_directory(dir.path(), Path::new("ignored"), "png", 10.0).unwrap();
+        assert_eq!(std::fs::read(dir.path().join("a.png")).unwrap(), original);
+        assert!(!dir.path().join("a.png.bak").exists());
+
+        // --force 时覆盖源文件，备份保留原始内容
+        let forced = ImageConverter::with_options(ConvertOptions { force: true, ..options });
+        forced.convert_directory(dir.path(), Path::new("ignored"), "png", 10.0).unwrap();
+        assert_eq!(std::fs::read(dir.path().join("a.png.bak")).unwrap(), original);
+        assert_eq!(image::open(dir.path().join("a.png")).unwrap().to_rgba8(), gradient(8, 8).to_rgba8());
+        assert!(!Path::new("ignored").exists());
+    }
+
+    #[test]
+    fn test_builder_applies_configured_defaults() {
+        let input = tempfile::tempdir().unwrap();
+        gradient(40, 20).save(input.path().join("wide.png")).unwrap();
+        std::fs::write(input.path().join("padding.png"), vec![0u8; 4096]).unwrap();
+
+        let converter = ImageConverter::builder()
+            .quality(95.0)
+            .aspect(1, 1)
+            .resize(Resize { width: 10, height: 10, mode: transform::FitMode::Fit })
+            .max_file_size(2048)
+            .build();
+        assert_eq!(converter.quality(), 95.0);
+        assert_eq!(converter.options().max_file_size, Some(2048));
+
+        let output = tempfile::tempdir().unwrap();
+        converter.convert_dir(input.path(), output.path(), "png").unwrap();
+        assert_eq!(image::open(output.path().join("wide.png")).unwrap().dimensions(), (10, 10));
+        // 超过大小上限的无效文件被过滤，没有产生输出
+        assert!(!output.path().join("padding.png").exists());
+
+        let single = output.path().join("single.jpg");
+        converter.convert_file(&input.path().join("wide.png"), &single, "jpeg").unwrap();
+        assert_eq!(image::open(&single).unwrap().dimensions(), (10, 10));
+    }
+
+    #[test]
+    fn test_builder_defaults_match_new() {
+        let converter = ImageConverterBuilder::default().build();
+        assert_eq!(converter.quality(), DEFAULT_QUALITY);
+        assert!(converter.options().resize.is_none());
+    }
+
+    #[test]
+    fn test_jpeg_output_extension() {
+        let input = tempfile::tempdir().unwrap();
+        gradient(8, 8).save(input.path().join("photo.png")).unwrap();
+
+        let output_name = |target_format: &str, extension: Option<&str>| {
+            let output = tempfile::tempdir().unwrap();
+            let converter = ImageConverter::with_options(ConvertOptions {
+                extension: extension.map(str::to_string),
+                ..Default::default()
+            });
+            converter
+                .convert_single_file(&input.path().join("photo.png"), output.path(), target_format, 80.0)
+                .unwrap();
+            let entry = std::fs::read_dir(output.path()).unwrap().next().unwrap().unwrap();
+            let detected = utils::detect_image_format_by_content(&entry.path()).unwrap();
+            assert_eq!(detected, utils::canonical_format(target_format));
+            entry.file_name().to_string_lossy().into_owned()
+        };
+
+        assert_eq!(output_name("jpeg", None), "photo.jpg");
+        assert_eq!(output_name("jpg", None), "photo.jpg");
+        assert_eq!(output_name("jpeg", Some("jpeg")), "photo.jpeg");
+        assert_eq!(output_name("webp", None), "photo.webp");
+    }
+
+    #[test]
+    fn test_skip_larger_than_filters_big_inputs() {
+        let input = tempfile::tempdir().unwrap();
+        gradient(8, 8).save(input.path().join("small.png")).unwrap();
+        // 末尾补零到8MB的有效PNG，不过滤时可以正常转换
+        let big = input.path().join("big.png");
+        gradient(8, 8).save(&big).unwrap();
+        std::fs::OpenOptions::new().write(true).open(&big).unwrap().set_len(8 << 20).unwrap();
+
+        let converted = |max_file_size: Option<u64>| {
+            let output = tempfile::tempdir().unwrap();
+            let converter = ImageConverter::with_options(ConvertOptions { max_file_size, ..Default::default() });
+            converter.convert_directory(input.path(), output.path(), "jpeg", 80.0).unwrap();
+            let mut names: Vec<String> = std::fs::read_dir(output.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(converted(None), ["big.jpg", "small.jpg"]);
+        assert_eq!(converted(Some(1 << 20)), ["small.jpg"]);
+    }
+
+    #[test]
+    fn test_skip_reasons_breakdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(8, 8).save(input.join("a.png")).unwrap();
+        // 内容相同，按哈希命名时第二个的输出已存在
+        gradient(8, 8).save(input.join("b.png")).unwrap();
+        let big = input.join("big.png");
+        gradient(8, 8).save(&big).unwrap();
+        std::fs::OpenOptions::new().write(true).open(&big).unwrap().set_len(2 << 20).unwrap();
+        let mut broken = std::fs::read(input.join("a.png")).unwrap();
+        broken.truncate(40);
+        std::fs::write(input.join("broken.png"), broken).unwrap();
+        std::fs::write(input.join("notes.txt"), "not an image").unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            name_by_hash: true,
+            max_file_size: Some(1 << 20),
+            ..Default::default()
+        });
+        let stats = converter
+            .convert_entries(&input, &dir.path().join("out"), "png", 80.0, None, None)
+            .unwrap();
+        assert_eq!((stats.converted, stats.skipped, stats.failed, stats.filtered), (1, 2, 1, 1));
+        let expected = SkipBreakdown { not_an_image: 1, already_exists: 1, too_large: 1, filtered: 0, failed: 1 };
+        assert_eq!(stats.skip_reasons, expected);
+
+        let values = stats.summary_values();
+        assert_eq!(values.skip_reasons, expected);
+        let json = serde_json::to_value(values).unwrap();
+        assert_eq!(json["skip_reasons"]["already_exists"], 1);
+        assert_eq!(json["skip_reasons"]["not_an_image"], 1);
+
+        // 压缩包中路径不安全的条目被过滤，不是图像的条目单独统计
+        let png = std::fs::read(input.join("a.png")).unwrap();
+        let archive = dir.path().join("batch.zip");
+        crate::archive::tests::write_zip(
+            &archive,
+            &[("a.png", &png), ("../evil.png", &png), ("notes.txt", b"not an image")],
+        );
+        let stats = ImageConverter::new()
+            .convert_archive_entries(&archive, &dir.path().join("unpacked"), "png", 80.0, None)
+            .unwrap();
+        assert_eq!(stats.skip_reasons, SkipBreakdown { not_an_image: 1, filtered: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn test_reject_format_blocks_bmp() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(8, 8).save(input.join("a.png")).unwrap();
+        // 扩展名是别名也按内容识别
+        gradient(8, 8).save_with_format(input.join("legacy.dib"), ImageFormat::Bmp).unwrap();
+        let output = dir.path().join("out");
+
+        let options = ConvertOptions { reject_formats: vec!["BMP".to_string()], ..Default::default() };
+        let converter = ImageConverter::with_options(options.clone());
+        let err = converter.convert_single_file(&input.join("legacy.dib"), &output, "png", 80.0).unwrap_err();
+        assert!(matches!(&err, PixForgeError::RejectedFormat { format, .. } if format == "bmp"), "{:?}", err);
+
+        let stats = converter.convert_entries(&input, &output, "png", 80.0, None, None).unwrap();
+        assert_eq!((stats.converted, stats.skipped), (1, 1));
+        assert!(!output.join("legacy.png").exists());
+
+        let fail_fast = ImageConverter::with_options(ConvertOptions { fail_fast: true, ..options });
+        let err = fail_fast.convert_directory(&input, &dir.path().join("strict"), "png", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::RejectedFormat { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn test_allow_input_rejects_gif() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(8, 8).save(input.join("a.png")).unwrap();
+        gradient(8, 8).to_rgb8().save(input.join("b.jpg")).unwrap();
+        gradient(8, 8).save(input.join("anim.gif")).unwrap();
+        let output = dir.path().join("out");
+
+        let options = ConvertOptions { allow_formats: vec!["png".to_string(), "JPG".to_string()], ..Default::default() };
+        let converter = ImageConverter::with_options(options.clone());
+        let err = converter.convert_single_file(&input.join("anim.gif"), &output, "png", 80.0).unwrap_err();
+        assert!(matches!(&err, PixForgeError::DisallowedFormat { format, .. } if format == "gif"), "{:?}", err);
+        assert!(err.to_string().contains("png, JPG"), "{}", err);
+
+        let stats = converter.convert_entries(&input, &output, "png", 80.0, None, None).unwrap();
+        assert_eq!((stats.converted, stats.skipped, stats.failed), (2, 1, 1));
+        assert!(!output.join("anim.png").exists());
+
+        let skipping = ImageConverter::with_options(ConvertOptions { skip_disallowed: true, ..options.clone() });
+        let stats = skipping.convert_entries(&input, &dir.path().join("skipped"), "png", 80.0, None, None).unwrap();
+        assert_eq!((stats.converted, stats.skipped, stats.failed), (2, 1, 0));
+        assert_eq!(stats.skip_reasons, SkipBreakdown { filtered: 1, ..Default::default() });
+
+        // 压缩包中的条目同样按内容检查
+        let gif = std::fs::read(input.join("anim.gif")).unwrap();
+        let png = std::fs::read(input.join("a.png")).unwrap();
+        let archive = dir.path().join("batch.zip");
+        crate::archive::tests::write_zip(&archive, &[("a.png", &png), ("anim.gif", &gif)]);
+        let stats = converter
+            .convert_archive_entries(&archive, &dir.path().join("unpacked"), "png", 80.0, None)
+            .unwrap();
+        assert_eq!((stats.converted, stats.failed), (1, 1));
+        assert!(!dir.path().join("unpacked").join("anim.png").exists());
+    }
+
+    #[test]
+    fn test_converter_is_send_and_sync() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<ImageConverter>();
+        assert_sync::<ImageConverter>();
+    }
+
+    /// 测试中收集进度行的缓冲区，转换器持有一份，测试持有另一份
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_progress_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(8, 8).save(input.join("a.png")).unwrap();
+        gradient(16, 16).save(input.join("b.png")).unwrap();
+        std::fs::write(input.join("broken.png"), b"\x89PNG\r\n\x1a\nnot really").unwrap();
+        std::fs::write(input.join("notes.txt"), b"not an image").unwrap();
+        let output = dir.path().join("out");
+
+        let buffer = SharedBuffer::default();
+        let mut converter = ImageConverter::with_options(ConvertOptions { progress_json: true, ..Default::default() });
+        converter.set_progress_writer(Box::new(buffer.clone()));
+        let stats = converter.convert_entries(&input, &output, "png", 80.0, None, None).unwrap();
+        assert_eq!((stats.converted, stats.failed), (2, 1));
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        // 非图像文件不算在进度中
+        assert_eq!(lines.len(), 3, "{}", text);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line["index"], i + 1);
+            assert_eq!(line["total"], 3);
+            let path = Path::new(line["path"].as_str().unwrap());
+            let name = path.file_name().unwrap().to_str().unwrap();
+            if name == "broken.png" {
+                assert_eq!((line["status"].as_str(), line["bytes_out"].as_u64()), (Some("failed"), Some(0)));
+            } else {
+                assert_eq!(line["status"], "converted");
+                let size = std::fs::metadata(output.join(name)).unwrap().len();
+                assert_eq!(line["bytes_out"], size);
+            }
+        }
+
+        // 流式遍历时不知道总数；预演不解码，损坏的文件同样计为将要转换
+        let buffer = SharedBuffer::default();
+        let options = ConvertOptions { progress_json: true, stream: true, dry_run: true, ..Default::default() };
+        let mut converter = ImageConverter::with_options(options);
+        converter.set_progress_writer(Box::new(buffer.clone()));
+        converter.convert_entries(&input, &dir.path().join("planned"), "png", 80.0, None, None).unwrap();
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let planned = text
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .inspect(|line| assert!(line["total"].is_null(), "{}", line))
+            .filter(|line| line["status"] == "planned")
+            .count();
+        assert_eq!(planned, 3, "{}", text);
+    }
+
+    #[test]
+    fn test_validate_first_aborts_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(8, 8).save(input.join("good.png")).unwrap();
+        let mut truncated = std::fs::read(input.join("good.png")).unwrap();
+        truncated.truncate(40);
+        std::fs::write(input.join("broken.png"), truncated).unwrap();
+        let output = dir.path().join("out");
+
+        let converter = ImageConverter::with_options(ConvertOptions { validate_first: true, ..Default::default() });
+        let err = converter.convert_directory(&input, &output, "webp", 80.0).unwrap_err();
+        assert!(err.to_string().contains("1 个输入无法解码"), "{}", err);
+        assert!(!output.exists());
+
+        // 不预先校验时照常转换能解码的文件
+        ImageConverter::new().convert_directory(&input, &output, "webp", 80.0).unwrap();
+        assert!(output.join("good.webp").exists());
+    }
+
+    #[test]
+    fn test_trust_extensions_skips_content_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("notes.png");
+        std::fs::write(&input, "not an image").unwrap();
+
+        let checked = ImageConverter::new();
+        assert!(!checked.is_image_input(&input));
+        let err = checked.convert_single_file(&input, dir.path(), "jpeg", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::UnsupportedSourceFormat { .. }), "{:?}", err);
+
+        let trusting = ImageConverter::with_options(ConvertOptions { trust_extensions: true, ..Default::default() });
+        assert!(trusting.is_image_input(&input));
+        assert!(!trusting.is_image_input(&dir.path().join("missing.png")));
+        let err = trusting.convert_single_file(&input, dir.path(), "jpeg", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::DecodeFailed { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn test_directory_picks_up_jfif_and_any_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        rgb_jpeg(&input.join("a.jfif"), 16, 16);
+        std::fs::copy(input.join("a.jfif"), input.join("b.bin")).unwrap();
+
+        let converted = |any_extension: bool| {
+            let output = tempfile::tempdir().unwrap();
+            let converter = ImageConverter::with_options(ConvertOptions { any_extension, ..Default::default() });
+            converter.convert_directory(&input, output.path(), "png", 80.0).unwrap();
+            let mut names: Vec<String> = std::fs::read_dir(output.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(converted(false), vec!["a.png"]);
+        assert_eq!(converted(true), vec!["a.png", "b.png"]);
+    }
+
+    #[test]
+    fn test_extract_alpha_mask() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("overlay.png");
+        RgbaImage::from_fn(16, 8, |x, _| Rgba([200, 100, 50, (x * 16) as u8])).save(&input).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            extract: Some(Channel::Alpha),
+            ..Default::default()
+        });
+        let output = dir.path().join("mask.png");
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+
+        let mask = image::open(&output).unwrap();
+        assert_eq!(mask.color(), ColorType::L8);
+        let mask = mask.to_luma8();
+        assert_eq!(mask.get_pixel(0, 3)[0], 0);
+        assert_eq!(mask.get_pixel(5, 3)[0], 80);
+        assert_eq!(mask.get_pixel(15, 0)[0], 240);
+    }
+
+    #[test]
+    fn test_tone_map_only_for_high_bit_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        // 线性16位场景：大部分很暗，右侧有一段高光
+        let scene = image::DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(64, 2, |x, _| {
+            let value = if x < 48 { x * 40 } else { 50000 + (x - 48) * 1000 } as u16;
+            image::Rgb([value, value, value])
+        }));
+        let deep = dir.path().join("deep.png");
+        scene.save(&deep).unwrap();
+        let shallow = dir.path().join("shallow.png");
+        scene.to_rgb8().save(&shallow).unwrap();
+
+        let convert = |input: &Path, tone_map: bool| {
+            let output = dir.path().join(format!("out_{}.png", tone_map));
+            let converter = ImageConverter::with_options(ConvertOptions { tone_map, ..Default::default() });
+            converter.convert_single_file(input, &output, "png", 80.0).unwrap();
+            image::open(&output).unwrap().to_rgb8()
+        };
+
+        assert_eq!(convert(&deep, false), scene.to_rgb8());
+        let mapped = convert(&deep, true);
+        assert_ne!(mapped, scene.to_rgb8());
+        // 暗部被提亮，高光仍然保留层次且最亮处为白色
+        assert!(mapped.get_pixel(40, 0)[0] > scene.to_rgb8().get_pixel(40, 0)[0]);
+        let highlights: std::collections::HashSet<u8> = (48..64).map(|x| mapped.get_pixel(x, 0)[0]).collect();
+        assert!(highlights.len() > 8);
+        assert_eq!(mapped.get_pixel(63, 0)[0], 255);
+
+        // 8位源图像不受影响
+        assert_eq!(convert(&shallow, true), scene.to_rgb8());
+    }
+
+    #[test]
+    fn test_source_quality_follows_jpeg_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let img = image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x * y) % 253) as u8])
+        });
+        let input = dir.path().join("low.jpg");
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, 30).encode_image(&img).unwrap();
+        std::fs::write(&input, &data).unwrap();
+        let png = dir.path().join("source.png");
+        img.save(&png).unwrap();
+
+        let size = |input: &Path, source_quality: bool| {
+            let output = dir.path().join("out.webp");
+            let converter = ImageConverter::with_options(ConvertOptions { source_quality, ..Default::default() });
+            converter.convert_single_file(input, &output, "webp", 95.0).unwrap();
+            std::fs::metadata(&output).unwrap().len()
+        };
+
+        // 低质量JPEG源按估计的质量30编码，而不是传入的95
+        assert!(size(&input, true) < size(&input, false));
+        // 非JPEG源不受影响
+        assert_eq!(size(&png, true), size(&png, false));
+    }
+
+    #[test]
+    fn test_resize_if_larger_caps_big_images_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let big = dir.path().join("big.png");
+        let small = dir.path().join("small.png");
+        gradient(200, 100).save(&big).unwrap();
+        gradient(40, 30).save(&small).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            resize_if_larger: Some((100, 100)),
+            ..Default::default()
+        });
+        let output = dir.path().join("out");
+        std::fs::create_dir(&output).unwrap();
+        for input in [&big, &small] {
+            converter.convert_single_file(input, &output, "png", 10.0).unwrap();
+        }
+
+        assert_eq!(image::open(output.join("big.png")).unwrap().dimensions(), (100, 50));
+        // 同格式且未超出尺寸时原样复制
+        assert_eq!(std::fs::read(output.join("small.png")).unwrap(), std::fs::read(&small).unwrap());
+
+        converter.convert_single_file(&small, &output, "webp", 80.0).unwrap();
+        assert_eq!(image::open(output.join("small.webp")).unwrap().dimensions(), (40, 30));
+
+        // 其它改变像素的设置照常执行，不直接复制
+        let gray = dir.path().join("gray.png");
+        DynamicImage::ImageLuma8(gradient(40, 30).to_luma8()).to_rgb8().save(&gray).unwrap();
+        let converter = ImageConverter::with_options(ConvertOptions {
+            resize_if_larger: Some((100, 100)),
+            auto_grayscale: true,
+            ..Default::default()
+        });
+        converter.convert_single_file(&gray, &output, "png", 80.0).unwrap();
+        assert!(matches!(image::open(output.join("gray.png")).unwrap(), DynamicImage::ImageLuma8(_)));
+    }
+
+    #[test]
+    fn test_pad_square_then_resize() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("wide.png");
+        image::RgbImage::from_pixel(300, 100, image::Rgb([255, 255, 255])).save(&input).unwrap();
+
+        let output = dir.path().join("square.png");
+        let converter = ImageConverter::with_options(ConvertOptions {
+            pad_square: Some(image::Rgba([0, 0, 0, 255])),
+            resize: Some(Resize { width: 30, height: 30, mode: transform::FitMode::Fit }),
+            ..Default::default()
+        });
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+
+        let img = image::open(&output).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (30, 30));
+        assert_eq!(img.get_pixel(15, 2), &image::Rgb([0, 0, 0]));
+        assert_eq!(img.get_pixel(15, 15), &image::Rgb([255, 255, 255]));
+        assert_eq!(img.get_pixel(15, 27), &image::Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_deskew_straightens_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = image::GrayImage::from_fn(300, 200, |x, y| {
+            image::Luma([if y % 20 < 3 && (20..180).contains(&y) && (20..280).contains(&x) { 0 } else { 255 }])
+        });
+        let input = dir.path().join("scan.png");
+        deskew::rotate(&image::DynamicImage::ImageLuma8(page), 4.0).save(&input).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions { deskew: true, ..Default::default() });
+        let output_dir = dir.path().join("out");
+        std::fs::create_dir(&output_dir).unwrap();
+        converter.convert_single_file(&input, &output_dir, "png", 80.0).unwrap();
+        let output = image::open(output_dir.join("scan.png")).unwrap();
+        assert_eq!((output.width(), output.height()), (300, 200));
+        assert!(deskew::estimate_skew(&output).unwrap().abs() < 0.3);
+    }
+
+    /// JPEG帧头 (SOF) 中的分量数
+    fn jpeg_components(data: &[u8]) -> u8 {
+        let sof = data
+            .windows(2)
+            .position(|marker| marker[0] == 0xFF && matches!(marker[1], 0xC0..=0xC2))
+            .expect("没有SOF标记");
+        data[sof + 9]
+    }
+
+    #[test]
+    fn test_grayscale_jpeg_has_one_component() {
+        let dir = tempfile::tempdir().unwrap();
+        let gray = image::GrayImage::from_fn(256, 256, |x, y| image::Luma([((x + y) / 2) as u8]));
+        let gray_input = dir.path().join("gray.png");
+        gray.save(&gray_input).unwrap();
+        let rgb_input = dir.path().join("rgb.png");
+        image::DynamicImage::ImageLuma8(gray).to_rgb8().save(&rgb_input).unwrap();
+
+        for subsampling in [None, Some(ChromaSubsampling::Yuv444)] {
+            let output = dir.path().join(format!("{:?}", subsampling));
+            std::fs::create_dir(&output).unwrap();
+            let converter = ImageConverter::with_options(ConvertOptions { jpeg_subsampling: subsampling, ..Default::default() });
+            converter.convert_single_file(&gray_input, &output, "jpeg", 90.0).unwrap();
+            converter.convert_single_file(&rgb_input, &output, "jpeg", 90.0).unwrap();
+
+            let gray_jpeg = std::fs::read(output.join("gray.jpg")).unwrap();
+            let rgb_jpeg = std::fs::read(output.join("rgb.jpg")).unwrap();
+            assert_eq!(jpeg_components(&gray_jpeg), 1);
+            assert_eq!(jpeg_components(&rgb_jpeg), 3);
+            // 省掉两个色度分量的量化表、霍夫曼表和每个块的数据，平滑图像至少小15%
+            assert!(gray_jpeg.len() * 20 < rgb_jpeg.len() * 17, "{} vs {}", gray_jpeg.len(), rgb_jpeg.len());
+            assert!(matches!(image::load_from_memory(&gray_jpeg).unwrap(), image::DynamicImage::ImageLuma8(_)));
+        }
+    }
+
+    #[test]
+    fn test_convert_archive_preserves_structure() {
+        let dir = tempfile::tempdir().unwrap();
+        let encode = |img: DynamicImage, format: ImageFormat| {
+            let mut data = std::io::Cursor::new(Vec::new());
+            img.write_to(&mut data, format).unwrap();
+            data.into_inner()
+        };
+        let png = encode(gradient(20, 10), ImageFormat::Png);
+        let jpeg = encode(DynamicImage::ImageRgb8(gradient(8, 12).to_rgb8()), ImageFormat::Jpeg);
+        let archive = dir.path().join("photos.zip");
+        crate::archive::tests::write_zip(
+            &archive,
+            &[("a.png", &png), ("nested/", b""), ("nested/b.jpg", &jpeg), ("nested/notes.txt", b"not an image")],
+        );
+
+        let output = dir.path().join("out");
+        let stats = ImageConverter::new().convert_archive_entries(&archive, &output, "webp", 80.0, None).unwrap();
+        assert_eq!((stats.converted, stats.skipped, stats.filtered), (2, 0, 1));
+        assert_eq!(image::open(output.join("a.webp")).unwrap().dimensions(), (20, 10));
+        assert_eq!(image::open(output.join("nested/b.webp")).unwrap().dimensions(), (8, 12));
+        assert!(!output.join("nested/notes.txt").exists());
+
+        let converter = ImageConverter::with_options(ConvertOptions { copy_unconverted: true, ..Default::default() });
+        converter.convert_archive(&archive, &output, "webp", 80.0).unwrap();
+        assert_eq!(std::fs::read(output.join("nested/notes.txt")).unwrap(), b"not an image");
+    }
+
+    #[test]
+    fn test_convert_archive_applies_batch_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let encode = |img: DynamicImage, format: ImageFormat| {
+            let mut data = std::io::Cursor::new(Vec::new());
+            img.write_to(&mut data, format).unwrap();
+            data.into_inner()
+        };
+        let png = encode(gradient(8, 8), ImageFormat::Png);
+        let jpeg = encode(DynamicImage::ImageRgb8(gradient(8, 8).to_rgb8()), ImageFormat::Jpeg);
+        let large = encode(gradient(256, 256), ImageFormat::Png);
+        let archive = dir.path().join("photos.zip");
+        crate::archive::tests::write_zip(&archive, &[("a.png", &png), ("a.jpg", &jpeg), ("large.png", &large)]);
+
+        // 扩展名换成webp后同名的条目不互相覆盖
+        let output = dir.path().join("out");
+        let stats = ImageConverter::new().convert_archive_entries(&archive, &output, "webp", 80.0, None).unwrap();
+        assert_eq!(stats.converted, 3);
+        assert!(output.join("a.webp").exists() && output.join("a_1.webp").exists());
+
+        let skip_large = ImageConverter::with_options(ConvertOptions {
+            max_file_size: Some(large.len() as u64 - 1),
+            ..Default::default()
+        });
+        let stats = skip_large.convert_archive_entries(&archive, &dir.path().join("small"), "png", 80.0, None).unwrap();
+        assert_eq!((stats.converted, stats.filtered), (2, 1));
+        assert!(!dir.path().join("small/large.png").exists());
+
+        let limited = ImageConverter::with_options(ConvertOptions { limit: Some(1), ..Default::default() });
+        let stats = limited.convert_archive_entries(&archive, &dir.path().join("limited"), "png", 80.0, None).unwrap();
+        assert_eq!(stats.converted, 1);
+
+        // 第一个输出超过上限一半，写出第二个前停止
+        let first = std::fs::metadata(output.join("a.webp")).unwrap().len();
+        let capped = ImageConverter::with_options(ConvertOptions { max_total_bytes: Some(first * 3 / 2), ..Default::default() });
+        let stats = capped.convert_archive_entries(&archive, &dir.path().join("capped"), "webp", 80.0, None).unwrap();
+        assert_eq!(stats.converted, 1);
+        assert!(stats.written_bytes <= first * 3 / 2);
+        assert!(!dir.path().join("capped/a_1.webp").exists());
+    }
+
+    #[test]
+    fn test_auto_orient_normalizes_orientation_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::jpegtran::tests::sample_jpeg(64, 48);
+        let mut with_exif = data[..2].to_vec();
+        with_exif.extend_from_slice(&crate::jpegtran::tests::exif_segment(6));
+        with_exif.extend_from_slice(&data[2..]);
+        let input = dir.path().join("portrait.jpg");
+        std::fs::write(&input, &with_exif).unwrap();
+        let output = dir.path().join("out");
+        std::fs::create_dir(&output).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions { auto_orient: true, ..Default::default() });
+        let orientation = |path: &Path| {
+            let reader = image::ImageReader::open(path).unwrap().with_guessed_format().unwrap();
+            reader.into_decoder().unwrap().orientation().unwrap()
+        };
+        assert_eq!(orientation(&input), image::metadata::Orientation::Rotate90);
+
+        // JPEG无损旋转保留了EXIF，方向标签改为1，看图软件不会再旋转一次
+        converter.convert_single_file(&input, &output, "jpeg", 80.0).unwrap();
+        let rotated = output.join("portrait.jpg");
+        assert_eq!(orientation(&rotated), image::metadata::Orientation::NoTransforms);
+        assert_eq!(image::open(&rotated).unwrap().dimensions(), (48, 64));
+
+        // 重新编码的输出不带EXIF
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+        let png = output.join("portrait.png");
+        assert_eq!(orientation(&png), image::metadata::Orientation::NoTransforms);
+        assert_eq!(image::open(&png).unwrap().dimensions(), (48, 64));
+    }
+
+    /// 带EXIF方向的无损WebP：VP8X扩展头 + 图像 + EXIF块，多帧时写成每帧100毫秒的动画
+    fn webp_with_orientation(frames: &[DynamicImage], orientation: u16) -> Vec<u8> {
+        fn chunk(body: &mut Vec<u8>, name: &[u8], data: &[u8]) {
+            body.extend_from_slice(name);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let u24 = |value: u32| value.to_le_bytes()[..3].to_vec();
+        // 无损编码结果去掉RIFF文件头后就是VP8L块
+        let vp8l = |img: &DynamicImage| {
+            let mut lossless = Vec::new();
+            img.write_with_encoder(image::codecs::webp::WebPEncoder::new_lossless(&mut lossless)).unwrap();
+            lossless.split_off(12)
+        };
+        let (width, height) = frames[0].dimensions();
+        let animated = frames.len() > 1;
+
+        let mut vp8x = vec![if animated { 0x0A } else { 0x08 }, 0, 0, 0];
+        vp8x.extend(u24(width - 1));
+        vp8x.extend(u24(height - 1));
+        let mut body = b"WEBP".to_vec();
+        chunk(&mut body, b"VP8X", &vp8x);
+        if animated {
+            chunk(&mut body, b"ANIM", &[0, 0, 0, 0, 0, 0]);
+            for frame in frames {
+                let mut anmf = [u24(0), u24(0), u24(width - 1), u24(height - 1), u24(100), vec![0]].concat();
+                anmf.extend(vp8l(frame));
+                chunk(&mut body, b"ANMF", &anmf);
+            }
+        } else {
+            body.extend(vp8l(&frames[0]));
+        }
+        // APP1段去掉标记、长度和 `Exif\0\0` 后就是WebP的EXIF块内容
+        chunk(&mut body, b"EXIF", &crate::jpegtran::tests::exif_segment(orientation)[10..]);
+
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        webp.extend_from_slice(&body);
+        webp
+    }
+
+    #[test]
+    fn test_auto_orient_webp_exif() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = gradient(32, 16);
+        let input = dir.path().join("phone.webp");
+        std::fs::write(&input, webp_with_orientation(std::slice::from_ref(&source), 6)).unwrap();
+        let output = dir.path().join("out");
+
+        let plain = ImageConverter::new();
+        plain.convert_single_file(&input, &output.join("plain.png"), "png", 80.0).unwrap();
+        assert_eq!(image::open(output.join("plain.png")).unwrap().dimensions(), (32, 16));
+
+        // 方向6：顺时针旋转90°后显示，WebP输出不带EXIF，像素需要已经转正
+        let converter = ImageConverter::with_options(ConvertOptions { auto_orient: true, ..Default::default() });
+        converter.convert_single_file(&input, &output.join("upright.png"), "png", 80.0).unwrap();
+        let upright = image::open(output.join("upright.png")).unwrap();
+        assert_eq!(upright.to_rgba8(), source.rotate90().to_rgba8());
+        converter.convert_single_file(&input, &output.join("upright.webp"), "webp", 80.0).unwrap();
+        assert_eq!(image::open(output.join("upright.webp")).unwrap().dimensions(), (16, 32));
+
+        // 保留动画时每一帧同样按方向旋转
+        let animated = dir.path().join("burst.webp");
+        std::fs::write(&animated, webp_with_orientation(&[gradient(32, 16), gradient(32, 16).fliph()], 6)).unwrap();
+        let converter = ImageConverter::with_options(ConvertOptions {
+            auto_orient: true,
+            fps: Some(10.0),
+            ..Default::default()
+        });
+        converter.convert_single_file(&animated, &output.join("burst.gif"), "gif", 80.0).unwrap();
+        let frames = animation::decode_frames(&output.join("burst.gif"), "gif").unwrap().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|frame| frame.buffer().dimensions() == (16, 32)));
+    }
+
+    #[test]
+    fn test_multi_size_ico_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("logo.png");
+        gradient(512, 512).save(&input).unwrap();
+        let output = dir.path().join("favicon.ico");
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            ico_sizes: vec![16, 32, 48, 256],
+            ico_sharpen: true,
+            ..Default::default()
+        });
+        converter.convert_single_file(&input, &output, "ico", 80.0).unwrap();
+
+        let data = std::fs::read(&output).unwrap();
+        assert_eq!(u16::from_le_bytes([data[4], data[5]]), 4);
+        for size in [16, 32, 48, 256] {
+            let frame = ico::decode_ico_bytes(&data, IcoPick::Exact(size, size)).unwrap();
+            assert_eq!(frame.dimensions(), (size, size));
+        }
+        assert_eq!(image::open(&output).unwrap().dimensions(), (256, 256));
+    }
+
+    #[test]
+    fn test_expect_min_fails_on_empty_directory() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let converter = ImageConverter::with_options(ConvertOptions { expect_min: Some(1), ..Default::default() });
+
+        let err = converter.convert_directory(input.path(), output.path(), "png", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::TooFewConverted { converted: 0, expected: 1 }), "{}", err);
+
+        // 被跳过的文件不计入
+        std::fs::write(input.path().join("broken.png"), b"\x89PNG\r\n\x1a\nbroken").unwrap();
+        assert!(converter.convert_directory(input.path(), output.path(), "png", 80.0).is_err());
+
+        gradient(4, 4).save(input.path().join("a.png")).unwrap();
+        converter.convert_directory(input.path(), output.path(), "png", 80.0).unwrap();
+        assert!(ImageConverter::new().convert_directory(tempfile::tempdir().unwrap().path(), output.path(), "png", 80.0).is_ok());
+    }
+
+    #[test]
+    fn test_convert_directory_into_zip() {
+        let input = tempfile::tempdir().unwrap();
+        std::fs::create_dir(input.path().join("nested")).unwrap();
+        gradient(12, 8).save(input.path().join("a.png")).unwrap();
+        gradient(6, 6).save(input.path().join("nested/b.png")).unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let target = output.path().join("results.zip");
+
+        ImageConverter::new().convert_directory(input.path(), &target, "webp", 80.0).unwrap();
+
+        let mut entries = Vec::new();
+        crate::archive::for_each_file(&target, None, |entry| {
+            let entry = entry.unwrap();
+            let size = image::load_from_memory_with_format(&entry.data, ImageFormat::WebP).unwrap().dimensions();
+            entries.push((entry.path, size));
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        entries.sort();
+        // 条目名与写到目录时的输出路径相同：默认不保留子目录
+        assert_eq!(entries, vec![(PathBuf::from("a.webp"), (12, 8)), (PathBuf::from("b.webp"), (6, 6))]);
+        // 暂存目录和临时文件都已删除
+        assert_eq!(std::fs::read_dir(output.path()).unwrap().count(), 1);
+
+        // 保留目录结构时条目名带子目录
+        let mirrored = output.path().join("mirrored.zip");
+        let converter = ImageConverter::with_options(ConvertOptions { collapse_depth: Some(1), ..Default::default() });
+        converter.convert_directory(input.path(), &mirrored, "webp", 80.0).unwrap();
+        let mut names = Vec::new();
+        crate::archive::for_each_file(&mirrored, None, |entry| {
+            names.push(entry.unwrap().path);
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        names.sort();
+        assert_eq!(names, vec![PathBuf::from("a.webp"), PathBuf::from("nested/b.webp")]);
+
+        // 压缩包到压缩包
+        let repacked = output.path().join("repacked.zip");
+        ImageConverter::new().convert_archive(&target, &repacked, "png", 80.0).unwrap();
+        let mut names = Vec::new();
+        crate::archive::for_each_file(&repacked, None, |entry| {
+            names.push(entry.unwrap().path);
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        names.sort();
+        assert_eq!(names, vec![PathBuf::from("a.png"), PathBuf::from("b.png")]);
+    }
+
+    #[test]
+    fn test_auto_grayscale_converts_gray_rgb_to_luma() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("scan.png");
+        image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([((x + y) * 4) as u8; 3])).save(&input).unwrap();
+        let output = dir.path().join("out");
+        std::fs::create_dir(&output).unwrap();
+
+        ImageConverter::new().convert_single_file(&input, &output, "png", 80.0).unwrap();
+        assert_eq!(image::open(output.join("scan.png")).unwrap().color(), ColorType::Rgb8);
+
+        let converter = ImageConverter::with_options(ConvertOptions { auto_grayscale: true, ..Default::default() });
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+        let converted = image::open(output.join("scan.png")).unwrap();
+        assert_eq!(converted.color(), ColorType::L8);
+        assert_eq!(converted.to_rgb8(), image::open(&input).unwrap().to_rgb8());
+
+        // 真正的彩色图像不受影响
+        let colored = dir.path().join("colored.png");
+        gradient(32, 32).save(&colored).unwrap();
+        converter.convert_single_file(&colored, &output, "png", 80.0).unwrap();
+        assert!(image::open(output.join("colored.png")).unwrap().color().has_color());
+    }
+
+    #[test]
+    fn test_trace_reports_variation_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("stripes.png");
+        let stripes = image::RgbImage::from_fn(128, 96, |x, _| image::Rgb([if x % 2 == 0 { 0 } else { 255 }; 3]));
+        stripes.save(&input).unwrap();
+        let trace_file = dir.path().join("trace.log");
+        let output = dir.path().join("out");
+        std::fs::create_dir(&output).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            trace: true,
+            trace_file: Some(trace_file.clone()),
+            ..Default::default()
+        });
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+
+        let trace = std::fs::read_to_string(&trace_file).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert!(lines.iter().all(|line| line.starts_with("[trace] ") && line.contains("stripes.png")), "{}", trace);
+        assert!(lines.iter().any(|line| line.contains("源格式 png, 尺寸 128x96, 颜色 Rgb8")), "{}", trace);
+        // 竖条纹：水平方向每个像素都变化，垂直方向不变
+        assert!(lines.iter().any(|line| line.contains("VerticalPattern (水平变化 765, 垂直变化 0)")), "{}", trace);
+        assert!(lines.iter().any(|line| line.contains("PNG过滤器 Up, 压缩级别 Best")), "{}", trace);
+        let size = std::fs::metadata(output.join("stripes.png")).unwrap().len();
+        assert!(lines.iter().any(|line| line.contains(&format!("输出 {} 字节", size))), "{}", trace);
+    }
+
+    #[test]
+    fn test_swap_rb_turns_red_into_blue() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("red.png");
+        image::RgbImage::from_pixel(4, 4, image::Rgb([255, 0, 0])).save(&input).unwrap();
+
+        let output = dir.path().join("blue.png");
+        let converter = ImageConverter::with_options(ConvertOptions { swap_rb: true, ..Default::default() });
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+        let img = image::open(&output).unwrap().to_rgb8();
+        assert!(img.pixels().all(|pixel| *pixel == image::Rgb([0, 0, 255])));
+    }
+
+    #[test]
+    fn test_max_bytes_lowers_quality_until_it_fits() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("noise.png");
+        write_noise_png(&input);
+
+        let size = |max_bytes: Option<u64>, min_quality: Option<f32>| {
+            let output = dir.path().join("out.jpg");
+            let converter = ImageConverter::with_options(ConvertOptions { max_bytes, min_quality, ..Default::default() });
+            converter.convert_single_file(&input, &output, "jpeg", 90.0).unwrap();
+            std::fs::metadata(&output).unwrap().len()
+        };
+
+        let full = size(None, None);
+        let budget = full / 2;
+        let fitted = size(Some(budget), None);
+        assert!(fitted <= budget, "{} > {}", fitted, budget);
+        assert!(fitted > budget / 2);
+    }
+
+    #[test]
+    fn test_min_quality_floor_wins_over_unreachable_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("noise.png");
+        write_noise_png(&input);
+
+        let encode = |quality: f32, max_bytes: Option<u64>, min_quality: Option<f32>| {
+            let output = dir.path().join("out.jpg");
+            let converter = ImageConverter::with_options(ConvertOptions { max_bytes, min_quality, ..Default::default() });
+            converter.convert_single_file(&input, &output, "jpeg", quality).unwrap();
+            std::fs::read(&output).unwrap()
+        };
+
+        // 100字节无法满足，按下限50写出，与直接以质量50编码相同
+        let floored = encode(90.0, Some(100), Some(50.0));
+        assert_eq!(floored, encode(50.0, None, None));
+        assert!(crate::jpeg_quality::estimate_quality(&floored).unwrap() >= 45.0);
+    }
+
+    #[test]
+    fn test_optimize_shrinks_png_without_changing_pixels() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("source.jpg");
+        rgb_jpeg(&input, 96, 64);
+
+        let encode = |optimize: bool| {
+            let output = dir.path().join(format!("out_{}.png", optimize));
+            let converter = ImageConverter::with_options(ConvertOptions { optimize, ..Default::default() });
+            converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+            (std::fs::metadata(&output).unwrap().len(), image::open(&output).unwrap().to_rgba8())
+        };
+
+        let (plain_size, plain) = encode(false);
+        let (optimized_size, optimized) = encode(true);
+        assert!(optimized_size <= plain_size, "{} > {}", optimized_size, plain_size);
+        assert_eq!(optimized, plain);
+    }
+
+    #[test]
+    fn test_png_output_is_deterministic() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("source.jpg");
+        rgb_jpeg(&input, 33, 21);
+
+        let encode = |name: &str| {
+            let output = dir.path().join(name);
+            ImageConverter::new().convert_single_file(&input, &output, "png", 80.0).unwrap();
+            std::fs::read(output).unwrap()
+        };
+        let first = encode("first.png");
+        // 修改源文件的时间，不依赖两次转换之间经过的时间
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(86_400);
+        std::fs::File::options().write(true).open(&input).unwrap().set_modified(modified).unwrap();
+        let second = encode("second.png");
+        assert_eq!(first, second);
+
+        // 逐块检查，不含时间戳或文本块
+        let mut chunks = Vec::new();
+        let mut offset = 8;
+        while offset + 8 <= first.len() {
+            let length = u32::from_be_bytes(first[offset..offset + 4].try_into().unwrap()) as usize;
+            chunks.push(String::from_utf8_lossy(&first[offset + 4..offset + 8]).into_owned());
+            offset += 12 + length;
+        }
+        assert!(chunks.iter().all(|chunk| !matches!(chunk.as_str(), "tIME" | "tEXt" | "zTXt" | "iTXt")), "{:?}", chunks);
+        assert_eq!(chunks.last().map(String::as_str), Some("IEND"));
+    }
+
+    #[test]
+    fn test_farbfeld_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("source.png");
+        let source = gradient(24, 16);
+        source.save(&input).unwrap();
+
+        let converter = ImageConverter::new();
+        let farbfeld = dir.path().join("out.ff");
+        converter.convert_single_file(&input, &farbfeld, "farbfeld", 80.0).unwrap();
+        assert_eq!(utils::detect_image_format_by_content(&farbfeld).as_deref(), Some("farbfeld"));
+
+        let png = dir.path().join("back.png");
+        converter.convert_single_file(&farbfeld, &png, "png", 80.0).unwrap();
+        assert_eq!(image::open(&png).unwrap().to_rgba8(), source.to_rgba8());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks_fire_per_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("source.png");
+        let broken = dir.path().join("broken.png");
+        gradient(8, 8).save(&input).unwrap();
+        std::fs::write(&broken, b"\x89PNG\r\n\x1a\nnot really").unwrap();
+
+        let log = dir.path().join("hooks.log");
+        let converter = ImageConverter::with_options(ConvertOptions {
+            on_success: Some(HookCommand::new(format!("echo ok {{output}} >> '{}'", log.display()))),
+            on_failure: Some(HookCommand::new(format!("echo fail {{input}} >> '{}'", log.display()))),
+            ..Default::default()
+        });
+
+        let output = dir.path().join("out.webp");
+        converter.convert_single_file(&input, &output, "webp", 80.0).unwrap();
+        assert!(converter.convert_single_file(&broken, &output, "webp", 80.0).is_err());
+        // 转换开始前的检查失败同样执行失败钩子
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, b"not an image").unwrap();
+        assert!(converter.convert_single_file(&notes, &output, "webp", 80.0).is_err());
+
+        let logged = std::fs::read_to_string(&log).unwrap();
+        let lines: Vec<&str> = logged.lines().collect();
+        assert_eq!(lines, vec![
+            format!("ok {}", output.display()),
+            format!("fail {}", broken.display()),
+            format!("fail {}", notes.display()),
+        ]);
+
+        // 批量转换中被拒绝的格式
+        let batch_log = dir.path().join("batch.log");
+        let input_dir = dir.path().join("in");
+        std::fs::create_dir(&input_dir).unwrap();
+        gradient(8, 8).save(input_dir.join("a.png")).unwrap();
+        let rejecting = ImageConverter::with_options(ConvertOptions {
+            reject_formats: vec!["png".to_string()],
+            on_failure: Some(HookCommand::new(format!("echo {{input}} {{output}} >> '{}'", batch_log.display()))),
+            ..Default::default()
+        });
+        let out_dir = dir.path().join("out");
+        rejecting.convert_directory(&input_dir, &out_dir, "webp", 80.0).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&batch_log).unwrap().trim(),
+            format!("{} {}", input_dir.join("a.png").display(), out_dir.join("a.webp").display())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fallback_decoder_converts_unknown_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let decoded = dir.path().join("decoded.png");
+        gradient(12, 6).save(&decoded).unwrap();
+        // 模拟dcraw之类的外部工具：忽略输入内容，写出一张PNG
+        let stub = dir.path().join("stub-decoder.sh");
+        std::fs::write(&stub, format!("#!/bin/sh\ntest -f \"$1\" && cp '{}' \"$2\"\n", decoded.display())).unwrap();
+
+        let input = dir.path().join("photo.cr2");
+        std::fs::write(&input, b"not an image PixForge knows").unwrap();
+        let converter = ImageConverter::with_options(ConvertOptions {
+            fallback_decoder: Some(FallbackDecoder::new(format!("sh '{}' {{input}} {{output_png}}", stub.display()))),
+            ..Default::default()
+        });
+
+        let output = dir.path().join("out");
+        std::fs::create_dir(&output).unwrap();
+        converter.convert_single_file(&input, &output, "webp", 80.0).unwrap();
+        let converted = image::open(output.join("photo.webp")).unwrap();
+        assert_eq!(converted.dimensions(), (12, 6));
+
+        // 外部命令失败时报告为解码失败，并保留命令的错误输出
+        let failing = ImageConverter::with_options(ConvertOptions {
+            fallback_decoder: Some(FallbackDecoder::new("echo 'unknown camera' >&2; exit 1")),
+            ..Default::default()
+        });
+        let err = failing.convert_single_file(&input, &output, "webp", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::DecodeFailed { .. }), "{:?}", err);
+        assert!(format!("{:#}", anyhow::Error::from(err)).contains("unknown camera"));
+
+        // 未设置后备命令时仍然是不支持的格式
+        let err = ImageConverter::new().convert_single_file(&input, &output, "webp", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::UnsupportedSourceFormat { .. }));
+    }
+
+    #[test]
+    fn test_adaptive_webp_quality() {
+        let converter = ImageConverter::with_options(ConvertOptions {
+            adaptive_quality: true,
+            ..Default::default()
+        });
+
+        let flat = DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 32, Rgba([30, 144, 255, 255])));
+        let detailed = DynamicImage::ImageRgba8(RgbaImage::from_fn(256, 256, |x, y| {
+            let noise = (x.wrapping_mul(2654435761) ^ y.wrapping_mul(40503)) as u8;
+            Rgba([noise, noise.wrapping_mul(3), noise.wrapping_add(91), 255])
+        }));
+
+        let flat_quality = converter.effective_webp_quality(80.0, converter.analyze_image_type(&flat).0);
+        let detailed_quality = converter.effective_webp_quality(80.0, converter.analyze_image_type(&detailed).0);
+        assert!(flat_quality < 80.0);
+        assert!(flat_quality < detailed_quality);
+
+        // 未开启时保持用户质量
+        let plain = ImageConverter::new();
+        assert_eq!(plain.effective_webp_quality(80.0, ImageType::SimpleGraphics), 80.0);
+    }
+
+    #[test]
+    fn test_adaptive_quality_caps_small_outputs() {
+        let converter = ImageConverter::with_options(ConvertOptions {
+            adaptive_quality: true,
+            ..Default::default()
+        });
+        let thumbnail = converter.size_capped_quality(90.0, 64, 64);
+        let large = converter.size_capped_quality(90.0, 2000, 2000);
+        assert!(thumbnail < large, "{} >= {}", thumbnail, large);
+        assert_eq!(large, 90.0);
+        // 上限不会提高本来就低的质量
+        assert_eq!(converter.size_capped_quality(50.0, 64, 64), 50.0);
+        assert_eq!(ImageConverter::new().size_capped_quality(90.0, 64, 64), 90.0);
+
+        // 编码结果与直接使用上限质量一致
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 4) as u8, (y * 4) as u8, ((x ^ y) * 4) as u8, 255])
+        }));
+        let dir = tempfile::tempdir().unwrap();
+        let (adaptive, capped) = (dir.path().join("adaptive.jpg"), dir.path().join("capped.jpg"));
+        converter.encode_image(&img, &adaptive, "jpeg", 90.0).unwrap();
+        ImageConverter::new().encode_image(&img, &capped, "jpeg", thumbnail).unwrap();
+        assert_eq!(std::fs::read(adaptive).unwrap(), std::fs::read(capped).unwrap());
+    }
+
+    #[test]
+    fn test_jpeg_restart_markers() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 4) as u8, (y * 4) as u8, 90, 255])
+        }));
+        let dir = tempfile::tempdir().unwrap();
+        let (plain, restarted) = (dir.path().join("plain.jpg"), dir.path().join("restarted.jpg"));
+        ImageConverter::new().encode_image(&img, &plain, "jpeg", 85.0).unwrap();
+        let converter = ImageConverter::with_options(ConvertOptions {
+            jpeg_restart: Some(1),
+            optimize: true,
+            ..Default::default()
+        });
+        converter.encode_image(&img, &restarted, "jpeg", 85.0).unwrap();
+
+        let count = crate::jpegtran::tests::restart_marker_count;
+        assert_eq!(count(&std::fs::read(&plain).unwrap()), 0);
+        assert!(count(&std::fs::read(&restarted).unwrap()) > 0);
+        assert_eq!(image::open(&restarted).unwrap().width(), 64);
+
+        // libjpeg拒绝的数据作为错误返回，不经过栈展开 (release构建中展开会直接终止进程)
+        let data = std::fs::read(&plain).unwrap();
+        let scan = data.windows(2).position(|marker| marker == [0xFF, 0xDA]).unwrap();
+        let err = converter.with_restart_markers("jpeg", data[..scan - 4].to_vec()).unwrap_err();
+        assert!(err.to_string().starts_with("写入JPEG重启标记失败: "), "{}", err);
+        assert_eq!(converter.with_restart_markers("png", b"not a jpeg".to_vec()).unwrap(), b"not a jpeg");
+    }
+
+    #[test]
+    fn test_webp_near_lossless_is_smaller_than_lossless() {
+        // 平缓的渐变上叠加轻微的噪声，近无损预处理可以抹掉低位的差异；颜色足够多，不会按调色板编码
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(128, 128, |x, y| {
+            let noise = ((x * 31 + y * 17) ^ (x * y)) % 5;
+            Rgba([(x + noise) as u8, (y + noise) as u8, ((x + y) / 2) as u8, 255])
+        }));
+        let quality = QualityProfile::new(80.0);
+        let lossless = ImageConverter::new().convert_to_webp(&img, quality, true).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions { webp_near_lossless: Some(40), ..Default::default() });
+        assert!(converter.webp_lossless(ImageType::SmoothPhoto));
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("near.webp");
+        converter.encode_image(&img, &output, "webp", 80.0).unwrap();
+        let near = std::fs::read(&output).unwrap();
+        assert!(near.len() < lossless.len(), "{} >= {}", near.len(), lossless.len());
+
+        // 仍然是无损编码的码流，像素只有轻微变化
+        assert_eq!(&near[12..16], b"VP8L");
+        let decoded = image::open(&output).unwrap().to_rgba8();
+        let max_diff = decoded
+            .pixels()
+            .zip(img.to_rgba8().pixels())
+            .flat_map(|(a, b)| a.0.iter().zip(b.0).map(|(x, y)| x.abs_diff(y)))
+            .max()
+            .unwrap();
+        assert!(max_diff <= 8, "{}", max_diff);
+    }
+
+    #[test]
+    fn test_analysis_samples_do_not_grow_with_image_size() {
+        // 网格点数只取决于采样预算，不随图像尺寸增长
+        for (width, height) in [(12_000, 8_000), (1_200, 800), (120, 80)] {
+            let (columns, rows) = ImageConverter::budget_grid(width, height, 2_500).unwrap();
+            let samples = columns * rows;
+            assert!((2_400..=2_600).contains(&samples), "{}x{}: {}", width, height, samples);
+            assert!(columns > rows);
+        }
+        // 像素不足时受图像尺寸限制
+        assert_eq!(ImageConverter::budget_grid(10, 100, 2_500), Some((9, 99)));
+        assert_eq!(ImageConverter::budget_grid(1, 100, 2_500), None);
+
+        let large = DynamicImage::ImageLuma8(image::GrayImage::new(12_000, 8_000));
+        let converter = ImageConverter::with_options(ConvertOptions {
+            analysis_samples: Some(2_500),
+            ..Default::default()
+        });
+        assert_eq!(converter.analyze_image_type(&large), (ImageType::SmoothPhoto, Some((0, 0))));
+
+        // 不指定时保持按尺寸采样，两种方式对同一条纹图像给出相同的类型
+        let stripes = DynamicImage::ImageRgb8(image::RgbImage::from_fn(400, 300, |_, y| {
+            image::Rgb([(y % 2 * 255) as u8; 3])
+        }));
+        assert_eq!(ImageConverter::new().analyze_image_type(&stripes).0, ImageType::HorizontalGraphics);
+        assert_eq!(converter.analyze_image_type(&stripes).0, ImageType::HorizontalGraphics);
+    }
+
+    #[test]
+    fn test_fractional_quality() {
+        // WebP保留小数精度
+        let converter = ImageConverter::new();
+        assert_eq!(converter.effective_webp_quality(12.5, ImageType::Mixed), 12.5);
+
+        // 整数编码器四舍五入
+        assert_eq!(QualityProfile::new(12.5).jpeg(), 13);
+        assert_eq!(QualityProfile::new(12.4).jpeg(), 12);
+        assert_eq!(QualityProfile::new(100.0).jpeg(), 100);
+    }
+
+    #[test]
+    fn test_flatten_over_checkerboard() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("icon.png");
+        let mut icon = RgbaImage::new(32, 32);
+        for (x, y, pixel) in icon.enumerate_pixels_mut() {
+            if (12..20).contains(&x) && (12..20).contains(&y) {
+                *pixel = Rgba([255, 0, 0, 255]);
+            }
+        }
+        icon.save(&input).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            flatten: Some(Backdrop::Checker),
+            ..Default::default()
+        });
+        let output = dir.path().join("preview.png");
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+        let preview = image::open(&output).unwrap();
+        assert!(!preview.color().has_alpha());
+
+        // 透明区域显示棋盘格，不透明的部分保持原样
+        let preview = preview.to_rgb8();
+        let cell = transform::CHECKER_CELL;
+        assert_eq!(preview.get_pixel(0, 0), &image::Rgb([255, 255, 255]));
+        assert_eq!(preview.get_pixel(cell, 0), &image::Rgb([204, 204, 204]));
+        assert_eq!(preview.get_pixel(cell, cell), &image::Rgb([255, 255, 255]));
+        assert_eq!(preview.get_pixel(15, 15), &image::Rgb([255, 0, 0]));
+
+        // 背景图像平铺在透明区域下
+        let tile = dir.path().join("tile.png");
+        RgbaImage::from_fn(4, 4, |x, _| if x < 2 { Rgba([0, 0, 255, 255]) } else { Rgba([0, 255, 0, 255]) })
+            .save(&tile)
+            .unwrap();
+        let converter = ImageConverter::with_options(ConvertOptions {
+            flatten: Some(Backdrop::Image(tile, transform::BackdropLayout::Tile)),
+            ..Default::default()
+        });
+        let output = dir.path().join("tiled.png");
+        converter.convert_single_file(&input, &output, "png", 80.0).unwrap();
+        let tiled = image::open(&output).unwrap().to_rgb8();
+        assert_eq!(tiled.get_pixel(4, 0), &image::Rgb([0, 0, 255]));
+        assert_eq!(tiled.get_pixel(6, 0), &image::Rgb([0, 255, 0]));
+    }
+
+    #[test]
+    fn test_palette_from_shares_reference_palette() {
+        let dir = tempfile::tempdir().unwrap();
+        let reference = dir.path().join("brand.png");
+        gradient(32, 32).save(&reference).unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(40, 24).save(input.join("a.png")).unwrap();
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(30, 30, |x, _| image::Rgb([200, (x * 8) as u8, 40])))
+            .save(input.join("b.png"))
+            .unwrap();
+        let output = dir.path().join("out");
+
+        let converter = ImageConverter::with_options(ConvertOptions {
+            palette_from: Some(reference.clone()),
+            ..Default::default()
+        });
+        converter.convert_directory(&input, &output, "gif", 80.0).unwrap();
+
+        let global_palette = |path: PathBuf| {
+            let data = std::fs::read(path).unwrap();
+            let decoder = gif::DecodeOptions::new().read_info(data.as_slice()).unwrap();
+            decoder.global_palette().unwrap().to_vec()
+        };
+        let expected = Palette::from_image(&image::open(&reference).unwrap()).unwrap();
+        assert_eq!(global_palette(output.join("a.gif")), expected.colors());
+        assert_eq!(global_palette(output.join("b.gif")), expected.colors());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stream_converts_entries_as_they_are_walked() {
+        // 大量非图像文件的目录树，开头和结尾各有一个图像目录
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        for group in 0..50 {
+            let sub = input.join(format!("m{:02}", group));
+            std::fs::create_dir_all(&sub).unwrap();
+            for index in 0..100 {
+                std::fs::write(sub.join(format!("{}.txt", index)), b"x").unwrap();
+            }
+        }
+        std::fs::create_dir_all(input.join("a")).unwrap();
+        std::fs::create_dir_all(input.join("z")).unwrap();
+        gradient(8, 8).save(input.join("a/first.png")).unwrap();
+
+        // 第一个文件转换后才在最后一个目录中新建文件：只有边遍历边转换才会处理到它
+        let late = input.join("z/late.png");
+        let run = |stream: bool, output: &Path| {
+            let converter = ImageConverter::with_options(ConvertOptions {
+                stream,
+                limit: Some(usize::MAX),
+                on_success: Some(HookCommand::new(format!("test -e '{0}' || cp {{input}} '{0}'", late.display()))),
+                ..Default::default()
+            });
+            converter.convert_directory(&input, output, "webp", 80.0).unwrap();
+            std::fs::remove_file(&late).unwrap();
+        };
+
+        let listed = dir.path().join("listed");
+        run(false, &listed);
+        assert!(listed.join("first.webp").exists());
+        assert!(!listed.join("late.webp").exists());
+
+        let streamed = dir.path().join("streamed");
+        run(true, &streamed);
+        assert!(streamed.join("first.webp").exists());
+        assert!(streamed.join("late.webp").exists());
+    }
+
+    #[test]
+    fn test_name_by_hash_dedupes_identical_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir_all(input.join("copy")).unwrap();
+        gradient(16, 16).save(input.join("a.png")).unwrap();
+        gradient(16, 16).save(input.join("copy/b.png")).unwrap();
+        gradient(24, 16).save(input.join("c.png")).unwrap();
+
+        let converter = ImageConverter::with_options(ConvertOptions { name_by_hash: true, ..Default::default() });
+        let output = dir.path().join("out");
+        let stats = converter.convert_entries(&input, &output, "png", 80.0, None, None).unwrap();
+        assert_eq!((stats.converted, stats.skipped), (2, 1));
+
+        let mut names: Vec<String> = std::fs::read_dir(&output)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 2);
+        for name in &names {
+            let data = std::fs::read(output.join(name)).unwrap();
+            assert_eq!(name, &format!("{}.png", sha256_hex(&data)));
+        }
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        // 单独转换相同的源文件得到同样的文件名
+        let single = dir.path().join("single");
+        converter.convert_single_file(&input.join("copy/b.png"), &single.join("b.png"), "png", 80.0).unwrap();
+        let single_name = std::fs::read_dir(&single).unwrap().next().unwrap().unwrap().file_name();
+        assert!(names.contains(&single_name.to_string_lossy().into_owned()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cancel_stops_batch_with_partial_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir_all(&input).unwrap();
+        for index in 0..5 {
+            gradient(8, 8).save(input.join(format!("{}.png", index))).unwrap();
+        }
+
+        // 第一个文件转换后钩子等待另一个线程设置取消标志
+        let (started, resumed) = (dir.path().join("started"), dir.path().join("resumed"));
+        let converter = ImageConverter::with_options(ConvertOptions {
+            limit: Some(usize::MAX),
+            on_success: Some(HookCommand::new(format!(
+                "touch '{}'; while [ ! -e '{1}' ]; do sleep 0.01; done",
+                started.display(),
+                resumed.display()
+            ))),
+            expect_min: Some(5),
+            ..Default::default()
+        });
+        let cancel = Arc::new(AtomicBool::new(false));
+        let canceller = {
+            let cancel = Arc::clone(&cancel);
+            std::thread::spawn(move || {
+                while !started.exists() {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                cancel.store(true, Ordering::Relaxed);
+                std::fs::write(resumed, b"").unwrap();
+            })
+        };
+
+        let output = dir.path().join("out");
+        let values = converter.convert_directory_cancellable(&input, &output, "webp", 80.0, &cancel).unwrap();
+        canceller.join().unwrap();
+        assert_eq!(values.converted, 1);
+        assert_eq!(values.failed, 0);
+        assert_eq!(std::fs::read_dir(&output).unwrap().count(), 1);
+        assert!(output.join("0.webp").exists());
+    }
+
+    #[test]
+    fn test_summary_values_count_failures_and_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(16, 16).save(input.join("a.png")).unwrap();
+        gradient(24, 8).save(input.join("b.png")).unwrap();
+        std::fs::write(input.join("broken.png"), b"\x89PNG\r\n\x1a\nbroken").unwrap();
+        std::fs::write(input.join("notes.txt"), b"x").unwrap();
+        let bytes_in: u64 = ["a.png", "b.png"].iter().map(|name| std::fs::metadata(input.join(name)).unwrap().len()).sum();
+
+        let output = dir.path().join("out");
+        let stats = ImageConverter::new().convert_entries(&input, &output, "png", 80.0, None, None).unwrap();
+        let values = stats.summary_values();
+        assert_eq!((values.converted, values.skipped, values.failed), (2, 1, 1));
+        assert_eq!(values.bytes_in, bytes_in);
+        assert_eq!(values.bytes_out, stats.written_bytes);
+
+        let template: SummaryTemplate = "{converted}/{total} failed={failed}".parse().unwrap();
+        assert_eq!(template.render(&values), "2/3 failed=1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_decode_timeout_skips_slow_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir(&input).unwrap();
+        gradient(8, 8).save(input.join("fast.png")).unwrap();
+        std::fs::write(input.join("stuck.cr2"), b"not an image PixForge knows").unwrap();
+        let decoded = dir.path().join("decoded.png");
+        gradient(8, 8).save(&decoded).unwrap();
+
+        // 模拟卡住的解码器：很久之后才写出结果
+        let slow = FallbackDecoder::new(format!("sleep 3; cp '{}' {{output_png}}", decoded.display()));
+        let converter = ImageConverter::with_options(ConvertOptions {
+            fallback_decoder: Some(slow),
+            decode_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        });
+
+        let start = std::time::Instant::now();
+        let output = dir.path().join("out");
+        let stats = converter.convert_entries(&input, &output, "png", 80.0, None, None).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+        assert_eq!((stats.converted, stats.failed), (1, 1));
+        assert!(output.join("fast.png").exists());
+        assert!(!output.join("stuck.png").exists());
+
+        let err = converter.convert_single_file(&input.join("stuck.cr2"), &output, "png", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::DecodeFailed { .. }), "{:?}", err);
+        assert!(format!("{:#}", anyhow::Error::from(err)).contains("解码超过 0.2 秒"));
+    }
+
+    #[test]
+    fn test_subpath_case_lowercases_mirrored_directories() {
+        let input = tempfile::tempdir().unwrap();
+        for dir in ["Photos/Summer", "photos/summer"] {
+            std::fs::create_dir_all(input.path().join(dir)).unwrap();
+        }
+        // 测试目录所在的文件系统不区分大小写时无法构造两个目录
+        if std::fs::read_dir(input.path()).unwrap().count() != 2 {
+            return;
+        }
+        for name in ["Photos/Summer/Beach.png", "Photos/Summer/dup.png", "photos/summer/dup.png"] {
+            gradient(8, 8).save(input.path().join(name)).unwrap();
+        }
+        let files = |subpath_case: SubpathCase| {
+            let output = tempfile::tempdir().unwrap();
+            let converter = ImageConverter::with_options(ConvertOptions {
+                collapse_depth: Some(2),
+                subpath_case,
+                ..Default::default()
+            });
+            converter.convert_directory(input.path(), output.path(), "png", 80.0).unwrap();
+            let mut files: Vec<String> = WalkDir::new(output.path())
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.path().strip_prefix(output.path()).unwrap().to_string_lossy().replace('\\', "/"))
+                .collect();
+            files.sort();
+            files
+        };
+
+        // 默认原样镜像
+        assert_eq!(
+            files(SubpathCase::Preserve),
+            vec!["Photos/Summer/Beach.png", "Photos/Summer/dup.png", "photos/summer/dup.png"]
+        );
+        // 统一为小写后同名文件按遍历顺序加序号，文件名保持原样
+        assert_eq!(
+            files(SubpathCase::Lower),
+            vec!["photos/summer/Beach.png", "photos/summer/dup.png", "photos/summer/dup_1.png"]
+        );
+        assert_eq!("LOWER".parse::<SubpathCase>(), Ok(SubpathCase::Lower));
+        assert!("upper".parse::<SubpathCase>().is_err());
+    }
+
+    #[test]
+    fn test_encode_image_from_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("nested/generated.png");
+        let img = gradient(20, 10);
+
+        ImageConverter::new().encode_image(&img, &output, "PNG", 80.0).unwrap();
+        assert_eq!(image::open(&output).unwrap().to_rgba8(), img.to_rgba8());
+
+        // 转换选项中的变换同样生效
+        let resized = dir.path().join("resized.png");
+        let converter = ImageConverter::with_options(ConvertOptions {
+            resize: Some(Resize { width: 10, height: 5, mode: transform::FitMode::Fill }),
+            ..Default::default()
+        });
+        converter.encode_image(&img, &resized, "png", 80.0).unwrap();
+        assert_eq!(image::open(&resized).unwrap().dimensions(), (10, 5));
+
+        let err = ImageConverter::new().encode_image(&img, &dir.path().join("x.bmp"), "bmp", 80.0).unwrap_err();
+        assert!(matches!(err, PixForgeError::UnsupportedTargetFormat(_)));
     }
 }
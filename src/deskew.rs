@@ -0,0 +1,218 @@
+//! 扫描文档的倾斜校正
+//!
+//! 在缩小后的灰度图上检测水平边缘，用霍夫变换在 ±15° 范围内寻找文字行和表格线最集中的角度，
+//! 再按该角度反向旋转图像
+
+use image::{DynamicImage, GrayImage, Rgba, Rgba32FImage};
+
+/// 可校正的最大倾斜角度 (度)
+pub const MAX_SKEW_DEGREES: f32 = 15.0;
+
+/// 估计倾斜前把较长边缩小到的像素数
+const ANALYSIS_SIZE: u32 = 1000;
+
+/// 灰度差超过该值的像素视为边缘
+const EDGE_THRESHOLD: i16 = 48;
+
+/// 边缘点太少时认为没有可用的线条
+const MIN_EDGE_POINTS: usize = 64;
+
+/// 参与投票的边缘点上限，超出时均匀抽取
+const MAX_EDGE_POINTS: usize = 200_000;
+
+/// 小于该角度时不旋转，避免无谓的重采样
+const MIN_CORRECTION_DEGREES: f32 = 0.05;
+
+/// 估计图像内容的倾斜角度 (度)
+///
+/// 正值表示线条向右下倾斜 (顺时针)，结果限制在 ±15° 内；找不到足够的边缘时返回 `None`
+pub fn estimate_skew(img: &DynamicImage) -> Option<f32> {
+    let gray = analysis_image(img);
+    let points = edge_points(&gray);
+    if points.len() < MIN_EDGE_POINTS {
+        return None;
+    }
+
+    // 先以0.5°步长粗搜，再在最佳角度附近以0.05°步长细搜
+    let coarse = best_angle(&points, gray.height(), -MAX_SKEW_DEGREES, MAX_SKEW_DEGREES, 0.5);
+    let fine = best_angle(
+        &points,
+        gray.height(),
+        (coarse - 0.5).max(-MAX_SKEW_DEGREES),
+        (coarse + 0.5).min(MAX_SKEW_DEGREES),
+        0.05,
+    );
+    Some(fine)
+}
+
+/// 估计倾斜角度并旋转校正，画布尺寸不变，旋转后露出的角落填充白色 (带透明通道时填充透明)
+///
+/// 返回校正后的图像和校正的角度，没有检测到倾斜时原样返回
+pub fn deskew(img: DynamicImage) -> (DynamicImage, f32) {
+    match estimate_skew(&img) {
+        Some(angle) if angle.abs() >= MIN_CORRECTION_DEGREES => (rotate(&img, -angle), angle),
+        _ => (img, 0.0),
+    }
+}
+
+/// 按 `degrees` 旋转图像 (正值为顺时针)，保持画布尺寸和颜色类型，双线性插值
+pub fn rotate(img: &DynamicImage, degrees: f32) -> DynamicImage {
+    let source = img.to_rgba32f();
+    let (width, height) = source.dimensions();
+    let fill = if img.color().has_alpha() { Rgba([1.0, 1.0, 1.0, 0.0]) } else { Rgba([1.0, 1.0, 1.0, 1.0]) };
+    let (sin, cos) = (-degrees).to_radians().sin_cos();
+    let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+
+    let rotated = Rgba32FImage::from_fn(width, height, |x, y| {
+        let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+        let sx = cx + dx * cos - dy * sin;
+        let sy = cy + dx * sin + dy * cos;
+        sample_bilinear(&source, sx, sy, fill)
+    });
+
+    let rotated = DynamicImage::ImageRgba32F(rotated);
+    match img {
+        DynamicImage::ImageLuma8(_) => rotated.to_luma8().into(),
+        DynamicImage::ImageLumaA8(_) => rotated.to_luma_alpha8().into(),
+        DynamicImage::ImageRgb8(_) => rotated.to_rgb8().into(),
+        DynamicImage::ImageRgba8(_) => rotated.to_rgba8().into(),
+        DynamicImage::ImageLuma16(_) => rotated.to_luma16().into(),
+        DynamicImage::ImageLumaA16(_) => rotated.to_luma_alpha16().into(),
+        DynamicImage::ImageRgb16(_) => rotated.to_rgb16().into(),
+        DynamicImage::ImageRgba16(_) => rotated.to_rgba16().into(),
+        DynamicImage::ImageRgb32F(_) => rotated.to_rgb32f().into(),
+        _ => rotated,
+    }
+}
+
+/// 双线性采样，超出图像的部分用 `fill` 的颜色
+fn sample_bilinear(source: &Rgba32FImage, x: f32, y: f32, fill: Rgba<f32>) -> Rgba<f32> {
+    let (width, height) = source.dimensions();
+    if x < -0.5 || y < -0.5 || x > width as f32 - 0.5 || y > height as f32 - 0.5 {
+        return fill;
+    }
+
+    let x = x.clamp(0.0, width as f32 - 1.0);
+    let y = y.clamp(0.0, height as f32 - 1.0);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let (p00, p10) = (source.get_pixel(x0, y0), source.get_pixel(x1, y0));
+    let (p01, p11) = (source.get_pixel(x0, y1), source.get_pixel(x1, y1));
+    let mut out = [0.0; 4];
+    for (channel, value) in out.iter_mut().enumerate() {
+        let top = p00[channel] * (1.0 - fx) + p10[channel] * fx;
+        let bottom = p01[channel] * (1.0 - fx) + p11[channel] * fx;
+        *value = top * (1.0 - fy) + bottom * fy;
+    }
+    Rgba(out)
+}
+
+/// 缩小到分析尺寸的灰度图，角度与缩放无关
+fn analysis_image(img: &DynamicImage) -> GrayImage {
+    let longest = img.width().max(img.height());
+    if longest <= ANALYSIS_SIZE {
+        return img.to_luma8();
+    }
+    img.resize(ANALYSIS_SIZE, ANALYSIS_SIZE, image::imageops::FilterType::Triangle).to_luma8()
+}
+
+/// 纵向灰度差较大的像素，即水平方向线条的上下边缘
+fn edge_points(gray: &GrayImage) -> Vec<(f32, f32)> {
+    let (width, height) = gray.dimensions();
+    let mut points = Vec::new();
+    for y in 0..height.saturating_sub(1) {
+        for x in 0..width {
+            let here = i16::from(gray.get_pixel(x, y)[0]);
+            let below = i16::from(gray.get_pixel(x, y + 1)[0]);
+            if (here - below).abs() > EDGE_THRESHOLD {
+                points.push((x as f32, y as f32 + 0.5));
+            }
+        }
+    }
+
+    if points.len() > MAX_EDGE_POINTS {
+        let step = points.len().div_ceil(MAX_EDGE_POINTS);
+        points = points.into_iter().step_by(step).collect();
+    }
+    points
+}
+
+/// 在 `[from, to]` 范围内按步长搜索霍夫投票最集中的角度
+///
+/// 每个角度把边缘点投到直线法向距离 `y·cosθ - x·sinθ` 的1像素分箱中，
+/// 分箱计数的平方和越大，说明边缘越集中在平行于该角度的直线上
+fn best_angle(points: &[(f32, f32)], height: u32, from: f32, to: f32, step: f32) -> f32 {
+    let max_x = points.iter().map(|&(x, _)| x).fold(0.0_f32, f32::max);
+    // 法向距离的范围：|x·sinθ| 不超过 max_x·sin15°
+    let offset = (max_x * MAX_SKEW_DEGREES.to_radians().sin()).ceil() + 2.0;
+    let bins = (height as f32 + 2.0 * offset).ceil() as usize + 1;
+    let mut accumulator = vec![0u32; bins];
+
+    let steps = ((to - from) / step).round() as i32;
+    let mut best = (0.0, 0u64);
+    for index in 0..=steps {
+        let angle = from + index as f32 * step;
+        let (sin, cos) = angle.to_radians().sin_cos();
+        accumulator.fill(0);
+        for &(x, y) in points {
+            let rho = y * cos - x * sin + offset;
+            accumulator[(rho as usize).min(bins - 1)] += 1;
+        }
+        let score = accumulator.iter().map(|&count| u64::from(count) * u64::from(count)).sum::<u64>();
+        // 得分相同时取绝对值更小的角度
+        if score > best.1 || (score == best.1 && angle.abs() < f32::abs(best.0)) {
+            best = (angle, score);
+        }
+    }
+    best.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, RgbImage};
+
+    /// 白底上若干条黑色横线，模拟文字行
+    fn lined_page(width: u32, height: u32) -> DynamicImage {
+        let page = GrayImage::from_fn(width, height, |x, y| {
+            let on_line = y % 24 < 4 && y > 20 && y < height - 20 && x > 30 && x < width - 30;
+            if on_line { Luma([0]) } else { Luma([255]) }
+        });
+        DynamicImage::ImageLuma8(page)
+    }
+
+    #[test]
+    fn test_estimate_skew_matches_applied_rotation() {
+        let page = lined_page(600, 400);
+        assert!(estimate_skew(&page).unwrap().abs() < 0.2);
+
+        for applied in [3.0_f32, -7.5, 12.0] {
+            let skewed = rotate(&page, applied);
+            let detected = estimate_skew(&skewed).unwrap();
+            assert!((detected - applied).abs() < 0.3, "applied {}, detected {}", applied, detected);
+        }
+    }
+
+    #[test]
+    fn test_deskew_straightens_and_keeps_size() {
+        let page = lined_page(600, 400);
+        let (straightened, angle) = deskew(rotate(&page, 5.0));
+        assert!((angle - 5.0).abs() < 0.3, "{}", angle);
+        assert_eq!((straightened.width(), straightened.height()), (600, 400));
+        assert!(matches!(straightened, DynamicImage::ImageLuma8(_)));
+        assert!(estimate_skew(&straightened).unwrap().abs() < 0.3);
+
+        // 角落露出的部分填充白色
+        let rotated = rotate(&DynamicImage::ImageRgb8(RgbImage::new(100, 100)), 10.0);
+        assert_eq!(rotated.to_rgb8().get_pixel(0, 0).0, [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_blank_page_is_left_alone() {
+        let blank = DynamicImage::ImageLuma8(GrayImage::from_pixel(200, 200, Luma([255])));
+        assert_eq!(estimate_skew(&blank), None);
+        assert_eq!(deskew(blank.clone()).0, blank);
+    }
+}
@@ -0,0 +1,97 @@
+//! 转换器对外返回的错误类型
+//!
+//! 内部仍使用 `anyhow` 传递错误，在能确定错误类别的位置构造 [`PixForgeError`]，
+//! 公开方法返回前再从错误链中取回，调用方可以按变体分别处理
+
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// 错误来源，可以由 `anyhow::Error` 或任意标准错误转换而来
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// PixForge转换错误
+#[derive(Debug, Error)]
+pub enum PixForgeError {
+    /// 输入不是可识别的图像文件
+    #[error("不支持的图像格式: {}", path.display())]
+    UnsupportedSourceFormat { path: PathBuf },
+
+    /// 目标格式没有对应的编码器
+    #[error("不支持的目标格式: {0}")]
+    UnsupportedTargetFormat(String),
+
+    /// 源图像解码失败
+    #[error("解码失败: {} (检测到的格式: {detected}, 目标格式: {target})", path.display())]
+    DecodeFailed {
+        path: PathBuf,
+        detected: String,
+        target: String,
+        #[source]
+        source: BoxError,
+    },
+
+    /// 编码目标格式失败
+    #[error("编码失败: {} (目标格式: {format})", path.display())]
+    EncodeFailed {
+        path: PathBuf,
+        format: String,
+        #[source]
+        source: BoxError,
+    },
+
+    /// 读写文件或创建目录失败
+    #[error("文件读写失败: {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// 输入文件超过 `max_file_size`
+    #[error("文件过大: {} ({size} > {limit} 字节)", path.display())]
+    TooLarge { path: PathBuf, size: u64, limit: u64 },
+
+    /// 输入的实际格式在 `reject_formats` 中
+    #[error("拒绝的格式: {} (检测到的格式: {format})", path.display())]
+    RejectedFormat { path: PathBuf, format: String },
+
+    /// 设置了 `allow_formats` 时输入的实际格式不在其中
+    #[error("不允许的输入格式: {} (检测到的格式: {format}，只接受: {allowed})", path.display())]
+    DisallowedFormat { path: PathBuf, format: String, allowed: String },
+
+    /// 批量转换成功的文件数少于 `expect_min`
+    #[error("转换的文件太少: {converted} 个，至少需要 {expected} 个")]
+    TooFewConverted { converted: u32, expected: u32 },
+
+    /// 其它错误，如无损校验失败、缺少透明通道等
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl PixForgeError {
+    /// 从内部错误链中取出已分类的错误，没有时归为 [`PixForgeError::Other`]
+    pub(crate) fn from_anyhow(error: anyhow::Error) -> Self {
+        error.downcast::<PixForgeError>().unwrap_or_else(PixForgeError::Other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_from_anyhow_recovers_classified_error() {
+        let error = anyhow::Error::new(PixForgeError::UnsupportedTargetFormat("bmp".to_string()))
+            .context("外层上下文");
+        assert!(matches!(
+            PixForgeError::from_anyhow(error),
+            PixForgeError::UnsupportedTargetFormat(format) if format == "bmp"
+        ));
+
+        let error = Err::<(), _>(anyhow::anyhow!("无损校验失败")).context("转换失败").unwrap_err();
+        let error = PixForgeError::from_anyhow(error);
+        assert!(matches!(error, PixForgeError::Other(_)));
+        assert_eq!(error.to_string(), "转换失败");
+    }
+}
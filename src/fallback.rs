@@ -0,0 +1,130 @@
+//! 用外部命令解码PixForge不支持的输入
+//!
+//! 命令模板中的 `{input}` 替换为源文件，`{output_png}` 替换为临时PNG文件，
+//! 命令成功后按普通PNG输入继续转换，例如
+//! `dcraw -c -w {input} | convert - {output_png}`
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::hooks::{shell_command, substitute};
+use crate::utils;
+
+/// 错误信息中保留的外部命令标准错误输出的最大字符数
+const STDERR_LIMIT: usize = 500;
+
+/// 同一进程内临时文件名的序号
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+/// 后备解码命令模板
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackDecoder {
+    template: String,
+}
+
+/// 后备解码得到的临时PNG文件，丢弃时删除
+#[derive(Debug)]
+pub struct DecodedPng {
+    path: PathBuf,
+}
+
+impl DecodedPng {
+    /// 临时PNG文件路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for DecodedPng {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl FallbackDecoder {
+    /// 创建命令模板
+    pub fn new(template: impl Into<String>) -> Self {
+        Self { template: template.into() }
+    }
+
+    /// 替换占位符，得到最终执行的命令行
+    pub fn render(&self, input: &Path, output_png: &Path) -> String {
+        substitute(&self.template, &[("{input}", input), ("{output_png}", output_png)])
+    }
+
+    /// 执行命令把输入转换为临时PNG
+    ///
+    /// 命令退出码非零、没有写出文件或写出的不是PNG时返回错误，
+    /// 错误信息包含命令行和标准错误输出
+    pub fn decode(&self, input: &Path) -> Result<DecodedPng> {
+        let serial = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+        let png = DecodedPng {
+            path: std::env::temp_dir().join(format!("pixforge-fallback-{}-{}.png", std::process::id(), serial)),
+        };
+        let command_line = self.render(input, png.path());
+
+        let output = shell_command(&command_line)
+            .output()
+            .with_context(|| format!("无法执行后备解码命令: {}", command_line))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim();
+            let status = match output.status.code() {
+                Some(code) => format!("退出码 {}", code),
+                None => "被信号终止".to_string(),
+            };
+            if stderr.is_empty() {
+                anyhow::bail!("后备解码命令{}: {}", status, command_line);
+            }
+            let stderr: String = stderr.chars().take(STDERR_LIMIT).collect();
+            anyhow::bail!("后备解码命令{}: {}\n{}", status, command_line, stderr);
+        }
+
+        match utils::detect_image_format_by_content(png.path()).as_deref() {
+            Some("png") => Ok(png),
+            Some(other) => anyhow::bail!("后备解码命令输出的不是PNG (检测到 {}): {}", other, command_line),
+            None => anyhow::bail!("后备解码命令没有写出PNG文件: {}", command_line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_quotes_paths() {
+        let decoder = FallbackDecoder::new("dcraw -c {input} > {output_png}");
+        let rendered = decoder.render(Path::new("my raw.cr2"), Path::new("/tmp/out.png"));
+        assert_eq!(rendered, "dcraw -c 'my raw.cr2' > '/tmp/out.png'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_does_not_rescan_paths() {
+        let decoder = FallbackDecoder::new("dcraw -c {input} > {output_png}");
+        let rendered = decoder.render(Path::new("x{output_png}$(id).cr2"), Path::new("/tmp/out.png"));
+        assert_eq!(rendered, "dcraw -c 'x{output_png}$(id).cr2' > '/tmp/out.png'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_decode_reports_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.raw");
+        fs::write(&input, b"raw").unwrap();
+
+        let err = FallbackDecoder::new("echo 'bad raw data' >&2; exit 2").decode(&input).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("退出码 2") && message.contains("bad raw data"), "{}", message);
+
+        let err = FallbackDecoder::new("true").decode(&input).unwrap_err();
+        assert!(err.to_string().contains("没有写出PNG文件"), "{}", err);
+
+        let err = FallbackDecoder::new("cp {input} {output_png}").decode(&input).unwrap_err();
+        assert!(err.to_string().contains("没有写出PNG文件"), "{}", err);
+    }
+}
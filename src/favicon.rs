@@ -0,0 +1,131 @@
+//! 从一张图像生成网站常用的图标文件
+//!
+//! 按惯例的文件名输出多尺寸的 `favicon.ico`、浏览器标签页用的 `favicon-16x16.png` 等PNG、
+//! iOS主屏幕的 `apple-touch-icon.png` (180) 和Android的 `android-chrome-192x192.png`、`android-chrome-512x512.png`
+
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ico::{self, SHARPEN_MAX_SIZE};
+
+/// 默认写入 `favicon.ico` 的帧尺寸
+pub const DEFAULT_ICO_SIZES: &[u32] = &[16, 32, 48];
+
+/// 默认输出的PNG尺寸
+pub const DEFAULT_PNG_SIZES: &[u32] = &[16, 32, 180, 192, 512];
+
+/// iOS主屏幕图标的尺寸
+const APPLE_TOUCH_SIZE: u32 = 180;
+
+/// Android主屏幕和PWA清单使用的尺寸
+const ANDROID_CHROME_SIZES: &[u32] = &[192, 512];
+
+/// 图标包的内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaviconOptions {
+    /// `favicon.ico` 的帧尺寸 (1-256)，为空时不生成ICO
+    pub ico_sizes: Vec<u32>,
+    /// 单独输出的PNG尺寸
+    pub png_sizes: Vec<u32>,
+    /// 不超过32像素的图标做轻微锐化
+    pub sharpen: bool,
+}
+
+impl Default for FaviconOptions {
+    fn default() -> Self {
+        Self { ico_sizes: DEFAULT_ICO_SIZES.to_vec(), png_sizes: DEFAULT_PNG_SIZES.to_vec(), sharpen: false }
+    }
+}
+
+/// 该尺寸PNG图标的惯用文件名
+pub fn png_file_name(size: u32) -> String {
+    if size == APPLE_TOUCH_SIZE {
+        "apple-touch-icon.png".to_string()
+    } else if ANDROID_CHROME_SIZES.contains(&size) {
+        format!("android-chrome-{0}x{0}.png", size)
+    } else {
+        format!("favicon-{0}x{0}.png", size)
+    }
+}
+
+/// 把图标包写入 `output_dir`，返回写出的文件
+///
+/// 每个尺寸都从原图直接缩放，非正方形的图像保持宽高比，居中放在透明的正方形画布上
+pub fn write_bundle(img: &DynamicImage, output_dir: &Path, options: &FaviconOptions) -> Result<Vec<PathBuf>> {
+    if options.ico_sizes.is_empty() && options.png_sizes.is_empty() {
+        anyhow::bail!("没有要生成的图标尺寸");
+    }
+    if let Some(&size) = options.png_sizes.iter().find(|&&size| size == 0) {
+        anyhow::bail!("无效的PNG图标尺寸: {}", size);
+    }
+    fs::create_dir_all(output_dir).with_context(|| format!("无法创建输出目录: {}", output_dir.display()))?;
+
+    let mut written = Vec::new();
+    if !options.ico_sizes.is_empty() {
+        let path = output_dir.join("favicon.ico");
+        let data = ico::encode_multi_size(img, &options.ico_sizes, options.sharpen)?;
+        fs::write(&path, data).with_context(|| format!("无法写入: {}", path.display()))?;
+        written.push(path);
+    }
+
+    let mut png_sizes = options.png_sizes.clone();
+    png_sizes.sort_unstable();
+    png_sizes.dedup();
+    for size in png_sizes {
+        let path = output_dir.join(png_file_name(size));
+        let frame = ico::square_frame(img, size, options.sharpen && size <= SHARPEN_MAX_SIZE);
+        frame.save(&path).with_context(|| format!("无法写入: {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ico::IcoPick;
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    #[test]
+    fn test_default_bundle_files_and_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let logo = RgbaImage::from_fn(600, 400, |x, y| Rgba([(x / 3) as u8, (y / 2) as u8, 90, 255]));
+        let logo = DynamicImage::ImageRgba8(logo);
+        let written = write_bundle(&logo, dir.path(), &FaviconOptions::default()).unwrap();
+        assert_eq!(written.len(), 6);
+
+        for (name, size) in [
+            ("favicon-16x16.png", 16),
+            ("favicon-32x32.png", 32),
+            ("apple-touch-icon.png", 180),
+            ("android-chrome-192x192.png", 192),
+            ("android-chrome-512x512.png", 512),
+        ] {
+            let icon = image::open(dir.path().join(name)).unwrap();
+            assert_eq!(icon.dimensions(), (size, size), "{}", name);
+            // 非正方形的源图像上下补透明
+            assert_eq!(icon.to_rgba8().get_pixel(size / 2, 0)[3], 0, "{}", name);
+        }
+
+        let data = fs::read(dir.path().join("favicon.ico")).unwrap();
+        for size in DEFAULT_ICO_SIZES {
+            let frame = ico::decode_ico_bytes(&data, IcoPick::Exact(*size, *size)).unwrap();
+            assert_eq!(frame.dimensions(), (*size, *size));
+        }
+    }
+
+    #[test]
+    fn test_custom_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let logo = DynamicImage::ImageRgba8(RgbaImage::from_pixel(64, 64, Rgba([200, 30, 30, 255])));
+        let options = FaviconOptions { ico_sizes: Vec::new(), png_sizes: vec![96, 180], sharpen: true };
+        let written = write_bundle(&logo, dir.path(), &options).unwrap();
+        assert_eq!(written, vec![dir.path().join("favicon-96x96.png"), dir.path().join("apple-touch-icon.png")]);
+        assert!(!dir.path().join("favicon.ico").exists());
+
+        let empty = FaviconOptions { ico_sizes: Vec::new(), png_sizes: Vec::new(), sharpen: false };
+        assert!(write_bundle(&logo, dir.path(), &empty).is_err());
+    }
+}
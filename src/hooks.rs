@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// 转换后执行的外部命令模板
+///
+/// 模板中的 `{input}` 与 `{output}` 会被替换为经过shell转义的路径，
+/// 然后交给系统shell执行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookCommand {
+    template: String,
+}
+
+impl HookCommand {
+    /// 创建命令模板
+    pub fn new(template: impl Into<String>) -> Self {
+        Self { template: template.into() }
+    }
+
+    /// 替换占位符，得到最终执行的命令行
+    pub fn render(&self, input: &Path, output: &Path) -> String {
+        substitute(&self.template, &[("{input}", input), ("{output}", output)])
+    }
+
+    /// 执行命令，非零退出码视为错误
+    pub fn run(&self, input: &Path, output: &Path) -> Result<()> {
+        self.execute(input, output, false)
+    }
+
+    /// 与 [`run`](Self::run) 相同，但命令的标准输出转到标准错误，用于标准输出只能写进度行的场合
+    pub fn run_to_stderr(&self, input: &Path, output: &Path) -> Result<()> {
+        self.execute(input, output, true)
+    }
+
+    fn execute(&self, input: &Path, output: &Path, stdout_to_stderr: bool) -> Result<()> {
+        let command_line = self.render(input, output);
+        let mut command = shell_command(&command_line);
+        if stdout_to_stderr {
+            command.stdout(std::io::stderr());
+        }
+        let status = command
+            .status()
+            .with_context(|| format!("无法执行钩子命令: {}", command_line))?;
+
+        if !status.success() {
+            match status.code() {
+                Some(code) => anyhow::bail!("钩子命令退出码 {}: {}", code, command_line),
+                None => anyhow::bail!("钩子命令被信号终止: {}", command_line),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 构造系统shell命令
+#[cfg(windows)]
+pub(crate) fn shell_command(command_line: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    // cmd不按C运行库的规则解析参数，命令行必须原样传入，不能再由 `arg` 加上反斜杠转义；
+    // /S 只去掉最外层的一对引号，命令行中其它引号保持不变
+    let mut command = Command::new("cmd");
+    command.raw_arg(format!("/S /C \"{}\"", command_line));
+    command
+}
+
+/// 构造系统shell命令
+#[cfg(not(windows))]
+pub(crate) fn shell_command(command_line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.args(["-c", command_line]);
+    command
+}
+
+/// 从左到右扫描一遍模板，把每个占位符换成经过shell转义的路径
+///
+/// 替换进去的路径不会再被扫描，文件名中的 `{output}` 等文本不会被当作占位符
+pub(crate) fn substitute(template: &str, placeholders: &[(&str, &Path)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(ch) = rest.chars().next() {
+        match placeholders.iter().find(|(token, _)| rest.starts_with(token)) {
+            Some((token, path)) => {
+                rendered.push_str(&shell_quote(&path.display().to_string()));
+                rest = &rest[token.len()..];
+            }
+            None => {
+                rendered.push(ch);
+                rest = &rest[ch.len_utf8()..];
+            }
+        }
+    }
+    rendered
+}
+
+/// 对路径做shell转义，避免空格或特殊字符破坏命令
+pub(crate) fn shell_quote(value: &str) -> String {
+    if cfg!(windows) {
+        cmd_quote(value)
+    } else {
+        posix_quote(value)
+    }
+}
+
+/// POSIX shell：单引号内没有任何特殊字符，单引号本身写成 `'\''`
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// cmd：双引号内的 `&`、`|`、`^` 等没有特殊含义，但 `%变量%` 仍会展开
+///
+/// cmd没有转义 `%` 的写法，每个 `%` 后面接上 `%cd:~,%` (展开为空的子串)，让它无法与后面的 `%` 组成变量名。
+/// 反斜杠在cmd中不是转义字符，双引号写成两个，引号的开闭状态保持不变 (Windows文件名本身不能包含双引号)
+fn cmd_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\"").replace('%', "%%cd:~,%"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_quotes_paths() {
+        let hook = HookCommand::new("cp {input} {output}");
+        let rendered = hook.render(Path::new("my photo.png"), Path::new("it's.webp"));
+        assert_eq!(rendered, "cp 'my photo.png' 'it'\\''s.webp'");
+    }
+
+    #[test]
+    fn test_cmd_quote_keeps_percent_literal() {
+        assert_eq!(cmd_quote(r"C:\My Photos\a&b.png"), r#""C:\My Photos\a&b.png""#);
+        assert_eq!(cmd_quote("100%PATH%.png"), r#""100%%cd:~,%PATH%%cd:~,%.png""#);
+        assert_eq!(cmd_quote(r#"say "hi""#), r#""say ""hi""""#);
+        assert_eq!(posix_quote("it's"), "'it'\\''s'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hostile_file_name_stays_quoted() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker.txt");
+        let injected = dir.path().join("injected");
+        let hook = HookCommand::new(format!("printf '%s\\n' {{input}} {{output}} > '{}'", marker.display()));
+
+        // 文件名中的 {output} 不能被再次替换，否则插入的输出路径会让引号提前闭合，$(...) 被shell执行
+        let input = format!("a{{output}}$(touch {}).png", injected.display());
+        let output = input.replace(".png", ".webp");
+        hook.run(Path::new(&input), Path::new(&output)).unwrap();
+        assert!(!injected.exists());
+        let lines: Vec<String> = fs::read_to_string(&marker).unwrap().lines().map(str::to_string).collect();
+        assert_eq!(lines, vec![input, output]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_echo_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker.txt");
+        let hook = HookCommand::new(format!("echo {{input}} {{output}} > '{}'", marker.display()));
+
+        hook.run(Path::new("a.png"), Path::new("a.webp")).unwrap();
+        assert_eq!(fs::read_to_string(&marker).unwrap().trim(), "a.png a.webp");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_reports_exit_code() {
+        let err = HookCommand::new("exit 3").run(Path::new("a"), Path::new("b")).unwrap_err();
+        assert!(err.to_string().contains("退出码 3"));
+    }
+}
@@ -0,0 +1,341 @@
+use anyhow::{Context, Result};
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::imageops::FilterType;
+use image::{DynamicImage, ExtendedColorType, RgbaImage};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::utils;
+
+/// ICO文件头长度 (ICONDIR)
+const ICO_HEADER_SIZE: usize = 6;
+
+/// ICO目录项长度 (ICONDIRENTRY)
+const ICO_ENTRY_SIZE: usize = 16;
+
+/// PNG文件签名，Vista之后的ICO帧可以直接嵌入PNG
+const PNG_SIGNATURE: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// 不超过该边长的帧在开启锐化时做反锐化掩模
+pub const SHARPEN_MAX_SIZE: u32 = 32;
+
+/// 按PNG嵌入的最小帧边长，更小的帧使用BMP
+const PNG_FRAME_MIN_SIZE: u32 = 256;
+
+/// ICO输入的帧选择策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IcoPick {
+    /// 选择面积最大的帧
+    #[default]
+    Largest,
+    /// 选择面积最小的帧
+    Smallest,
+    /// 选择指定尺寸的帧
+    Exact(u32, u32),
+}
+
+impl FromStr for IcoPick {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "largest" => Ok(IcoPick::Largest),
+            "smallest" => Ok(IcoPick::Smallest),
+            other => utils::parse_dimensions(other)
+                .map(|(width, height)| IcoPick::Exact(width, height))
+                .map_err(|_| format!("无效的ICO帧选择: {} (可选 largest, smallest, NxN)", s)),
+        }
+    }
+}
+
+/// ICO目录中的单个帧条目
+#[derive(Debug, Clone, Copy)]
+struct IcoEntry {
+    index: usize,
+    width: u32,
+    height: u32,
+    bit_count: u16,
+    offset: usize,
+    size: usize,
+}
+
+impl IcoEntry {
+    fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+}
+
+/// 解码ICO文件，按策略选择其中一帧
+///
+/// # 参数
+/// * `path` - ICO文件路径
+/// * `pick` - 帧选择策略
+pub fn decode_ico(path: &Path, pick: IcoPick) -> Result<image::DynamicImage> {
+    let data = fs::read(path)
+        .with_context(|| format!("无法读取ICO文件: {}", path.display()))?;
+    decode_ico_bytes(&data, pick)
+}
+
+/// 从内存中的ICO数据解码选中的帧
+pub fn decode_ico_bytes(data: &[u8], pick: IcoPick) -> Result<image::DynamicImage> {
+    let entries = parse_entries(data)?;
+    let entry = select_entry(&entries, pick)?;
+
+    let frame = &data[entry.offset..entry.offset + entry.size];
+    if frame.starts_with(PNG_SIGNATURE) {
+        return image::load_from_memory_with_format(frame, image::ImageFormat::Png)
+            .context("ICO中的PNG帧解码失败");
+    }
+
+    // BMP帧没有独立的文件头，重新包装成只含这一帧的ICO交给image解码
+    let single = wrap_single_entry(data, entry);
+    image::load_from_memory_with_format(&single, image::ImageFormat::Ico)
+        .context("ICO中的BMP帧解码失败")
+}
+
+/// 生成包含多个尺寸的ICO
+///
+/// 每一帧都直接从原图用Lanczos3缩放，而不是从上一个尺寸逐级缩小；非正方形的图像保持宽高比，
+/// 居中放在透明的正方形画布上。`sharpen` 为真时对不超过32像素的帧做轻微锐化，
+/// 弥补缩小后的模糊。256像素的帧按PNG嵌入 (Windows Vista起支持)，其余帧使用32位BMP，兼容旧系统
+pub fn encode_multi_size(img: &DynamicImage, sizes: &[u32], sharpen: bool) -> Result<Vec<u8>> {
+    let mut sizes = sizes.to_vec();
+    sizes.sort_unstable();
+    sizes.dedup();
+    if let Some(&size) = sizes.iter().find(|&&size| !(1..=256).contains(&size)) {
+        anyhow::bail!("ICO帧尺寸必须在1-256之间: {}", size);
+    }
+
+    let frames = sizes
+        .iter()
+        .map(|&size| {
+            let frame = square_frame(img, size, sharpen && size <= SHARPEN_MAX_SIZE);
+            if size >= PNG_FRAME_MIN_SIZE {
+                IcoFrame::as_png(frame.as_raw(), size, size, ExtendedColorType::Rgba8)
+            } else {
+                IcoFrame::with_encoded(bmp_frame(&frame), size, size, ExtendedColorType::Rgba8)
+            }
+        })
+        .collect::<image::ImageResult<Vec<_>>>()
+        .context("ICO帧编码失败")?;
+
+    let mut data = Vec::new();
+    IcoEncoder::new(&mut data).encode_images(&frames).context("ICO编码失败")?;
+    Ok(data)
+}
+
+/// 从原图缩放出 `size`x`size` 的帧，不足的一边用透明像素补齐
+pub fn square_frame(img: &DynamicImage, size: u32, sharpen: bool) -> RgbaImage {
+    let mut scaled = img.resize(size, size, FilterType::Lanczos3);
+    if sharpen {
+        scaled = scaled.unsharpen(0.5, 1);
+    }
+    let scaled = scaled.to_rgba8();
+    let mut frame = RgbaImage::new(size, size);
+    let x = (size - scaled.width()) / 2;
+    let y = (size - scaled.height()) / 2;
+    image::imageops::overlay(&mut frame, &scaled, i64::from(x), i64::from(y));
+    frame
+}
+
+/// ICO中的32位BMP帧：BITMAPINFOHEADER (高度含AND掩码，为两倍)、自下而上的BGRA像素、全0的AND掩码
+fn bmp_frame(frame: &RgbaImage) -> Vec<u8> {
+    let (width, height) = frame.dimensions();
+    // AND掩码每像素1位，每行按4字节对齐
+    let mask_row = width.div_ceil(32) as usize * 4;
+    let pixel_bytes = (width * height * 4) as usize;
+
+    let mut dib = Vec::with_capacity(40 + pixel_bytes + mask_row * height as usize);
+    dib.extend_from_slice(&40u32.to_le_bytes());
+    dib.extend_from_slice(&(width as i32).to_le_bytes());
+    dib.extend_from_slice(&(height as i32 * 2).to_le_bytes());
+    dib.extend_from_slice(&1u16.to_le_bytes());
+    dib.extend_from_slice(&32u16.to_le_bytes());
+    dib.extend_from_slice(&0u32.to_le_bytes());
+    dib.extend_from_slice(&(pixel_bytes as u32).to_le_bytes());
+    dib.extend_from_slice(&[0u8; 16]);
+    for row in frame.rows().rev() {
+        for pixel in row {
+            let [r, g, b, a] = pixel.0;
+            dib.extend_from_slice(&[b, g, r, a]);
+        }
+    }
+    dib.resize(dib.len() + mask_row * height as usize, 0);
+    dib
+}
+
+/// 解析ICO目录
+fn parse_entries(data: &[u8]) -> Result<Vec<IcoEntry>> {
+    if data.len() < ICO_HEADER_SIZE || data[0..4] != [0x00, 0x00, 0x01, 0x00] {
+        anyhow::bail!("不是有效的ICO文件");
+    }
+
+    let count = u16::from_le_bytes([data[4], data[5]]) as usize;
+    if count == 0 {
+        anyhow::bail!("ICO文件中没有任何帧");
+    }
+
+    let mut entries = Vec::with_capacity(count);
+    for index in 0..count {
+        let start = ICO_HEADER_SIZE + index * ICO_ENTRY_SIZE;
+        let raw = data
+            .get(start..start + ICO_ENTRY_SIZE)
+            .context("ICO目录被截断")?;
+
+        // 宽高字段为0表示256
+        let width = if raw[0] == 0 { 256 } else { raw[0] as u32 };
+        let height = if raw[1] == 0 { 256 } else { raw[1] as u32 };
+        let bit_count = u16::from_le_bytes([raw[6], raw[7]]);
+        let size = u32::from_le_bytes([raw[8], raw[9], raw[10], raw[11]]) as usize;
+        let offset = u32::from_le_bytes([raw[12], raw[13], raw[14], raw[15]]) as usize;
+
+        if offset.checked_add(size).is_none_or(|end| end > data.len()) {
+            anyhow::bail!("ICO帧数据越界: 第 {} 帧", index + 1);
+        }
+
+        entries.push(IcoEntry { index, width, height, bit_count, offset, size });
+    }
+
+    Ok(entries)
+}
+
+/// 按策略选择帧，面积相同时优先选择色深更高的帧
+fn select_entry(entries: &[IcoEntry], pick: IcoPick) -> Result<IcoEntry> {
+    let selected = match pick {
+        IcoPick::Largest => entries
+            .iter()
+            .max_by_key(|entry| (entry.area(), entry.bit_count)),
+        IcoPick::Smallest => entries
+            .iter()
+            .min_by_key(|entry| (entry.area(), std::cmp::Reverse(entry.bit_count))),
+        IcoPick::Exact(width, height) => entries
+            .iter()
+            .filter(|entry| entry.width == width && entry.height == height)
+            .max_by_key(|entry| entry.bit_count),
+    };
+
+    selected.copied().with_context(|| {
+        let available: Vec<String> = entries
+            .iter()
+            .map(|entry| format!("{}x{}", entry.width, entry.height))
+            .collect();
+        format!("ICO中没有匹配的帧，可用尺寸: {}", available.join(", "))
+    })
+}
+
+/// 构造只包含单个帧的ICO数据
+fn wrap_single_entry(data: &[u8], entry: IcoEntry) -> Vec<u8> {
+    let mut single = Vec::with_capacity(ICO_HEADER_SIZE + ICO_ENTRY_SIZE + entry.size);
+    single.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x01, 0x00]);
+
+    // 保留原目录项的前12字节，只改写数据偏移
+    let start = ICO_HEADER_SIZE + entry.index * ICO_ENTRY_SIZE;
+    single.extend_from_slice(&data[start..start + 12]);
+    single.extend_from_slice(&((ICO_HEADER_SIZE + ICO_ENTRY_SIZE) as u32).to_le_bytes());
+    single.extend_from_slice(&data[entry.offset..entry.offset + entry.size]);
+    single
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// 构造包含 16x16(红) 与 48x48(蓝) 两帧的ICO
+    fn multi_frame_ico() -> Vec<u8> {
+        let small = RgbaImage::from_pixel(16, 16, Rgba([255, 0, 0, 255]));
+        let large = RgbaImage::from_pixel(48, 48, Rgba([0, 0, 255, 255]));
+        let frames = vec![
+            IcoFrame::as_png(small.as_raw(), 16, 16, ExtendedColorType::Rgba8).unwrap(),
+            IcoFrame::as_png(large.as_raw(), 48, 48, ExtendedColorType::Rgba8).unwrap(),
+        ];
+
+        let mut data = Vec::new();
+        IcoEncoder::new(&mut data).encode_images(&frames).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_ico_pick_from_str() {
+        assert_eq!("largest".parse::<IcoPick>(), Ok(IcoPick::Largest));
+        assert_eq!("Smallest".parse::<IcoPick>(), Ok(IcoPick::Smallest));
+        assert_eq!("32x32".parse::<IcoPick>(), Ok(IcoPick::Exact(32, 32)));
+        assert!("huge".parse::<IcoPick>().is_err());
+    }
+
+    #[test]
+    fn test_decode_ico_picks_frame() {
+        let data = multi_frame_ico();
+
+        let largest = decode_ico_bytes(&data, IcoPick::Largest).unwrap().to_rgba8();
+        assert_eq!(largest.dimensions(), (48, 48));
+        assert_eq!(largest.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+
+        let smallest = decode_ico_bytes(&data, IcoPick::Smallest).unwrap().to_rgba8();
+        assert_eq!(smallest.dimensions(), (16, 16));
+        assert_eq!(smallest.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+
+        let exact = decode_ico_bytes(&data, IcoPick::Exact(16, 16)).unwrap();
+        assert_eq!((exact.width(), exact.height()), (16, 16));
+
+        assert!(decode_ico_bytes(&data, IcoPick::Exact(32, 32)).is_err());
+    }
+
+    #[test]
+    fn test_decode_ico_bmp_frame() {
+        // 2x2 32位BMP帧: BITMAPINFOHEADER + BGRA像素 + AND掩码
+        let mut dib = Vec::new();
+        dib.extend_from_slice(&40u32.to_le_bytes());
+        dib.extend_from_slice(&2i32.to_le_bytes());
+        dib.extend_from_slice(&4i32.to_le_bytes()); // 高度包含AND掩码，需翻倍
+        dib.extend_from_slice(&1u16.to_le_bytes());
+        dib.extend_from_slice(&32u16.to_le_bytes());
+        dib.extend_from_slice(&[0u8; 24]);
+        for _ in 0..4 {
+            dib.extend_from_slice(&[0, 255, 0, 255]);
+        }
+        dib.extend_from_slice(&[0u8; 8]);
+
+        let mut data = vec![0x00, 0x00, 0x01, 0x00, 0x01, 0x00];
+        data.extend_from_slice(&[2, 2, 0, 0]);
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&(dib.len() as u32).to_le_bytes());
+        data.extend_from_slice(&22u32.to_le_bytes());
+        data.extend_from_slice(&dib);
+
+        let decoded = decode_ico_bytes(&data, IcoPick::Largest).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (2, 2));
+        assert_eq!(decoded.get_pixel(1, 1), &Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn test_encode_multi_size_frames() {
+        // 宽图：左半红、右半蓝
+        let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(600, 300, |x, _| {
+            if x < 300 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }
+        }));
+        let data = encode_multi_size(&source, &[256, 16, 32, 16], true).unwrap();
+
+        let entries = parse_entries(&data).unwrap();
+        let sizes: Vec<(u32, u32)> = entries.iter().map(|entry| (entry.width, entry.height)).collect();
+        assert_eq!(sizes, vec![(16, 16), (32, 32), (256, 256)]);
+        let is_png = |entry: &IcoEntry| data[entry.offset..].starts_with(PNG_SIGNATURE);
+        assert!(!is_png(&entries[0]) && !is_png(&entries[1]));
+        assert!(is_png(&entries[2]));
+        assert!(entries.iter().all(|entry| entry.bit_count == 32));
+
+        for size in [16, 32, 256] {
+            let frame = decode_ico_bytes(&data, IcoPick::Exact(size, size)).unwrap().to_rgba8();
+            assert_eq!(frame.dimensions(), (size, size));
+            // 保持宽高比：上下留出透明边，中间左红右蓝
+            assert_eq!(frame.get_pixel(0, 0)[3], 0);
+            let middle = size / 2;
+            assert_eq!(frame.get_pixel(1, middle).0, [255, 0, 0, 255], "{}", size);
+            assert_eq!(frame.get_pixel(size - 2, middle).0, [0, 0, 255, 255], "{}", size);
+        }
+
+        assert!(encode_multi_size(&source, &[512], false).is_err());
+    }
+}
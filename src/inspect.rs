@@ -0,0 +1,298 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use walkdir::WalkDir;
+
+use crate::utils;
+
+/// 单个图像的尺寸信息
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageDimensions {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// 只读取文件头获取图像尺寸，不解码像素
+pub fn read_dimensions(path: &Path) -> Result<(u32, u32)> {
+    image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .with_context(|| format!("无法打开图像: {}", path.display()))?
+        .into_dimensions()
+        .with_context(|| format!("无法读取图像尺寸: {}", path.display()))
+}
+
+/// 收集输入路径下的图像文件
+///
+/// 输入为文件时直接返回，为目录时递归遍历并按文件名排序，
+/// 只保留通过 is_image_file 过滤的文件
+pub fn collect_image_files(input: &Path) -> Vec<PathBuf> {
+    if input.is_file() {
+        return vec![input.to_path_buf()];
+    }
+
+    WalkDir::new(input)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| utils::is_image_file(path))
+        .collect()
+}
+
+/// 扫描输入路径下所有图像的尺寸
+///
+/// 返回成功读取的尺寸列表，以及读取失败的文件和原因
+pub fn scan_dimensions(input: &Path) -> (Vec<ImageDimensions>, Vec<(PathBuf, anyhow::Error)>) {
+    let mut dimensions = Vec::new();
+    let mut failures = Vec::new();
+
+    for path in collect_image_files(input) {
+        match read_dimensions(&path) {
+            Ok((width, height)) => dimensions.push(ImageDimensions { path, width, height }),
+            Err(e) => failures.push((path, e)),
+        }
+    }
+
+    (dimensions, failures)
+}
+
+/// 尺寸分布按长边分组的上界，超过最后一个上界的图像归入最后一组
+pub const DIMENSION_BUCKETS: &[u32] = &[256, 512, 1024, 2048, 4096];
+
+/// 尺寸分布的分组方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistogramGrouping {
+    /// 按长边落入 [`DIMENSION_BUCKETS`] 的区间分组
+    Buckets,
+    /// 按精确的宽x高分组
+    Exact,
+}
+
+impl FromStr for HistogramGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "buckets" => Ok(HistogramGrouping::Buckets),
+            "exact" => Ok(HistogramGrouping::Exact),
+            _ => Err(format!("无效的分组方式: {} (可选 buckets, exact)", s)),
+        }
+    }
+}
+
+/// 尺寸分布中的一组
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistogramBin {
+    /// 长边区间 (如 `257-512`) 或精确尺寸 (如 `1920x1080`)
+    pub label: String,
+    pub count: usize,
+}
+
+/// 统计尺寸分布
+///
+/// 按区间分组时列出全部区间 (包括没有图像的)，从小到大排列；
+/// 按精确尺寸分组时只列出出现过的尺寸，数量多的在前，数量相同时按宽、高排列
+pub fn dimension_histogram(dimensions: &[ImageDimensions], grouping: HistogramGrouping) -> Vec<HistogramBin> {
+    match grouping {
+        HistogramGrouping::Buckets => {
+            let mut counts = vec![0; DIMENSION_BUCKETS.len() + 1];
+            for entry in dimensions {
+                let longest = entry.width.max(entry.height);
+                counts[DIMENSION_BUCKETS.partition_point(|&bound| bound < longest)] += 1;
+            }
+            counts
+                .into_iter()
+                .enumerate()
+                .map(|(index, count)| {
+                    let lower = index.checked_sub(1).map(|previous| DIMENSION_BUCKETS[previous]);
+                    let label = match (lower, DIMENSION_BUCKETS.get(index)) {
+                        (None, Some(upper)) => format!("≤{}", upper),
+                        (Some(lower), Some(upper)) => format!("{}-{}", lower + 1, upper),
+                        (Some(lower), None) => format!(">{}", lower),
+                        (None, None) => unreachable!("区间上界不为空"),
+                    };
+                    HistogramBin { label, count }
+                })
+                .collect()
+        }
+        HistogramGrouping::Exact => {
+            let mut counts: BTreeMap<(u32, u32), usize> = BTreeMap::new();
+            for entry in dimensions {
+                *counts.entry((entry.width, entry.height)).or_default() += 1;
+            }
+            let mut sizes: Vec<_> = counts.into_iter().collect();
+            sizes.sort_by(|(a_size, a_count), (b_size, b_count)| b_count.cmp(a_count).then(a_size.cmp(b_size)));
+            sizes
+                .into_iter()
+                .map(|((width, height), count)| HistogramBin { label: format!("{}x{}", width, height), count })
+                .collect()
+        }
+    }
+}
+
+/// 按文件内容识别的格式，以及扩展名是否与内容一致
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FormatProbe {
+    pub path: PathBuf,
+    /// 按魔数识别的格式名，如 `png`、`jpeg`
+    pub format: String,
+    /// 格式对应的MIME类型，如 `image/png`
+    pub mime_type: &'static str,
+    /// 扩展名与内容一致；不一致 (包括没有扩展名) 时可能是伪装了扩展名的上传文件
+    pub extension_matches: bool,
+}
+
+/// 只读取文件头识别格式，无法识别时返回 `None`
+pub fn probe_format(path: &Path) -> Option<FormatProbe> {
+    let format = utils::detect_image_format_by_content(path)?;
+    Some(FormatProbe {
+        path: path.to_path_buf(),
+        mime_type: utils::mime_type(&format),
+        extension_matches: utils::extension_matches_format(path, &format),
+        format,
+    })
+}
+
+/// 按文件内容统计的格式分布
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FormatReport {
+    /// 每种格式的文件数，按格式名排序
+    pub formats: BTreeMap<String, usize>,
+    /// 识别出格式的文件，按路径排序
+    pub files: Vec<FormatProbe>,
+    /// 无法识别格式的文件
+    pub undetected: Vec<PathBuf>,
+}
+
+impl FormatReport {
+    /// 扫描的文件总数
+    pub fn total(&self) -> usize {
+        self.formats.values().sum::<usize>() + self.undetected.len()
+    }
+
+    /// 扩展名与内容不一致的文件
+    pub fn mismatched(&self) -> impl Iterator<Item = &FormatProbe> {
+        self.files.iter().filter(|probe| !probe.extension_matches)
+    }
+}
+
+/// 统计输入路径下所有文件的实际格式，不转换、不解码
+///
+/// 不按扩展名过滤，扩展名错误或无法识别的文件都会被统计
+pub fn scan_formats(input: &Path) -> FormatReport {
+    let mut report = FormatReport::default();
+
+    let files = WalkDir::new(input)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path());
+    for path in files {
+        match probe_format(&path) {
+            Some(probe) => {
+                *report.formats.entry(probe.format.clone()).or_default() += 1;
+                report.files.push(probe);
+            }
+            None => report.undetected.push(path),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_scan_dimensions() {
+        let dir = tempfile::tempdir().unwrap();
+        RgbImage::from_pixel(40, 20, Rgb([1, 2, 3])).save(dir.path().join("a.png")).unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        RgbImage::from_pixel(7, 9, Rgb([1, 2, 3])).save(dir.path().join("nested/b.jpg")).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+
+        let (dimensions, failures) = scan_dimensions(dir.path());
+        assert!(failures.is_empty());
+        let sizes: Vec<(u32, u32)> = dimensions.iter().map(|d| (d.width, d.height)).collect();
+        assert_eq!(sizes, vec![(40, 20), (7, 9)]);
+    }
+
+    #[test]
+    fn test_dimension_histogram() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, width, height) in
+            [("a.png", 100, 80), ("b.png", 80, 256), ("c.png", 300, 200), ("d.png", 300, 200), ("e.png", 5000, 10)]
+        {
+            RgbImage::new(width, height).save(dir.path().join(name)).unwrap();
+        }
+        let (dimensions, _) = scan_dimensions(dir.path());
+
+        let bins = dimension_histogram(&dimensions, HistogramGrouping::Buckets);
+        let counts: Vec<(&str, usize)> = bins.iter().map(|bin| (bin.label.as_str(), bin.count)).collect();
+        assert_eq!(
+            counts,
+            vec![("≤256", 2), ("257-512", 2), ("513-1024", 0), ("1025-2048", 0), ("2049-4096", 0), (">4096", 1)]
+        );
+
+        let bins = dimension_histogram(&dimensions, HistogramGrouping::Exact);
+        let counts: Vec<(&str, usize)> = bins.iter().map(|bin| (bin.label.as_str(), bin.count)).collect();
+        assert_eq!(counts, vec![("300x200", 2), ("80x256", 1), ("100x80", 1), ("5000x10", 1)]);
+        assert_eq!("EXACT".parse::<HistogramGrouping>(), Ok(HistogramGrouping::Exact));
+    }
+
+    #[test]
+    fn test_scan_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        RgbImage::new(4, 4).save(dir.path().join("a.png")).unwrap();
+        RgbImage::new(4, 4).save(dir.path().join("b.png")).unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        RgbImage::new(4, 4).save(dir.path().join("nested/c.jpg")).unwrap();
+        // 扩展名错误时按内容统计
+        RgbImage::new(4, 4).save_with_format(dir.path().join("d.png"), image::ImageFormat::Gif).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+
+        let report = scan_formats(dir.path());
+        let formats: Vec<(&str, usize)> = report.formats.iter().map(|(f, n)| (f.as_str(), *n)).collect();
+        assert_eq!(formats, vec![("gif", 1), ("jpeg", 1), ("png", 2)]);
+        assert_eq!(report.undetected, vec![dir.path().join("notes.txt")]);
+        assert_eq!(report.total(), 5);
+        let mismatched: Vec<&Path> = report.mismatched().map(|probe| probe.path.as_path()).collect();
+        assert_eq!(mismatched, vec![dir.path().join("d.png")]);
+    }
+
+    #[test]
+    fn test_probe_reports_spoofed_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let spoofed = dir.path().join("avatar.png");
+        RgbImage::new(4, 4).save_with_format(&spoofed, image::ImageFormat::Jpeg).unwrap();
+        let probe = probe_format(&spoofed).unwrap();
+        assert_eq!(probe.format, "jpeg");
+        assert_eq!(probe.mime_type, "image/jpeg");
+        assert!(!probe.extension_matches);
+
+        let genuine = dir.path().join("avatar.jpeg");
+        std::fs::copy(&spoofed, &genuine).unwrap();
+        assert!(probe_format(&genuine).unwrap().extension_matches);
+
+        let json = serde_json::to_value(&probe).unwrap();
+        assert_eq!(json["mime_type"], "image/jpeg");
+        assert_eq!(json["extension_matches"], false);
+        std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+        assert!(probe_format(&dir.path().join("notes.txt")).is_none());
+    }
+
+    #[test]
+    fn test_read_dimensions_without_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("noext");
+        RgbImage::new(13, 5).save_with_format(&path, image::ImageFormat::Png).unwrap();
+
+        assert_eq!(read_dimensions(&path).unwrap(), (13, 5));
+    }
+}
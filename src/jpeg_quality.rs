@@ -0,0 +1,122 @@
+//! 从量化表估计JPEG的编码质量
+//!
+//! 按libjpeg的质量缩放公式反推：把亮度量化表与标准表逐项比较得到缩放比例，
+//! 再换算成0-100的质量。这只是估计值，使用自定义量化表的编码器（如mozjpeg
+//! 的默认表）得到的结果只能作为参考
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// JPEG标准 (Annex K) 亮度量化表，按zigzag顺序排列，与DQT段中的存储顺序一致
+const STD_LUMINANCE_ZIGZAG: [u16; 64] = [
+    16, 11, 12, 14, 12, 10, 16, 14, 13, 14, 18, 17, 16, 19, 24, 40, 26, 24, 22, 22, 24, 49, 35, 37, 29,
+    40, 58, 51, 61, 60, 57, 51, 56, 55, 64, 72, 92, 78, 64, 68, 87, 69, 55, 56, 80, 109, 81, 87, 95, 98,
+    103, 104, 103, 62, 77, 113, 121, 112, 100, 120, 92, 101, 103, 99,
+];
+
+/// 读取文件并估计JPEG质量，不是JPEG或找不到亮度量化表时返回 `Ok(None)`
+pub fn estimate_file_quality(path: &Path) -> Result<Option<f32>> {
+    let data = fs::read(path).with_context(|| format!("无法读取文件: {}", path.display()))?;
+    Ok(estimate_quality(&data))
+}
+
+/// 从JPEG数据的亮度量化表估计质量 (1-100)
+pub fn estimate_quality(data: &[u8]) -> Option<f32> {
+    let table = luminance_table(data)?;
+
+    // libjpeg: 量化值 = (标准值 * scale + 50) / 100，取各项比例的平均值反推scale
+    let scale: f64 = table
+        .iter()
+        .zip(STD_LUMINANCE_ZIGZAG)
+        .map(|(&value, standard)| value as f64 * 100.0 / standard as f64)
+        .sum::<f64>()
+        / 64.0;
+
+    // libjpeg: quality < 50 时 scale = 5000 / quality，否则 scale = 200 - 2 * quality
+    let quality = if scale <= 100.0 { (200.0 - scale) / 2.0 } else { 5000.0 / scale };
+    Some(quality.round().clamp(1.0, 100.0) as f32)
+}
+
+/// 找到编号为0的量化表（通常是亮度表），在SOS之前的DQT段中查找
+fn luminance_table(data: &[u8]) -> Option<[u16; 64]> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut offset = 2;
+    while offset + 4 <= data.len() {
+        if data[offset] != 0xFF {
+            return None;
+        }
+        let marker = data[offset + 1];
+        // 填充字节
+        if marker == 0xFF {
+            offset += 1;
+            continue;
+        }
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+
+        let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let segment = data.get(offset + 4..offset + 2 + length)?;
+        if marker == 0xDB {
+            if let Some(table) = find_table(segment, 0) {
+                return Some(table);
+            }
+        }
+        offset += 2 + length;
+    }
+    None
+}
+
+/// 在一个DQT段中查找指定编号的量化表，段中可能连续存放多张表
+fn find_table(mut segment: &[u8], id: u8) -> Option<[u16; 64]> {
+    while let Some((&header, rest)) = segment.split_first() {
+        let precision = header >> 4;
+        let entry_size = if precision == 0 { 1 } else { 2 };
+        let values = rest.get(..64 * entry_size)?;
+
+        if header & 0x0F == id {
+            let mut table = [0u16; 64];
+            for (index, value) in table.iter_mut().enumerate() {
+                *value = if entry_size == 1 {
+                    values[index] as u16
+                } else {
+                    u16::from_be_bytes([values[index * 2], values[index * 2 + 1]])
+                };
+            }
+            return Some(table);
+        }
+        segment = &rest[64 * entry_size..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+
+    fn encode(quality: u8) -> Vec<u8> {
+        let img = image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([(x * 8) as u8, (y * 8) as u8, 90]));
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, quality).encode_image(&img).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_estimates_known_quality() {
+        for quality in [30u8, 50, 75, 90, 95] {
+            let estimated = estimate_quality(&encode(quality)).unwrap();
+            assert!((estimated - quality as f32).abs() <= 3.0, "质量 {} 估计为 {}", quality, estimated);
+        }
+    }
+
+    #[test]
+    fn test_non_jpeg_has_no_estimate() {
+        assert_eq!(estimate_quality(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(estimate_quality(&[0xFF, 0xD8, 0xFF, 0xD9]), None);
+    }
+}
@@ -0,0 +1,374 @@
+//! JPEG无损变换
+//!
+//! 通过libjpeg的transupp直接在DCT系数上完成旋转和翻转（与jpegtran相同），
+//! 不经过解码和重新编码，因此不会损失画质
+//!
+//! libjpeg的调用流程在 `src/jpegtran_shim.c` 中，致命错误用setjmp/longjmp返回为错误码，
+//! 不经过Rust的栈展开，损坏的输入在 `panic = "abort"` 的release构建中同样只会得到 `Err`
+
+use anyhow::Result;
+use mozjpeg_sys::*;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint, c_ulong, c_void};
+
+use crate::transform::Reorient;
+
+/// 与libjpeg的 `JMSG_LENGTH_MAX` 相同
+const MESSAGE_LENGTH: usize = 200;
+
+/// `pixforge_jpegtran` 的返回值：尺寸不满足MCU对齐，无法完全无损
+const NOT_PERFECT: c_int = 1;
+
+extern "C" {
+    fn free(ptr: *mut c_void);
+
+    fn pixforge_jpegtran(
+        data: *const u8,
+        size: c_ulong,
+        transform: c_int,
+        optimize_coding: c_int,
+        restart_rows: c_int,
+        restart_interval: c_uint,
+        out: *mut *mut u8,
+        out_size: *mut c_ulong,
+        message: *mut c_char,
+        message_len: usize,
+    ) -> c_int;
+}
+
+/// 对JPEG数据执行无损旋转/翻转
+///
+/// 图像尺寸不是MCU块的整数倍时无法做到完全无损，此时返回 `Ok(None)`，
+/// 由调用方回退到解码重编码的路径。输出保留原文件的全部标记段，
+/// EXIF方向标签会被重置为1，避免查看器重复旋转
+pub fn transform(data: &[u8], reorient: Reorient) -> Result<Option<Vec<u8>>> {
+    if reorient.is_identity() {
+        return Ok(Some(data.to_vec()));
+    }
+
+    match run_transform(data, reorient, false, None) {
+        Ok(Some(mut output)) => {
+            reset_exif_orientation(&mut output);
+            reset_xmp_orientation(&mut output);
+            Ok(Some(output))
+        }
+        Ok(None) => Ok(None),
+        Err(message) => anyhow::bail!("JPEG无损变换失败: {}", message),
+    }
+}
+
+/// 无损重写JPEG，使用按图像内容优化的霍夫曼表 (与 `jpegtran -optimize` 相同)
+///
+/// 只重新做熵编码，DCT系数不变，解码结果与原数据完全一致
+pub fn optimize(data: &[u8]) -> Result<Vec<u8>> {
+    match run_transform(data, Reorient::IDENTITY, true, None) {
+        Ok(Some(output)) => Ok(output),
+        Ok(None) => anyhow::bail!("JPEG优化失败"),
+        Err(message) => anyhow::bail!("JPEG优化失败: {}", message),
+    }
+}
+
+/// 无损重写JPEG，每 `rows` 行MCU插入一个重启标记 (RST0-RST7，与 `jpegtran -restart N` 相同)
+///
+/// 解码器遇到损坏的数据时可以从下一个重启标记继续，只丢失损坏所在的区间；
+/// 每个标记占2字节，并且在标记处重新开始DC差分编码，文件略微增大。
+/// 只重新做熵编码，霍夫曼表改为按内容优化，DCT系数不变；输出为顺序扫描
+pub fn set_restart_interval(data: &[u8], rows: u16) -> Result<Vec<u8>> {
+    match run_transform(data, Reorient::IDENTITY, true, Some(rows)) {
+        Ok(Some(output)) => Ok(output),
+        Ok(None) => anyhow::bail!("写入JPEG重启标记失败"),
+        Err(message) => anyhow::bail!("写入JPEG重启标记失败: {}", message),
+    }
+}
+
+/// 转换为transupp的变换代码
+fn transform_code(reorient: Reorient) -> JXFORM_CODE {
+    match (reorient.flip, reorient.quarter_turns % 4) {
+        (false, 0) => JXFORM_CODE_JXFORM_NONE,
+        (false, 1) => JXFORM_CODE_JXFORM_ROT_90,
+        (false, 2) => JXFORM_CODE_JXFORM_ROT_180,
+        (false, _) => JXFORM_CODE_JXFORM_ROT_270,
+        (true, 0) => JXFORM_CODE_JXFORM_FLIP_H,
+        (true, 1) => JXFORM_CODE_JXFORM_TRANSVERSE,
+        (true, 2) => JXFORM_CODE_JXFORM_FLIP_V,
+        (true, _) => JXFORM_CODE_JXFORM_TRANSPOSE,
+    }
+}
+
+/// 按jpegtran的流程执行变换，`optimize_coding` 为真时重新计算霍夫曼表
+///
+/// `restart_rows` 为每个重启区间的MCU行数，未指定时沿用源文件的重启间隔；
+/// 尺寸不满足MCU对齐时返回 `Ok(None)`，libjpeg的错误信息作为 `Err` 返回
+fn run_transform(
+    data: &[u8],
+    reorient: Reorient,
+    optimize_coding: bool,
+    restart_rows: Option<u16>,
+) -> Result<Option<Vec<u8>>, String> {
+    let mut out: *mut u8 = std::ptr::null_mut();
+    let mut out_size: c_ulong = 0;
+    let mut message = [0 as c_char; MESSAGE_LENGTH];
+    let status = unsafe {
+        pixforge_jpegtran(
+            data.as_ptr(),
+            data.len() as c_ulong,
+            transform_code(reorient) as c_int,
+            c_int::from(optimize_coding),
+            restart_rows.map_or(0, c_int::from),
+            restart_interval(data),
+            &mut out,
+            &mut out_size,
+            message.as_mut_ptr(),
+            message.len(),
+        )
+    };
+    match status {
+        0 => unsafe {
+            let output = std::slice::from_raw_parts(out, out_size as usize).to_vec();
+            free(out as *mut c_void);
+            Ok(Some(output))
+        },
+        NOT_PERFECT => Ok(None),
+        _ => Err(unsafe { CStr::from_ptr(message.as_ptr()) }.to_string_lossy().into_owned()),
+    }
+}
+
+/// 将JPEG中EXIF的方向标签改写为1（正常方向）
+///
+/// 原地修改标签值，文件长度不变；没有EXIF或没有方向标签时不做任何事
+pub fn reset_exif_orientation(jpeg: &mut [u8]) {
+    if let Some(position) = find_orientation_value(jpeg) {
+        let (offset, little_endian) = position;
+        let value = if little_endian { [1, 0] } else { [0, 1] };
+        jpeg[offset..offset + 2].copy_from_slice(&value);
+    }
+}
+
+/// XMP数据包中方向属性的两种写法，后面紧跟一位数字的方向值
+const XMP_ORIENTATION_PATTERNS: &[&[u8]] = &[b"tiff:Orientation=\"", b"tiff:Orientation='", b"<tiff:Orientation>"];
+
+/// 把XMP数据包中的 `tiff:Orientation` 改为1
+///
+/// 数码相机和编辑软件常在XMP中重复记录方向，部分看图软件优先读取XMP；
+/// 原地替换一位数字，文件长度不变
+pub fn reset_xmp_orientation(jpeg: &mut [u8]) {
+    for (start, end) in header_segments(jpeg, 0xE1) {
+        if !jpeg[start..end].starts_with(b"http://ns.adobe.com/xap/1.0/\0") {
+            continue;
+        }
+        for pattern in XMP_ORIENTATION_PATTERNS {
+            let mut from = start;
+            while let Some(found) = find_bytes(&jpeg[from..end], pattern) {
+                let value = from + found + pattern.len();
+                if value < end && (b'1'..=b'8').contains(&jpeg[value]) {
+                    jpeg[value] = b'1';
+                }
+                from = value;
+            }
+        }
+    }
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// 源文件DRI段记录的重启间隔 (以MCU计)，没有DRI段时为0
+fn restart_interval(jpeg: &[u8]) -> c_uint {
+    header_segments(jpeg, 0xDD)
+        .last()
+        .filter(|(start, end)| end - start == 2)
+        .map_or(0, |&(start, _)| c_uint::from(u16::from_be_bytes([jpeg[start], jpeg[start + 1]])))
+}
+
+/// 扫描数据之前所有 `wanted` 标记段的内容范围 (不含标记和长度字段)
+fn header_segments(jpeg: &[u8], wanted: u8) -> Vec<(usize, usize)> {
+    let mut segments = Vec::new();
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return segments;
+    }
+
+    let mut position = 2;
+    while position + 4 <= jpeg.len() && jpeg[position] == 0xFF {
+        let marker = jpeg[position + 1];
+        let length = u16::from_be_bytes([jpeg[position + 2], jpeg[position + 3]]) as usize;
+        // 扫描数据开始后只关心熵编码数据，不再查找
+        if marker == 0xDA {
+            break;
+        }
+        if marker == wanted {
+            segments.push((position + 4, (position + 2 + length).min(jpeg.len())));
+        }
+        position += 2 + length;
+    }
+    segments
+}
+
+/// 查找EXIF方向标签值在文件中的位置，返回偏移量和字节序
+fn find_orientation_value(jpeg: &[u8]) -> Option<(usize, bool)> {
+    let (segment_start, segment_end) = header_segments(jpeg, 0xE1)
+        .into_iter()
+        .find(|&(start, end)| end >= start && jpeg[start..end].starts_with(b"Exif\0\0"))?;
+    let tiff_start = segment_start + 6;
+    find_tiff_orientation(&jpeg[tiff_start..segment_end]).map(|(offset, little_endian)| (tiff_start + offset, little_endian))
+}
+
+/// 在TIFF结构的IFD0中查找方向标签 (0x0112)
+fn find_tiff_orientation(tiff: &[u8]) -> Option<(usize, bool)> {
+    let little_endian = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?, *tiff.get(offset + 2)?, *tiff.get(offset + 3)?];
+        Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+
+    let ifd = read_u32(4)? as usize;
+    let count = read_u16(ifd)? as usize;
+    (0..count)
+        .map(|index| ifd + 2 + index * 12)
+        .find(|&entry| read_u16(entry) == Some(0x0112))
+        .map(|entry| (entry + 8, little_endian))
+        .filter(|(offset, _)| offset + 2 <= tiff.len())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{DynamicImage, RgbImage};
+
+    pub(crate) fn sample_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+        });
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, 90).encode_image(&img).unwrap();
+        data
+    }
+
+    /// 构造带方向标签的EXIF APP1段
+    pub(crate) fn exif_segment(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&0x0112u16.to_be_bytes());
+        tiff.extend_from_slice(&3u16.to_be_bytes());
+        tiff.extend_from_slice(&1u32.to_be_bytes());
+        tiff.extend_from_slice(&orientation.to_be_bytes());
+        tiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        segment.extend_from_slice(b"Exif\0\0");
+        segment.extend_from_slice(&tiff);
+        segment
+    }
+
+    fn mean_abs_diff(a: &DynamicImage, b: &DynamicImage) -> f64 {
+        let (a, b) = (a.to_rgb8(), b.to_rgb8());
+        let total: u64 = a
+            .as_raw()
+            .iter()
+            .zip(b.as_raw())
+            .map(|(x, y)| (*x as i32 - *y as i32).unsigned_abs() as u64)
+            .sum();
+        total as f64 / a.as_raw().len() as f64
+    }
+
+    #[test]
+    fn test_lossless_rotation_is_near_identical() {
+        let data = sample_jpeg(64, 48);
+        let rotated = transform(&data, Reorient::from_degrees(90).unwrap()).unwrap().unwrap();
+
+        let expected = image::load_from_memory(&data).unwrap().rotate90();
+        let actual = image::load_from_memory(&rotated).unwrap();
+        assert_eq!((actual.width(), actual.height()), (48, 64));
+        assert!(mean_abs_diff(&expected, &actual) < 1.0);
+    }
+
+    #[test]
+    fn test_optimize_is_smaller_and_lossless() {
+        // 尺寸不必按MCU对齐
+        let data = sample_jpeg(150, 70);
+        let optimized = optimize(&data).unwrap();
+        assert!(optimized.len() < data.len(), "{} >= {}", optimized.len(), data.len());
+
+        let expected = image::load_from_memory(&data).unwrap();
+        let actual = image::load_from_memory(&optimized).unwrap();
+        assert_eq!(expected.to_rgb8(), actual.to_rgb8());
+    }
+
+    /// 熵编码数据中RST0-RST7标记的个数
+    pub fn restart_marker_count(data: &[u8]) -> usize {
+        data.windows(2).filter(|pair| pair[0] == 0xFF && (0xD0..=0xD7).contains(&pair[1])).count()
+    }
+
+    #[test]
+    fn test_restart_markers_are_inserted_losslessly() {
+        let data = sample_jpeg(150, 70);
+        assert_eq!(restart_marker_count(&data), 0);
+        let restarted = set_restart_interval(&data, 1).unwrap();
+        let markers = restart_marker_count(&restarted);
+        assert!(markers > 0);
+        // 间隔越大标记越少
+        assert!(restart_marker_count(&set_restart_interval(&data, 2).unwrap()) < markers);
+
+        let expected = image::load_from_memory(&data).unwrap();
+        let actual = image::load_from_memory(&restarted).unwrap();
+        assert_eq!(expected.to_rgb8(), actual.to_rgb8());
+
+        // 后续的无损变换保留重启间隔
+        assert_eq!(restart_marker_count(&optimize(&restarted).unwrap()), markers);
+    }
+
+    #[test]
+    fn test_unaligned_size_requests_fallback() {
+        let data = sample_jpeg(50, 30);
+        assert!(transform(&data, Reorient::from_degrees(90).unwrap()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_corrupt_data_is_an_error() {
+        let mut data = sample_jpeg(64, 48);
+        data.truncate(20);
+        assert!(transform(&data, Reorient::from_degrees(180).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_orientation_tag_is_reset() {
+        let data = sample_jpeg(64, 48);
+        let mut with_exif = data[..2].to_vec();
+        with_exif.extend_from_slice(&exif_segment(6));
+        with_exif.extend_from_slice(&data[2..]);
+
+        let rotated = transform(&with_exif, Reorient::from_degrees(90).unwrap()).unwrap().unwrap();
+        let (offset, little_endian) = find_orientation_value(&rotated).unwrap();
+        assert!(!little_endian);
+        assert_eq!(&rotated[offset..offset + 2], &[0, 1]);
+    }
+
+    #[test]
+    fn test_xmp_orientation_is_reset() {
+        let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta><rdf:Description tiff:Orientation=\"6\">\
+            <tiff:Orientation>6</tiff:Orientation></rdf:Description></x:xmpmeta>";
+        let data = sample_jpeg(64, 48);
+        let mut with_xmp = data[..2].to_vec();
+        with_xmp.extend_from_slice(&exif_segment(6));
+        with_xmp.extend_from_slice(&[0xFF, 0xE1]);
+        with_xmp.extend_from_slice(&((xmp.len() + 2) as u16).to_be_bytes());
+        with_xmp.extend_from_slice(xmp);
+        with_xmp.extend_from_slice(&data[2..]);
+
+        let rotated = transform(&with_xmp, Reorient::from_degrees(90).unwrap()).unwrap().unwrap();
+        let text = String::from_utf8_lossy(&rotated);
+        assert!(text.contains(r#"tiff:Orientation="1""#), "{}", text);
+        assert!(text.contains("<tiff:Orientation>1</tiff:Orientation>"));
+        assert!(!text.contains("Orientation>6") && !text.contains(r#"Orientation="6""#));
+    }
+}
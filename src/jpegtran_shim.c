@@ -0,0 +1,145 @@
+/*
+ * JPEG无损变换的libjpeg调用流程 (与jpegtran相同)
+ *
+ * libjpeg的致命错误要求error_exit不返回。这里用setjmp/longjmp回到入口，
+ * 把错误作为返回值交给Rust，不经过Rust的栈展开，因此在 panic = "abort" 的构建中同样安全。
+ * longjmp只跳过本文件中的C栈帧。
+ */
+
+#include <setjmp.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "jpeglib.h"
+#include "transupp.h"
+
+/* 尺寸不满足MCU对齐，无法完全无损 */
+#define PIXFORGE_NOT_PERFECT 1
+/* libjpeg报告了致命错误，错误信息写入message */
+#define PIXFORGE_LIBJPEG_ERROR (-1)
+
+struct guarded_error {
+    struct jpeg_error_mgr pub;
+    jmp_buf *jump;
+    char *message;
+    size_t message_len;
+};
+
+static void guarded_error_exit(j_common_ptr cinfo) {
+    struct guarded_error *err = (struct guarded_error *)cinfo->err;
+    char buffer[JMSG_LENGTH_MAX];
+    (*cinfo->err->format_message)(cinfo, buffer);
+    if (err->message_len > 0) {
+        snprintf(err->message, err->message_len, "%s", buffer);
+    }
+    longjmp(*err->jump, 1);
+}
+
+/* 警告 (例如数据提前结束) 不输出，libjpeg会按规范补全数据继续 */
+static void silence_message(j_common_ptr cinfo, int level) {
+    (void)cinfo;
+    (void)level;
+}
+
+static void guard(struct guarded_error *err, jmp_buf *jump, char *message, size_t message_len) {
+    jpeg_std_error(&err->pub);
+    err->pub.error_exit = guarded_error_exit;
+    err->pub.emit_message = silence_message;
+    err->jump = jump;
+    err->message = message;
+    err->message_len = message_len;
+}
+
+/*
+ * 对data执行transform，optimize_coding非0时重新计算霍夫曼表
+ *
+ * restart_rows大于0时每restart_rows行MCU插入重启标记，否则使用restart_interval (源文件的间隔，0为没有)；
+ * 有重启间隔时改为顺序扫描，不少解码器不支持渐进式扫描中的重启标记。
+ * 成功时返回0，*out为malloc分配的输出，由调用方free
+ */
+int pixforge_jpegtran(
+    const unsigned char *data,
+    unsigned long size,
+    int transform,
+    int optimize_coding,
+    int restart_rows,
+    unsigned int restart_interval,
+    unsigned char **out,
+    unsigned long *out_size,
+    char *message,
+    size_t message_len
+) {
+    struct jpeg_decompress_struct src;
+    struct jpeg_compress_struct dst;
+    struct guarded_error src_err;
+    struct guarded_error dst_err;
+    jpeg_transform_info info;
+    jvirt_barray_ptr *src_coefficients;
+    jvirt_barray_ptr *dst_coefficients;
+    jmp_buf jump;
+    volatile int status = 0;
+
+    *out = NULL;
+    *out_size = 0;
+    memset(&info, 0, sizeof(info));
+    info.transform = (JXFORM_CODE)transform;
+    info.perfect = TRUE;
+    info.trim = FALSE;
+
+    src.err = &src_err.pub;
+    dst.err = &dst_err.pub;
+    guard(&src_err, &jump, message, message_len);
+    guard(&dst_err, &jump, message, message_len);
+    jpeg_create_decompress(&src);
+    jpeg_create_compress(&dst);
+
+    if (setjmp(jump)) {
+        status = PIXFORGE_LIBJPEG_ERROR;
+        goto done;
+    }
+
+    jpeg_mem_src(&src, data, size);
+    jcopy_markers_setup(&src, JCOPYOPT_ALL);
+    jpeg_read_header(&src, TRUE);
+
+    if (!jtransform_request_workspace(&src, &info)) {
+        status = PIXFORGE_NOT_PERFECT;
+        goto done;
+    }
+
+    src_coefficients = jpeg_read_coefficients(&src);
+    jpeg_copy_critical_parameters(&src, &dst);
+    dst_coefficients = jtransform_adjust_parameters(&src, &dst, src_coefficients, &info);
+    if (optimize_coding) {
+        dst.optimize_coding = TRUE;
+    }
+    if (restart_rows > 0) {
+        dst.restart_in_rows = restart_rows;
+    } else {
+        dst.restart_interval = restart_interval;
+    }
+    if (dst.restart_in_rows > 0 || dst.restart_interval > 0) {
+        jpeg_c_set_bool_param(&dst, JBOOLEAN_OPTIMIZE_SCANS, FALSE);
+        dst.scan_info = NULL;
+        dst.num_scans = 0;
+    }
+
+    jpeg_mem_dest(&dst, out, out_size);
+    jpeg_write_coefficients(&dst, dst_coefficients);
+    jcopy_markers_execute(&src, &dst, JCOPYOPT_ALL);
+    jtransform_execute_transform(&src, &dst, src_coefficients, &info);
+
+    jpeg_finish_compress(&dst);
+    jpeg_finish_decompress(&src);
+
+done:
+    jpeg_destroy_compress(&dst);
+    jpeg_destroy_decompress(&src);
+    if (status != 0 && *out != NULL) {
+        free(*out);
+        *out = NULL;
+        *out_size = 0;
+    }
+    return status;
+}
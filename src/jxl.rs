@@ -0,0 +1,113 @@
+//! JPEG XL的读取与无损输出
+//!
+//! 需要 `jxl` 特性：解码使用jxl-oxide，编码使用zune-jpegxl (只支持无损)。
+//! 未启用特性时读写都返回明确的错误
+
+use anyhow::Result;
+use image::DynamicImage;
+use std::path::Path;
+
+#[cfg(not(feature = "jxl"))]
+const DISABLED_HINT: &str = "当前构建未启用JPEG XL支持，请使用 `--features jxl` 重新编译";
+
+/// 解码JPEG XL的第一帧，保留16位精度；解码器会按文件中的方向信息旋转
+#[cfg(feature = "jxl")]
+pub fn decode(path: &Path) -> Result<DynamicImage> {
+    use anyhow::Context;
+    use jxl_oxide::JxlImage;
+
+    let image = JxlImage::builder()
+        .open(path)
+        .map_err(|e| anyhow::anyhow!(e))
+        .with_context(|| format!("无法打开图像: {}", path.display()))?;
+    let render = image
+        .render_frame(0)
+        .map_err(|e| anyhow::anyhow!(e))
+        .with_context(|| format!("无法解码JPEG XL: {}", path.display()))?;
+
+    let mut stream = render.stream();
+    let (width, height, channels) = (stream.width(), stream.height(), stream.channels());
+    let mut samples = vec![0u16; width as usize * height as usize * channels as usize];
+    stream.write_to_buffer(&mut samples);
+
+    let img = match channels {
+        1 => image::ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLuma16),
+        2 => image::ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLumaA16),
+        3 => image::ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb16),
+        4 => image::ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba16),
+        _ => anyhow::bail!("不支持的JPEG XL通道数 {} (CMYK): {}", channels, path.display()),
+    };
+    img.ok_or_else(|| anyhow::anyhow!("JPEG XL像素数据不完整: {}", path.display()))
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn decode(path: &Path) -> Result<DynamicImage> {
+    anyhow::bail!("{}: {}", DISABLED_HINT, path.display())
+}
+
+/// 无损编码为JPEG XL，高位深图像按16位编码，其余按8位
+#[cfg(feature = "jxl")]
+pub fn encode_lossless(img: &DynamicImage) -> Result<Vec<u8>> {
+    use zune_core::bit_depth::BitDepth;
+    use zune_core::colorspace::ColorSpace;
+    use zune_core::options::EncoderOptions;
+    use zune_jpegxl::JxlSimpleEncoder;
+
+    let color = img.color();
+    let colorspace = match (color.has_color(), color.has_alpha()) {
+        (false, false) => ColorSpace::Luma,
+        (false, true) => ColorSpace::LumaA,
+        (true, false) => ColorSpace::RGB,
+        (true, true) => ColorSpace::RGBA,
+    };
+    let high_bit_depth = color.bytes_per_pixel() / color.channel_count() > 1;
+
+    let (depth, data) = if high_bit_depth {
+        let samples: Vec<u16> = match colorspace {
+            ColorSpace::Luma => img.to_luma16().into_raw(),
+            ColorSpace::LumaA => img.to_luma_alpha16().into_raw(),
+            ColorSpace::RGB => img.to_rgb16().into_raw(),
+            _ => img.to_rgba16().into_raw(),
+        };
+        // zune-jpegxl按本机字节序读取16位样本
+        (BitDepth::Sixteen, samples.iter().flat_map(|sample| sample.to_ne_bytes()).collect())
+    } else {
+        let samples = match colorspace {
+            ColorSpace::Luma => img.to_luma8().into_raw(),
+            ColorSpace::LumaA => img.to_luma_alpha8().into_raw(),
+            ColorSpace::RGB => img.to_rgb8().into_raw(),
+            _ => img.to_rgba8().into_raw(),
+        };
+        (BitDepth::Eight, samples)
+    };
+
+    let options = EncoderOptions::new(img.width() as usize, img.height() as usize, colorspace, depth);
+    let mut encoded = Vec::new();
+    JxlSimpleEncoder::new(&data, options)
+        .encode(&mut encoded)
+        .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+    Ok(encoded)
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn encode_lossless(_img: &DynamicImage) -> Result<Vec<u8>> {
+    anyhow::bail!("{}", DISABLED_HINT)
+}
+
+#[cfg(all(test, feature = "jxl"))]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(33, 17, |x, y| {
+            Rgba([(x * 7) as u8, (y * 13) as u8, (x ^ y) as u8, 200 + (x % 50) as u8])
+        }));
+
+        let path = dir.path().join("image.jxl");
+        std::fs::write(&path, encode_lossless(&source).unwrap()).unwrap();
+        assert_eq!(decode(&path).unwrap().to_rgba8(), source.to_rgba8());
+    }
+}
@@ -0,0 +1,46 @@
+//! PixForge - 图像格式转换库
+//!
+//! 命令行工具 `pixforge` 基于本库实现，也可以直接在其它程序中调用
+//!
+//! 关闭默认的 `encode` 特性时不编译转换器和各格式的编码器，
+//! 只保留格式检测、尺寸读取和解码等只读功能
+
+#[cfg(feature = "encode")]
+pub mod animation;
+#[cfg(feature = "encode")]
+pub mod archive;
+pub mod colors;
+pub mod compare;
+#[cfg(feature = "encode")]
+pub mod converter;
+pub mod deskew;
+#[cfg(feature = "encode")]
+pub mod error;
+pub mod fallback;
+pub mod favicon;
+pub mod hooks;
+pub mod ico;
+pub mod inspect;
+pub mod jpeg_quality;
+#[cfg(feature = "encode")]
+pub mod jpegtran;
+pub mod jxl;
+pub mod montage;
+#[cfg(feature = "encode")]
+pub mod palette;
+pub mod pdf;
+pub mod profile;
+#[cfg(feature = "encode")]
+pub mod quality;
+pub mod recipe;
+pub mod resume;
+#[cfg(feature = "encode")]
+pub mod sidecar;
+pub mod summary;
+pub mod tiff_stream;
+#[cfg(feature = "encode")]
+pub mod tiles;
+pub mod transform;
+pub mod utils;
+#[cfg(feature = "encode")]
+pub mod watch;
@@ -1,44 +1,528 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use std::path::{Path, PathBuf};
 
-mod converter;
-mod utils;
+use pixforge::inspect::{self, HistogramGrouping};
 
-use converter::ImageConverter;
+#[cfg(feature = "encode")]
+use anyhow::Context;
+#[cfg(feature = "encode")]
+use std::sync::Arc;
 
-/// 支持的图像格式列表
-const SUPPORTED_FORMATS: &[&str] = &["png", "jpeg", "jpg", "gif", "webp", "ico"];
+#[cfg(feature = "encode")]
+use pixforge::archive;
+#[cfg(feature = "encode")]
+use pixforge::converter::{ChromaSubsampling, ConvertOptions, ImageConverter, LargerAction, LimitMode, SubpathCase, DEFAULT_QUALITY, INPUT_FORMATS, TARGET_FORMATS};
+#[cfg(feature = "encode")]
+use pixforge::fallback::FallbackDecoder;
+#[cfg(feature = "encode")]
+use pixforge::favicon::{self, FaviconOptions};
+#[cfg(feature = "encode")]
+use pixforge::hooks::HookCommand;
+#[cfg(feature = "encode")]
+use pixforge::ico::IcoPick;
+#[cfg(feature = "encode")]
+use pixforge::montage::{self, MontageOptions};
+#[cfg(feature = "encode")]
+use pixforge::recipe::Recipe;
+#[cfg(feature = "encode")]
+use pixforge::summary::SummaryTemplate;
+#[cfg(feature = "encode")]
+use pixforge::tiles::{self, TileNaming};
+#[cfg(feature = "encode")]
+use pixforge::transform::{Backdrop, BackdropLayout, Channel, FitMode, MissingAlpha, OrientationTarget, Reorient, Resize, DEFAULT_CONTAIN_BACKGROUND};
+
+/// 默认目标格式的环境变量，优先级低于 `--to` 和 `-o` 的扩展名
+#[cfg(feature = "encode")]
+const FORMAT_ENV: &str = "PIXFORGE_FORMAT";
+
+/// 转换模式的提示信息，`--progress-json` 时写到标准错误，标准输出只有进度行
+#[cfg(feature = "encode")]
+macro_rules! notice {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
 /// PixForge - 强大的图像格式转换工具
 ///
 /// 支持批量转换和单文件转换，提供质量控制选项
+#[cfg(feature = "encode")]
 #[derive(Parser)]
 #[command(name = "pixforge")]
 #[command(about = "🎨 PixForge - 优雅的图像格式转换工具")]
 #[command(version = "0.1.0")]
 #[command(author = "PixForge Team")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct CliArgs {
-    /// 目标格式 (png, jpeg, jpg, gif, webp, ico)
+    /// 子命令，缺省时执行转换
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// 目标格式 (png, jpeg, jpg, gif, webp, ico, farbfeld, ff, tga)
     #[arg(long, value_name = "FORMAT")]
-    #[arg(help = "目标图像格式")]
-    to: String,
+    #[arg(help = "目标图像格式；单文件转换且 -o 是带扩展名的文件路径时可省略，按扩展名推断，否则使用环境变量 PIXFORGE_FORMAT")]
+    to: Option<String>,
 
     /// 输入文件或目录路径
-    #[arg(value_name = "INPUT")]
-    #[arg(help = "要转换的图像文件或包含图像的目录")]
-    input: PathBuf,
+    #[arg(value_name = "INPUT", required = true)]
+    #[arg(help = "要转换的图像文件、包含图像的目录或者图像的zip压缩包")]
+    input: Option<PathBuf>,
     
     /// 输出目录 (默认与输入文件同目录)
     #[arg(short = 'o', long, value_name = "OUTPUT")]
-    #[arg(help = "输出目录，默认与输入文件在同一目录")]
+    #[arg(help = "输出目录，默认与输入文件在同一目录；批量转换时以 .zip 结尾则把结果打包成zip压缩包")]
     output: Option<PathBuf>,
 
-    /// 图像质量 (0-100，默认80)
-    #[arg(short = 'q', long, value_name = "QUALITY")]
-    #[arg(help = "图像质量控制，范围0-100，80是推荐值")]
-    #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
-    quality: Option<u8>,
+    /// 图像质量 (0-100，默认80；JPEG源默认使用估计的源质量)
+    #[arg(short = 'q', long, value_name = "QUALITY", env = "PIXFORGE_QUALITY")]
+    #[arg(help = "图像质量控制，范围0-100，可以是小数 (WebP使用小数精度)，80是推荐值；未指定时JPEG源按量化表估计原始质量")]
+    #[arg(value_parser = parse_quality)]
+    quality: Option<f32>,
+
+    /// ICO输入的帧选择策略 (largest, smallest, NxN)
+    #[arg(long, value_name = "PICK", default_value = "largest")]
+    #[arg(help = "从多尺寸ICO中选择哪一帧进行转换: largest, smallest 或 NxN")]
+    ico_pick: IcoPick,
+
+    /// ICO输出的帧尺寸
+    #[arg(long, value_name = "SIZES", value_delimiter = ',', value_parser = parse_ico_size)]
+    #[arg(help = "输出包含多个尺寸的ICO，例如 16,32,48,256；每一帧都从原图直接缩放，256帧按PNG嵌入，其余帧为BMP")]
+    ico_sizes: Vec<u32>,
+
+    /// 锐化小尺寸ICO帧
+    #[arg(long)]
+    #[arg(help = "多尺寸ICO中不超过32像素的帧做轻微锐化，避免缩小后发虚")]
+    ico_sharpen: bool,
+
+    /// GIF调色板的参考图像
+    #[arg(long, value_name = "REFERENCE")]
+    #[arg(help = "从参考图像生成一个调色板，批量中的所有GIF输出都使用它，保证同一系列图像颜色一致 (默认每张GIF单独量化)")]
+    palette_from: Option<PathBuf>,
+
+    /// 无损目标格式写出后校验像素一致性
+    #[arg(long)]
+    #[arg(help = "按无损方式编码 (png, ico, farbfeld, tga, ppm/pgm, jxl, 无损WebP) 后重新解码，像素与写入的图像不一致则判定失败")]
+    verify_lossless: bool,
+
+    /// 不检查写出的文件
+    #[arg(long)]
+    #[arg(help = "跳过写出后的检查 (默认重新打开输出，确认能识别为目标格式并读出文件头)")]
+    no_verify_output: bool,
+
+    /// 根据图像内容和输出尺寸自适应调整质量
+    #[arg(long)]
+    #[arg(help = "WebP输出时根据内容类型调整质量：平坦图形降低，细节照片提高；JPEG和WebP输出不超过512x512时按像素数限制质量上限 (64x64以内60，128x128以内70，256x256以内80，512x512以内88)")]
+    adaptive_quality: bool,
+
+    /// 根据图像内容选择WebP无损或有损
+    #[arg(long)]
+    #[arg(help = "WebP输出时根据内容类型选择编码方式：平坦图形和单方向变化的图案用无损，照片和复杂内容用有损；配合 -v 输出每个文件的选择")]
+    auto_webp_mode: bool,
+
+    /// WebP近无损预处理级别
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=100))]
+    #[arg(help = "WebP输出使用近无损编码：先按级别N (0-100，越小预处理越多、文件越小，100等同无损) 微调像素再无损编码，视觉上无损但比纯无损更小；指定后所有WebP输出都不使用质量")]
+    webp_near_lossless: Option<u8>,
+
+    /// 内容类型分析的采样点数
+    #[arg(long, value_name = "N", value_parser = parse_analysis_samples)]
+    #[arg(help = "分析图像内容类型 (选择PNG过滤器和 --adaptive-quality) 时在整幅图像上均匀取固定数量的采样点，与图像尺寸无关；默认按图像尺寸采样")]
+    analysis_samples: Option<usize>,
+
+    /// 转换成功后执行的命令
+    #[arg(long, value_name = "CMD")]
+    #[arg(help = "每个文件转换成功后通过shell执行的命令，支持 {input} 和 {output} 占位符")]
+    on_success: Option<String>,
+
+    /// 转换失败后执行的命令
+    #[arg(long, value_name = "CMD")]
+    #[arg(help = "每个文件转换失败后通过shell执行的命令，支持 {input} 和 {output} 占位符")]
+    on_failure: Option<String>,
+
+    /// 无法识别的输入使用的外部解码命令
+    #[arg(long, value_name = "CMD")]
+    #[arg(help = "PixForge无法解码的输入 (如相机RAW、SVG) 先交给外部命令转换为PNG，{input} 与 {output_png} 会替换为转义后的路径")]
+    fallback_decoder: Option<String>,
+
+    /// 单个文件的解码时限
+    #[arg(long, value_name = "SECS", value_parser = parse_timeout)]
+    #[arg(help = "单个文件解码超过该秒数就按失败跳过，避免异常文件卡住整个批次 (也作用于 --fallback-decoder)")]
+    decode_timeout: Option<std::time::Duration>,
+
+    /// 批量转换最多处理的文件数
+    #[arg(long, value_name = "N")]
+    #[arg(help = "批量转换时最多处理N个文件（按文件名排序），用于快速试验参数")]
+    limit: Option<usize>,
+
+    /// --limit 的计数方式 (converted, attempted)
+    #[arg(long, value_name = "MODE", default_value = "converted")]
+    #[arg(help = "--limit 的计数方式: converted 只计成功的文件, attempted 计所有尝试的文件")]
+    limit_mode: LimitMode,
+
+    /// 批量转换的输出总字节数上限
+    #[arg(long, value_name = "BYTES")]
+    #[arg(help = "批量转换写出的输出总字节数上限；达到上限或写出下一个文件会超出时停止，并报告剩余未转换的文件数")]
+    max_total_bytes: Option<u64>,
+
+    /// 至少需要转换的文件数
+    #[arg(long, value_name = "N")]
+    #[arg(help = "批量转换成功的文件少于N个时以错误退出，用于在CI中发现没有匹配到任何文件等错误")]
+    expect_min: Option<u32>,
+
+    /// 统计摘要模板
+    #[arg(long, value_name = "TEMPLATE")]
+    #[arg(help = "批量转换结束时按模板输出一行统计，代替默认摘要；占位符: {converted} {skipped} {failed} {total} {bytes_in} {bytes_out} {saved_percent}，字面花括号写成 {{ 和 }}")]
+    summary_template: Option<SummaryTemplate>,
+
+    /// 以JSON输出统计摘要
+    #[arg(long, conflicts_with = "summary_template")]
+    #[arg(help = "批量转换结束时把统计输出为一行JSON，代替默认摘要；skip_reasons 按原因 (非图像、已存在、过大、被过滤、失败) 分类未转换的文件")]
+    summary_json: bool,
+
+    /// 逐文件输出JSON进度
+    #[arg(long)]
+    #[arg(help = "批量转换时每处理完一个文件向标准输出写一行JSON (NDJSON)：index、total、path、status、bytes_out；其它提示信息改写到标准错误")]
+    progress_json: bool,
+
+    /// 输出比源文件大时不写出转换结果
+    #[arg(long)]
+    #[arg(help = "转换结果比源文件大时不写出，按 --larger-action 保留原文件或跳过")]
+    skip_larger: bool,
+
+    /// --skip-larger 的处理方式 (keep, skip)
+    #[arg(long, value_name = "ACTION", default_value = "keep", requires = "skip_larger")]
+    #[arg(help = "--skip-larger 的处理方式: keep 复制源文件 (格式不同时保留源扩展名), skip 不写出")]
+    larger_action: LargerAction,
+
+    /// 批量转换的状态文件
+    #[arg(long, value_name = "PATH")]
+    #[arg(help = "批量转换时将已完成的输入记录到状态文件 (按路径、修改时间和大小识别)")]
+    state: Option<PathBuf>,
+
+    /// 从状态文件继续上次的批量转换
+    #[arg(long, requires = "state")]
+    #[arg(help = "跳过状态文件中已完成且之后未修改的输入；不加此参数时状态文件会被清空重新记录")]
+    resume: bool,
+
+    /// 按输出格式分子目录
+    #[arg(long)]
+    #[arg(help = "批量转换时将输出放入以格式命名的子目录 (如 output/webp/)")]
+    split_by_format: bool,
+
+    /// 跳过的子目录名
+    #[arg(long = "exclude-dir", value_name = "NAME")]
+    #[arg(help = "批量转换时跳过该名称的子目录及其全部内容 (如 node_modules、.git)，按目录名匹配，可重复指定")]
+    exclude_dirs: Vec<String>,
+
+    /// 保留的目录层数
+    #[arg(long, value_name = "N")]
+    #[arg(help = "批量转换时保留输入的前N层目录，更深的文件放入第N层的目录，重名时加 _1、_2 等序号")]
+    collapse_depth: Option<usize>,
+
+    /// 输出中保留的目录名大小写
+    #[arg(long, value_name = "MODE", default_value = "preserve")]
+    #[arg(help = "--collapse-depth 和压缩包输入在输出中保留的目录名: preserve 原样复制, lower 转为小写 (源文件系统不区分大小写而目标区分时保证路径确定)")]
+    subpath_case: SubpathCase,
+
+    /// 按内容哈希命名输出
+    #[arg(long, conflicts_with_all = ["in_place", "all_pages"])]
+    #[arg(help = "输出文件按编码结果的SHA-256命名 (如 3a7bd3e2....webp)，相同内容只写出一次，重复的文件跳过")]
+    name_by_hash: bool,
+
+    /// 按EXIF方向标签自动旋转
+    #[arg(long)]
+    #[arg(help = "按EXIF方向标签自动旋转图像；JPEG到JPEG且只有旋转时无损完成")]
+    auto_orient: bool,
+
+    /// 顺时针旋转角度 (90, 180, 270)
+    #[arg(long, value_name = "DEGREES")]
+    #[arg(help = "顺时针旋转指定角度 (90, 180, 270)，在EXIF方向校正之后执行")]
+    #[arg(value_parser = parse_rotation)]
+    rotate: Option<Reorient>,
+
+    /// 校正扫描文档的倾斜
+    #[arg(long)]
+    #[arg(help = "估计扫描文档的倾斜角度 (±15°以内) 并旋转拉正，在EXIF校正和 --rotate 之后执行；露出的角落填充白色")]
+    deskew: bool,
+
+    /// 统一画面方向 (landscape, portrait)
+    #[arg(long, value_name = "ORIENTATION")]
+    #[arg(help = "宽高与指定方向不符的图像顺时针旋转90°，在EXIF校正和 --rotate 之后执行；正方形图像不旋转")]
+    normalize_orientation: Option<OrientationTarget>,
+
+    /// 居中裁剪到指定宽高比 (W:H)
+    #[arg(long, value_name = "W:H")]
+    #[arg(help = "缩放前居中裁剪到指定宽高比，例如 1:1、16:9")]
+    #[arg(value_parser = parse_aspect)]
+    aspect: Option<(u32, u32)>,
+
+    /// 缩放到指定尺寸 (WxH 保持宽高比，WxH! 强制精确尺寸)
+    #[arg(long, value_name = "WxH")]
+    #[arg(help = "缩放到指定尺寸以内并保持宽高比，加 ! 后缀 (如 256x256!) 则拉伸到精确尺寸")]
+    resize: Option<Resize>,
+
+    /// 缩放时源图像映射到目标尺寸的方式
+    #[arg(long, value_name = "MODE")]
+    #[arg(help = "--resize/--match-size 的缩放方式，覆盖 ! 后缀和 --match-mode: fill 拉伸, fit 缩放到尺寸以内, cover 填满并居中裁掉超出部分, contain 缩放到尺寸以内并用 --background 填充两侧")]
+    fit: Option<FitMode>,
+
+    /// 透明区域合成的背景
+    #[arg(long, value_name = "COLOR")]
+    #[arg(help = "带透明通道的图像编码前合成到该背景上，例如 #ffffff、white 或 checker (棋盘格)；同时是 --fit contain 两侧空白的填充 (未指定时为黑色)")]
+    background: Option<Backdrop>,
+
+    /// 透明区域合成的背景图像
+    #[arg(long, value_name = "PATH", conflicts_with = "background")]
+    #[arg(help = "带透明通道的图像编码前合成到该图像上，按 --background-layout 铺满；--fit contain 两侧空白同样显示背景图像")]
+    background_image: Option<PathBuf>,
+
+    /// 背景图像的布局
+    #[arg(long, value_name = "LAYOUT", default_value = "tile", requires = "background_image")]
+    #[arg(help = "背景图像铺满画布的方式: tile 按原尺寸平铺, scale 保持宽高比缩放到覆盖画布并居中裁剪")]
+    background_layout: BackdropLayout,
+
+    /// 按文件指定变换的处理方案
+    #[arg(long, value_name = "PATH")]
+    #[arg(help = "JSON处理方案，按文件名为每个文件指定裁剪 (crop)、旋转 (rotate) 和缩放 (resize)，替换对应的命令行设置")]
+    recipe: Option<PathBuf>,
+
+    /// 源图像已小于缩放目标时不放大
+    #[arg(long)]
+    #[arg(help = "源图像宽高都不超过缩放目标时保持原尺寸；与 ! 后缀互不影响，! 只决定是否保持宽高比")]
+    no_upscale: bool,
+
+    /// 只缩小超出尺寸的图像
+    #[arg(long, value_name = "WxH", conflicts_with_all = ["resize", "match_size"])]
+    #[arg(help = "只缩小宽或高超出 WxH 的图像 (保持宽高比)，较小的图像不变；格式相同时直接复制不重新压缩")]
+    #[arg(value_parser = parse_dimensions)]
+    resize_if_larger: Option<(u32, u32)>,
+
+    /// 填充为正方形
+    #[arg(long)]
+    #[arg(help = "以较长边为边长填充为居中的正方形 (不裁剪)，在 --resize 之前执行")]
+    pad_square: bool,
+
+    /// 填充颜色
+    #[arg(long, value_name = "COLOR", default_value = "black", requires = "pad_square")]
+    #[arg(help = "--pad-square 的背景颜色，例如 #ffffff、black、transparent")]
+    #[arg(value_parser = parse_color)]
+    pad_color: image::Rgba<u8>,
+
+    /// 只输出单个通道 (alpha, red, green, blue, luma)
+    #[arg(long, value_name = "CHANNEL")]
+    #[arg(help = "将指定通道提取为单通道灰度图像后再编码，例如从PNG中取出alpha蒙版")]
+    extract: Option<Channel>,
+
+    /// 源图像没有透明通道时 --extract alpha 的处理方式 (error, white)
+    #[arg(long, value_name = "MODE", default_value = "error")]
+    #[arg(help = "源图像没有透明通道时 --extract alpha 的处理方式: error 报错, white 输出全白蒙版")]
+    missing_alpha: MissingAlpha,
+
+    /// 高位深图像降到8位时做色调映射
+    #[arg(long)]
+    #[arg(help = "16位或浮点图像输出为8位格式时使用Reinhard色调映射压缩高光，而不是直接线性缩放截断")]
+    tone_map: bool,
+
+    /// 输出字节预算
+    #[arg(long, value_name = "BYTES")]
+    #[arg(help = "JPEG/WebP输出超过该字节数时，在 --min-quality 与 -q 之间二分查找能满足预算的最高质量")]
+    max_bytes: Option<u64>,
+
+    /// 按预算降低质量时的下限
+    #[arg(long, value_name = "N", requires = "max_bytes")]
+    #[arg(help = "--max-bytes 搜索的最低质量；优先于预算，最低质量下仍超出预算时按最低质量写出并警告")]
+    #[arg(value_parser = parse_quality)]
+    min_quality: Option<f32>,
+
+    /// 编码后无损优化
+    #[arg(long)]
+    #[arg(help = "编码后再做无损优化：PNG使用oxipng，JPEG重新计算霍夫曼表")]
+    optimize: bool,
+
+    /// JPEG重启间隔
+    #[arg(long, value_name = "ROWS", value_parser = parse_jpeg_restart)]
+    #[arg(help = "JPEG输出每隔ROWS行MCU插入一个重启标记，损坏时解码器可以从下一个标记恢复；文件略微增大")]
+    jpeg_restart: Option<u16>,
+
+    /// 交换红蓝通道
+    #[arg(long)]
+    #[arg(help = "编码前交换红蓝通道，修正被当作RGB的BGR数据")]
+    swap_rb: bool,
+
+    /// 透明度二值化阈值 (0-255)
+    #[arg(long, value_name = "N")]
+    #[arg(help = "编码前把alpha低于N的像素设为完全透明，不低于N的设为完全不透明，适合ICO等只有1位透明度的图标")]
+    alpha_threshold: Option<u8>,
+
+    /// JPEG XL无损编码
+    #[arg(long)]
+    #[arg(help = "JPEG XL输出使用无损编码 (需要以 jxl 特性编译)；目前只支持无损，--to jxl 时必须指定")]
+    jxl_lossless: bool,
+
+    /// PDF渲染分辨率
+    #[arg(long, value_name = "DPI", value_parser = parse_dpi)]
+    #[arg(help = "PDF输入按该分辨率光栅化 (需要以 pdf 特性编译)，范围1-2400，默认150")]
+    pdf_dpi: Option<f32>,
+
+    /// 转换PDF的全部页面
+    #[arg(long)]
+    #[arg(help = "PDF输入转换全部页面，输出文件名加 _p1、_p2 等页码；默认只转换第一页")]
+    all_pages: bool,
+
+    /// 输出平均颜色和主色
+    #[arg(long)]
+    #[arg(help = "输出每个图像的平均颜色和最多5种主色 (k-means)；直接复制或无损旋转的文件不解码，不输出")]
+    extract_colors: bool,
+
+    /// 输出颜色数量
+    #[arg(long)]
+    #[arg(help = "输出每个图像不重复的颜色数量，并指出R、G、B处处相等、实际是灰度的图像")]
+    color_count: bool,
+
+    /// 灰度图像自动转为灰度输出
+    #[arg(long)]
+    #[arg(help = "R、G、B处处相等的彩色图像 (例如按彩色保存的扫描件) 转为灰度后再编码，输出更小；配合 -v 输出被转换的文件")]
+    auto_grayscale: bool,
+
+    /// 动画目标帧率
+    #[arg(long, value_name = "N", value_parser = parse_fps)]
+    #[arg(help = "GIF输出保留动画并按目标帧率取样减少帧数 (源为GIF或动态WebP)，目标高于源帧率时保留全部帧")]
+    fps: Option<f32>,
+
+    /// 按参考图像的尺寸缩放所有输出
+    #[arg(long, value_name = "REFERENCE", conflicts_with = "resize")]
+    #[arg(help = "读取参考图像的尺寸，将每个输出缩放到该尺寸")]
+    match_size: Option<PathBuf>,
+
+    /// --match-size 的缩放方式 (fill, fit)
+    #[arg(long, value_name = "MODE", default_value = "fill")]
+    #[arg(help = "--match-size 的缩放方式: fill 拉伸到精确尺寸, fit 保持宽高比缩放到尺寸以内 (也可用 --fit 的 cover、contain)")]
+    match_mode: FitMode,
+
+    /// WebP输出丢弃透明通道
+    #[arg(long)]
+    #[arg(help = "WebP输出总是丢弃透明通道；完全不透明的图像默认就按RGB编码")]
+    webp_no_alpha: bool,
+
+    /// JPEG色度抽样 (444, 422, 420)
+    #[arg(long, value_name = "MODE")]
+    #[arg(help = "JPEG色度抽样: 444 彩色文字边缘清晰但文件更大, 420 文件最小, 422 介于两者之间；未指定时保持默认编码器")]
+    jpeg_subsampling: Option<ChromaSubsampling>,
+
+    /// 不检查扩展名
+    #[arg(long)]
+    #[arg(help = "不检查扩展名，只按文件头的魔数签名识别图像")]
+    any_extension: bool,
+
+    /// 拒绝的输入格式
+    #[arg(long = "reject-format", value_name = "FORMAT")]
+    #[arg(help = "按文件内容检测到该格式时报错而不转换，可重复指定，例如 --reject-format bmp")]
+    reject_formats: Vec<String>,
+
+    /// 只接受的输入格式
+    #[arg(long = "allow-input", value_name = "FORMAT")]
+    #[arg(help = "只转换按文件内容检测到该格式的输入，其他格式在解码前拒绝，可重复指定，例如 --allow-input png")]
+    allow_formats: Vec<String>,
+
+    /// 不在允许列表中的输入计为跳过
+    #[arg(long, requires = "allow_formats")]
+    #[arg(help = "批量转换时把不在 --allow-input 中的文件计为跳过而不是失败")]
+    skip_disallowed: bool,
+
+    /// 第一个失败就停止
+    #[arg(long)]
+    #[arg(help = "批量转换时第一个文件失败或被 --reject-format 拒绝就停止，并以错误退出")]
+    fail_fast: bool,
+
+    /// 转换前先校验全部输入
+    #[arg(long)]
+    #[arg(help = "批量转换前先解码全部输入，有任何文件损坏就列出并中止，不转换任何文件")]
+    validate_first: bool,
+
+    /// 边遍历边转换
+    #[arg(long, conflicts_with = "in_place")]
+    #[arg(help = "批量转换时边遍历目录边转换，不预先列出全部文件，内存占用与目录树大小无关 (适合数百万个文件的目录)")]
+    stream: bool,
+
+    /// 只按扩展名识别图像
+    #[arg(long, conflicts_with = "any_extension")]
+    #[arg(help = "只按扩展名识别图像，不读取文件头 (适合网络存储上的大目录)；扩展名不符的文件在解码时才报错")]
+    trust_extensions: bool,
+
+    /// 指定输入格式
+    #[arg(long, value_name = "FORMAT", value_parser = parse_input_format)]
+    #[arg(help = "把输入文件当作该格式解码，不按文件头识别，用于没有魔数的TGA或文件头损坏的数据；只能用于单个文件 (png, jpeg, gif, webp, ico, tiff, tga, dds, farbfeld, pnm, ppm, pgm, pbm, jxl, pdf)")]
+    input_format: Option<String>,
+
+    /// 输出文件扩展名
+    #[arg(long, value_name = "EXT")]
+    #[arg(help = "输出文件扩展名 (不含点号)，默认按目标格式选择，JPEG输出为 .jpg；例如 --extension jpeg")]
+    extension: Option<String>,
+
+    /// 只列出将要执行的转换
+    #[arg(long)]
+    #[arg(help = "只列出每个输入和对应的输出，不写任何文件、不执行钩子、不修改状态文件")]
+    dry_run: bool,
+
+    /// 预演时报告输出大小
+    #[arg(long, requires = "dry_run")]
+    #[arg(help = "预演时把每个文件完整编码到内存中，报告准确的输出大小和总大小 (比单纯预演慢)")]
+    estimate_sizes: bool,
+
+    /// 原地转换
+    #[arg(long, conflicts_with_all = ["output", "split_by_format", "collapse_depth"])]
+    #[arg(help = "输出写到源文件旁边，转换成功后删除源文件 (需要确认或 --yes)")]
+    in_place: bool,
+
+    /// 原地转换时保留备份
+    #[arg(long, requires = "in_place")]
+    #[arg(help = "原地转换时把源文件改名为 .bak 而不是删除")]
+    backup: bool,
+
+    /// 格式不变时也原地转换
+    #[arg(long, requires = "in_place")]
+    #[arg(help = "原地转换时格式和扩展名都不变也重新编码并覆盖源文件")]
+    force: bool,
+
+    /// 跳过确认
+    #[arg(short = 'y', long)]
+    #[arg(help = "不询问确认，直接执行原地转换")]
+    yes: bool,
+
+    /// 跳过过大的输入文件
+    #[arg(long, value_name = "BYTES")]
+    #[arg(help = "跳过大于该字节数的输入文件，只检查文件大小，不读取文件头")]
+    skip_larger_than: Option<u64>,
+
+    /// 输出各阶段耗时
+    #[arg(long)]
+    #[arg(help = "统计解码、变换、分析、编码和写入各阶段的累计耗时，结束时输出")]
+    profile: bool,
+
+    /// 输出编码决策
+    #[arg(long)]
+    #[arg(help = "逐个文件输出源格式、尺寸、颜色类型、内容分析的水平/垂直变化、PNG过滤器、压缩级别和最终大小，写到标准错误")]
+    trace: bool,
+
+    /// 编码决策写入的文件
+    #[arg(long, value_name = "PATH")]
+    #[arg(help = "把 --trace 的内容追加到该文件而不是标准错误，指定时自动开启 --trace")]
+    trace_file: Option<PathBuf>,
+
+    /// 清理残留的临时文件
+    #[arg(long)]
+    #[arg(help = "批量转换前删除输出目录 (原地转换时为输入目录) 中上次中断留下的 .pixforge-tmp 临时文件；一小时内修改过的不删除，以免影响同时运行的其它进程")]
+    clean_temp: bool,
+
+    /// 复制不转换的文件
+    #[arg(long)]
+    #[arg(help = "转换zip压缩包时把不是图像的条目原样写到输出目录；默认跳过")]
+    copy_unconverted: bool,
 
     /// 详细输出模式
     #[arg(short = 'v', long)]
@@ -46,37 +530,519 @@ struct CliArgs {
     verbose: bool,
 }
 
+/// 只读构建 (关闭 `encode` 特性) 的命令行，只提供检测和读取尺寸的子命令
+#[cfg(not(feature = "encode"))]
+#[derive(Parser)]
+#[command(name = "pixforge")]
+#[command(about = "🎨 PixForge - 图像格式检测工具 (只读构建)")]
+#[command(version = "0.1.0")]
+#[command(author = "PixForge Team")]
+struct CliArgs {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// 不做转换的辅助子命令
+#[derive(Subcommand)]
+enum Commands {
+    /// 比较两张图像的像素差异
+    #[cfg(feature = "encode")]
+    Compare(CompareArgs),
+    /// 只读取文件头，列出图像尺寸
+    Dimensions(DimensionsArgs),
+    /// 从一张图像生成网站图标包 (favicon.ico 和各尺寸PNG)
+    #[cfg(feature = "encode")]
+    Favicon(FaviconArgs),
+    /// 只按文件内容统计目录中的图像格式，不转换
+    Formats(FormatsArgs),
+    /// 将目录中的图像拼成一张联系表
+    #[cfg(feature = "encode")]
+    Montage(MontageArgs),
+    /// 把一张大图切成固定尺寸的瓦片
+    #[cfg(feature = "encode")]
+    Split(SplitArgs),
+    /// 监视目录，自动转换新增或修改的图像
+    #[cfg(feature = "encode")]
+    Watch(WatchArgs),
+}
+
+/// compare 子命令参数
+#[cfg(feature = "encode")]
+#[derive(Args)]
+struct CompareArgs {
+    /// 第一张图像 (通常为源图像)
+    #[arg(value_name = "A")]
+    #[arg(help = "第一张图像，通常为转换前的源图像")]
+    first: PathBuf,
+
+    /// 第二张图像 (通常为转换结果)
+    #[arg(value_name = "B")]
+    #[arg(help = "第二张图像，通常为转换后的输出")]
+    second: PathBuf,
+
+    /// 差异热力图输出文件
+    #[arg(long, value_name = "PATH")]
+    #[arg(help = "写出差异热力图，格式由扩展名决定；差异按最大值归一化，从黑经红、黄到白")]
+    diff_out: Option<PathBuf>,
+
+    /// 尺寸不一致时缩放
+    #[arg(long)]
+    #[arg(help = "尺寸不一致时把第二张图像缩放到第一张的尺寸再比较，默认报错")]
+    resize: bool,
+}
+
+/// dimensions 子命令参数
+#[derive(Args)]
+struct DimensionsArgs {
+    /// 输入文件或目录路径
+    #[arg(value_name = "INPUT")]
+    #[arg(help = "要读取尺寸的图像文件或目录")]
+    input: PathBuf,
+
+    /// 以JSON格式输出
+    #[arg(long)]
+    #[arg(help = "以JSON数组输出尺寸清单")]
+    json: bool,
+
+    /// 输出尺寸分布
+    #[arg(long, value_name = "GROUPING", num_args = 0..=1, default_missing_value = "buckets")]
+    #[arg(help = "只输出尺寸分布：buckets (默认) 按长边分为 ≤256、257-512、513-1024、1025-2048、2049-4096、>4096；exact 按精确的宽x高分组，数量多的在前")]
+    histogram: Option<HistogramGrouping>,
+}
+
+/// favicon 子命令参数
+#[cfg(feature = "encode")]
+#[derive(Args)]
+struct FaviconArgs {
+    /// 源图像
+    #[arg(value_name = "INPUT")]
+    #[arg(help = "图标的源图像，建议使用不小于512x512的正方形图像")]
+    input: PathBuf,
+
+    /// 输出目录
+    #[arg(short = 'o', long, value_name = "OUTPUT")]
+    #[arg(help = "图标包的输出目录，不存在时自动创建")]
+    output: PathBuf,
+
+    /// favicon.ico 的帧尺寸
+    #[arg(long, value_name = "SIZES", value_delimiter = ',', default_value = "16,32,48")]
+    #[arg(value_parser = parse_ico_size)]
+    #[arg(help = "favicon.ico 包含的帧尺寸 (1-256)")]
+    ico_sizes: Vec<u32>,
+
+    /// 单独输出的PNG尺寸
+    #[arg(long, value_name = "SIZES", value_delimiter = ',', default_value = "16,32,180,192,512")]
+    #[arg(value_parser = parse_icon_size)]
+    #[arg(help = "单独输出的PNG尺寸；180 命名为 apple-touch-icon.png，192、512 为 android-chrome-NxN.png，其余为 favicon-NxN.png")]
+    png_sizes: Vec<u32>,
+
+    /// 不生成 favicon.ico
+    #[arg(long)]
+    #[arg(help = "只输出PNG图标，不生成 favicon.ico")]
+    no_ico: bool,
+
+    /// 锐化小尺寸图标
+    #[arg(long)]
+    #[arg(help = "不超过32像素的图标做轻微锐化，避免缩小后发虚")]
+    sharpen: bool,
+}
+
+/// formats 子命令参数
+#[derive(Args)]
+struct FormatsArgs {
+    /// 输入文件或目录路径
+    #[arg(value_name = "INPUT")]
+    #[arg(help = "要统计格式的文件或目录")]
+    input: PathBuf,
+
+    /// 以JSON格式输出
+    #[arg(long)]
+    #[arg(help = "以JSON输出各格式的文件数和无法识别的文件")]
+    json: bool,
+}
+
+/// montage 子命令参数
+#[cfg(feature = "encode")]
+#[derive(Args)]
+struct MontageArgs {
+    /// 输入目录
+    #[arg(value_name = "INPUT")]
+    #[arg(help = "包含图像的目录")]
+    input: PathBuf,
+
+    /// 输出文件，格式由扩展名决定
+    #[arg(short = 'o', long, value_name = "OUTPUT")]
+    #[arg(help = "联系表输出文件，格式由扩展名决定")]
+    output: PathBuf,
+
+    /// 每行的缩略图数量
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    #[arg(help = "每行的缩略图数量")]
+    columns: u32,
+
+    /// 单个格子的尺寸
+    #[arg(long, value_name = "WxH", default_value = "128x128")]
+    #[arg(help = "单个缩略图格子的尺寸")]
+    #[arg(value_parser = parse_dimensions)]
+    tile_size: (u32, u32),
+
+    /// 背景颜色
+    #[arg(long, value_name = "COLOR", default_value = "white")]
+    #[arg(help = "背景颜色，例如 #ffffff、black、transparent")]
+    #[arg(value_parser = parse_color)]
+    background: image::Rgba<u8>,
+}
+
+/// split 子命令参数
+#[cfg(feature = "encode")]
+#[derive(Args)]
+struct SplitArgs {
+    /// 源图像
+    #[arg(value_name = "INPUT")]
+    #[arg(help = "要切成瓦片的图像文件")]
+    input: PathBuf,
+
+    /// 输出目录
+    #[arg(short = 'o', long, value_name = "OUTPUT")]
+    #[arg(help = "瓦片的输出目录，不存在时自动创建")]
+    output: PathBuf,
+
+    /// 目标格式
+    #[arg(long, value_name = "FORMAT", env = FORMAT_ENV)]
+    #[arg(help = "瓦片的格式 (png, jpeg, jpg, gif, webp, ico, farbfeld, ff, tga)")]
+    to: String,
+
+    /// 质量设置
+    #[arg(short = 'q', long, value_name = "QUALITY", env = "PIXFORGE_QUALITY")]
+    #[arg(help = "图像质量 (0-100，可为小数)")]
+    #[arg(value_parser = parse_quality)]
+    quality: Option<f32>,
+
+    /// 瓦片尺寸
+    #[arg(long, value_name = "N", default_value_t = 256, value_parser = parse_tile_size)]
+    #[arg(help = "瓦片的边长 (像素)；最右一列和最下一行不足N时保留实际尺寸")]
+    tile_size: u32,
+
+    /// 按 z/x/y 目录结构命名
+    #[arg(long, value_name = "Z")]
+    #[arg(help = "按地图瓦片的 Z/x/y.扩展名 目录结构输出，默认命名为 <源文件名>_x_y.扩展名")]
+    zoom: Option<u32>,
+}
+
+/// watch 子命令参数
+#[cfg(feature = "encode")]
+#[derive(Args)]
+struct WatchArgs {
+    /// 要监视的目录
+    #[arg(value_name = "DIR")]
+    #[arg(help = "要监视的输入目录")]
+    input: PathBuf,
+
+    /// 目标格式
+    #[arg(long, value_name = "FORMAT", env = FORMAT_ENV)]
+    #[arg(help = "目标格式 (png, jpeg, jpg, gif, webp, ico, farbfeld, ff, tga)")]
+    to: String,
+
+    /// 输出目录
+    #[arg(short = 'o', long, value_name = "OUTPUT")]
+    #[arg(help = "输出目录 (默认与批量转换相同)")]
+    output: Option<PathBuf>,
+
+    /// 质量设置
+    #[arg(short = 'q', long, value_name = "QUALITY", env = "PIXFORGE_QUALITY")]
+    #[arg(help = "图像质量 (0-100，可为小数)")]
+    #[arg(value_parser = parse_quality)]
+    quality: Option<f32>,
+
+    /// 去抖时间
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    #[arg(help = "文件最后一次变化后等待的毫秒数，避免读到尚未写完的文件")]
+    debounce: u64,
+}
+
+#[cfg(feature = "encode")]
 fn main() -> Result<()> {
     // 正常模式：解析命令行参数
     let args = CliArgs::parse();
 
+    match &args.command {
+        Some(Commands::Compare(compare_args)) => run_compare(compare_args),
+        Some(Commands::Dimensions(dimensions_args)) => run_dimensions(dimensions_args),
+        Some(Commands::Favicon(favicon_args)) => run_favicon(favicon_args),
+        Some(Commands::Formats(formats_args)) => run_formats(formats_args),
+        Some(Commands::Montage(montage_args)) => run_montage(montage_args),
+        Some(Commands::Split(split_args)) => run_split(split_args),
+        Some(Commands::Watch(watch_args)) => run_watch(watch_args),
+        None => run_convert(args),
+    }
+}
+
+#[cfg(not(feature = "encode"))]
+fn main() -> Result<()> {
+    match &CliArgs::parse().command {
+        Commands::Dimensions(dimensions_args) => run_dimensions(dimensions_args),
+        Commands::Formats(formats_args) => run_formats(formats_args),
+    }
+}
+
+/// 执行图像转换
+#[cfg(feature = "encode")]
+fn run_convert(args: CliArgs) -> Result<()> {
+    let input = args.input.context("❌ 缺少输入路径")?;
+
     // 验证输入路径
-    validate_input_path(&args.input)?;
+    validate_input_path(&input)?;
 
-    // 验证目标格式
-    validate_target_format(&args.to)?;
+    // 确定并验证目标格式
+    let default_format = std::env::var(FORMAT_ENV).ok().filter(|format| !format.is_empty());
+    let to = determine_target_format(&input, args.to.as_deref(), args.output.as_deref(), default_format.as_deref())?;
+    validate_target_format(&to)?;
+    // 编码器只有无损模式，不指定时在开始转换前报错，而不是每个文件都失败
+    if pixforge::utils::canonical_format(&to) == "jxl" && !args.jxl_lossless {
+        anyhow::bail!("❌ JPEG XL输出目前只支持无损编码，请加上 --jxl-lossless");
+    }
+
+    if args.input_format.is_some() && !input.is_file() {
+        anyhow::bail!("❌ --input-format 只能用于单个输入文件: {}", input.display());
+    }
 
     // 确定输出路径
-    let output_path = determine_output_path(&args.input, &args.output);
+    let output_path = determine_output_path(&input, &args.output);
 
     // 获取质量设置
-    let quality = args.quality.unwrap_or(80);
+    let quality = args.quality.unwrap_or(DEFAULT_QUALITY);
     
     if args.verbose {
-        print_conversion_info(&args.input, &output_path, &args.to, quality);
+        print_conversion_info(&input, &output_path, &to, quality, args.progress_json);
     }
 
+    // 解析缩放目标
+    let resize = match &args.match_size {
+        Some(reference) => Some(Resize::from_reference(reference, args.match_mode)?),
+        None => args.resize,
+    };
+    let resize = resize.map(|mut resize| {
+        resize.mode = match args.fit.unwrap_or(resize.mode) {
+            // 图案和图像背景在编码前合成，这里先留出透明空白
+            FitMode::Contain(_) => FitMode::Contain(match &args.background {
+                Some(Backdrop::Color(color)) => *color,
+                Some(_) => image::Rgba([0, 0, 0, 0]),
+                None if args.background_image.is_some() => image::Rgba([0, 0, 0, 0]),
+                None => DEFAULT_CONTAIN_BACKGROUND,
+            }),
+            mode => mode,
+        };
+        resize
+    });
+
+    if args.optimize && !cfg!(feature = "optimize") {
+        notice!(args.progress_json, "⚠️  编译时未启用 optimize 特性，--optimize 只优化JPEG");
+    }
+
+    if args.in_place && !args.yes && !args.dry_run {
+        confirm_in_place(args.backup)?;
+    }
+
+    if args.on_success.is_some() || args.on_failure.is_some() {
+        notice!(args.progress_json, "⚠️  已启用钩子命令，将为每个文件执行外部shell命令，请确认命令来源可信");
+    }
+
+    if args.fallback_decoder.is_some() {
+        notice!(args.progress_json, "⚠️  已启用后备解码命令，无法识别的输入会交给外部shell命令处理，请确认命令来源可信");
+    }
+
+    let recipe = args.recipe.as_deref().map(Recipe::load).transpose()?;
+
     // 执行转换
-    let converter = ImageConverter::new();
+    let options = ConvertOptions {
+        ico_pick: args.ico_pick,
+        ico_sizes: args.ico_sizes,
+        ico_sharpen: args.ico_sharpen,
+        palette_from: args.palette_from,
+        verify_lossless: args.verify_lossless,
+        no_verify_output: args.no_verify_output,
+        adaptive_quality: args.adaptive_quality,
+        auto_webp_mode: args.auto_webp_mode,
+        webp_near_lossless: args.webp_near_lossless,
+        analysis_samples: args.analysis_samples,
+        on_success: args.on_success.map(HookCommand::new),
+        on_failure: args.on_failure.map(HookCommand::new),
+        fallback_decoder: args.fallback_decoder.map(FallbackDecoder::new),
+        decode_timeout: args.decode_timeout,
+        limit: args.limit,
+        limit_mode: args.limit_mode,
+        max_total_bytes: args.max_total_bytes,
+        expect_min: args.expect_min,
+        summary_template: args.summary_template,
+        summary_json: args.summary_json,
+        progress_json: args.progress_json,
+        split_by_format: args.split_by_format,
+        exclude_dirs: args.exclude_dirs,
+        collapse_depth: args.collapse_depth,
+        subpath_case: args.subpath_case,
+        name_by_hash: args.name_by_hash,
+        state_file: args.state.clone(),
+        resume: args.resume,
+        auto_orient: args.auto_orient,
+        rotate: args.rotate.unwrap_or_default(),
+        crop: None,
+        recipe: recipe.map(Arc::new),
+        deskew: args.deskew,
+        normalize_orientation: args.normalize_orientation,
+        aspect: args.aspect,
+        resize,
+        no_upscale: args.no_upscale,
+        resize_if_larger: args.resize_if_larger,
+        pad_square: args.pad_square.then_some(args.pad_color),
+        extract: args.extract,
+        missing_alpha: args.missing_alpha,
+        tone_map: args.tone_map,
+        swap_rb: args.swap_rb,
+        alpha_threshold: args.alpha_threshold,
+        flatten: match &args.background_image {
+            Some(path) => Some(Backdrop::Image(path.clone(), args.background_layout)),
+            None => args.background.clone(),
+        },
+        pdf_dpi: args.pdf_dpi,
+        pdf_all_pages: args.all_pages,
+        jxl_lossless: args.jxl_lossless,
+        extract_colors: args.extract_colors,
+        color_count: args.color_count,
+        auto_grayscale: args.auto_grayscale,
+        verbose: args.verbose,
+        fps: args.fps,
+        jpeg_subsampling: args.jpeg_subsampling,
+        skip_larger: args.skip_larger.then_some(args.larger_action),
+        webp_no_alpha: args.webp_no_alpha,
+        profile: args.profile,
+        trace: args.trace || args.trace_file.is_some(),
+        trace_file: args.trace_file,
+        clean_temp: args.clean_temp,
+        copy_unconverted: args.copy_unconverted,
+        any_extension: args.any_extension,
+        trust_extensions: args.trust_extensions,
+        reject_formats: args.reject_formats,
+        allow_formats: args.allow_formats,
+        skip_disallowed: args.skip_disallowed,
+        fail_fast: args.fail_fast,
+        validate_first: args.validate_first,
+        stream: args.stream,
+        max_file_size: args.skip_larger_than,
+        input_format: args.input_format,
+        extension: args.extension,
+        source_quality: args.quality.is_none(),
+        optimize: args.optimize,
+        jpeg_restart: args.jpeg_restart,
+        max_bytes: args.max_bytes,
+        min_quality: args.min_quality,
+        dry_run: args.dry_run,
+        estimate_sizes: args.estimate_sizes,
+        in_place: args.in_place,
+        backup: args.backup,
+        force: args.force,
+    };
+    let converter = ImageConverter::builder().options(options).quality(quality).build();
+
+    if archive::is_zip(&input) {
+        notice!(args.progress_json, "🗜️  压缩包转换模式");
+        converter.convert_archive(&input, &output_path, &to, converter.quality())?;
+    } else if input.is_file() {
+        if archive::is_zip_output(&output_path) {
+            anyhow::bail!("❌ 单文件转换不能输出到zip压缩包，请转换目录或zip压缩包: {}", output_path.display());
+        }
+        notice!(args.progress_json, "🖼️  单文件转换模式");
+        converter.convert_file(&input, &output_path, &to)?;
+    } else {
+        notice!(args.progress_json, "📁 批量转换模式");
+        converter.convert_dir(&input, &output_path, &to)?;
+    }
+
+    Ok(())
+}
+
+/// 原地转换会删除源文件，执行前在终端确认
+#[cfg(feature = "encode")]
+fn confirm_in_place(backup: bool) -> Result<()> {
+    let action = if backup { "改名为 .bak" } else { "删除" };
+    print!("⚠️  原地转换成功后会{}源文件，确认继续? [y/N] ", action);
+    std::io::Write::flush(&mut std::io::stdout())?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        anyhow::bail!("❌ 已取消原地转换 (使用 --yes 跳过确认)");
+    }
+    Ok(())
+}
 
-    if args.input.is_file() {
-        println!("🖼️  单文件转换模式");
-        converter.convert_single_file(&args.input, &output_path, &args.to, quality)?;
+/// 输出图像尺寸清单
+fn run_dimensions(args: &DimensionsArgs) -> Result<()> {
+    validate_input_path(&args.input)?;
+
+    let (dimensions, failures) = inspect::scan_dimensions(&args.input);
+
+    if let Some(grouping) = args.histogram {
+        let bins = inspect::dimension_histogram(&dimensions, grouping);
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&bins)?);
+        } else {
+            print_histogram(&bins);
+            println!("📊 共 {} 张图像", dimensions.len());
+        }
+    } else if args.json {
+        println!("{}", serde_json::to_string_pretty(&dimensions)?);
     } else {
-        println!("📁 批量转换模式");
-        converter.convert_directory(&args.input, &output_path, &args.to, quality)?;
+        for entry in &dimensions {
+            println!("{}x{}\t{}", entry.width, entry.height, entry.path.display());
+        }
+    }
+
+    for (path, e) in &failures {
+        eprintln!("⚠️  无法读取尺寸: {} ({})", path.display(), e);
+    }
+    Ok(())
+}
+
+/// 以文本条形图输出尺寸分布，最长的条为40个字符
+fn print_histogram(bins: &[inspect::HistogramBin]) {
+    const BAR_WIDTH: usize = 40;
+    let max_count = bins.iter().map(|bin| bin.count).max().unwrap_or_default();
+    let label_width = bins.iter().map(|bin| bin.label.chars().count()).max().unwrap_or_default();
+    let count_width = max_count.to_string().len();
+    for bin in bins {
+        let bar = if max_count == 0 { 0 } else { (bin.count * BAR_WIDTH).div_ceil(max_count) };
+        println!("{:<label_width$}  {:>count_width$}  {}", bin.label, bin.count, "█".repeat(bar));
     }
+}
+
+/// 统计目录中的图像格式
+fn run_formats(args: &FormatsArgs) -> Result<()> {
+    validate_input_path(&args.input)?;
+
+    let report = inspect::scan_formats(&args.input);
 
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    for (format, count) in &report.formats {
+        println!("{}\t{}", format, count);
+    }
+    for path in &report.undetected {
+        println!("⚠️  无法识别: {}", path.display());
+    }
+    let mut mismatched = 0;
+    for probe in report.mismatched() {
+        println!("🚩 扩展名与内容不符: {} (实际为 {}，{})", probe.path.display(), probe.format, probe.mime_type);
+        mismatched += 1;
+    }
+    println!(
+        "📊 共 {} 个文件，{} 个无法识别，{} 个扩展名与内容不符",
+        report.total(),
+        report.undetected.len(),
+        mismatched
+    );
     Ok(())
 }
 
@@ -88,30 +1054,315 @@ fn validate_input_path(input: &Path) -> Result<()> {
     Ok(())
 }
 
+/// 确定目标格式
+///
+/// 优先使用 --to；单文件转换且输出是带扩展名的文件路径（不是已存在的目录）时，
+/// 按输出扩展名推断；再其次使用环境变量给出的默认格式，都没有时必须指定 --to
+#[cfg(feature = "encode")]
+fn determine_target_format(input: &Path, to: Option<&str>, output: Option<&Path>, default: Option<&str>) -> Result<String> {
+    if let Some(to) = to {
+        return Ok(to.to_string());
+    }
+
+    let file_output = output.filter(|output| input.is_file() && !archive::is_zip(input) && !output.is_dir());
+    if let Some(output) = file_output {
+        let extension = pixforge::utils::get_extension(output);
+        if !extension.is_empty() {
+            return Ok(extension);
+        }
+    }
+    if let Some(default) = default {
+        return Ok(default.to_string());
+    }
+
+    match file_output {
+        Some(output) => anyhow::bail!("❌ 无法从输出路径推断格式: {}，请使用 --to 指定", output.display()),
+        None => anyhow::bail!("❌ 缺少目标格式 --to (只有单文件转换且 -o 指定了带扩展名的文件时才能省略)"),
+    }
+}
+
 /// 验证目标格式是否支持
+#[cfg(feature = "encode")]
 fn validate_target_format(format: &str) -> Result<()> {
-    if !SUPPORTED_FORMATS.contains(&format.to_lowercase().as_str()) {
+    if !TARGET_FORMATS.contains(&format.to_lowercase().as_str()) {
         anyhow::bail!(
             "❌ 不支持的目标格式: {}\n📋 支持的格式: {}",
             format,
-            SUPPORTED_FORMATS.join(", ")
+            TARGET_FORMATS.join(", ")
         );
     }
     Ok(())
 }
 
+/// 生成网站图标包
+#[cfg(feature = "encode")]
+fn run_favicon(args: &FaviconArgs) -> Result<()> {
+    validate_input_path(&args.input)?;
+    if !args.input.is_file() {
+        anyhow::bail!("图标的源图像必须是文件: {}", args.input.display());
+    }
+
+    let img = ImageConverter::new().decode(&args.input)?;
+    if img.width() != img.height() {
+        println!("⚠️  源图像不是正方形 ({}x{})，图标将居中并补透明边", img.width(), img.height());
+    }
+    let options = FaviconOptions {
+        ico_sizes: if args.no_ico { Vec::new() } else { args.ico_sizes.clone() },
+        png_sizes: args.png_sizes.clone(),
+        sharpen: args.sharpen,
+    };
+    let written = favicon::write_bundle(&img, &args.output, &options)?;
+    for path in &written {
+        println!("✅ {}", path.display());
+    }
+    println!("🎉 图标包已生成: {} ({} 个文件)", args.output.display(), written.len());
+    Ok(())
+}
+
+/// 生成联系表
+#[cfg(feature = "encode")]
+fn run_montage(args: &MontageArgs) -> Result<()> {
+    validate_input_path(&args.input)?;
+
+    let paths = inspect::collect_image_files(&args.input);
+    let options = MontageOptions {
+        columns: args.columns,
+        tile_width: args.tile_size.0,
+        tile_height: args.tile_size.1,
+        background: args.background,
+    };
+
+    let (sheet, failures) = montage::build_montage(&paths, &options)?;
+    for (path, e) in &failures {
+        println!("⚠️  跳过: {} ({})", path.display(), e);
+    }
+
+    // JPEG不支持透明度，保存前去掉alpha通道
+    let sheet = match pixforge::utils::get_extension(&args.output).as_str() {
+        "jpg" | "jpeg" => image::DynamicImage::ImageRgb8(image::DynamicImage::ImageRgba8(sheet).to_rgb8()),
+        _ => image::DynamicImage::ImageRgba8(sheet),
+    };
+    sheet
+        .save(&args.output)
+        .with_context(|| format!("联系表保存失败: {}", args.output.display()))?;
+
+    println!(
+        "🎉 联系表已生成: {} ({} 张图像)",
+        args.output.display(),
+        paths.len() - failures.len()
+    );
+    Ok(())
+}
+
+/// 把图像切成瓦片
+#[cfg(feature = "encode")]
+fn run_split(args: &SplitArgs) -> Result<()> {
+    validate_input_path(&args.input)?;
+    if !args.input.is_file() {
+        anyhow::bail!("要切分的源图像必须是文件: {}", args.input.display());
+    }
+    validate_target_format(&args.to)?;
+    let format = args.to.to_lowercase();
+
+    let converter = ImageConverter::new();
+    let img = converter.decode(&args.input)?;
+    let naming = match args.zoom {
+        Some(zoom) => TileNaming::Zxy(zoom),
+        None => TileNaming::Grid(args.input.file_stem().unwrap_or_default().to_string_lossy().into_owned()),
+    };
+    let quality = args.quality.unwrap_or(DEFAULT_QUALITY);
+    let written = tiles::split(&converter, &img, &args.output, &naming, &format, quality, args.tile_size)?;
+    println!(
+        "🎉 已切分为 {} 个瓦片 ({}x{}，每块 {} 像素): {}",
+        written.len(),
+        img.width().div_ceil(args.tile_size),
+        img.height().div_ceil(args.tile_size),
+        args.tile_size,
+        args.output.display()
+    );
+    Ok(())
+}
+
+/// 比较两张图像并输出差异指标
+#[cfg(feature = "encode")]
+fn run_compare(args: &CompareArgs) -> Result<()> {
+    let converter = ImageConverter::new();
+    let first = converter.decode(&args.first)?;
+    let second = converter.decode(&args.second)?;
+
+    let (comparison, first, second) = pixforge::compare::compare(&first, &second, args.resize)?;
+    let (first_size, second_size) = (comparison.first, comparison.second);
+    println!(
+        "📐 尺寸: {}x{} / {}x{} ({})",
+        first_size.0,
+        first_size.1,
+        second_size.0,
+        second_size.1,
+        if comparison.dimensions_match() { "一致" } else { "不一致，已缩放第二张图像" }
+    );
+    println!("📊 平均绝对差: {:.4} (最大 {})", comparison.mean_abs_diff, comparison.max_diff);
+    if comparison.psnr.is_infinite() {
+        println!("📈 PSNR: ∞ (完全相同)");
+    } else {
+        println!("📈 PSNR: {:.2} dB", comparison.psnr);
+    }
+
+    if let Some(diff_out) = &args.diff_out {
+        pixforge::compare::diff_heatmap(&first, &second)
+            .save(diff_out)
+            .with_context(|| format!("差异图保存失败: {}", diff_out.display()))?;
+        println!("🗺️  差异图已生成: {}", diff_out.display());
+    }
+    Ok(())
+}
+
+/// 监视目录并持续转换，直到进程被中断
+#[cfg(feature = "encode")]
+fn run_watch(args: &WatchArgs) -> Result<()> {
+    if !args.input.is_dir() {
+        anyhow::bail!("❌ 监视路径不是目录: {}", args.input.display());
+    }
+    validate_target_format(&args.to)?;
+
+    let output_path = determine_output_path(&args.input, &args.output);
+    pixforge::watch::watch_directory(
+        &ImageConverter::new(),
+        &args.input,
+        &output_path,
+        &args.to,
+        args.quality.unwrap_or(DEFAULT_QUALITY),
+        std::time::Duration::from_millis(args.debounce),
+        || true,
+    )
+}
+
+/// 解析 WxH 尺寸参数
+#[cfg(feature = "encode")]
+fn parse_dimensions(value: &str) -> Result<(u32, u32), String> {
+    pixforge::utils::parse_dimensions(value).map_err(|e| e.to_string())
+}
+
+/// 解析质量参数
+#[cfg(feature = "encode")]
+fn parse_quality(value: &str) -> Result<f32, String> {
+    pixforge::utils::parse_quality(value).map_err(|e| e.to_string())
+}
+
+/// 解析帧率参数
+#[cfg(feature = "encode")]
+fn parse_fps(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(fps) if fps.is_finite() && fps > 0.0 => Ok(fps),
+        _ => Err(format!("无效的帧率: {} (需要大于0的数字)", value)),
+    }
+}
+
+/// 解析解码时限
+#[cfg(feature = "encode")]
+fn parse_timeout(value: &str) -> Result<std::time::Duration, String> {
+    match value.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs > 0.0 => Ok(std::time::Duration::from_secs_f64(secs)),
+        _ => Err(format!("无效的时限: {} (需要大于0的秒数)", value)),
+    }
+}
+
+/// 解析PNG图标尺寸
+#[cfg(feature = "encode")]
+fn parse_icon_size(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(size) if (1..=4096).contains(&size) => Ok(size),
+        _ => Err(format!("无效的图标尺寸: {} (需要1-4096之间的整数)", value)),
+    }
+}
+
+/// 解析输入格式，返回规范名称
+#[cfg(feature = "encode")]
+fn parse_input_format(value: &str) -> Result<String, String> {
+    let format = pixforge::utils::canonical_format(value.trim());
+    if INPUT_FORMATS.contains(&format.as_str()) {
+        Ok(format)
+    } else {
+        Err(format!("不支持的输入格式: {} (支持: {})", value, INPUT_FORMATS.join(", ")))
+    }
+}
+
+/// 解析瓦片尺寸
+#[cfg(feature = "encode")]
+fn parse_tile_size(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(format!("无效的瓦片尺寸: {} (需要大于0的整数)", value)),
+    }
+}
+
+/// 解析ICO帧尺寸
+#[cfg(feature = "encode")]
+fn parse_ico_size(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(size) if (1..=256).contains(&size) => Ok(size),
+        _ => Err(format!("无效的ICO尺寸: {} (需要1-256之间的整数)", value)),
+    }
+}
+
+/// 解析JPEG重启间隔
+#[cfg(feature = "encode")]
+fn parse_jpeg_restart(value: &str) -> Result<u16, String> {
+    match value.parse::<u16>() {
+        Ok(rows) if rows > 0 => Ok(rows),
+        _ => Err(format!("无效的重启间隔: {} (需要1-65535之间的整数)", value)),
+    }
+}
+
+/// 解析采样点数参数
+#[cfg(feature = "encode")]
+fn parse_analysis_samples(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(samples) if samples > 0 => Ok(samples),
+        _ => Err(format!("无效的采样点数: {} (需要大于0的整数)", value)),
+    }
+}
+
+/// 解析PDF渲染分辨率
+#[cfg(feature = "encode")]
+fn parse_dpi(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(dpi) if (1.0..=2400.0).contains(&dpi) => Ok(dpi),
+        _ => Err(format!("无效的DPI: {} (需要1-2400之间的数字)", value)),
+    }
+}
+
+/// 解析颜色参数
+#[cfg(feature = "encode")]
+fn parse_color(value: &str) -> Result<image::Rgba<u8>, String> {
+    pixforge::utils::parse_color(value).map_err(|e| e.to_string())
+}
+
+/// 解析 --rotate 参数
+#[cfg(feature = "encode")]
+fn parse_rotation(value: &str) -> Result<Reorient, String> {
+    let degrees: u32 = value.parse().map_err(|_| format!("无效的旋转角度: {}", value))?;
+    Reorient::from_degrees(degrees).map_err(|e| e.to_string())
+}
+
+/// 解析 --aspect 参数
+#[cfg(feature = "encode")]
+fn parse_aspect(value: &str) -> Result<(u32, u32), String> {
+    pixforge::utils::parse_aspect_ratio(value).map_err(|e| e.to_string())
+}
+
 /// 确定输出路径
+#[cfg(feature = "encode")]
 fn determine_output_path(input: &Path, output: &Option<PathBuf>) -> PathBuf {
     match output {
         Some(path) => path.clone(),
         None => {
-            if input.is_file() {
+            if input.is_file() && !archive::is_zip(input) {
                 // 单文件：输出到同目录
                 input.parent()
                     .unwrap_or_else(|| Path::new("."))
                     .to_path_buf()
             } else {
-                // 目录：创建pixforge子目录
+                // 目录和压缩包：创建pixforge子目录
                 input.parent()
                     .unwrap_or_else(|| Path::new("."))
                     .join("pixforge_output")
@@ -121,11 +1372,128 @@ fn determine_output_path(input: &Path, output: &Option<PathBuf>) -> PathBuf {
 }
 
 /// 打印转换信息
-fn print_conversion_info(input: &Path, output: &Path, format: &str, quality: u8) {
-    println!("🔧 转换配置:");
-    println!("   📂 输入: {}", input.display());
-    println!("   📁 输出: {}", output.display());
-    println!("   🎯 格式: {}", format.to_uppercase());
-    println!("   ⚡ 质量: {}%", quality);
-    println!();
+#[cfg(feature = "encode")]
+fn print_conversion_info(input: &Path, output: &Path, format: &str, quality: f32, to_stderr: bool) {
+    notice!(to_stderr, "🔧 转换配置:");
+    notice!(to_stderr, "   📂 输入: {}", input.display());
+    notice!(to_stderr, "   📁 输出: {}", output.display());
+    notice!(to_stderr, "   🎯 格式: {}", format.to_uppercase());
+    notice!(to_stderr, "   ⚡ 质量: {}%", quality);
+    notice!(to_stderr, "");
+}
+
+#[cfg(feature = "encode")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// 读取 PIXFORGE_* 环境变量的测试互斥执行
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_infers_format_from_output_extension() {
+        let _env = ENV_LOCK.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.png");
+        image::RgbImage::new(8, 8).save(&input).unwrap();
+        let output = dir.path().join("out.webp");
+
+        let args = CliArgs::parse_from([
+            "pixforge".as_ref(),
+            input.as_os_str(),
+            "-o".as_ref(),
+            output.as_os_str(),
+        ]);
+        run_convert(args).unwrap();
+
+        assert_eq!(pixforge::utils::detect_image_format_by_content(&output).as_deref(), Some("webp"));
+    }
+
+    #[test]
+    fn test_requires_to_without_file_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.png");
+        image::RgbImage::new(8, 8).save(&input).unwrap();
+
+        // 输出是目录、没有扩展名或没有指定输出时都无法推断
+        assert!(determine_target_format(&input, None, Some(dir.path()), None).is_err());
+        assert!(determine_target_format(&input, None, Some(&dir.path().join("out")), None).is_err());
+        assert!(determine_target_format(&input, None, None, None).is_err());
+        assert!(determine_target_format(dir.path(), None, Some(&dir.path().join("out.png")), None).is_err());
+
+        // 推断出的格式同样需要通过校验
+        let inferred = determine_target_format(&input, None, Some(&dir.path().join("out.bmp")), None).unwrap();
+        assert!(validate_target_format(&inferred).is_err());
+
+        assert_eq!(determine_target_format(&input, None, Some(&dir.path().join("out.JPG")), None).unwrap(), "jpg");
+        assert_eq!(determine_target_format(&input, Some("png"), Some(&dir.path().join("out.webp")), None).unwrap(), "png");
+    }
+
+    #[test]
+    fn test_jxl_output_requires_lossless_flag() {
+        let _env = ENV_LOCK.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.png");
+        image::RgbImage::new(8, 8).save(&input).unwrap();
+
+        let args = CliArgs::parse_from(["pixforge".as_ref(), input.as_os_str(), "--to".as_ref(), "jxl".as_ref()]);
+        let err = run_convert(args).unwrap_err();
+        assert!(err.to_string().contains("--jxl-lossless"), "{}", err);
+        assert!(!dir.path().join("photo.jxl").exists());
+    }
+
+    #[test]
+    fn test_pdf_dpi_range() {
+        let parse = |dpi: &str| CliArgs::try_parse_from(["pixforge".to_string(), "report.pdf".into(), format!("--pdf-dpi={}", dpi)]);
+        assert_eq!(parse("1").unwrap().pdf_dpi, Some(1.0));
+        assert_eq!(parse("2400").unwrap().pdf_dpi, Some(2400.0));
+        for dpi in ["0", "0.5", "-72", "2401", "NaN", "inf", "abc"] {
+            let err = parse(dpi).err().unwrap();
+            assert!(err.to_string().contains("1-2400"), "{}: {}", dpi, err);
+        }
+    }
+
+    #[test]
+    fn test_env_defaults_below_cli_flags() {
+        let _env = ENV_LOCK.lock().unwrap();
+        std::env::set_var("PIXFORGE_QUALITY", "55");
+        assert_eq!(CliArgs::parse_from(["pixforge", "in.png"]).quality, Some(55.0));
+        assert_eq!(CliArgs::parse_from(["pixforge", "in.png", "-q", "90"]).quality, Some(90.0));
+        std::env::set_var("PIXFORGE_QUALITY", "150");
+        assert!(CliArgs::try_parse_from(["pixforge", "in.png"]).is_err());
+        std::env::remove_var("PIXFORGE_QUALITY");
+        assert_eq!(CliArgs::parse_from(["pixforge", "in.png"]).quality, None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("photo.png");
+        image::RgbImage::new(8, 8).save(&input).unwrap();
+        std::env::set_var(FORMAT_ENV, "webp");
+        run_convert(CliArgs::parse_from(["pixforge".as_ref(), input.as_os_str()])).unwrap();
+        run_convert(CliArgs::parse_from(["pixforge".as_ref(), input.as_os_str(), "--to".as_ref(), "jpeg".as_ref()])).unwrap();
+        std::env::remove_var(FORMAT_ENV);
+        assert_eq!(pixforge::utils::detect_image_format_by_content(&dir.path().join("photo.webp")).as_deref(), Some("webp"));
+        assert_eq!(pixforge::utils::detect_image_format_by_content(&dir.path().join("photo.jpg")).as_deref(), Some("jpeg"));
+
+        // -o 的扩展名同样优先于环境变量
+        assert_eq!(determine_target_format(&input, None, Some(&dir.path().join("out.gif")), Some("webp")).unwrap(), "gif");
+        assert_eq!(determine_target_format(dir.path(), None, None, Some("webp")).unwrap(), "webp");
+    }
+}
+
+#[cfg(all(test, not(feature = "encode")))]
+mod read_only_tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_minimal_build_exposes_only_read_only_commands() {
+        let command = CliArgs::command();
+        let names: Vec<_> = command.get_subcommands().map(|subcommand| subcommand.get_name()).collect();
+        assert_eq!(names, ["dimensions", "formats"]);
+
+        assert!(CliArgs::try_parse_from(["pixforge", "--to", "png", "photo.jpg"]).is_err());
+        assert!(CliArgs::try_parse_from(["pixforge", "compare", "a.png", "b.png"]).is_err());
+        assert!(CliArgs::try_parse_from(["pixforge", "dimensions", "photos", "--json"]).is_ok());
+    }
 }
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+
+/// 联系表 (montage) 的排版参数
+#[derive(Debug, Clone, Copy)]
+pub struct MontageOptions {
+    /// 每行的缩略图数量
+    pub columns: u32,
+    /// 单个格子的宽度
+    pub tile_width: u32,
+    /// 单个格子的高度
+    pub tile_height: u32,
+    /// 背景颜色
+    pub background: Rgba<u8>,
+}
+
+impl Default for MontageOptions {
+    fn default() -> Self {
+        Self {
+            columns: 4,
+            tile_width: 128,
+            tile_height: 128,
+            background: Rgba([255, 255, 255, 255]),
+        }
+    }
+}
+
+/// 将多张图像按网格拼成一张联系表
+///
+/// 每张图像保持宽高比缩放到格子以内并居中，无法解码的图像会被跳过，
+/// 跳过的文件和原因一并返回
+pub fn build_montage(
+    paths: &[PathBuf],
+    options: &MontageOptions,
+) -> Result<(RgbaImage, Vec<(PathBuf, anyhow::Error)>)> {
+    if options.columns == 0 {
+        anyhow::bail!("列数必须大于0");
+    }
+
+    let mut tiles = Vec::with_capacity(paths.len());
+    let mut failures = Vec::new();
+    for path in paths {
+        match load_tile(path, options) {
+            Ok(tile) => tiles.push(tile),
+            Err(e) => failures.push((path.clone(), e)),
+        }
+    }
+
+    if tiles.is_empty() {
+        anyhow::bail!("没有可用于拼接的图像");
+    }
+
+    let columns = options.columns.min(tiles.len() as u32);
+    let rows = (tiles.len() as u32).div_ceil(columns);
+    let mut sheet = RgbaImage::from_pixel(
+        columns * options.tile_width,
+        rows * options.tile_height,
+        options.background,
+    );
+
+    for (index, tile) in tiles.iter().enumerate() {
+        let column = index as u32 % columns;
+        let row = index as u32 / columns;
+        let x = column * options.tile_width + (options.tile_width - tile.width()) / 2;
+        let y = row * options.tile_height + (options.tile_height - tile.height()) / 2;
+        imageops::overlay(&mut sheet, tile, x as i64, y as i64);
+    }
+
+    Ok((sheet, failures))
+}
+
+/// 解码并缩放单个缩略图
+fn load_tile(path: &Path, options: &MontageOptions) -> Result<RgbaImage> {
+    let img = image::open(path)?;
+    Ok(img
+        .resize(options.tile_width, options.tile_height, FilterType::Lanczos3)
+        .to_rgba8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_montage_grid() {
+        let dir = tempfile::tempdir().unwrap();
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0]];
+        let paths: Vec<PathBuf> = colors
+            .iter()
+            .enumerate()
+            .map(|(index, color)| {
+                let path = dir.path().join(format!("{}.png", index));
+                image::RgbImage::from_pixel(20, 20, image::Rgb(*color)).save(&path).unwrap();
+                path
+            })
+            .collect();
+
+        let options = MontageOptions {
+            columns: 2,
+            tile_width: 10,
+            tile_height: 10,
+            ..Default::default()
+        };
+        let (sheet, failures) = build_montage(&paths, &options).unwrap();
+
+        assert!(failures.is_empty());
+        assert_eq!(sheet.dimensions(), (20, 20));
+        assert_eq!(sheet.get_pixel(5, 5), &Rgba([255, 0, 0, 255]));
+        assert_eq!(sheet.get_pixel(15, 5), &Rgba([0, 255, 0, 255]));
+        assert_eq!(sheet.get_pixel(5, 15), &Rgba([0, 0, 255, 255]));
+        assert_eq!(sheet.get_pixel(15, 15), &Rgba([255, 255, 0, 255]));
+    }
+
+    #[test]
+    fn test_build_montage_letterboxes_with_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wide.png");
+        image::RgbImage::from_pixel(40, 20, image::Rgb([0, 0, 0])).save(&path).unwrap();
+
+        let options = MontageOptions {
+            columns: 3,
+            tile_width: 10,
+            tile_height: 10,
+            background: Rgba([255, 255, 255, 255]),
+        };
+        let (sheet, _) = build_montage(&[path], &options).unwrap();
+
+        // 只有一张图时列数收缩为1
+        assert_eq!(sheet.dimensions(), (10, 10));
+        assert_eq!(sheet.get_pixel(5, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(sheet.get_pixel(5, 5), &Rgba([0, 0, 0, 255]));
+    }
+}
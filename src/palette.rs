@@ -0,0 +1,126 @@
+//! 从参考图像生成的共享GIF调色板
+//!
+//! 默认每张GIF各自量化出调色板，同一系列的图像颜色会略有差异；`--palette-from` 用NeuQuant从参考图像
+//! 生成一个调色板，批量中的每张GIF都映射到它，并作为全局调色板写入
+
+use anyhow::{Context, Result};
+use color_quant::NeuQuant;
+use image::DynamicImage;
+use std::borrow::Cow;
+
+/// 调色板中的不透明颜色数量，最后一个索引留给透明像素
+pub const PALETTE_COLORS: usize = 255;
+
+/// 透明像素使用的调色板索引
+const TRANSPARENT_INDEX: u8 = PALETTE_COLORS as u8;
+
+/// NeuQuant的采样因子 (1-30)，参考图像只量化一次，使用最精确的1
+const SAMPLE_FACTOR: i32 = 1;
+
+/// alpha低于该值的像素按透明处理，GIF只有完全透明和不透明两种
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// 从参考图像量化得到的调色板
+pub struct Palette {
+    quant: NeuQuant,
+    /// 按索引排列的RGB三元组，包括末尾的透明项
+    colors: Vec<u8>,
+}
+
+impl Palette {
+    /// 用参考图像的不透明像素训练调色板，图像没有不透明像素时返回错误
+    pub fn from_image(reference: &DynamicImage) -> Result<Self> {
+        let pixels: Vec<u8> = reference
+            .to_rgba8()
+            .pixels()
+            .filter(|pixel| pixel[3] >= ALPHA_THRESHOLD)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+            .collect();
+        if pixels.is_empty() {
+            anyhow::bail!("参考图像没有不透明像素，无法生成调色板");
+        }
+
+        let quant = NeuQuant::new(SAMPLE_FACTOR, PALETTE_COLORS, &pixels);
+        let mut colors = quant.color_map_rgb();
+        colors.extend_from_slice(&[0, 0, 0]);
+        Ok(Self { quant, colors })
+    }
+
+    /// 调色板的RGB三元组，与GIF全局调色板的字节一致
+    pub fn colors(&self) -> &[u8] {
+        &self.colors
+    }
+
+    /// 把图像的每个像素映射到最接近的调色板颜色，编码为单帧GIF
+    pub fn encode_gif(&self, img: &DynamicImage) -> Result<Vec<u8>> {
+        let rgba = img.to_rgba8();
+        let (width, height) = (u16::try_from(rgba.width()), u16::try_from(rgba.height()));
+        let (Ok(width), Ok(height)) = (width, height) else {
+            anyhow::bail!("GIF尺寸不能超过65535: {}x{}", rgba.width(), rgba.height());
+        };
+
+        let mut transparent = false;
+        let indices: Vec<u8> = rgba
+            .pixels()
+            .map(|pixel| {
+                if pixel[3] < ALPHA_THRESHOLD {
+                    transparent = true;
+                    TRANSPARENT_INDEX
+                } else {
+                    self.quant.index_of(&[pixel[0], pixel[1], pixel[2], 255]) as u8
+                }
+            })
+            .collect();
+
+        let frame = gif::Frame {
+            width,
+            height,
+            buffer: Cow::Owned(indices),
+            transparent: transparent.then_some(TRANSPARENT_INDEX),
+            ..gif::Frame::default()
+        };
+        let mut buffer = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut buffer, width, height, &self.colors).context("写入GIF文件头失败")?;
+            encoder.write_frame(&frame).context("写入GIF帧失败")?;
+        }
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn global_palette(gif: &[u8]) -> Vec<u8> {
+        let decoder = gif::DecodeOptions::new().read_info(gif).unwrap();
+        decoder.global_palette().unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_encode_maps_to_reference_palette() {
+        let reference = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| {
+            Rgba([(x * 8) as u8, (y * 8) as u8, 200, 255])
+        }));
+        let palette = Palette::from_image(&reference).unwrap();
+        assert_eq!(palette.colors().len(), (PALETTE_COLORS + 1) * 3);
+
+        let mut img = RgbaImage::from_pixel(4, 4, Rgba([250, 0, 200, 255]));
+        img.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        let gif = palette.encode_gif(&DynamicImage::ImageRgba8(img)).unwrap();
+        assert_eq!(global_palette(&gif), palette.colors());
+
+        let decoded = image::load_from_memory(&gif).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+        let pixel = decoded.get_pixel(1, 1);
+        assert_eq!(pixel[3], 255);
+        assert!(pixel[0] > 200 && pixel[1] < 40, "{:?}", pixel);
+    }
+
+    #[test]
+    fn test_transparent_reference_is_rejected() {
+        let reference = DynamicImage::ImageRgba8(RgbaImage::new(8, 8));
+        assert!(Palette::from_image(&reference).is_err());
+    }
+}
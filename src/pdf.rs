@@ -0,0 +1,129 @@
+//! PDF页面的光栅化
+//!
+//! 需要 `pdf` 特性，使用hayro在白色背景上渲染页面；未启用特性时返回明确的错误
+
+use anyhow::Result;
+use image::DynamicImage;
+use std::path::Path;
+
+/// 未指定 `--pdf-dpi` 时的渲染分辨率
+pub const DEFAULT_DPI: f32 = 150.0;
+
+#[cfg(not(feature = "pdf"))]
+const DISABLED_HINT: &str = "当前构建未启用PDF支持，请使用 `--features pdf` 重新编译";
+
+/// 按指定DPI渲染页面，`pages` 为0起始的页码，为 `None` 时渲染全部页面
+#[cfg(feature = "pdf")]
+pub fn render(path: &Path, pages: Option<usize>, dpi: f32) -> Result<Vec<DynamicImage>> {
+    use anyhow::Context;
+    use hayro::hayro_interpret::InterpreterSettings;
+    use hayro::hayro_syntax::Pdf;
+    use hayro::vello_cpu::color::palette::css::WHITE;
+    use hayro::{PixmapSettings, RenderCache, RenderSettings};
+
+    let data = std::fs::read(path).with_context(|| format!("无法打开图像: {}", path.display()))?;
+    let pdf = Pdf::new(data).map_err(|e| anyhow::anyhow!("无法解析PDF ({:?}): {}", e, path.display()))?;
+    let all_pages = pdf.pages();
+    if all_pages.is_empty() {
+        anyhow::bail!("PDF没有页面: {}", path.display());
+    }
+    let selected = match pages {
+        Some(index) => all_pages
+            .get(index..=index)
+            .ok_or_else(|| anyhow::anyhow!("PDF只有 {} 页: {}", all_pages.len(), path.display()))?,
+        None => &all_pages[..],
+    };
+
+    // PDF的坐标单位是1/72英寸
+    let scale = dpi / 72.0;
+    let cache = RenderCache::new();
+    let interpreter_settings = InterpreterSettings::default();
+    let pixmap_settings = PixmapSettings { x_scale: scale, y_scale: scale, bg_color: WHITE };
+    selected
+        .iter()
+        .map(|page| {
+            // hayro的画布宽高为u16，超出时会被截断而不是报错
+            let (width, height) = page.render_dimensions();
+            let limit = f32::from(u16::MAX);
+            if width * scale > limit || height * scale > limit {
+                anyhow::bail!(
+                    "PDF页面在 {} DPI 下超出最大渲染尺寸 ({}像素)，请降低 --pdf-dpi: {}",
+                    dpi,
+                    u16::MAX,
+                    path.display()
+                );
+            }
+            let pixmap = hayro::render(page, &cache, &interpreter_settings, &RenderSettings::default(), &pixmap_settings);
+            let (width, height) = (u32::from(pixmap.width()), u32::from(pixmap.height()));
+            // 背景不透明，预乘alpha与直通alpha相同
+            image::RgbaImage::from_raw(width, height, pixmap.data_as_u8_slice().to_vec())
+                .map(|rgba| DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8()))
+                .ok_or_else(|| anyhow::anyhow!("PDF页面像素数据不完整: {}", path.display()))
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "pdf"))]
+pub fn render(path: &Path, _pages: Option<usize>, _dpi: f32) -> Result<Vec<DynamicImage>> {
+    anyhow::bail!("{}: {}", DISABLED_HINT, path.display())
+}
+
+#[cfg(all(test, feature = "pdf"))]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 生成一页 `width` x `height` 点、中间有黑色矩形的PDF
+    pub(crate) fn one_page_pdf(width: u32, height: u32) -> Vec<u8> {
+        let content = format!("0 0 0 rg {} {} {} {} re f", width / 4, height / 4, width / 2, height / 2);
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            format!("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents 4 0 R >>", width, height),
+            format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content),
+        ];
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (index, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        pdf.extend_from_slice(
+            format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref).as_bytes(),
+        );
+        pdf
+    }
+
+    #[test]
+    fn test_render_one_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("receipt.pdf");
+        std::fs::write(&path, one_page_pdf(72, 144)).unwrap();
+
+        let pages = render(&path, Some(0), 144.0).unwrap();
+        assert_eq!(pages.len(), 1);
+        let page = pages[0].to_rgb8();
+        assert_eq!(page.dimensions(), (144, 288));
+        assert_eq!(page.get_pixel(2, 2).0, [255, 255, 255]);
+        assert_eq!(page.get_pixel(72, 144).0, [0, 0, 0]);
+
+        assert!(render(&path, Some(1), 72.0).is_err());
+    }
+
+    #[test]
+    fn test_render_rejects_oversized_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poster.pdf");
+        // 36英寸宽的页面在2400 DPI下需要86400像素
+        std::fs::write(&path, one_page_pdf(2592, 72)).unwrap();
+
+        let err = render(&path, Some(0), 2400.0).unwrap_err();
+        assert!(err.to_string().contains("--pdf-dpi"), "{}", err);
+        assert_eq!(render(&path, Some(0), 1.0).unwrap()[0].width(), 36);
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// 转换流程中计时的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Decode,
+    Transform,
+    Analyze,
+    Encode,
+    Write,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [Stage::Decode, Stage::Transform, Stage::Analyze, Stage::Encode, Stage::Write];
+
+    fn label(self) -> &'static str {
+        match self {
+            Stage::Decode => "解码",
+            Stage::Transform => "变换",
+            Stage::Analyze => "分析",
+            Stage::Encode => "编码",
+            Stage::Write => "写入",
+        }
+    }
+}
+
+/// 按阶段累计整个批次的耗时
+///
+/// 关闭时 `time` 只多一次布尔判断，不读取时钟。
+/// 派生的转换器共用同一个计时器，可以跨线程使用
+#[derive(Debug, Default)]
+pub struct Profiler {
+    enabled: bool,
+    totals: Mutex<[Duration; 5]>,
+    files: AtomicU32,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, ..Default::default() }
+    }
+
+    /// 执行闭包并将耗时计入指定阶段
+    pub fn time<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+
+        let start = Instant::now();
+        let result = f();
+        self.totals()[stage as usize] += start.elapsed();
+        result
+    }
+
+    /// 清空累计的耗时，每次转换开始时调用，报告只包含本次转换
+    pub fn reset(&self) {
+        if self.enabled {
+            *self.totals() = Default::default();
+            self.files.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// 记录完成了一个文件
+    pub fn finish_file(&self) {
+        if self.enabled {
+            self.files.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 生成耗时报告，未开启时返回 `None`
+    pub fn report(&self) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+
+        let totals = *self.totals();
+        let sum: Duration = totals.iter().sum();
+        let mut report = format!("⏱️  性能分析 ({} 个文件):", self.files.load(Ordering::Relaxed));
+        for stage in Stage::ALL {
+            let total = totals[stage as usize];
+            let share = if sum.is_zero() { 0.0 } else { total.as_secs_f64() / sum.as_secs_f64() * 100.0 };
+            let _ = write!(report, "\n   {}: {:>10.2} ms ({:>5.1}%)", stage.label(), total.as_secs_f64() * 1000.0, share);
+        }
+        let _ = write!(report, "\n   合计: {:>10.2} ms", sum.as_secs_f64() * 1000.0);
+        Some(report)
+    }
+
+    /// 计时的闭包panic不影响已累计的数据
+    fn totals(&self) -> MutexGuard<'_, [Duration; 5]> {
+        self.totals.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_profiler_reports_nothing() {
+        let profiler = Profiler::new(false);
+        assert_eq!(profiler.time(Stage::Decode, || 42), 42);
+        assert!(profiler.report().is_none());
+    }
+
+    #[test]
+    fn test_report_lists_every_stage() {
+        let profiler = Profiler::new(true);
+        profiler.time(Stage::Encode, || std::thread::sleep(Duration::from_millis(2)));
+        profiler.finish_file();
+
+        let report = profiler.report().unwrap();
+        assert!(report.contains("1 个文件"), "{}", report);
+        for stage in Stage::ALL {
+            assert!(report.contains(stage.label()), "{}", report);
+        }
+        assert!(profiler.totals()[Stage::Encode as usize] >= Duration::from_millis(2));
+    }
+
+    #[test]
+    fn test_reset_clears_totals() {
+        let profiler = Profiler::new(true);
+        profiler.time(Stage::Decode, || std::thread::sleep(Duration::from_millis(1)));
+        profiler.finish_file();
+        profiler.reset();
+
+        assert!(profiler.report().unwrap().contains("0 个文件"));
+        assert!(profiler.totals().iter().all(Duration::is_zero));
+    }
+}
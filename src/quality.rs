@@ -0,0 +1,82 @@
+//! 把统一的0-100质量换算成各编码器自己的参数
+//!
+//! 所有目标格式共用一个质量值，各编码器的参数范围和含义不同，换算规则集中在这里：
+//!
+//! | 格式 | 参数 | 换算 |
+//! |------|------|------|
+//! | JPEG | 整数质量 1-100 | 四舍五入，至少为1 |
+//! | WebP | 浮点质量 0-100 | 原样使用，保留小数精度 |
+//! | PNG | 压缩级别 | 0-20 为 Fast，21-60 为 Default，61-100 为 Best |
+//!
+//! PNG是无损格式，质量只影响压缩耗时和文件大小；过滤器按图像内容选择，不受质量影响。
+//! GIF、ICO、farbfeld、TGA、PNM和JPEG XL (无损) 不使用质量
+
+use image::codecs::png::CompressionType;
+
+/// 一个0-100质量值在各编码器中的参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityProfile {
+    quality: f32,
+}
+
+impl QualityProfile {
+    /// 创建质量配置，超出0-100的值截断到范围内
+    pub fn new(quality: f32) -> Self {
+        Self { quality: quality.clamp(0.0, 100.0) }
+    }
+
+    /// 统一的0-100质量
+    pub fn quality(&self) -> f32 {
+        self.quality
+    }
+
+    /// JPEG编码器的整数质量，0按libjpeg的处理方式换成1
+    pub fn jpeg(&self) -> u8 {
+        (self.quality.round() as u8).max(1)
+    }
+
+    /// WebP编码器的浮点质量
+    pub fn webp(&self) -> f32 {
+        self.quality
+    }
+
+    /// PNG的压缩级别
+    pub fn png_compression(&self) -> CompressionType {
+        match self.quality.round() as u8 {
+            0..=20 => CompressionType::Fast,
+            21..=60 => CompressionType::Default,
+            _ => CompressionType::Best,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jpeg_rounds_to_integer() {
+        assert_eq!(QualityProfile::new(12.5).jpeg(), 13);
+        assert_eq!(QualityProfile::new(12.4).jpeg(), 12);
+        assert_eq!(QualityProfile::new(100.0).jpeg(), 100);
+        assert_eq!(QualityProfile::new(0.0).jpeg(), 1);
+        assert_eq!(QualityProfile::new(150.0).jpeg(), 100);
+    }
+
+    #[test]
+    fn test_webp_keeps_fraction() {
+        assert_eq!(QualityProfile::new(12.5).webp(), 12.5);
+        assert_eq!(QualityProfile::new(80.0).webp(), 80.0);
+        assert_eq!(QualityProfile::new(-5.0).webp(), 0.0);
+    }
+
+    #[test]
+    fn test_png_compression_levels() {
+        assert!(matches!(QualityProfile::new(0.0).png_compression(), CompressionType::Fast));
+        assert!(matches!(QualityProfile::new(20.4).png_compression(), CompressionType::Fast));
+        assert!(matches!(QualityProfile::new(21.0).png_compression(), CompressionType::Default));
+        assert!(matches!(QualityProfile::new(60.0).png_compression(), CompressionType::Default));
+        assert!(matches!(QualityProfile::new(80.0).png_compression(), CompressionType::Best));
+        assert!(matches!(QualityProfile::new(100.0).png_compression(), CompressionType::Best));
+    }
+}
@@ -0,0 +1,144 @@
+//! 按文件指定裁剪、旋转和缩放的处理方案
+//!
+//! 方案是一个JSON对象，键为输入文件名 (批量转换时也可以是相对输入目录的路径)，
+//! 值为该文件的变换：
+//!
+//! ```json
+//! {
+//!   "cat.jpg": { "crop": { "x": 10, "y": 20, "width": 300, "height": 200 }, "rotate": 90 },
+//!   "raw/dog.png": { "resize": "256x256!" }
+//! }
+//! ```
+//!
+//! 方案中写出的变换替换对应的命令行设置，没写的沿用命令行设置；未列出的文件不受影响
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::transform::{CropRect, Reorient, Resize};
+
+/// 单个文件的变换
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecipeEntry {
+    /// 在EXIF方向校正之后、旋转之前执行的裁剪
+    pub crop: Option<CropRect>,
+    /// 替换 `--rotate`
+    pub rotate: Option<Reorient>,
+    /// 替换 `--resize`
+    pub resize: Option<Resize>,
+}
+
+/// 整个处理方案
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recipe {
+    entries: BTreeMap<String, RecipeEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawEntry {
+    crop: Option<RawCrop>,
+    rotate: Option<u32>,
+    resize: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCrop {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Recipe {
+    /// 读取并校验方案文件
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("无法读取处理方案: {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("无效的处理方案: {}", path.display()))
+    }
+
+    /// 解析方案JSON，未知字段、非90倍数的旋转和空裁剪区域都视为错误
+    pub fn parse(text: &str) -> Result<Self> {
+        let raw: BTreeMap<String, RawEntry> = serde_json::from_str(text)?;
+        let mut entries = BTreeMap::new();
+        for (name, raw) in raw {
+            let entry = Self::validate(raw).with_context(|| format!("文件 {} 的设置无效", name))?;
+            entries.insert(name.replace('\\', "/"), entry);
+        }
+        Ok(Self { entries })
+    }
+
+    fn validate(raw: RawEntry) -> Result<RecipeEntry> {
+        let crop = match raw.crop {
+            Some(crop) if crop.width == 0 || crop.height == 0 => anyhow::bail!("裁剪区域的宽高必须大于0"),
+            Some(RawCrop { x, y, width, height }) => Some(CropRect { x, y, width, height }),
+            None => None,
+        };
+        let rotate = raw.rotate.map(Reorient::from_degrees).transpose()?;
+        let resize = raw
+            .resize
+            .map(|resize| resize.parse::<Resize>().map_err(|e| anyhow::anyhow!(e)))
+            .transpose()?;
+        Ok(RecipeEntry { crop, rotate, resize })
+    }
+
+    /// 查找文件的设置，`relative` 为相对输入目录的路径，先按完整相对路径匹配，再按文件名匹配
+    ///
+    /// 返回匹配到的键和设置
+    pub fn lookup(&self, relative: &Path) -> Option<(&str, &RecipeEntry)> {
+        let relative_key = relative.to_string_lossy().replace('\\', "/");
+        let file_name = relative.file_name().map(|name| name.to_string_lossy().into_owned());
+        self.entries
+            .get_key_value(relative_key.as_str())
+            .or_else(|| file_name.and_then(|name| self.entries.get_key_value(name.as_str())))
+            .map(|(key, entry)| (key.as_str(), entry))
+    }
+
+    /// 方案中列出的全部文件名
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let recipe = Recipe::parse(
+            r#"{
+                "cat.jpg": { "crop": { "x": 1, "y": 2, "width": 30, "height": 20 }, "rotate": 270 },
+                "raw/dog.png": { "resize": "64x64!" }
+            }"#,
+        )
+        .unwrap();
+
+        let (key, cat) = recipe.lookup(Path::new("pets/cat.jpg")).unwrap();
+        assert_eq!(key, "cat.jpg");
+        assert_eq!(cat.crop, Some(CropRect { x: 1, y: 2, width: 30, height: 20 }));
+        assert_eq!(cat.rotate, Some(Reorient::from_degrees(270).unwrap()));
+        assert!(cat.resize.is_none());
+
+        assert!(recipe.lookup(Path::new("raw/dog.png")).is_some());
+        assert!(recipe.lookup(Path::new("other/dog.png")).is_none());
+        assert_eq!(recipe.names().collect::<Vec<_>>(), vec!["cat.jpg", "raw/dog.png"]);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_entries() {
+        assert!(Recipe::parse(r#"{ "a.png": { "rotate": 45 } }"#).is_err());
+        assert!(Recipe::parse(r#"{ "a.png": { "resize": "big" } }"#).is_err());
+        assert!(Recipe::parse(r#"{ "a.png": { "crop": { "x": 0, "y": 0, "width": 0, "height": 5 } } }"#).is_err());
+        assert!(Recipe::parse(r#"{ "a.png": { "flip": true } }"#).is_err());
+        assert!(Recipe::parse(r#"["a.png"]"#).is_err());
+
+        let err = Recipe::parse(r#"{ "a.png": { "rotate": 45 } }"#).unwrap_err();
+        assert!(format!("{:#}", err).contains("a.png"), "{:#}", err);
+    }
+}
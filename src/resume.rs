@@ -0,0 +1,147 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// 输入文件的身份：路径加修改时间和大小，文件被修改后不再视为已完成
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    size: u64,
+}
+
+/// 状态文件中的一行
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    #[serde(flatten)]
+    stamp: FileStamp,
+}
+
+/// 批量转换的断点续传状态
+///
+/// 状态文件每行记录一个已完成的输入 (JSON)，每完成一个文件追加一行，
+/// 中途被打断时最多丢失最后一行，读取时会忽略不完整的行
+pub struct ResumeState {
+    file: File,
+    completed: HashMap<PathBuf, FileStamp>,
+}
+
+impl ResumeState {
+    /// 打开状态文件
+    ///
+    /// `resume` 为真时读取已有记录并继续追加，否则清空后重新记录
+    pub fn open(path: &Path, resume: bool) -> Result<Self> {
+        let mut completed = HashMap::new();
+        let mut partial_line = false;
+        if resume && path.exists() {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("无法读取状态文件: {}", path.display()))?;
+            for line in content.lines() {
+                if let Ok(entry) = serde_json::from_str::<Entry>(line) {
+                    completed.insert(entry.path, entry.stamp);
+                }
+            }
+            partial_line = !content.is_empty() && !content.ends_with('\n');
+        }
+
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(path)
+            .with_context(|| format!("无法写入状态文件: {}", path.display()))?;
+
+        // 上次被打断时留下的不完整行单独结束，避免与新记录连在一起
+        if partial_line {
+            file.write_all(b"\n")?;
+        }
+
+        Ok(Self { file, completed })
+    }
+
+    /// 输入在上次运行中已经完成，且之后没有被修改
+    pub fn is_completed(&self, input: &Path) -> bool {
+        match (self.completed.get(&Self::key(input)), Self::stamp(input)) {
+            (Some(recorded), Ok(current)) => *recorded == current,
+            _ => false,
+        }
+    }
+
+    /// 记录一个已完成的输入
+    pub fn record(&mut self, input: &Path) -> Result<()> {
+        let entry = Entry { path: Self::key(input), stamp: Self::stamp(input)? };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .context("无法写入状态文件")?;
+
+        self.completed.insert(entry.path, entry.stamp);
+        Ok(())
+    }
+
+    /// 以绝对路径作为键，工作目录变化后仍能匹配
+    fn key(input: &Path) -> PathBuf {
+        input.canonicalize().unwrap_or_else(|_| input.to_path_buf())
+    }
+
+    fn stamp(input: &Path) -> Result<FileStamp> {
+        let metadata = fs::metadata(input)?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(FileStamp {
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            size: metadata.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_survives_reopen_and_detects_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.jsonl");
+        let input = dir.path().join("a.png");
+        fs::write(&input, b"first").unwrap();
+
+        let mut state = ResumeState::open(&state_path, false).unwrap();
+        assert!(!state.is_completed(&input));
+        state.record(&input).unwrap();
+        drop(state);
+
+        // 模拟写到一半被打断的最后一行
+        let mut file = OpenOptions::new().append(true).open(&state_path).unwrap();
+        file.write_all(b"{\"path\":\"/trunc").unwrap();
+        drop(file);
+
+        let mut state = ResumeState::open(&state_path, true).unwrap();
+        assert!(state.is_completed(&input));
+
+        // 新记录不会和不完整的行连在一起
+        let other = dir.path().join("b.png");
+        fs::write(&other, b"other").unwrap();
+        state.record(&other).unwrap();
+        assert!(ResumeState::open(&state_path, true).unwrap().is_completed(&other));
+
+        // 大小变化后需要重新处理
+        fs::write(&input, b"second version").unwrap();
+        assert!(!state.is_completed(&input));
+
+        // 不续传时清空记录
+        drop(state);
+        ResumeState::open(&state_path, false).unwrap();
+        assert_eq!(fs::read_to_string(&state_path).unwrap(), "");
+    }
+}
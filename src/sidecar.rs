@@ -0,0 +1,105 @@
+//! 单个文件的转换设置覆盖
+//!
+//! 图像旁边放一个同名加 `.pixforge` 后缀的文件 (如 `image.png.pixforge`)，
+//! 内容为逗号或换行分隔的 `key=value`，`#` 开头的行是注释：
+//!
+//! ```text
+//! format=webp,quality=95
+//! ```
+//!
+//! 支持的键为 `format` 和 `quality`，未写的键沿用命令行设置
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::converter::TARGET_FORMATS;
+use crate::utils;
+
+/// 附属设置文件的后缀
+pub const SIDECAR_SUFFIX: &str = ".pixforge";
+
+/// 从附属设置文件读取的覆盖项
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sidecar {
+    /// 目标格式
+    pub format: Option<String>,
+    /// 质量 (0-100)
+    pub quality: Option<f32>,
+}
+
+/// 图像对应的附属设置文件路径
+pub fn sidecar_path(input: &Path) -> PathBuf {
+    let mut path = input.as_os_str().to_owned();
+    path.push(SIDECAR_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// 读取图像的附属设置，文件不存在时返回 `Ok(None)`
+pub fn load(input: &Path) -> Result<Option<Sidecar>> {
+    let path = sidecar_path(input);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(&path).with_context(|| format!("无法读取设置文件: {}", path.display()))?;
+    parse(&text)
+        .map(Some)
+        .with_context(|| format!("无效的设置文件: {}", path.display()))
+}
+
+/// 解析 `key=value` 形式的设置
+pub fn parse(text: &str) -> Result<Sidecar> {
+    let mut sidecar = Sidecar::default();
+
+    let entries = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty());
+    for entry in entries {
+        let (key, value) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("缺少 '=': {}", entry))?;
+        let value = value.trim();
+        match key.trim().to_lowercase().as_str() {
+            "format" => {
+                let format = value.to_lowercase();
+                if !TARGET_FORMATS.contains(&format.as_str()) {
+                    anyhow::bail!("不支持的目标格式: {}", value);
+                }
+                sidecar.format = Some(format);
+            }
+            "quality" => sidecar.quality = Some(utils::parse_quality(value)?),
+            other => anyhow::bail!("未知的设置项: {}", other),
+        }
+    }
+
+    Ok(sidecar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let sidecar = parse("format=WebP, quality=95").unwrap();
+        assert_eq!(sidecar.format.as_deref(), Some("webp"));
+        assert_eq!(sidecar.quality, Some(95.0));
+
+        let sidecar = parse("# 只覆盖质量\nquality = 12.5\n").unwrap();
+        assert_eq!(sidecar, Sidecar { format: None, quality: Some(12.5) });
+
+        assert!(parse("format=bmp").is_err());
+        assert!(parse("quality=120").is_err());
+        assert!(parse("colour=red").is_err());
+        assert!(parse("webp").is_err());
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(sidecar_path(Path::new("dir/image.png")), PathBuf::from("dir/image.png.pixforge"));
+    }
+}
@@ -0,0 +1,228 @@
+//! 批量转换结束时的统计摘要模板
+//!
+//! 模板中的 `{converted}` 等占位符替换为统计数字，`{{` 和 `}}` 输出字面的花括号，
+//! 例如 `pixforge converted={converted} saved={saved_percent}%`
+
+use serde::Serialize;
+use std::str::FromStr;
+
+/// 模板可以使用的占位符
+pub const PLACEHOLDERS: &[&str] = &["converted", "skipped", "failed", "total", "bytes_in", "bytes_out", "saved_percent"];
+
+/// 文件没有被转换的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// 不是可识别的图像
+    NotAnImage,
+    /// 输出已经存在：续传时上次已完成、原地转换时格式没有变化、按哈希命名时内容相同
+    AlreadyExists,
+    /// 输入超过 `--skip-larger-than`，或开启 `--skip-larger` 时输出比源文件大
+    TooLarge,
+    /// 被其它条件排除，例如压缩包中路径不安全的条目
+    Filtered,
+    /// 转换失败
+    Failed,
+}
+
+/// 按原因分类的未转换文件数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SkipBreakdown {
+    pub not_an_image: u32,
+    pub already_exists: u32,
+    pub too_large: u32,
+    pub filtered: u32,
+    pub failed: u32,
+}
+
+impl SkipBreakdown {
+    /// 记录一个没有转换的文件
+    pub fn record(&mut self, reason: SkipReason) {
+        *match reason {
+            SkipReason::NotAnImage => &mut self.not_an_image,
+            SkipReason::AlreadyExists => &mut self.already_exists,
+            SkipReason::TooLarge => &mut self.too_large,
+            SkipReason::Filtered => &mut self.filtered,
+            SkipReason::Failed => &mut self.failed,
+        } += 1;
+    }
+
+    /// 非零的分类及其数量，用于输出
+    pub fn nonzero(&self) -> Vec<(&'static str, u32)> {
+        [
+            ("非图像", self.not_an_image),
+            ("已存在", self.already_exists),
+            ("过大", self.too_large),
+            ("被过滤", self.filtered),
+            ("失败", self.failed),
+        ]
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .collect()
+    }
+}
+
+/// 一次批量转换的统计数字
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SummaryValues {
+    /// 转换成功的文件数
+    pub converted: u32,
+    /// 没有转换的文件数，包括转换失败的文件
+    pub skipped: u32,
+    /// 转换失败的文件数
+    pub failed: u32,
+    /// 遍历到的图像数 (转换成功、跳过和被过滤条件排除的图像，不含非图像文件)
+    pub total: u32,
+    /// 转换成功的输入文件总字节数
+    pub bytes_in: u64,
+    /// 写出的输出文件总字节数
+    pub bytes_out: u64,
+    /// 没有转换的文件按原因的分类；非图像文件和续传时已完成的文件只计入分类，不计入上面的数字
+    pub skip_reasons: SkipBreakdown,
+}
+
+impl SummaryValues {
+    /// 输出相对输入节省的百分比，输出更大时为负数，没有输入时为0
+    pub fn saved_percent(&self) -> f64 {
+        if self.bytes_in == 0 {
+            return 0.0;
+        }
+        (1.0 - self.bytes_out as f64 / self.bytes_in as f64) * 100.0
+    }
+}
+
+/// 一个文件处理完后的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressStatus {
+    Converted,
+    /// 预演时将要转换
+    Planned,
+    Skipped,
+    Failed,
+}
+
+/// `--progress-json` 在批量转换中每处理完一个文件输出的一行
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProgressEvent {
+    /// 第几个文件，从1开始
+    pub index: usize,
+    /// 要处理的图像总数，流式遍历时事先不知道，为 `null`
+    pub total: Option<usize>,
+    /// 输入文件
+    pub path: String,
+    pub status: ProgressStatus,
+    /// 这个文件写出的字节数，没有写出时为0
+    pub bytes_out: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+/// 解析后的摘要模板，占位符在解析时检查
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummaryTemplate {
+    parts: Vec<Part>,
+}
+
+impl SummaryTemplate {
+    /// 用统计数字替换占位符
+    pub fn render(&self, values: &SummaryValues) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Placeholder(name) => rendered.push_str(&match *name {
+                    "converted" => values.converted.to_string(),
+                    "skipped" => values.skipped.to_string(),
+                    "failed" => values.failed.to_string(),
+                    "total" => values.total.to_string(),
+                    "bytes_in" => values.bytes_in.to_string(),
+                    "bytes_out" => values.bytes_out.to_string(),
+                    "saved_percent" => format!("{:.1}", values.saved_percent()),
+                    _ => unreachable!("占位符已在解析时检查"),
+                }),
+            }
+        }
+        rendered
+    }
+}
+
+impl FromStr for SummaryTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("占位符 {{{} 没有闭合", name)),
+                        }
+                    }
+                    let Some(&placeholder) = PLACEHOLDERS.iter().find(|&&known| known == name) else {
+                        return Err(format!("未知的占位符 {{{}}}，可用: {}", name, PLACEHOLDERS.join(", ")));
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                }
+                '}' => return Err("模板中有多余的 }，字面的花括号写成 {{ 或 }}".to_string()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_custom_template() {
+        let values = SummaryValues {
+            converted: 8,
+            skipped: 2,
+            failed: 1,
+            total: 12,
+            bytes_in: 4000,
+            bytes_out: 1000,
+            ..Default::default()
+        };
+        let template: SummaryTemplate =
+            "pixforge ok={converted} skip={skipped} fail={failed} of {total}, {bytes_in}->{bytes_out} ({saved_percent}%) {{done}}"
+                .parse()
+                .unwrap();
+        assert_eq!(template.render(&values), "pixforge ok=8 skip=2 fail=1 of 12, 4000->1000 (75.0%) {done}");
+        assert_eq!(SummaryValues::default().saved_percent(), 0.0);
+    }
+
+    #[test]
+    fn test_invalid_placeholders_are_rejected() {
+        let error = "{converted} {elapsed}".parse::<SummaryTemplate>().unwrap_err();
+        assert!(error.contains("{elapsed}"), "{}", error);
+        assert!("{converted".parse::<SummaryTemplate>().is_err());
+        assert!("done}".parse::<SummaryTemplate>().is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! 大尺寸TIFF的流式缩小
+//!
+//! 逐个条带/图块解码，直接累加到目标尺寸的缓冲区中并求区域平均，
+//! 峰值内存只与输出尺寸和单个条带的大小有关，不需要载入整幅图像
+
+use anyhow::{Context, Result};
+use image::{DynamicImage, ImageBuffer};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use crate::transform::{FitMode, Resize};
+
+/// 按条带流式解码TIFF并缩小到目标尺寸
+///
+/// 只处理缩小；缩放方式为 `cover` 或 `contain`、目标不小于源图像、颜色类型不是8/16位灰度或RGB(A)、
+/// 或者采样按平面存储时返回 `Ok(None)`，由调用方回退到整幅解码
+pub fn decode_downscaled(path: &Path, resize: &Resize) -> Result<Option<DynamicImage>> {
+    if matches!(resize.mode, FitMode::Cover | FitMode::Contain(_)) {
+        return Ok(None);
+    }
+    let file = File::open(path).with_context(|| format!("无法打开图像: {}", path.display()))?;
+    let mut decoder = Decoder::new(BufReader::new(file))
+        .with_context(|| format!("无法解析TIFF: {}", path.display()))?;
+
+    let (width, height) = decoder.dimensions()?;
+    let (out_width, out_height) = resize.output_dimensions(width, height);
+    if out_width >= width || out_height >= height {
+        return Ok(None);
+    }
+
+    let (channels, bits) = match decoder.colortype()? {
+        ColorType::Gray(bits) => (1, bits),
+        ColorType::GrayA(bits) => (2, bits),
+        ColorType::RGB(bits) => (3, bits),
+        ColorType::RGBA(bits) => (4, bits),
+        _ => return Ok(None),
+    };
+    if bits != 8 && bits != 16 {
+        return Ok(None);
+    }
+    // 平面存储时每个条带只含一个通道
+    if decoder.find_tag_unsigned::<u16>(Tag::PlanarConfiguration)?.unwrap_or(1) != 1 {
+        return Ok(None);
+    }
+
+    let mut accumulator = Accumulator::new((width, height), (out_width, out_height), channels);
+    let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+    let chunks_across = width.div_ceil(chunk_width);
+    let chunk_count = chunks_across * height.div_ceil(chunk_height);
+
+    for index in 0..chunk_count {
+        let origin = ((index % chunks_across) * chunk_width, (index / chunks_across) * chunk_height);
+        let size = decoder.chunk_data_dimensions(index);
+        match decoder
+            .read_chunk(index)
+            .with_context(|| format!("TIFF解码失败: {}", path.display()))?
+        {
+            DecodingResult::U8(samples) => accumulator.add(&samples, origin, size),
+            DecodingResult::U16(samples) => accumulator.add(&samples, origin, size),
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(Some(accumulator.finish(bits)))
+}
+
+/// 目标尺寸的累加缓冲区，每个输出像素记录其覆盖区域内的采样和与像素数
+struct Accumulator {
+    source_height: u64,
+    out_width: u32,
+    out_height: u32,
+    channels: usize,
+    /// 源图像每一列对应的输出列
+    column_map: Vec<u32>,
+    sums: Vec<u64>,
+    counts: Vec<u32>,
+}
+
+impl Accumulator {
+    fn new(source: (u32, u32), output: (u32, u32), channels: usize) -> Self {
+        let column_map = (0..source.0 as u64)
+            .map(|x| (x * output.0 as u64 / source.0 as u64) as u32)
+            .collect();
+        let pixels = output.0 as usize * output.1 as usize;
+
+        Self {
+            source_height: source.1 as u64,
+            out_width: output.0,
+            out_height: output.1,
+            channels,
+            column_map,
+            sums: vec![0; pixels * channels],
+            counts: vec![0; pixels],
+        }
+    }
+
+    /// 累加一个条带/图块的采样
+    fn add<T: Copy + Into<u64>>(&mut self, samples: &[T], origin: (u32, u32), size: (u32, u32)) {
+        let row_len = size.0 as usize * self.channels;
+
+        for (row, row_samples) in samples.chunks_exact(row_len).take(size.1 as usize).enumerate() {
+            let y = (origin.1 as usize + row) as u64;
+            let out_y = (y * self.out_height as u64 / self.source_height) as usize;
+
+            for (column, pixel) in row_samples.chunks_exact(self.channels).enumerate() {
+                let out_x = self.column_map[origin.0 as usize + column] as usize;
+                let out_index = out_y * self.out_width as usize + out_x;
+
+                self.counts[out_index] += 1;
+                let sums = &mut self.sums[out_index * self.channels..][..self.channels];
+                for (sum, sample) in sums.iter_mut().zip(pixel) {
+                    *sum += (*sample).into();
+                }
+            }
+        }
+    }
+
+    /// 求区域平均，得到与源图像通道和位深一致的图像
+    fn finish(self, bits: u8) -> DynamicImage {
+        let channels = self.channels;
+        let averages = self.sums.iter().enumerate().map(|(index, &sum)| {
+            let count = self.counts[index / channels].max(1) as u64;
+            (sum + count / 2) / count
+        });
+        let (width, height) = (self.out_width, self.out_height);
+
+        if bits == 8 {
+            let raw: Vec<u8> = averages.map(|value| value as u8).collect();
+            match channels {
+                1 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, raw).unwrap()),
+                2 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, raw).unwrap()),
+                3 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, raw).unwrap()),
+                _ => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, raw).unwrap()),
+            }
+        } else {
+            let raw: Vec<u16> = averages.map(|value| value as u16).collect();
+            match channels {
+                1 => DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, raw).unwrap()),
+                2 => DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, raw).unwrap()),
+                3 => DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, raw).unwrap()),
+                _ => DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, raw).unwrap()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// 四个象限颜色不同的图像，左上角附近有渐变
+    fn quadrants(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| match (x < width / 2, y < height / 2) {
+            (true, true) => Rgb([(x % 256) as u8, (y % 256) as u8, 0]),
+            (false, true) => Rgb([255, 0, 0]),
+            (true, false) => Rgb([0, 255, 0]),
+            (false, false) => Rgb([0, 0, 255]),
+        })
+    }
+
+    #[test]
+    fn test_streamed_downscale_matches_full_decode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.tiff");
+        quadrants(2000, 1600).save(&path).unwrap();
+
+        // 峰值内存的测量需要替换全局分配器，在 tests/tiff_stream_memory.rs 中单独运行
+        let resize = Resize { width: 200, height: 200, mode: FitMode::Fit };
+        let streamed = decode_downscaled(&path, &resize).unwrap().unwrap().to_rgb8();
+        assert_eq!(streamed.dimensions(), (200, 160));
+
+        let expected = image::open(&path).unwrap().resize_exact(200, 160, image::imageops::FilterType::Triangle);
+        let expected = expected.to_rgb8();
+        let diff: u64 = expected
+            .as_raw()
+            .iter()
+            .zip(streamed.as_raw())
+            .map(|(a, b)| (*a as i32 - *b as i32).unsigned_abs() as u64)
+            .sum();
+        assert!((diff as f64 / expected.as_raw().len() as f64) < 2.0);
+        assert_eq!(streamed.get_pixel(150, 40), &Rgb([255, 0, 0]));
+        assert_eq!(streamed.get_pixel(150, 120), &Rgb([0, 0, 255]));
+    }
+
+    #[test]
+    fn test_upscale_is_not_streamed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("small.tiff");
+        quadrants(40, 20).save(&path).unwrap();
+
+        let resize = Resize { width: 80, height: 40, mode: FitMode::Fill };
+        assert!(decode_downscaled(&path, &resize).unwrap().is_none());
+    }
+}
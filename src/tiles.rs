@@ -0,0 +1,132 @@
+//! 把一张大图切成固定尺寸的瓦片，用于地图等按坐标加载的场景
+//!
+//! 瓦片的网格坐标从左上角开始，x向右、y向下递增；
+//! 最右一列和最下一行不足瓦片尺寸时保留实际尺寸，不补边
+
+use anyhow::Result;
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+
+use crate::converter::ImageConverter;
+use crate::transform::CropRect;
+use crate::utils;
+
+/// 瓦片文件的命名方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TileNaming {
+    /// `<名称>_<x>_<y>.<扩展名>`，全部放在输出目录下
+    Grid(String),
+    /// 地图瓦片常用的 `<z>/<x>/<y>.<扩展名>` 目录结构，值为缩放级别z
+    Zxy(u32),
+}
+
+impl TileNaming {
+    /// 坐标为 (x, y) 的瓦片的输出路径
+    pub fn path(&self, output_dir: &Path, x: u32, y: u32, extension: &str) -> PathBuf {
+        match self {
+            TileNaming::Grid(name) => output_dir.join(format!("{}_{}_{}.{}", name, x, y, extension)),
+            TileNaming::Zxy(zoom) => {
+                output_dir.join(zoom.to_string()).join(x.to_string()).join(format!("{}.{}", y, extension))
+            }
+        }
+    }
+}
+
+/// 一个瓦片的网格坐标和它在源图像中的区域
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub rect: CropRect,
+}
+
+/// 按行列出覆盖整张图像所需的瓦片
+pub fn tile_grid(width: u32, height: u32, tile_size: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    if tile_size == 0 {
+        return tiles;
+    }
+    for y in 0..height.div_ceil(tile_size) {
+        for x in 0..width.div_ceil(tile_size) {
+            let (left, top) = (x * tile_size, y * tile_size);
+            let rect = CropRect {
+                x: left,
+                y: top,
+                width: tile_size.min(width - left),
+                height: tile_size.min(height - top),
+            };
+            tiles.push(Tile { x, y, rect });
+        }
+    }
+    tiles
+}
+
+/// 把图像切成 `tile_size` 的瓦片，用 `converter` 按目标格式逐个编码写入 `output_dir`，返回写出的文件
+pub fn split(
+    converter: &ImageConverter,
+    img: &DynamicImage,
+    output_dir: &Path,
+    naming: &TileNaming,
+    target_format: &str,
+    quality: f32,
+    tile_size: u32,
+) -> Result<Vec<PathBuf>> {
+    if tile_size == 0 {
+        anyhow::bail!("瓦片尺寸必须大于0");
+    }
+    let extension = utils::default_extension(target_format);
+    let mut written = Vec::new();
+    for tile in tile_grid(img.width(), img.height(), tile_size) {
+        let path = naming.path(output_dir, tile.x, tile.y, &extension);
+        converter.encode_image(&tile.rect.apply(img), &path, target_format, quality)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    /// 四个象限颜色不同的图像
+    fn quadrants(size: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(size, size, |x, y| {
+            let (right, bottom) = (x >= size / 2, y >= size / 2);
+            Rgba([if right { 255 } else { 0 }, if bottom { 255 } else { 0 }, 0, 255])
+        }))
+    }
+
+    #[test]
+    fn test_split_into_four_tiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let naming = TileNaming::Grid("map".to_string());
+        let written = split(&ImageConverter::new(), &quadrants(512), dir.path(), &naming, "png", 90.0, 256).unwrap();
+        assert_eq!(written.len(), 4);
+
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let path = dir.path().join(format!("map_{}_{}.png", x, y));
+            assert!(written.contains(&path), "{}", path.display());
+            let tile = image::open(&path).unwrap();
+            assert_eq!(tile.dimensions(), (256, 256));
+            let expected = Rgba([if x == 1 { 255 } else { 0 }, if y == 1 { 255 } else { 0 }, 0, 255]);
+            assert_eq!(tile.get_pixel(128, 128), expected, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_edge_tiles_and_zxy_naming() {
+        let tiles = tile_grid(600, 300, 256);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2], Tile { x: 2, y: 0, rect: CropRect { x: 512, y: 0, width: 88, height: 256 } });
+        assert_eq!(tiles[5].rect, CropRect { x: 512, y: 256, width: 88, height: 44 });
+
+        let dir = tempfile::tempdir().unwrap();
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(600, 300));
+        split(&ImageConverter::new(), &img, dir.path(), &TileNaming::Zxy(3), "png", 90.0, 256).unwrap();
+        let corner = image::open(dir.path().join("3").join("2").join("1.png")).unwrap();
+        assert_eq!(corner.dimensions(), (88, 44));
+
+        assert!(split(&ImageConverter::new(), &img, dir.path(), &TileNaming::Zxy(3), "png", 90.0, 0).is_err());
+    }
+}
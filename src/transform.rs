@@ -0,0 +1,868 @@
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::{inspect, utils};
+
+/// 缩放时源图像映射到目标尺寸的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitMode {
+    /// 拉伸到精确的目标尺寸
+    #[default]
+    Fill,
+    /// 保持宽高比，缩放到目标尺寸以内
+    Fit,
+    /// 保持宽高比缩放到完全覆盖目标尺寸，居中裁掉超出的部分
+    Cover,
+    /// 保持宽高比缩放到目标尺寸以内，居中放到目标尺寸的画布上，两侧空白填充该颜色
+    Contain(image::Rgba<u8>),
+}
+
+/// `contain` 未指定背景时的填充颜色
+pub const DEFAULT_CONTAIN_BACKGROUND: image::Rgba<u8> = image::Rgba([0, 0, 0, 255]);
+
+impl FromStr for FitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fill" => Ok(FitMode::Fill),
+            "fit" => Ok(FitMode::Fit),
+            "cover" => Ok(FitMode::Cover),
+            "contain" => Ok(FitMode::Contain(DEFAULT_CONTAIN_BACKGROUND)),
+            _ => Err(format!("无效的缩放方式: {} (可选 fill, fit, cover, contain)", s)),
+        }
+    }
+}
+
+/// 缩放目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resize {
+    pub width: u32,
+    pub height: u32,
+    pub mode: FitMode,
+}
+
+impl FromStr for Resize {
+    type Err = String;
+
+    /// 解析 `WxH`（保持宽高比）或 `WxH!`（强制精确尺寸）
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (dimensions, mode) = match s.trim().strip_suffix('!') {
+            Some(dimensions) => (dimensions, FitMode::Fill),
+            None => (s, FitMode::Fit),
+        };
+
+        utils::parse_dimensions(dimensions)
+            .map(|(width, height)| Resize { width, height, mode })
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Resize {
+    /// 以参考图像的尺寸作为缩放目标
+    ///
+    /// 只读取参考图像的文件头，参考文件必须存在且是图像
+    pub fn from_reference(reference: &Path, mode: FitMode) -> Result<Self> {
+        if !reference.exists() {
+            anyhow::bail!("参考图像不存在: {}", reference.display());
+        }
+        if !utils::is_image_file(reference) {
+            anyhow::bail!("参考文件不是图像: {}", reference.display());
+        }
+
+        let (width, height) = inspect::read_dimensions(reference)?;
+        Ok(Self { width, height, mode })
+    }
+
+    /// 源图像在两个方向上都不超过目标尺寸时，缩放会放大图像
+    pub fn would_upscale(&self, width: u32, height: u32) -> bool {
+        width <= self.width && height <= self.height && (width, height) != (self.width, self.height)
+    }
+
+    /// 缩放后的输出尺寸，与 `apply` 的结果一致
+    pub fn output_dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.mode {
+            FitMode::Fill | FitMode::Cover | FitMode::Contain(_) => (self.width, self.height),
+            FitMode::Fit => {
+                let ratio = f64::min(
+                    self.width as f64 / width as f64,
+                    self.height as f64 / height as f64,
+                );
+                let scale = |value: u32| ((value as f64 * ratio).round() as u32).max(1);
+                (scale(width), scale(height))
+            }
+        }
+    }
+
+    /// 对图像执行缩放
+    pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+        if img.width() == self.width && img.height() == self.height {
+            return img;
+        }
+
+        match self.mode {
+            FitMode::Fill => img.resize_exact(self.width, self.height, FilterType::Lanczos3),
+            FitMode::Fit => img.resize(self.width, self.height, FilterType::Lanczos3),
+            FitMode::Cover => img.resize_to_fill(self.width, self.height, FilterType::Lanczos3),
+            FitMode::Contain(background) => {
+                let fitted = img.resize(self.width, self.height, FilterType::Lanczos3);
+                letterbox(&fitted, self.width, self.height, background)
+            }
+        }
+    }
+}
+
+/// 把图像居中放到 `width`x`height` 的画布上，空白处填充背景颜色
+///
+/// 图像和背景都不透明时输出RGB，否则输出RGBA
+fn letterbox(img: &DynamicImage, width: u32, height: u32, background: image::Rgba<u8>) -> DynamicImage {
+    if (img.width(), img.height()) == (width, height) {
+        return img.clone();
+    }
+
+    let mut canvas = image::RgbaImage::from_pixel(width, height, background);
+    image::imageops::overlay(
+        &mut canvas,
+        &img.to_rgba8(),
+        (width.saturating_sub(img.width()) / 2) as i64,
+        (height.saturating_sub(img.height()) / 2) as i64,
+    );
+
+    let canvas = DynamicImage::ImageRgba8(canvas);
+    if img.color().has_alpha() || background[3] != 255 {
+        canvas
+    } else {
+        DynamicImage::ImageRgb8(canvas.to_rgb8())
+    }
+}
+
+/// 裁剪区域，坐标以左上角为原点
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropRect {
+    /// 裁剪图像，超出图像边界的部分被截掉；只复制裁剪区域的像素
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let x = self.x.min(img.width().saturating_sub(1));
+        let y = self.y.min(img.height().saturating_sub(1));
+        let width = self.width.min(img.width() - x);
+        let height = self.height.min(img.height() - y);
+        img.crop_imm(x, y, width, height)
+    }
+}
+
+/// 旋转与翻转的组合
+///
+/// 表示先按需水平翻转，再顺时针旋转 `quarter_turns` 个90°，
+/// 八种组合恰好覆盖EXIF的全部方向值，并且可以互相合成
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Reorient {
+    pub flip: bool,
+    pub quarter_turns: u8,
+}
+
+impl Reorient {
+    /// 不做任何变换
+    pub const IDENTITY: Reorient = Reorient { flip: false, quarter_turns: 0 };
+
+    /// 顺时针旋转指定角度，只接受90的倍数
+    pub fn from_degrees(degrees: u32) -> Result<Self> {
+        if !degrees.is_multiple_of(90) {
+            anyhow::bail!("旋转角度必须是90的倍数: {}", degrees);
+        }
+        Ok(Self { flip: false, quarter_turns: ((degrees / 90) % 4) as u8 })
+    }
+
+    /// 从image的方向值转换
+    pub fn from_orientation(orientation: Orientation) -> Self {
+        let (flip, quarter_turns) = match orientation {
+            Orientation::NoTransforms => (false, 0),
+            Orientation::Rotate90 => (false, 1),
+            Orientation::Rotate180 => (false, 2),
+            Orientation::Rotate270 => (false, 3),
+            Orientation::FlipHorizontal => (true, 0),
+            Orientation::FlipVertical => (true, 2),
+            // 先旋转再翻转，等价于先翻转再反向旋转
+            Orientation::Rotate90FlipH => (true, 3),
+            Orientation::Rotate270FlipH => (true, 1),
+        };
+        Self { flip, quarter_turns }
+    }
+
+    /// 转换为image的方向值
+    pub fn to_orientation(self) -> Orientation {
+        match (self.flip, self.quarter_turns % 4) {
+            (false, 0) => Orientation::NoTransforms,
+            (false, 1) => Orientation::Rotate90,
+            (false, 2) => Orientation::Rotate180,
+            (false, _) => Orientation::Rotate270,
+            (true, 0) => Orientation::FlipHorizontal,
+            (true, 1) => Orientation::Rotate270FlipH,
+            (true, 2) => Orientation::FlipVertical,
+            (true, _) => Orientation::Rotate90FlipH,
+        }
+    }
+
+    /// 先执行当前变换，再执行 `next`
+    pub fn then(self, next: Reorient) -> Reorient {
+        if next.flip {
+            // 翻转与旋转交换位置时旋转方向取反
+            Reorient {
+                flip: !self.flip,
+                quarter_turns: (next.quarter_turns + 4 - self.quarter_turns % 4) % 4,
+            }
+        } else {
+            Reorient {
+                flip: self.flip,
+                quarter_turns: (self.quarter_turns + next.quarter_turns) % 4,
+            }
+        }
+    }
+
+    /// 是否交换宽高
+    pub fn swaps_axes(self) -> bool {
+        !self.quarter_turns.is_multiple_of(2)
+    }
+
+    /// 是否为恒等变换
+    pub fn is_identity(self) -> bool {
+        !self.flip && self.quarter_turns.is_multiple_of(4)
+    }
+
+    /// 对图像执行变换
+    pub fn apply(self, mut img: DynamicImage) -> DynamicImage {
+        if !self.is_identity() {
+            img.apply_orientation(self.to_orientation());
+        }
+        img
+    }
+}
+
+/// `--extract` 可提取的通道
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Alpha,
+    Red,
+    Green,
+    Blue,
+    /// 亮度 (按image的灰度转换公式计算)
+    Luma,
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "alpha" => Ok(Channel::Alpha),
+            "red" => Ok(Channel::Red),
+            "green" => Ok(Channel::Green),
+            "blue" => Ok(Channel::Blue),
+            "luma" => Ok(Channel::Luma),
+            _ => Err(format!("无效的通道: {} (可选 alpha, red, green, blue, luma)", s)),
+        }
+    }
+}
+
+/// 源图像没有透明通道时 `--extract alpha` 的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingAlpha {
+    /// 报错
+    #[default]
+    Error,
+    /// 视为完全不透明，输出全白蒙版
+    White,
+}
+
+impl FromStr for MissingAlpha {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(MissingAlpha::Error),
+            "white" => Ok(MissingAlpha::White),
+            _ => Err(format!("无效的处理方式: {} (可选 error, white)", s)),
+        }
+    }
+}
+
+/// `--normalize-orientation` 要求的画面方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrientationTarget {
+    /// 横向 (宽大于高)
+    Landscape,
+    /// 纵向 (高大于宽)
+    Portrait,
+}
+
+impl OrientationTarget {
+    /// 宽高与目标方向不一致时顺时针旋转90°，正方形图像不旋转
+    pub fn correction(self, width: u32, height: u32) -> Reorient {
+        let mismatched = match self {
+            OrientationTarget::Landscape => height > width,
+            OrientationTarget::Portrait => width > height,
+        };
+        if mismatched {
+            Reorient { flip: false, quarter_turns: 1 }
+        } else {
+            Reorient::IDENTITY
+        }
+    }
+}
+
+impl FromStr for OrientationTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "landscape" => Ok(OrientationTarget::Landscape),
+            "portrait" => Ok(OrientationTarget::Portrait),
+            _ => Err(format!("无效的方向: {} (可选 landscape, portrait)", s)),
+        }
+    }
+}
+
+/// 将指定通道提取为8位灰度图像
+pub fn extract_channel(img: &DynamicImage, channel: Channel, missing_alpha: MissingAlpha) -> Result<DynamicImage> {
+    let index = match channel {
+        Channel::Luma => return Ok(DynamicImage::ImageLuma8(img.to_luma8())),
+        Channel::Alpha if !img.color().has_alpha() => match missing_alpha {
+            MissingAlpha::Error => anyhow::bail!("源图像没有透明通道，无法提取alpha"),
+            MissingAlpha::White => {
+                return Ok(DynamicImage::ImageLuma8(image::GrayImage::from_pixel(
+                    img.width(),
+                    img.height(),
+                    image::Luma([u8::MAX]),
+                )))
+            }
+        },
+        Channel::Red => 0,
+        Channel::Green => 1,
+        Channel::Blue => 2,
+        Channel::Alpha => 3,
+    };
+
+    let rgba = img.to_rgba8();
+    Ok(DynamicImage::ImageLuma8(image::GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        image::Luma([rgba.get_pixel(x, y)[index]])
+    })))
+}
+
+/// Reinhard全局色调映射，将高位深图像压缩到8位
+///
+/// 把像素值视为线性亮度，先按对数平均亮度曝光到中灰 (key 0.18)，
+/// 再以最亮像素为白点压缩：最亮处恰好映射为白色，高光按比例压缩而不是被截断
+pub fn tone_map(img: &DynamicImage) -> DynamicImage {
+    const KEY: f32 = 0.18;
+    const DELTA: f32 = 1e-4;
+
+    let mut pixels = img.to_rgba32f();
+    let luminance = |p: &image::Rgba<f32>| (0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2]).max(0.0);
+
+    let count = (pixels.width() as f64 * pixels.height() as f64).max(1.0);
+    let log_sum: f64 = pixels.pixels().map(|p| ((DELTA + luminance(p)) as f64).ln()).sum();
+    let exposure = KEY / (log_sum / count).exp() as f32;
+    let white = pixels.pixels().map(|p| luminance(p) * exposure).fold(0.0f32, f32::max);
+
+    if white > 0.0 {
+        for pixel in pixels.pixels_mut() {
+            let l = luminance(pixel) * exposure;
+            if l <= 0.0 {
+                continue;
+            }
+            let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+            let scale = mapped / l * exposure;
+            for channel in &mut pixel.0[..3] {
+                *channel *= scale;
+            }
+        }
+    }
+
+    let output = image::RgbaImage::from_fn(pixels.width(), pixels.height(), |x, y| {
+        let pixel = pixels.get_pixel(x, y);
+        image::Rgba(pixel.0.map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8))
+    });
+    if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(output)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(output).to_rgb8())
+    }
+}
+
+/// 以较长边为边长创建纯色正方形画布，将图像居中放置，不裁剪任何像素
+///
+/// 源图像带透明通道或背景色不是完全不透明时输出RGBA，否则输出RGB
+pub fn pad_to_square(img: DynamicImage, background: image::Rgba<u8>) -> DynamicImage {
+    let (width, height) = (img.width(), img.height());
+    if width == height {
+        return img;
+    }
+
+    let side = width.max(height);
+    letterbox(&img, side, side, background)
+}
+
+/// 棋盘格背景每个方格的边长 (像素)
+pub const CHECKER_CELL: u32 = 8;
+
+/// 棋盘格背景交替的两种颜色，与常见图像编辑器显示透明区域的方式一致
+const CHECKER_COLORS: [image::Rgba<u8>; 2] = [image::Rgba([255, 255, 255, 255]), image::Rgba([204, 204, 204, 255])];
+
+/// 合成透明图像时垫在下面的背景
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backdrop {
+    /// 纯色
+    Color(image::Rgba<u8>),
+    /// 白色和浅灰交替的棋盘格，左上角为白色
+    Checker,
+    /// 背景图像，按布局铺满与源图像相同的尺寸
+    Image(PathBuf, BackdropLayout),
+}
+
+impl FromStr for Backdrop {
+    type Err = String;
+
+    /// 解析 `checker` 或颜色，背景图像由单独的参数指定
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("checker") {
+            return Ok(Backdrop::Checker);
+        }
+        utils::parse_color(s).map(Backdrop::Color).map_err(|e| format!("{} 或 checker", e))
+    }
+}
+
+/// 背景图像铺满画布的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackdropLayout {
+    /// 从左上角开始按原尺寸重复
+    #[default]
+    Tile,
+    /// 保持宽高比缩放到覆盖画布，居中裁掉超出的部分
+    Scale,
+}
+
+impl FromStr for BackdropLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tile" => Ok(BackdropLayout::Tile),
+            "scale" => Ok(BackdropLayout::Scale),
+            _ => Err(format!("无效的背景布局: {} (可选 tile, scale)", s)),
+        }
+    }
+}
+
+impl BackdropLayout {
+    /// 用背景图像铺满 `width`x`height` 的画布
+    pub fn fill(self, pattern: &DynamicImage, width: u32, height: u32) -> image::RgbaImage {
+        match self {
+            BackdropLayout::Tile => {
+                let pattern = pattern.to_rgba8();
+                image::RgbaImage::from_fn(width, height, |x, y| {
+                    *pattern.get_pixel(x % pattern.width(), y % pattern.height())
+                })
+            }
+            BackdropLayout::Scale => pattern.resize_to_fill(width, height, FilterType::Lanczos3).to_rgba8(),
+        }
+    }
+}
+
+/// 生成棋盘格画布
+pub fn checkerboard(width: u32, height: u32) -> image::RgbaImage {
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        CHECKER_COLORS[((x / CHECKER_CELL + y / CHECKER_CELL) % 2) as usize]
+    })
+}
+
+/// 把图像合成到同尺寸的背景画布上
+///
+/// 背景完全不透明时输出RGB，否则输出RGBA
+pub fn flatten(img: &DynamicImage, mut canvas: image::RgbaImage) -> DynamicImage {
+    let opaque = canvas.pixels().all(|pixel| pixel[3] == 255);
+    image::imageops::overlay(&mut canvas, &img.to_rgba8(), 0, 0);
+    if opaque {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8())
+    } else {
+        DynamicImage::ImageRgba8(canvas)
+    }
+}
+
+/// 原地交换红蓝通道 (RGB <-> BGR)
+///
+/// 灰度图像没有颜色通道，保持不变
+pub fn swap_red_blue(img: &mut DynamicImage) {
+    match img {
+        DynamicImage::ImageRgb8(buffer) => buffer.pixels_mut().for_each(|p| p.0.swap(0, 2)),
+        DynamicImage::ImageRgba8(buffer) => buffer.pixels_mut().for_each(|p| p.0.swap(0, 2)),
+        DynamicImage::ImageRgb16(buffer) => buffer.pixels_mut().for_each(|p| p.0.swap(0, 2)),
+        DynamicImage::ImageRgba16(buffer) => buffer.pixels_mut().for_each(|p| p.0.swap(0, 2)),
+        DynamicImage::ImageRgb32F(buffer) => buffer.pixels_mut().for_each(|p| p.0.swap(0, 2)),
+        DynamicImage::ImageRgba32F(buffer) => buffer.pixels_mut().for_each(|p| p.0.swap(0, 2)),
+        _ => {}
+    }
+}
+
+/// 原地二值化透明度：alpha低于 `threshold` 的像素变为完全透明，其余变为完全不透明
+///
+/// `threshold` 按8位刻度给出，高位深图像按比例换算；没有透明通道的图像保持不变
+pub fn threshold_alpha(img: &mut DynamicImage, threshold: u8) {
+    let threshold16 = u16::from(threshold) * 257;
+    let threshold32 = f32::from(threshold) / 255.0;
+    match img {
+        DynamicImage::ImageLumaA8(buffer) => buffer.pixels_mut().for_each(|p| p[1] = if p[1] < threshold { 0 } else { u8::MAX }),
+        DynamicImage::ImageRgba8(buffer) => buffer.pixels_mut().for_each(|p| p[3] = if p[3] < threshold { 0 } else { u8::MAX }),
+        DynamicImage::ImageLumaA16(buffer) => buffer.pixels_mut().for_each(|p| p[1] = if p[1] < threshold16 { 0 } else { u16::MAX }),
+        DynamicImage::ImageRgba16(buffer) => buffer.pixels_mut().for_each(|p| p[3] = if p[3] < threshold16 { 0 } else { u16::MAX }),
+        DynamicImage::ImageRgba32F(buffer) => buffer.pixels_mut().for_each(|p| p[3] = if p[3] < threshold32 { 0.0 } else { 1.0 }),
+        _ => {}
+    }
+}
+
+/// 居中裁剪到指定宽高比
+///
+/// 选取符合比例的最大居中区域，比例已经一致时原样返回
+pub fn crop_to_aspect(img: DynamicImage, aspect: (u32, u32)) -> DynamicImage {
+    let (width, height) = (img.width() as u64, img.height() as u64);
+    let (ratio_w, ratio_h) = (aspect.0 as u64, aspect.1 as u64);
+
+    let (crop_w, crop_h) = if width * ratio_h > height * ratio_w {
+        // 过宽，裁掉左右两侧
+        ((height * ratio_w / ratio_h).max(1), height)
+    } else {
+        // 过高，裁掉上下两侧
+        (width, (width * ratio_h / ratio_w).max(1))
+    };
+
+    if crop_w == width && crop_h == height {
+        return img;
+    }
+
+    let x = (width - crop_w) / 2;
+    let y = (height - crop_h) / 2;
+    img.crop_imm(x as u32, y as u32, crop_w as u32, crop_h as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_fit_mode_from_str() {
+        assert_eq!("FILL".parse::<FitMode>(), Ok(FitMode::Fill));
+        assert_eq!("fit".parse::<FitMode>(), Ok(FitMode::Fit));
+        assert_eq!("Cover".parse::<FitMode>(), Ok(FitMode::Cover));
+        assert_eq!("contain".parse::<FitMode>(), Ok(FitMode::Contain(DEFAULT_CONTAIN_BACKGROUND)));
+        assert!("stretch".parse::<FitMode>().is_err());
+    }
+
+    /// 左右两半分别为红色和蓝色、中间一条绿色竖线的横向图像
+    fn banner() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(300, 100, |x, _| match x {
+            145..=154 => Rgb([0, 255, 0]),
+            0..=144 => Rgb([255, 0, 0]),
+            _ => Rgb([0, 0, 255]),
+        }))
+    }
+
+    #[test]
+    fn test_fit_fill_stretches_to_box() {
+        let resize = Resize { width: 100, height: 100, mode: FitMode::Fill };
+        assert_eq!(resize.output_dimensions(300, 100), (100, 100));
+        let filled = resize.apply(banner()).to_rgb8();
+        assert_eq!(filled.dimensions(), (100, 100));
+        // 拉伸后左右两半仍然各占一半
+        assert_eq!(filled.get_pixel(10, 50).0, [255, 0, 0]);
+        assert_eq!(filled.get_pixel(90, 50).0, [0, 0, 255]);
+    }
+
+    #[test]
+    fn test_fit_cover_crops_and_keeps_center() {
+        let resize = Resize { width: 100, height: 100, mode: FitMode::Cover };
+        assert_eq!(resize.output_dimensions(300, 100), (100, 100));
+        let covered = resize.apply(banner()).to_rgb8();
+        assert_eq!(covered.dimensions(), (100, 100));
+        // 两侧被裁掉，中心的绿线保留在中间并且没有被压缩
+        let center = covered.get_pixel(50, 50).0;
+        assert!(center[1] > 200 && center[0] < 50 && center[2] < 50, "{:?}", center);
+        let green = (0..100).filter(|&x| covered.get_pixel(x, 50)[1] > 128).count();
+        assert!((8..=12).contains(&green), "{}", green);
+        assert_eq!(covered.get_pixel(5, 50).0, [255, 0, 0]);
+        assert_eq!(covered.get_pixel(95, 50).0, [0, 0, 255]);
+    }
+
+    #[test]
+    fn test_fit_contain_letterboxes_with_background() {
+        let background = image::Rgba([255, 255, 255, 255]);
+        let resize = Resize { width: 100, height: 100, mode: FitMode::Contain(background) };
+        assert_eq!(resize.output_dimensions(300, 100), (100, 100));
+        let contained = resize.apply(banner());
+        assert!(matches!(contained, DynamicImage::ImageRgb8(_)));
+        let contained = contained.to_rgb8();
+        assert_eq!(contained.dimensions(), (100, 100));
+        // 缩小到100x33后上下留白
+        assert_eq!(contained.get_pixel(50, 5).0, [255, 255, 255]);
+        assert_eq!(contained.get_pixel(50, 95).0, [255, 255, 255]);
+        assert_eq!(contained.get_pixel(5, 50).0, [255, 0, 0]);
+        assert_eq!(contained.get_pixel(95, 50).0, [0, 0, 255]);
+
+        // 透明背景保留透明通道
+        let transparent = Resize { width: 100, height: 100, mode: FitMode::Contain(image::Rgba([0, 0, 0, 0])) };
+        assert_eq!(transparent.apply(banner()).to_rgba8().get_pixel(50, 5)[3], 0);
+    }
+
+    #[test]
+    fn test_resize_modes() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(200, 100));
+
+        let fill = Resize { width: 50, height: 50, mode: FitMode::Fill }.apply(img.clone());
+        assert_eq!((fill.width(), fill.height()), (50, 50));
+
+        let fit_resize = Resize { width: 50, height: 50, mode: FitMode::Fit };
+        assert_eq!(fit_resize.output_dimensions(200, 100), (50, 25));
+        let fit = fit_resize.apply(img);
+        assert_eq!((fit.width(), fit.height()), (50, 25));
+    }
+
+    #[test]
+    fn test_would_upscale() {
+        let resize = Resize { width: 500, height: 500, mode: FitMode::Fit };
+        assert!(resize.would_upscale(100, 100));
+        assert!(!resize.would_upscale(600, 100));
+        assert!(!resize.would_upscale(500, 500));
+    }
+
+    #[test]
+    fn test_resize_from_str() {
+        assert_eq!(
+            "256x128".parse::<Resize>(),
+            Ok(Resize { width: 256, height: 128, mode: FitMode::Fit })
+        );
+        assert_eq!(
+            "64x64!".parse::<Resize>(),
+            Ok(Resize { width: 64, height: 64, mode: FitMode::Fill })
+        );
+        assert!("64".parse::<Resize>().is_err());
+    }
+
+    #[test]
+    fn test_crop_to_aspect_landscape() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(300, 100, |x, _| {
+            image::Rgb([if (100..200).contains(&x) { 255 } else { 0 }, 0, 0])
+        }));
+
+        let cropped = crop_to_aspect(img, (1, 1)).to_rgb8();
+        assert_eq!(cropped.dimensions(), (100, 100));
+        // 中间区域被保留
+        assert!(cropped.pixels().all(|p| p[0] == 255));
+    }
+
+    #[test]
+    fn test_crop_to_aspect_portrait() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(90, 400));
+
+        let cropped = crop_to_aspect(img, (16, 9));
+        assert_eq!((cropped.width(), cropped.height()), (90, 50));
+    }
+
+    #[test]
+    fn test_reorient_round_trips_orientation() {
+        for exif in 1..=8 {
+            let orientation = Orientation::from_exif(exif).unwrap();
+            assert_eq!(Reorient::from_orientation(orientation).to_orientation(), orientation);
+        }
+    }
+
+    #[test]
+    fn test_reorient_composition_matches_pixels() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(3, 2, |x, y| image::Rgb([x as u8, y as u8, 0])));
+
+        for first in 1..=8 {
+            for second in 1..=8 {
+                let a = Reorient::from_orientation(Orientation::from_exif(first).unwrap());
+                let b = Reorient::from_orientation(Orientation::from_exif(second).unwrap());
+
+                let sequential = b.apply(a.apply(img.clone()));
+                let composed = a.then(b).apply(img.clone());
+                assert_eq!(sequential.to_rgb8(), composed.to_rgb8(), "{} then {}", first, second);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reorient_from_degrees() {
+        let rotate90 = Reorient::from_degrees(90).unwrap();
+        assert_eq!(rotate90.to_orientation(), Orientation::Rotate90);
+        assert!(rotate90.then(Reorient::from_degrees(270).unwrap()).is_identity());
+        assert!(Reorient::from_degrees(45).is_err());
+    }
+
+    #[test]
+    fn test_orientation_correction() {
+        let quarter = Reorient::from_degrees(90).unwrap();
+        assert_eq!(OrientationTarget::Landscape.correction(30, 40), quarter);
+        assert_eq!(OrientationTarget::Landscape.correction(40, 30), Reorient::IDENTITY);
+        assert_eq!(OrientationTarget::Portrait.correction(40, 30), quarter);
+        assert_eq!(OrientationTarget::Portrait.correction(30, 30), Reorient::IDENTITY);
+        assert!(quarter.swaps_axes());
+        assert!(!Reorient::from_degrees(180).unwrap().swaps_axes());
+        assert_eq!("Portrait".parse(), Ok(OrientationTarget::Portrait));
+    }
+
+    #[test]
+    fn test_extract_channels() {
+        let img = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(2, 2, image::Rgba([10, 20, 30, 128])));
+        let pick = |channel| extract_channel(&img, channel, MissingAlpha::Error).unwrap().to_luma8().get_pixel(0, 0)[0];
+        assert_eq!(pick(Channel::Red), 10);
+        assert_eq!(pick(Channel::Green), 20);
+        assert_eq!(pick(Channel::Blue), 30);
+        assert_eq!(pick(Channel::Alpha), 128);
+        assert!("depth".parse::<Channel>().is_err());
+    }
+
+    #[test]
+    fn test_extract_alpha_without_alpha_channel() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(3, 2));
+        assert!(extract_channel(&img, Channel::Alpha, MissingAlpha::Error).is_err());
+
+        let mask = extract_channel(&img, Channel::Alpha, MissingAlpha::White).unwrap().to_luma8();
+        assert_eq!(mask.dimensions(), (3, 2));
+        assert!(mask.pixels().all(|p| p[0] == 255));
+    }
+
+    #[test]
+    fn test_tone_map_keeps_highlight_detail() {
+        // 线性HDR渐变，右侧3/4超出白色
+        let hdr = DynamicImage::ImageRgb32F(image::Rgb32FImage::from_fn(256, 4, |x, _| {
+            let value = x as f32 / 32.0;
+            image::Rgb([value, value, value])
+        }));
+        let highlights = |img: &RgbImage| {
+            let mut values: Vec<u8> = (192..256).map(|x| img.get_pixel(x, 0)[0]).collect();
+            values.dedup();
+            values.len()
+        };
+
+        // 直接缩放时高光全部截断为白色
+        assert_eq!(highlights(&hdr.to_rgb8()), 1);
+
+        let mapped = tone_map(&hdr).to_rgb8();
+        assert!(highlights(&mapped) > 10);
+        assert_eq!(mapped.get_pixel(255, 0)[0], 255);
+        let row: Vec<u8> = (0..256).map(|x| mapped.get_pixel(x, 0)[0]).collect();
+        assert!(row.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn test_pad_to_square_centers_wide_image() {
+        let wide = DynamicImage::ImageRgb8(RgbImage::from_pixel(40, 20, Rgb([200, 10, 10])));
+        let padded = pad_to_square(wide, image::Rgba([0, 0, 255, 255]));
+        assert_eq!(padded.color(), image::ColorType::Rgb8);
+
+        let padded = padded.to_rgb8();
+        assert_eq!(padded.dimensions(), (40, 40));
+        // 上下各10行背景，中间是源图像
+        assert_eq!(padded.get_pixel(20, 9), &Rgb([0, 0, 255]));
+        assert_eq!(padded.get_pixel(20, 10), &Rgb([200, 10, 10]));
+        assert_eq!(padded.get_pixel(20, 29), &Rgb([200, 10, 10]));
+        assert_eq!(padded.get_pixel(20, 30), &Rgb([0, 0, 255]));
+        assert_eq!(padded.get_pixel(0, 20), &Rgb([200, 10, 10]));
+    }
+
+    #[test]
+    fn test_swap_red_blue_keeps_alpha_and_gray() {
+        let mut rgba = DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(2, 2, image::Rgba([1u16, 2, 3, 4])));
+        swap_red_blue(&mut rgba);
+        assert_eq!(rgba.to_rgba16().get_pixel(1, 1).0, [3, 2, 1, 4]);
+
+        let mut gray = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(2, 2, image::Luma([9])));
+        swap_red_blue(&mut gray);
+        assert_eq!(gray.to_luma8().get_pixel(0, 0).0, [9]);
+    }
+
+    #[test]
+    fn test_threshold_alpha_straddles_threshold() {
+        let mut rgba = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(4, 1, |x, _| {
+            image::Rgba([10, 20, 30, [0, 127, 128, 255][x as usize]])
+        }));
+        threshold_alpha(&mut rgba, 128);
+        let alphas: Vec<u8> = rgba.to_rgba8().pixels().map(|p| p[3]).collect();
+        assert_eq!(alphas, vec![0, 0, 255, 255]);
+        assert_eq!(rgba.to_rgba8().get_pixel(1, 0).0[..3], [10, 20, 30]);
+
+        let mut deep = DynamicImage::ImageLumaA16(image::ImageBuffer::from_fn(2, 1, |x, _| {
+            image::LumaA([500u16, [128 * 257 - 1, 128 * 257][x as usize]])
+        }));
+        threshold_alpha(&mut deep, 128);
+        assert_eq!(deep.to_luma_alpha16().pixels().map(|p| p[1]).collect::<Vec<_>>(), vec![0, u16::MAX]);
+
+        let mut opaque = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb([1, 2, 3])));
+        threshold_alpha(&mut opaque, 200);
+        assert_eq!(opaque.to_rgb8().get_pixel(0, 0).0, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_flatten_over_backdrops() {
+        let mut img = image::RgbaImage::from_pixel(20, 10, image::Rgba([0, 0, 0, 0]));
+        img.put_pixel(1, 1, image::Rgba([255, 0, 0, 255]));
+        img.put_pixel(2, 1, image::Rgba([0, 0, 255, 128]));
+        let img = DynamicImage::ImageRgba8(img);
+
+        let flat = flatten(&img, image::RgbaImage::from_pixel(20, 10, image::Rgba([0, 255, 0, 255])));
+        let flat = flat.as_rgb8().unwrap();
+        assert_eq!(flat.get_pixel(0, 0), &image::Rgb([0, 255, 0]));
+        assert_eq!(flat.get_pixel(1, 1), &image::Rgb([255, 0, 0]));
+        let blended = flat.get_pixel(2, 1);
+        assert!(blended[1] > 100 && blended[2] > 100, "{:?}", blended);
+
+        // 平铺时按背景图像的尺寸重复，缩放时覆盖整个画布
+        let pattern = image::RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+        let pattern = DynamicImage::ImageRgba8(pattern);
+        let tiled = BackdropLayout::Tile.fill(&pattern, 7, 5);
+        assert_eq!(tiled.get_pixel(4, 3), &image::Rgba([1, 1, 0, 255]));
+        assert_eq!(BackdropLayout::Scale.fill(&pattern, 7, 5).dimensions(), (7, 5));
+
+        // 背景不是完全不透明时保留透明通道
+        let over_transparent = flatten(&img, image::RgbaImage::new(20, 10));
+        assert!(over_transparent.color().has_alpha());
+
+        assert_eq!("Checker".parse::<Backdrop>(), Ok(Backdrop::Checker));
+        assert_eq!("#ffffff".parse::<Backdrop>(), Ok(Backdrop::Color(image::Rgba([255, 255, 255, 255]))));
+        assert!("plaid".parse::<Backdrop>().is_err());
+    }
+
+    #[test]
+    fn test_tone_map_keeps_alpha() {
+        let img = DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(8, 1, |x, _| {
+            image::Rgba([(x * 8000) as u16, 0, 0, (x * 8000) as u16])
+        }));
+        let mapped = tone_map(&img);
+        assert!(mapped.color().has_alpha());
+        assert_eq!(mapped.to_rgba8().get_pixel(4, 0)[3], img.to_rgba8().get_pixel(4, 0)[3]);
+    }
+
+    #[test]
+    fn test_from_reference() {
+        let dir = tempfile::tempdir().unwrap();
+        let reference = dir.path().join("reference.png");
+        RgbImage::new(64, 48).save(&reference).unwrap();
+
+        let resize = Resize::from_reference(&reference, FitMode::Fill).unwrap();
+        assert_eq!((resize.width, resize.height), (64, 48));
+
+        let text = dir.path().join("notes.png");
+        std::fs::write(&text, "not an image").unwrap();
+        assert!(Resize::from_reference(&text, FitMode::Fill).is_err());
+        assert!(Resize::from_reference(&dir.path().join("missing.png"), FitMode::Fill).is_err());
+    }
+}
@@ -1,11 +1,23 @@
 use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 
 /// 支持的图像文件扩展名列表
 const SUPPORTED_IMAGE_EXTENSIONS: &[&str] = &[
     "png", "jpeg", "jpg", "gif", "webp", "svg", "ico",
-    "bmp", "tiff", "tif", "avif", "heic", "heif"
+    "bmp", "tiff", "tif", "avif", "heic", "heif", "ff", "farbfeld", "tga", "dds",
+    "pnm", "ppm", "pgm", "pbm", "jxl", "pdf"
+];
+
+/// 常见的扩展名别名及其实际格式
+const EXTENSION_ALIASES: &[(&str, &str)] = &[
+    ("jfif", "jpeg"),
+    ("jpe", "jpeg"),
+    ("pjpeg", "jpeg"),
+    ("pjp", "jpeg"),
+    ("dib", "bmp"),
 ];
 
 /// 图像文件魔数签名
@@ -39,6 +51,26 @@ const IMAGE_SIGNATURES: &[ImageSignature] = &[
         signature: &[0x42, 0x4D],
         format: "bmp",
     },
+    ImageSignature {
+        signature: b"farbfeld",
+        format: "farbfeld",
+    },
+    ImageSignature {
+        signature: b"DDS ",
+        format: "dds",
+    },
+    ImageSignature {
+        signature: &[0x00, 0x00, 0x00, 0x0C, 0x4A, 0x58, 0x4C, 0x20, 0x0D, 0x0A, 0x87, 0x0A],
+        format: "jxl", // ISOBMFF容器
+    },
+    ImageSignature {
+        signature: &[0xFF, 0x0A],
+        format: "jxl", // 裸码流
+    },
+    ImageSignature {
+        signature: b"%PDF-",
+        format: "pdf",
+    },
     ImageSignature {
         signature: &[0x49, 0x49, 0x2A, 0x00],
         format: "tiff",
@@ -49,16 +81,24 @@ const IMAGE_SIGNATURES: &[ImageSignature] = &[
     },
 ];
 
+/// TGA 2.0 文件末尾18字节的签名
+pub const TGA_FOOTER_SIGNATURE: &[u8] = b"TRUEVISION-XFILE.\0";
+
 /// 检查文件是否为图像文件
 ///
 /// 首先检查扩展名，然后验证文件内容的魔数签名
 pub fn is_image_file(path: &Path) -> bool {
+    is_image_file_with(path, false)
+}
+
+/// 检查文件是否为图像文件，`any_extension` 为真时跳过扩展名检查，只看魔数签名
+pub fn is_image_file_with(path: &Path, any_extension: bool) -> bool {
     if !path.is_file() {
         return false;
     }
 
     // 快速扩展名检查
-    if !has_potential_image_extension(path) {
+    if !any_extension && !has_potential_image_extension(path) {
         return false;
     }
 
@@ -67,11 +107,13 @@ pub fn is_image_file(path: &Path) -> bool {
 }
 
 /// 检查文件扩展名是否可能是图像格式
-fn has_potential_image_extension(path: &Path) -> bool {
+pub fn has_potential_image_extension(path: &Path) -> bool {
     path.extension()
         .and_then(|ext| ext.to_str())
         .map(|ext_str| {
-            SUPPORTED_IMAGE_EXTENSIONS.contains(&ext_str.to_lowercase().as_str())
+            let lowercased = ext_str.to_lowercase();
+            SUPPORTED_IMAGE_EXTENSIONS.contains(&lowercased.as_str())
+                || EXTENSION_ALIASES.iter().any(|(alias, _)| *alias == lowercased)
         })
         .unwrap_or(true) // 没有扩展名也可能是图片文件
 }
@@ -105,6 +147,10 @@ pub fn detect_image_format_by_content(path: &Path) -> Option<String> {
         }
     }
 
+    if let Some(format) = pnm_format(&buffer) {
+        return Some(format.to_string());
+    }
+
     // 检查SVG (文本格式)
     if let Ok(text) = std::str::from_utf8(&buffer) {
         if is_svg_content(text) {
@@ -112,9 +158,46 @@ pub fn detect_image_format_by_content(path: &Path) -> Option<String> {
         }
     }
 
+    // TGA没有文件头魔数，放在最后检查
+    if is_tga(&mut file, &buffer, path) {
+        return Some("tga".to_string());
+    }
+
     None
 }
 
+/// 按PNM文件头识别具体格式
+///
+/// 魔数 `P1`-`P6` 后必须紧跟空白：P1/P4为pbm，P2/P5为pgm，P3/P6为ppm
+fn pnm_format(header: &[u8]) -> Option<&'static str> {
+    match header {
+        [b'P', kind @ b'1'..=b'6', next, ..] if next.is_ascii_whitespace() => Some(match kind {
+            b'1' | b'4' => "pbm",
+            b'2' | b'5' => "pgm",
+            _ => "ppm",
+        }),
+        _ => None,
+    }
+}
+
+/// 检查TGA文件
+///
+/// TGA 2.0 文件末尾有固定签名；旧版TGA没有任何签名，
+/// 只有扩展名为 `.tga` 且文件头的颜色表类型和图像类型合法时才认为是TGA
+fn is_tga(file: &mut File, header: &[u8], path: &Path) -> bool {
+    let mut footer = [0u8; 18];
+    if file.seek(SeekFrom::End(-(footer.len() as i64))).is_ok()
+        && file.read_exact(&mut footer).is_ok()
+        && footer == TGA_FOOTER_SIGNATURE
+    {
+        return true;
+    }
+
+    let color_map_type = header[1];
+    let image_type = header[2];
+    get_extension(path) == "tga" && color_map_type <= 1 && matches!(image_type, 1 | 2 | 3 | 9 | 10 | 11)
+}
+
 /// 验证WebP文件签名
 fn validate_webp_signature(buffer: &[u8]) -> Option<String> {
     if buffer.len() >= 12 && &buffer[8..12] == b"WEBP" {
@@ -169,16 +252,209 @@ pub fn change_extension(path: &Path, new_extension: &str) -> String {
         .unwrap_or_else(|| format!("output.{}", new_extension))
 }
 
+/// 目标格式默认使用的输出扩展名
+///
+/// JPEG的各种写法统一输出为更常见的 `.jpg`，其它格式沿用格式名
+pub fn default_extension(format: &str) -> String {
+    match canonical_format(format).as_str() {
+        "jpeg" => "jpg".to_string(),
+        _ => format.to_lowercase(),
+    }
+}
+
+/// 格式名的规范形式，用于比较同一格式的不同写法（如 jpg 与 jpeg）
+pub fn canonical_format(format: &str) -> String {
+    let lowercased = format.to_lowercase();
+    if let Some((_, real)) = EXTENSION_ALIASES.iter().find(|(alias, _)| *alias == lowercased) {
+        return real.to_string();
+    }
+    match lowercased.as_str() {
+        "jpg" => "jpeg".to_string(),
+        "ff" => "farbfeld".to_string(),
+        "tif" => "tiff".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// `detect_image_format_by_content` 识别出的格式对应的MIME类型，未知格式为 `application/octet-stream`
+pub fn mime_type(format: &str) -> &'static str {
+    match canonical_format(format).as_str() {
+        "png" => "image/png",
+        "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/vnd.microsoft.icon",
+        "bmp" => "image/bmp",
+        "tiff" => "image/tiff",
+        "svg" => "image/svg+xml",
+        "jxl" => "image/jxl",
+        "dds" => "image/vnd-ms.dds",
+        "tga" => "image/x-tga",
+        "farbfeld" => "image/x-farbfeld",
+        "ppm" => "image/x-portable-pixmap",
+        "pgm" => "image/x-portable-graymap",
+        "pbm" => "image/x-portable-bitmap",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// 文件扩展名是否与按内容识别的格式一致，没有扩展名时不一致
+///
+/// 同一格式的不同写法 (如 `.jpg`、`.jfif`) 都算一致，`.pnm` 可以是ppm、pgm和pbm中的任意一种
+pub fn extension_matches_format(path: &Path, format: &str) -> bool {
+    let extension = get_extension(path);
+    let format = canonical_format(format);
+    canonical_format(&extension) == format || (extension == "pnm" && matches!(format.as_str(), "ppm" | "pgm" | "pbm"))
+}
+
+/// 两个路径是否指向同一个已存在的文件
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// 临时输出文件名的后缀
+pub const TEMP_SUFFIX: &str = ".pixforge-tmp";
+
+/// 进程内已分配的临时文件序号
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 为输出文件生成同目录下的临时文件路径：`.{文件名}.{进程号}.{序号}.pixforge-tmp`
+///
+/// 进程号区分同时运行的多个进程，序号在进程内原子递增，区分同一进程中并发的线程，
+/// 因此多个进程或线程写入同一目录、甚至同一输出文件时临时文件也不会重名
+pub fn temp_path(output: &Path) -> PathBuf {
+    let file_name = output.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let counter = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    output.with_file_name(format!(".{}.{}.{}{}", file_name, std::process::id(), counter, TEMP_SUFFIX))
+}
+
+/// 是否是 [`temp_path`] 生成的临时文件
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(TEMP_SUFFIX))
+}
+
+/// `--clean-temp` 只删除超过这个时间没有修改的临时文件，更新的可能正由其它进程写入
+pub const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// 删除目录及其子目录中残留的临时文件 (例如进程崩溃时留下的)，返回删除的数量
+///
+/// 只删除至少 `min_age` 没有修改过的文件；读不到修改时间或修改时间在未来的文件保留
+pub fn clean_temp_files(dir: &Path, min_age: Duration) -> std::io::Result<usize> {
+    let is_stale = |entry: &walkdir::DirEntry| {
+        entry
+            .metadata()
+            .ok()
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age >= min_age)
+    };
+    let mut removed = 0;
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(Result::ok) {
+        if entry.file_type().is_file() && is_temp_file(entry.path()) && is_stale(&entry) {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// 解析 `WxH` 形式的尺寸字符串
+///
+/// 宽高都必须是正整数，分隔符大小写不敏感，例如 `256x256`、`1920X1080`
+pub fn parse_dimensions(value: &str) -> anyhow::Result<(u32, u32)> {
+    let lowercased = value.trim().to_lowercase();
+    let (width, height) = lowercased
+        .split_once('x')
+        .ok_or_else(|| anyhow::anyhow!("无效的尺寸: {} (格式应为 WxH)", value))?;
+
+    let width: u32 = width.parse().map_err(|_| anyhow::anyhow!("无效的宽度: {}", value))?;
+    let height: u32 = height.parse().map_err(|_| anyhow::anyhow!("无效的高度: {}", value))?;
+    if width == 0 || height == 0 {
+        anyhow::bail!("尺寸必须大于0: {}", value);
+    }
+
+    Ok((width, height))
+}
+
+/// 解析 `W:H` 形式的宽高比
+///
+/// 两个分量都必须是正整数，例如 `1:1`、`16:9`
+pub fn parse_aspect_ratio(value: &str) -> anyhow::Result<(u32, u32)> {
+    let (width, height) = value
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("无效的宽高比: {} (格式应为 W:H)", value))?;
+
+    let width: u32 = width.trim().parse().map_err(|_| anyhow::anyhow!("无效的宽高比: {}", value))?;
+    let height: u32 = height.trim().parse().map_err(|_| anyhow::anyhow!("无效的宽高比: {}", value))?;
+    if width == 0 || height == 0 {
+        anyhow::bail!("宽高比必须大于0: {}", value);
+    }
+
+    Ok((width, height))
+}
+
+/// 解析质量参数
+///
+/// 接受 0-100 之间的整数或小数，例如 `80`、`12.5`
+pub fn parse_quality(value: &str) -> anyhow::Result<f32> {
+    let quality: f32 = value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("无效的质量: {}", value))?;
+    if !(0.0..=100.0).contains(&quality) {
+        anyhow::bail!("质量必须在0-100之间: {}", value);
+    }
+    Ok(quality)
+}
+
+/// 解析颜色
+///
+/// 支持 `#RRGGBB`、`#RRGGBBAA`（井号可省略）以及 white、black、transparent 等常用名称
+pub fn parse_color(value: &str) -> anyhow::Result<image::Rgba<u8>> {
+    let lowercased = value.trim().to_lowercase();
+    let named = match lowercased.as_str() {
+        "white" => Some([255, 255, 255, 255]),
+        "black" => Some([0, 0, 0, 255]),
+        "gray" | "grey" => Some([128, 128, 128, 255]),
+        "red" => Some([255, 0, 0, 255]),
+        "green" => Some([0, 255, 0, 255]),
+        "blue" => Some([0, 0, 255, 255]),
+        "transparent" => Some([0, 0, 0, 0]),
+        _ => None,
+    };
+    if let Some(channels) = named {
+        return Ok(image::Rgba(channels));
+    }
+
+    let hex = lowercased.trim_start_matches('#');
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("无效的颜色: {} (格式应为 #RRGGBB 或 #RRGGBBAA)", value);
+    }
+
+    let channel = |index: usize| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).unwrap_or(0);
+    let alpha = if hex.len() == 8 { channel(3) } else { 255 };
+    Ok(image::Rgba([channel(0), channel(1), channel(2), alpha]))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
 
     #[test]
     fn test_has_potential_image_extension() {
         assert!(has_potential_image_extension(&PathBuf::from("test.png")));
         assert!(has_potential_image_extension(&PathBuf::from("test.jpg")));
         assert!(has_potential_image_extension(&PathBuf::from("test.JPEG")));
+        assert!(has_potential_image_extension(&PathBuf::from("test.ff")));
+        assert!(has_potential_image_extension(&PathBuf::from("test.jfif")));
+        assert!(has_potential_image_extension(&PathBuf::from("test.PJPEG")));
         assert!(!has_potential_image_extension(&PathBuf::from("test.txt")));
         assert!(has_potential_image_extension(&PathBuf::from("test"))); // 无扩展名
     }
@@ -192,6 +468,85 @@ mod tests {
         assert_eq!(change_extension(&path, "png"), "image.png");
     }
 
+    #[test]
+    fn test_jfif_and_any_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let jfif = dir.path().join("photo.jfif");
+        image::RgbImage::new(8, 8).save_with_format(&jfif, image::ImageFormat::Jpeg).unwrap();
+        assert!(is_image_file(&jfif));
+
+        let unusual = dir.path().join("photo.dat");
+        std::fs::copy(&jfif, &unusual).unwrap();
+        assert!(!is_image_file(&unusual));
+        assert!(is_image_file_with(&unusual, true));
+
+        // 跳过扩展名检查时仍然要求魔数匹配
+        let text = dir.path().join("notes.dat");
+        std::fs::write(&text, "not an image").unwrap();
+        assert!(!is_image_file_with(&text, true));
+    }
+
+    #[test]
+    fn test_pnm_format() {
+        assert_eq!(pnm_format(b"P6\n8 8\n255\n"), Some("ppm"));
+        assert_eq!(pnm_format(b"P3 1 1 255"), Some("ppm"));
+        assert_eq!(pnm_format(b"P5\n"), Some("pgm"));
+        assert_eq!(pnm_format(b"P1\r\n"), Some("pbm"));
+        assert_eq!(pnm_format(b"P4\t"), Some("pbm"));
+        // P7是PAM，不属于PNM家族；魔数后没有空白也不是PNM
+        assert_eq!(pnm_format(b"P7\n"), None);
+        assert_eq!(pnm_format(b"P6x"), None);
+        assert!(has_potential_image_extension(&PathBuf::from("frame.PPM")));
+    }
+
+    #[test]
+    fn test_tga_without_footer_needs_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        // 无颜色表的未压缩真彩色TGA头，没有TGA 2.0文件尾
+        let mut data = vec![0u8, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 24, 0];
+        data.extend_from_slice(&[0, 0, 255]);
+
+        let tga = dir.path().join("old.tga");
+        std::fs::write(&tga, &data).unwrap();
+        assert_eq!(detect_image_format_by_content(&tga).as_deref(), Some("tga"));
+
+        let unknown = dir.path().join("old.bin");
+        std::fs::write(&unknown, &data).unwrap();
+        assert_eq!(detect_image_format_by_content(&unknown), None);
+    }
+
+    #[test]
+    fn test_default_extension() {
+        assert_eq!(default_extension("jpeg"), "jpg");
+        assert_eq!(default_extension("JPG"), "jpg");
+        assert_eq!(default_extension("webp"), "webp");
+        assert_eq!(default_extension("ff"), "ff");
+    }
+
+    #[test]
+    fn test_canonical_format() {
+        assert_eq!(canonical_format("JPG"), "jpeg");
+        assert_eq!(canonical_format("jpeg"), "jpeg");
+        assert_eq!(canonical_format("ff"), "farbfeld");
+        assert_eq!(canonical_format("png"), "png");
+        assert_eq!(canonical_format("jfif"), "jpeg");
+        assert_eq!(canonical_format("dib"), "bmp");
+    }
+
+    #[test]
+    fn test_mime_type_and_extension_match() {
+        assert_eq!(mime_type("jpeg"), "image/jpeg");
+        assert_eq!(mime_type("JPG"), "image/jpeg");
+        assert_eq!(mime_type("ppm"), "image/x-portable-pixmap");
+        assert_eq!(mime_type("unknown"), "application/octet-stream");
+
+        assert!(extension_matches_format(Path::new("photo.JFIF"), "jpeg"));
+        assert!(extension_matches_format(Path::new("scan.pnm"), "pgm"));
+        assert!(!extension_matches_format(Path::new("scan.ppm"), "pgm"));
+        assert!(!extension_matches_format(Path::new("photo.png"), "jpeg"));
+        assert!(!extension_matches_format(Path::new("photo"), "jpeg"));
+    }
+
     #[test]
     fn test_get_extension() {
         assert_eq!(get_extension(&PathBuf::from("test.PNG")), "png");
@@ -206,4 +561,83 @@ mod tests {
         assert!(is_svg_content("<?XML version=\"1.0\"?>")); // 大小写不敏感
         assert!(!is_svg_content("<html>"));
     }
+
+    #[test]
+    fn test_parse_dimensions() {
+        assert_eq!(parse_dimensions("256x128").unwrap(), (256, 128));
+        assert_eq!(parse_dimensions("32X32").unwrap(), (32, 32));
+        assert!(parse_dimensions("0x10").is_err());
+        assert!(parse_dimensions("256").is_err());
+        assert!(parse_dimensions("axb").is_err());
+    }
+
+    #[test]
+    fn test_parse_aspect_ratio() {
+        assert_eq!(parse_aspect_ratio("16:9").unwrap(), (16, 9));
+        assert_eq!(parse_aspect_ratio(" 1 : 1 ").unwrap(), (1, 1));
+        assert!(parse_aspect_ratio("0:1").is_err());
+        assert!(parse_aspect_ratio("16x9").is_err());
+        assert!(parse_aspect_ratio("a:b").is_err());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#ff8000").unwrap(), image::Rgba([255, 128, 0, 255]));
+        assert_eq!(parse_color("00000080").unwrap(), image::Rgba([0, 0, 0, 128]));
+        assert_eq!(parse_color("White").unwrap(), image::Rgba([255, 255, 255, 255]));
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("#gggggg").is_err());
+    }
+
+    #[test]
+    fn test_parse_quality() {
+        assert_eq!(parse_quality("80").unwrap(), 80.0);
+        assert_eq!(parse_quality("12.5").unwrap(), 12.5);
+        assert!(parse_quality("100.1").is_err());
+        assert!(parse_quality("-1").is_err());
+        assert!(parse_quality("NaN").is_err());
+    }
+
+    #[test]
+    fn test_temp_path_is_unique_across_threads() {
+        let output = Path::new("out/photo.webp");
+        let handles: Vec<_> = (0..16)
+            .map(|_| std::thread::spawn(move || (0..200).map(|_| temp_path(output)).collect::<Vec<_>>()))
+            .collect();
+        let mut names = std::collections::HashSet::new();
+        for handle in handles {
+            for path in handle.join().unwrap() {
+                assert_eq!(path.parent(), Some(Path::new("out")));
+                assert!(is_temp_file(&path), "{}", path.display());
+                assert!(names.insert(path));
+            }
+        }
+        assert_eq!(names.len(), 16 * 200);
+    }
+
+    #[test]
+    fn test_clean_temp_files_removes_only_temps() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        let outputs = [dir.path().join("a.png"), dir.path().join("nested/b.png")];
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        for output in &outputs {
+            std::fs::write(output, b"image").unwrap();
+            let temp = temp_path(output);
+            std::fs::write(&temp, b"partial").unwrap();
+            File::options().write(true).open(&temp).unwrap().set_modified(two_hours_ago).unwrap();
+        }
+        // 名字相近但不是临时文件
+        std::fs::write(dir.path().join("c.pixforge-tmp"), b"keep").unwrap();
+        // 刚写入的临时文件可能属于正在运行的进程
+        let live = temp_path(&dir.path().join("d.png"));
+        std::fs::write(&live, b"writing").unwrap();
+
+        assert_eq!(clean_temp_files(dir.path(), STALE_TEMP_AGE).unwrap(), 2);
+        assert!(outputs.iter().all(|output| output.exists()));
+        assert!(dir.path().join("c.pixforge-tmp").exists());
+        assert!(live.exists());
+        assert_eq!(clean_temp_files(dir.path(), STALE_TEMP_AGE).unwrap(), 0);
+        assert_eq!(clean_temp_files(dir.path(), Duration::ZERO).unwrap(), 1);
+    }
 }
@@ -0,0 +1,159 @@
+//! 监视目录并自动转换新文件
+//!
+//! 复制大文件时会连续产生多次修改事件，每个路径在最后一次事件之后静默一段时间
+//! 才开始转换，避免读到写了一半的文件
+
+use anyhow::{Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::converter::ImageConverter;
+use crate::utils;
+
+/// 等待事件的轮询间隔，决定检查停止条件和去抖到期的频率
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 按路径合并短时间内的重复事件
+#[derive(Debug)]
+pub struct Debouncer {
+    delay: Duration,
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl Debouncer {
+    pub fn new(delay: Duration) -> Self {
+        Self { delay, pending: HashMap::new() }
+    }
+
+    /// 记录一次事件，同一路径的新事件会推迟其到期时间
+    pub fn push(&mut self, path: PathBuf, now: Instant) {
+        self.pending.insert(path, now);
+    }
+
+    /// 取出最后一次事件距今已超过去抖时间的路径，按路径排序
+    pub fn take_ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut ready: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, last)| now.duration_since(**last) >= self.delay)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &ready {
+            self.pending.remove(path);
+        }
+        ready.sort();
+        ready
+    }
+}
+
+/// 监视 `input_dir` 中新建或修改的图像并逐个转换到 `output_dir`
+///
+/// 每次轮询都会调用 `keep_running`，返回 `false` 时停止；命令行中传入始终返回
+/// `true` 的闭包，一直运行到进程被中断。已经是目标格式的文件和输出目录中的文件
+/// 会被忽略，避免输出目录与输入目录相同时反复转换自己的输出
+pub fn watch_directory(
+    converter: &ImageConverter,
+    input_dir: &Path,
+    output_dir: &Path,
+    target_format: &str,
+    quality: f32,
+    debounce: Duration,
+    mut keep_running: impl FnMut() -> bool,
+) -> Result<()> {
+    std::fs::create_dir_all(output_dir)?;
+    let output_dir = output_dir.canonicalize()?;
+    let target = utils::canonical_format(target_format);
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).context("无法创建文件监视器")?;
+    watcher
+        .watch(input_dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("无法监视目录: {}", input_dir.display()))?;
+
+    println!("👀 正在监视: {} (按 Ctrl+C 停止)", input_dir.display());
+
+    let mut debouncer = Debouncer::new(debounce);
+    while keep_running() {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    let now = Instant::now();
+                    for path in event.paths {
+                        debouncer.push(path, now);
+                    }
+                }
+            }
+            Ok(Err(e)) => println!("⚠️  监视出错: {}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        for path in debouncer.take_ready(Instant::now()) {
+            if !converter.is_image_input(&path) {
+                continue;
+            }
+            if utils::canonical_format(&utils::get_extension(&path)) == target
+                || path.parent().and_then(|parent| parent.canonicalize().ok()).as_deref() == Some(&output_dir)
+            {
+                continue;
+            }
+
+            if let Err(e) = converter.convert_single_file(&path, &output_dir, target_format, quality) {
+                println!("❌ {:#}", anyhow::Error::from(e));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer_waits_for_quiet_period() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(100));
+        let path = PathBuf::from("a.png");
+
+        debouncer.push(path.clone(), start);
+        debouncer.push(path.clone(), start + Duration::from_millis(80));
+        // 第二次事件推迟了到期时间
+        assert!(debouncer.take_ready(start + Duration::from_millis(120)).is_empty());
+        assert_eq!(debouncer.take_ready(start + Duration::from_millis(180)), vec![path]);
+        assert!(debouncer.take_ready(start + Duration::from_millis(500)).is_empty());
+    }
+
+    #[test]
+    fn test_created_file_is_converted() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let expected = output.path().join("dropped.jpg");
+
+        let source = input.path().join("dropped.png");
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            image::RgbImage::from_pixel(8, 8, image::Rgb([10, 200, 30])).save(source).unwrap();
+        });
+
+        let converter = ImageConverter::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        watch_directory(
+            &converter,
+            input.path(),
+            output.path(),
+            "jpeg",
+            80.0,
+            Duration::from_millis(100),
+            || !expected.exists() && Instant::now() < deadline,
+        )
+        .unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(image::open(&expected).unwrap().width(), 8);
+    }
+}
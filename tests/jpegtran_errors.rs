@@ -0,0 +1,70 @@
+//! 损坏的JPEG在无损变换中报告为错误，不依赖栈展开
+
+#![cfg(feature = "encode")]
+
+use image::codecs::jpeg::JpegEncoder;
+use image::RgbImage;
+use pixforge::jpegtran;
+use pixforge::transform::Reorient;
+use std::path::Path;
+use std::process::Command;
+
+fn sample_jpeg() -> Vec<u8> {
+    let img = RgbImage::from_fn(64, 48, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 90]));
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut data, 90).encode_image(&img).unwrap();
+    data
+}
+
+/// 在扫描开始之前截断：libjpeg读不到SOS，属于致命错误
+fn truncated_header(data: &[u8]) -> &[u8] {
+    let scan = data.windows(2).position(|marker| marker == [0xFF, 0xDA]).unwrap();
+    &data[..scan - 4]
+}
+
+#[test]
+fn test_corrupt_jpeg_is_an_error() {
+    let data = sample_jpeg();
+    let truncated = truncated_header(&data);
+    let rotate = Reorient::from_degrees(90).unwrap();
+
+    let err = jpegtran::transform(truncated, rotate).unwrap_err().to_string();
+    // 错误信息来自libjpeg
+    assert!(err.len() > "JPEG无损变换失败: ".len() && err.starts_with("JPEG无损变换失败: "), "{}", err);
+    assert!(jpegtran::optimize(truncated).is_err());
+    assert!(jpegtran::set_restart_interval(truncated, 1).is_err());
+
+    // 帧头中的分量数为0
+    let frame = data.windows(2).position(|marker| marker == [0xFF, 0xC0]).unwrap();
+    let mut corrupt = data.clone();
+    corrupt[frame + 9] = 0;
+    assert!(jpegtran::transform(&corrupt, rotate).is_err());
+
+    // 熵编码数据中途截断只是警告，libjpeg补全数据后照常输出
+    let partial = &data[..data.len() - 200];
+    assert!(jpegtran::transform(partial, rotate).unwrap().is_some());
+}
+
+/// 测试本身总是按panic = "unwind" 编译，这里用 `abort-check` 配置单独编译示例程序再运行
+#[test]
+fn test_corrupt_jpeg_does_not_abort_release_build() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("truncated.jpg");
+    std::fs::write(&input, truncated_header(&sample_jpeg())).unwrap();
+
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .current_dir(manifest_dir)
+        .env("CARGO_TARGET_DIR", manifest_dir.join("target").join("abort-check"))
+        .args(["run", "-q", "--profile", "abort-check", "--no-default-features", "--features", "encode"])
+        .args(["--example", "jpegtran_check", "--"])
+        .arg(&input)
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}\n{}\n{}", output.status, stdout, String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", stdout);
+    assert!(lines.iter().all(|line| line.starts_with("error: ")), "{}", stdout);
+}
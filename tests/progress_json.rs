@@ -0,0 +1,65 @@
+//! `--progress-json` 时标准输出只有进度行，其它提示信息写到标准错误
+
+#![cfg(feature = "encode")]
+
+use image::RgbImage;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+fn sample_png(path: &Path) {
+    RgbImage::from_fn(8, 8, |x, y| image::Rgb([(x * 30) as u8, (y * 30) as u8, 90])).save(path).unwrap();
+}
+
+/// 运行命令行并检查标准输出的每一行都是进度JSON，返回各行的 `status`
+fn progress_statuses(input: &Path, output: &Path) -> Vec<String> {
+    let result = Command::new(env!("CARGO_BIN_EXE_pixforge"))
+        .arg(input)
+        .args(["--to", "webp", "--verbose", "--progress-json", "-o"])
+        .arg(output)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(!result.stderr.is_empty());
+
+    String::from_utf8(result.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let event: serde_json::Value = serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {}", e, line));
+            event["status"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn test_directory_stdout_is_ndjson() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("in");
+    std::fs::create_dir(&input).unwrap();
+    sample_png(&input.join("a.png"));
+    sample_png(&input.join("b.png"));
+    std::fs::write(input.join("broken.png"), b"\x89PNG\r\n\x1a\nnot really").unwrap();
+
+    let mut statuses = progress_statuses(&input, &dir.path().join("out"));
+    statuses.sort();
+    assert_eq!(statuses, ["converted", "converted", "failed"]);
+}
+
+#[test]
+fn test_archive_stdout_is_ndjson() {
+    let dir = tempfile::tempdir().unwrap();
+    let png = dir.path().join("a.png");
+    sample_png(&png);
+    let archive = dir.path().join("photos.zip");
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+    for name in ["a.png", "nested/b.png"] {
+        writer.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+        writer.write_all(&std::fs::read(&png).unwrap()).unwrap();
+    }
+    writer.start_file("notes.txt", zip::write::SimpleFileOptions::default()).unwrap();
+    writer.write_all(b"not an image").unwrap();
+    writer.finish().unwrap();
+
+    assert_eq!(progress_statuses(&archive, &dir.path().join("out")), ["converted", "converted"]);
+}
@@ -0,0 +1,64 @@
+//! 流式缩小TIFF的峰值内存
+//!
+//! 统计内存需要替换全局分配器，放在单独的测试程序中，不影响库的其它测试
+
+use image::{Rgb, RgbImage};
+use pixforge::tiff_stream::decode_downscaled;
+use pixforge::transform::{FitMode, Resize};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// 按线程统计堆内存峰值，避免并行运行的其它测试干扰测量
+struct TrackingAllocator;
+
+thread_local! {
+    static CURRENT: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+fn track(delta: isize) {
+    let _ = CURRENT.try_with(|current| {
+        current.set(current.get() + delta);
+        let _ = PEAK.try_with(|peak| peak.set(peak.get().max(current.get())));
+    });
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        track(layout.size() as isize);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        track(-(layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+/// 执行闭包并返回期间相对起点的堆内存峰值
+fn peak_allocation<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let start = CURRENT.with(Cell::get);
+    PEAK.with(|peak| peak.set(start));
+    let result = f();
+    (result, (PEAK.with(Cell::get) - start) as usize)
+}
+
+#[test]
+fn test_streamed_downscale_peak_memory() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("scan.tiff");
+    let source = RgbImage::from_fn(2000, 1600, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 0]));
+    source.save(&path).unwrap();
+    let full_size = source.as_raw().len();
+    drop(source);
+
+    let resize = Resize { width: 200, height: 200, mode: FitMode::Fit };
+    let (streamed, peak) = peak_allocation(|| decode_downscaled(&path, &resize).unwrap().unwrap());
+
+    assert_eq!((streamed.width(), streamed.height()), (200, 160));
+    // 远小于整幅解码所需的内存
+    assert!(peak < full_size / 4, "峰值内存 {} 字节", peak);
+}